@@ -0,0 +1,75 @@
+//! Cost of turning a header-heavy Pingora request head into a `PingoraHttpRequest`
+//!
+//! `cargo bench -p pingora_web --bench request_headers`
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use pingora_http::RequestHeader;
+use pingora_web::PingoraHttpRequest;
+
+const ROUNDS: u32 = 200_000;
+
+/// A browser-like head: cookies, client hints, tracing and forwarding headers
+fn head() -> RequestHeader {
+    let mut head = RequestHeader::build("GET", b"/api/v1/items?page=2", None).unwrap();
+    head.append_header("host", "example.com").unwrap();
+    head.append_header(
+        "user-agent",
+        "Mozilla/5.0 (X11; Linux x86_64) Gecko/20100101 Firefox/128.0",
+    )
+    .unwrap();
+    head.append_header("accept", "text/html,application/xhtml+xml;q=0.9,*/*;q=0.8")
+        .unwrap();
+    head.append_header("accept-language", "en-US,en;q=0.5")
+        .unwrap();
+    head.append_header("accept-encoding", "gzip, deflate, br, zstd")
+        .unwrap();
+    head.append_header("cookie", format!("session={}", "a1b2c3d4".repeat(32)))
+        .unwrap();
+    head.append_header(
+        "traceparent",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+    )
+    .unwrap();
+    for i in 0..24 {
+        head.append_header(
+            format!("x-custom-{i}"),
+            format!("value-{i}-{}", "x".repeat(40)),
+        )
+        .unwrap();
+    }
+    head
+}
+
+/// What the App did before: one string round trip per header
+fn rebuild(head: &RequestHeader) -> PingoraHttpRequest {
+    let path = String::from_utf8_lossy(head.raw_path()).to_string();
+    let mut req = PingoraHttpRequest::new(head.method.clone(), path);
+    for (name, value) in head.headers.iter() {
+        if let Ok(v) = value.to_str() {
+            req = req.header(name.as_str(), v);
+        }
+    }
+    req
+}
+
+fn bench(name: &str, head: &RequestHeader, make: fn(&RequestHeader) -> PingoraHttpRequest) {
+    black_box(make(head));
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        black_box(make(black_box(head)));
+    }
+    println!("{name:<24} {:>10.2?} per request", start.elapsed() / ROUNDS);
+}
+
+fn main() {
+    let head = head();
+    println!("{} headers", head.headers.len());
+    bench("string rebuild", &head, rebuild);
+    bench(
+        "from_request_header",
+        &head,
+        PingoraHttpRequest::from_request_header,
+    );
+}
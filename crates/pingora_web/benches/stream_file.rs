@@ -0,0 +1,85 @@
+//! Throughput of the static file paths, draining bodies the way the App writes them
+//!
+//! `cargo bench -p pingora_web --bench stream_file [--features mmap]`
+
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use pingora_web::StatusCode;
+use pingora_web::response::{Body, PingoraWebHttpResponse};
+
+const FILE_SIZE: usize = 64 * 1024 * 1024;
+const ROUNDS: u32 = 10;
+/// The App's default segment size for in-memory bodies
+const SEGMENT: usize = 64 * 1024;
+
+/// Pull every chunk and copy it out, as writing it to the socket would
+async fn drain(res: PingoraWebHttpResponse) -> usize {
+    let mut sink = Vec::with_capacity(4 * 1024 * 1024);
+    let mut total = 0;
+    let mut write = |chunk: &[u8]| {
+        sink.clear();
+        sink.extend_from_slice(chunk);
+        total += sink.len();
+    };
+    match res.body {
+        Body::Bytes(mut bytes) => {
+            while !bytes.is_empty() {
+                write(&bytes.split_to(SEGMENT.min(bytes.len())));
+            }
+        }
+        Body::Stream(mut stream) => {
+            while let Some(chunk) = stream.next().await {
+                write(&chunk);
+            }
+        }
+    }
+    total
+}
+
+async fn bench<F: Fn() -> PingoraWebHttpResponse>(name: &str, make: F) {
+    // Warm up the page cache
+    drain(make()).await;
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        assert!(drain(make()).await >= FILE_SIZE);
+    }
+    let elapsed: Duration = start.elapsed();
+    let mib = (FILE_SIZE as f64 * f64::from(ROUNDS)) / (1024.0 * 1024.0);
+    println!(
+        "{name:<24} {:>8.1} MiB/s  ({:.2?} per file)",
+        mib / elapsed.as_secs_f64(),
+        elapsed / ROUNDS
+    );
+}
+
+fn main() {
+    let path = std::env::temp_dir().join(format!("pingora_web_bench_{}.bin", std::process::id()));
+    let data: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, data).unwrap();
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        bench("stream_file (64KiB)", || {
+            PingoraWebHttpResponse::stream_file(StatusCode::OK, &path)
+        })
+        .await;
+        for read_size in [256 * 1024, 1024 * 1024] {
+            let name = format!("read size {}KiB", read_size / 1024);
+            bench(&name, || {
+                PingoraWebHttpResponse::stream_file_with_read_size(StatusCode::OK, &path, read_size)
+            })
+            .await;
+        }
+        #[cfg(feature = "mmap")]
+        bench("mmap_file", || {
+            PingoraWebHttpResponse::mmap_file(StatusCode::OK, &path).unwrap()
+        })
+        .await;
+    });
+
+    std::fs::remove_file(&path).ok();
+}
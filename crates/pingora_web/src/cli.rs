@@ -0,0 +1,209 @@
+//! Command-line flags shared by every service's `main()`
+//!
+//! ```no_run
+//! use pingora_web::App;
+//! use pingora_web::cli::Cli;
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let cli = Cli::from_env();
+//!     let config = cli.app_config()?;
+//!     // e.g. tracing_subscriber::fmt().with_max_level(config.level_filter()...)
+//!     let mut app = App::from_config(&config);
+//!     app.get_fn("/", |_req| "hello");
+//!     cli.run(app)
+//! }
+//! ```
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tracing::level_filters::LevelFilter;
+
+use crate::App;
+use crate::server::{AppConfig, ConfigError};
+
+pub const USAGE: &str = "\
+Options:
+  --config <FILE>             Load settings from a .yaml/.yml or .json file
+  --listen <ADDR>             Serve plain HTTP on ADDR (repeatable, or comma-separated)
+  --log-level <LEVEL>         off, error, warn, info, debug or trace
+  --check                     Validate the configuration and exit without binding
+  --graceful-timeout <SECS>   Grace period for in-flight work on shutdown
+  -h, --help                  Print this help";
+
+/// Why the command line was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliError {
+    /// `-h` or `--help` was given
+    Help,
+    /// An unknown flag, a missing value or a value that doesn't parse
+    Invalid(String),
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Help => f.write_str(USAGE),
+            Self::Invalid(e) => write!(f, "{e}\n\n{USAGE}"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Parsed common flags
+///
+/// Settings apply in order: the `--config` file, `PINGORA_WEB_*` environment
+/// variables (see `AppConfig::with_env`), then the flags.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cli {
+    pub config: Option<PathBuf>,
+    pub listen: Vec<String>,
+    pub log_level: Option<String>,
+    pub check: bool,
+    pub graceful_timeout: Option<Duration>,
+}
+
+impl Cli {
+    /// Parse the process arguments, printing usage and exiting on `--help` or a bad flag
+    pub fn from_env() -> Self {
+        match Self::parse_from(std::env::args().skip(1)) {
+            Ok(cli) => cli,
+            Err(CliError::Help) => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /// Parse arguments without the program name; `--flag value` and `--flag=value` both work
+    pub fn parse_from<I, T>(args: I) -> Result<Self, CliError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut cli = Self::default();
+        let mut args = args.into_iter().map(Into::into);
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| CliError::Invalid(format!("{flag} needs a value")))
+            };
+            match flag.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--check" => cli.check = true,
+                "--config" => cli.config = Some(value()?.into()),
+                "--listen" => cli.listen.extend(
+                    value()?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|a| !a.is_empty())
+                        .map(String::from),
+                ),
+                "--log-level" => {
+                    let level = value()?;
+                    if LevelFilter::from_str(&level).is_err() {
+                        return Err(CliError::Invalid(format!("invalid --log-level {level:?}")));
+                    }
+                    cli.log_level = Some(level);
+                }
+                "--graceful-timeout" => {
+                    let secs = value()?;
+                    let parsed = secs.trim_end_matches('s').parse().map_err(|_| {
+                        CliError::Invalid(format!("invalid --graceful-timeout {secs:?}"))
+                    })?;
+                    cli.graceful_timeout = Some(Duration::from_secs(parsed));
+                }
+                _ => return Err(CliError::Invalid(format!("unknown argument {flag:?}"))),
+            }
+        }
+        Ok(cli)
+    }
+
+    /// The config file (or defaults), then the environment, then the flags
+    pub fn app_config(&self) -> Result<AppConfig, ConfigError> {
+        let config = match &self.config {
+            Some(path) => AppConfig::from_file(path)?,
+            None => AppConfig::default(),
+        };
+        Ok(self.apply(config.with_env()?))
+    }
+
+    fn apply(&self, mut config: AppConfig) -> AppConfig {
+        if !self.listen.is_empty() {
+            config.listen = self.listen.clone();
+        }
+        if let Some(level) = &self.log_level {
+            config.log_level = Some(level.clone());
+        }
+        if let Some(timeout) = self.graceful_timeout {
+            config.graceful_shutdown_timeout_secs = Some(timeout.as_secs());
+        }
+        config
+    }
+
+    /// Serve `app`, or with `--check` only validate it (see `App::check`)
+    pub fn run<S: Send + Sync + 'static>(
+        &self,
+        app: App<S>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.check {
+            app.check()?;
+            println!("configuration ok");
+            return Ok(());
+        }
+        app.run()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_config() {
+        let cli = Cli::parse_from([
+            "--listen",
+            "0.0.0.0:80,0.0.0.0:81",
+            "--log-level=debug",
+            "--graceful-timeout",
+            "30s",
+            "--check",
+        ])
+        .unwrap();
+        assert!(cli.check);
+        assert_eq!(cli.graceful_timeout, Some(Duration::from_secs(30)));
+
+        let file = AppConfig::from_yaml("listen: [\"127.0.0.1:8080\"]\nlog_level: warn").unwrap();
+        let config = cli.apply(file);
+        assert_eq!(config.listen, ["0.0.0.0:80", "0.0.0.0:81"]);
+        assert_eq!(config.level_filter(), Some(LevelFilter::DEBUG));
+        assert_eq!(config.graceful_shutdown_timeout_secs, Some(30));
+
+        assert_eq!(Cli::parse_from(["-h"]), Err(CliError::Help));
+        let err = Cli::parse_from(["--log-level", "loud"]).unwrap_err();
+        assert_eq!(
+            err,
+            CliError::Invalid("invalid --log-level \"loud\"".into())
+        );
+        let err = Cli::parse_from(["--config"]).unwrap_err();
+        assert_eq!(err, CliError::Invalid("--config needs a value".into()));
+        assert!(matches!(
+            Cli::parse_from(["serve"]),
+            Err(CliError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use pingora_core::modules::http::compression::ResponseCompression;
+use pingora_core::modules::http::{HttpModule, HttpModuleCtx};
+
+/// Annotation read by the compression module: level override, `"0"` disables it
+pub const COMPRESSION_LEVEL: &str = "compression.level";
+/// Annotation written by the compression module: negotiated encoding (e.g. `"gzip"`)
+pub const COMPRESSION_ALGORITHM: &str = "compression.algorithm";
+
+/// Annotation written by the App: response body bytes sent, after module filters
+pub const RESPONSE_BODY_BYTES: &str = "response.body_bytes";
+/// Annotation written by the App when a response was cut short: the failed
+/// `WriteStage`, e.g. `"body"`
+pub const RESPONSE_WRITE_FAILURE: &str = "response.write_failure";
+
+type Callback = Box<dyn FnOnce(&Annotations) + Send>;
+
+/// Per-request string variables shared between middleware and pingora HTTP modules
+///
+/// Read it with `req.annotations()`. Clones share the same values, so a copy
+/// kept by middleware sees what modules write after the handler returned.
+/// Modules take part once bridged with `App::bridge_annotations::<M>()`; the
+/// compression module is always bridged.
+#[derive(Clone, Default)]
+pub struct Annotations(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    values: Mutex<HashMap<String, String>>,
+    on_complete: Mutex<Vec<Callback>>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, key: impl Into<String>, value: impl Into<String>) {
+        self.0
+            .values
+            .lock()
+            .unwrap()
+            .insert(key.into(), value.into());
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.0.values.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.0.values.lock().unwrap().remove(key)
+    }
+
+    /// Copy of all current values
+    pub fn snapshot(&self) -> HashMap<String, String> {
+        self.0.values.lock().unwrap().clone()
+    }
+
+    /// Run `f` once the response has been written to the connection
+    ///
+    /// By then modules have exported their final values, e.g. the negotiated
+    /// `COMPRESSION_ALGORITHM` and the `RESPONSE_BODY_BYTES` sent. Callbacks are
+    /// dropped unrun if the connection fails or the App is driven through
+    /// `App::handle` directly.
+    pub fn on_complete(&self, f: impl FnOnce(&Annotations) + Send + 'static) {
+        self.0.on_complete.lock().unwrap().push(Box::new(f));
+    }
+
+    /// Run and clear the `on_complete` callbacks
+    pub(crate) fn complete(&self) {
+        let callbacks = std::mem::take(&mut *self.0.on_complete.lock().unwrap());
+        for f in callbacks {
+            f(self);
+        }
+    }
+}
+
+impl std::fmt::Debug for Annotations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Annotations")
+            .field(&self.snapshot())
+            .finish()
+    }
+}
+
+/// A pingora HTTP module that exchanges values with request annotations
+///
+/// `export_annotations` runs after the module's request header filter and
+/// again once the response is written; `import_annotations` runs after the
+/// handler returned, right before the response header filter.
+pub trait AnnotatedModule: HttpModule + 'static {
+    /// Read values set by middleware and handlers
+    fn import_annotations(&mut self, _annotations: &Annotations) {}
+
+    /// Publish values for middleware and logging
+    fn export_annotations(&self, _annotations: &Annotations) {}
+}
+
+impl AnnotatedModule for ResponseCompression {
+    fn import_annotations(&mut self, annotations: &Annotations) {
+        if let Some(level) = annotations
+            .get(COMPRESSION_LEVEL)
+            .and_then(|v| v.parse().ok())
+        {
+            self.adjust_level(level);
+        }
+    }
+
+    fn export_annotations(&self, annotations: &Annotations) {
+        if let Some((algorithm, ..)) = self.get_info() {
+            annotations.set(COMPRESSION_ALGORITHM, algorithm);
+        }
+    }
+}
+
+/// Direction of an annotation exchange with bridged modules
+#[derive(Clone, Copy)]
+pub(crate) enum Exchange {
+    Import,
+    Export,
+}
+
+/// Type-erased bridge to one module type inside a per-request module context
+pub(crate) type ModuleBridge = fn(&mut HttpModuleCtx, &Annotations, Exchange);
+
+pub(crate) fn bridge<M: AnnotatedModule>(
+    ctx: &mut HttpModuleCtx,
+    annotations: &Annotations,
+    exchange: Exchange,
+) {
+    if let Some(module) = ctx.get_mut::<M>() {
+        match exchange {
+            Exchange::Import => module.import_annotations(annotations),
+            Exchange::Export => module.export_annotations(annotations),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_values() {
+        let annotations = Annotations::new();
+        let seen = annotations.clone();
+        annotations.set("tag", "blue");
+        assert_eq!(seen.get("tag").as_deref(), Some("blue"));
+        assert_eq!(seen.remove("tag").as_deref(), Some("blue"));
+        assert!(annotations.snapshot().is_empty());
+    }
+
+    #[test]
+    fn completion_callbacks_run_once() {
+        let annotations = Annotations::new();
+        let seen = Arc::new(Mutex::new(None));
+        let record = seen.clone();
+        annotations.on_complete(move |a| *record.lock().unwrap() = a.get(RESPONSE_BODY_BYTES));
+        annotations.set(RESPONSE_BODY_BYTES, "42");
+        annotations.complete();
+        annotations.set(RESPONSE_BODY_BYTES, "0");
+        annotations.complete();
+        assert_eq!(seen.lock().unwrap().as_deref(), Some("42"));
+    }
+}
@@ -0,0 +1,263 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use http::StatusCode;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::error::ResponseError;
+
+/// Error reading the request body with `req.body_bytes()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BodyError {
+    /// The body exceeds the request's body limit (bytes)
+    TooLarge(usize),
+    /// The client connection failed while the body was read
+    Read(String),
+    /// Writing the body to or reading it from its temporary file failed
+    Spool(String),
+}
+
+impl std::fmt::Display for BodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "Request body exceeds {limit} bytes"),
+            BodyError::Read(e) => write!(f, "Failed to read request body: {e}"),
+            BodyError::Spool(e) => write!(f, "Failed to spool request body: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+impl ResponseError for BodyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BodyError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            BodyError::Read(_) => StatusCode::BAD_REQUEST,
+            BodyError::Spool(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// When request bodies go to temporary files instead of memory, see `App::set_body_spool`
+#[derive(Clone, Debug)]
+pub struct BodySpool {
+    /// Bodies larger than this many bytes are spooled
+    pub threshold: usize,
+    /// Directory for the files (default: `std::env::temp_dir()`)
+    pub dir: PathBuf,
+}
+
+impl BodySpool {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            dir: std::env::temp_dir(),
+        }
+    }
+
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+}
+
+/// A request body in a temporary file, available via `req.spooled_body()`
+///
+/// The file is deleted when the last reference is dropped, i.e. with the
+/// request unless a handler keeps it (e.g. in a spawned task).
+#[derive(Debug)]
+pub struct SpooledBody {
+    path: PathBuf,
+    len: u64,
+}
+
+impl SpooledBody {
+    /// A new, empty file in `dir` and its handle
+    async fn create(dir: &Path) -> std::io::Result<(Self, tokio::fs::File)> {
+        let name = format!("pingora-web-body-{}", crate::utils::request_id::generate());
+        let path = dir.join(name);
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+        Ok((Self { path, len: 0 }, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size in bytes (never zero)
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub async fn open(&self) -> std::io::Result<tokio::fs::File> {
+        tokio::fs::File::open(&self.path).await
+    }
+
+    /// The body as a stream of chunks
+    pub async fn stream(&self) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+        Ok(tokio_util::io::ReaderStream::new(self.open().await?).boxed())
+    }
+}
+
+impl Drop for SpooledBody {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A body read from the connection
+pub(crate) enum ReadBody {
+    Memory(Bytes),
+    Spooled(SpooledBody),
+}
+
+/// Ask the connection task for the body, reading at most `limit` bytes and
+/// spooling it to a file when `spool` applies
+pub(crate) struct BodyDemand {
+    pub(crate) limit: usize,
+    pub(crate) spool: Option<Arc<BodySpool>>,
+    pub(crate) reply: oneshot::Sender<Result<ReadBody, BodyError>>,
+}
+
+/// Accumulates body chunks in memory, moving them to a file past the spool threshold
+pub(crate) struct BodyCollector<'a> {
+    spool: Option<&'a BodySpool>,
+    body: bytes::BytesMut,
+    file: Option<(SpooledBody, tokio::fs::File)>,
+    len: usize,
+}
+
+fn spool_error(e: std::io::Error) -> BodyError {
+    BodyError::Spool(e.to_string())
+}
+
+impl<'a> BodyCollector<'a> {
+    pub(crate) fn new(spool: Option<&'a BodySpool>) -> Self {
+        Self {
+            spool,
+            body: bytes::BytesMut::new(),
+            file: None,
+            len: 0,
+        }
+    }
+
+    /// Bytes collected so far
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) async fn push(&mut self, chunk: &[u8]) -> Result<(), BodyError> {
+        use tokio::io::AsyncWriteExt;
+
+        self.len += chunk.len();
+        if let Some((_, out)) = &mut self.file {
+            return out.write_all(chunk).await.map_err(spool_error);
+        }
+        match self.spool {
+            Some(spool) if self.len > spool.threshold => {
+                let created = SpooledBody::create(&spool.dir).await.map_err(spool_error)?;
+                // The file is removed again if writing fails
+                let (_, out) = self.file.insert(created);
+                out.write_all(&self.body).await.map_err(spool_error)?;
+                out.write_all(chunk).await.map_err(spool_error)?;
+                self.body = bytes::BytesMut::new();
+                Ok(())
+            }
+            _ => {
+                self.body.extend_from_slice(chunk);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) async fn finish(self) -> Result<ReadBody, BodyError> {
+        use tokio::io::AsyncWriteExt;
+
+        match self.file {
+            Some((mut spooled, mut out)) => {
+                out.flush().await.map_err(spool_error)?;
+                spooled.len = self.len as u64;
+                Ok(ReadBody::Spooled(spooled))
+            }
+            None => Ok(ReadBody::Memory(self.body.freeze())),
+        }
+    }
+}
+
+/// Handle a request uses to pull its unread body from the connection
+#[derive(Debug)]
+pub(crate) struct PendingBody {
+    demands: mpsc::Sender<BodyDemand>,
+}
+
+impl PendingBody {
+    /// A handle and the demand queue the connection task must serve
+    pub(crate) fn channel() -> (Self, mpsc::Receiver<BodyDemand>) {
+        let (demands, rx) = mpsc::channel(1);
+        (Self { demands }, rx)
+    }
+
+    pub(crate) async fn read(
+        self,
+        limit: usize,
+        spool: Option<Arc<BodySpool>>,
+    ) -> Result<ReadBody, BodyError> {
+        let (reply, result) = oneshot::channel();
+        let demand = BodyDemand {
+            limit,
+            spool,
+            reply,
+        };
+        if self.demands.send(demand).await.is_err() {
+            return Err(BodyError::Read("connection closed".to_string()));
+        }
+        result
+            .await
+            .unwrap_or_else(|_| Err(BodyError::Read("connection closed".to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn large_bodies_go_to_a_temp_file() {
+        let dir = std::env::temp_dir();
+        let spool = BodySpool::new(4).dir(dir.clone());
+
+        let mut small = BodyCollector::new(Some(&spool));
+        small.push(b"abc").await.unwrap();
+        assert!(matches!(small.finish().await.unwrap(), ReadBody::Memory(b) if b == "abc"));
+
+        let mut large = BodyCollector::new(Some(&spool));
+        large.push(b"abc").await.unwrap();
+        large.push(b"defgh").await.unwrap();
+        let ReadBody::Spooled(spooled) = large.finish().await.unwrap() else {
+            panic!("expected a spooled body");
+        };
+        assert_eq!(spooled.len(), 8);
+        assert!(spooled.path().starts_with(&dir));
+        let streamed: Vec<u8> = spooled
+            .stream()
+            .await
+            .unwrap()
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+        assert_eq!(streamed, b"abcdefgh");
+
+        let path = spooled.path().to_path_buf();
+        drop(spooled);
+        assert!(!path.exists());
+    }
+}
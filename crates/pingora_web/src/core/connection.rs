@@ -0,0 +1,89 @@
+use std::net::SocketAddr;
+
+/// Transport details of the connection a request arrived on
+///
+/// Set by the App for every request served over a listener; read it with
+/// `req.connection()`. Requests built in-process (tests, warmup) have none.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    /// Client address (`None` for Unix sockets)
+    pub peer_addr: Option<SocketAddr>,
+    /// Local address the connection was accepted on (`None` for Unix sockets)
+    pub local_addr: Option<SocketAddr>,
+    /// TLS details, when the connection is encrypted
+    pub tls: Option<TlsInfo>,
+}
+
+/// TLS details of a connection
+#[derive(Debug, Clone, Default)]
+pub struct TlsInfo {
+    /// Server name the client asked for (SNI)
+    pub sni: Option<String>,
+    /// Negotiated application protocol (`"h2"` or `"http/1.1"`)
+    pub alpn: Option<String>,
+    /// Protocol version, e.g. `"TLSv1.3"`
+    pub version: String,
+    /// Negotiated cipher suite
+    pub cipher: String,
+    /// Client certificate, when mutual TLS is used
+    pub client_cert: Option<ClientCert>,
+}
+
+/// Summary of the client certificate presented during mutual TLS
+#[derive(Debug, Clone, Default)]
+pub struct ClientCert {
+    pub organization: Option<String>,
+    pub serial_number: Option<String>,
+    /// Digest of the certificate, usable as a stable identity
+    pub digest: Vec<u8>,
+}
+
+impl ConnectionInfo {
+    /// Collect connection details from a Pingora session
+    pub(crate) fn from_session(session: &pingora::protocols::http::ServerSession) -> Self {
+        let inet = |addr: Option<&pingora::protocols::l4::socket::SocketAddr>| {
+            addr.and_then(|a| a.as_inet()).copied()
+        };
+
+        let tls = session
+            .digest()
+            .and_then(|d| d.ssl_digest.as_ref())
+            .map(|ssl| TlsInfo {
+                sni: Self::sni(session),
+                alpn: session.stream().and_then(|s| s.selected_alpn_proto()).map(
+                    |alpn| match alpn {
+                        pingora_core::protocols::ALPN::H1 => "http/1.1".to_string(),
+                        _ => "h2".to_string(),
+                    },
+                ),
+                version: ssl.version.to_string(),
+                cipher: ssl.cipher.to_string(),
+                client_cert: (!ssl.cert_digest.is_empty()).then(|| ClientCert {
+                    organization: ssl.organization.clone(),
+                    serial_number: ssl.serial_number.clone(),
+                    digest: ssl.cert_digest.clone(),
+                }),
+            });
+
+        Self {
+            peer_addr: inet(session.client_addr()),
+            local_addr: inet(session.server_addr()),
+            tls,
+        }
+    }
+
+    #[cfg(any(feature = "openssl", feature = "boringssl"))]
+    fn sni(session: &pingora::protocols::http::ServerSession) -> Option<String> {
+        use pingora_core::tls::ssl::NameType;
+        session
+            .stream()
+            .and_then(|s| s.get_ssl())
+            .and_then(|ssl| ssl.servername(NameType::HOST_NAME))
+            .map(ToString::to_string)
+    }
+
+    #[cfg(not(any(feature = "openssl", feature = "boringssl")))]
+    fn sni(_session: &pingora::protocols::http::ServerSession) -> Option<String> {
+        None
+    }
+}
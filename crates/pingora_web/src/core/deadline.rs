@@ -0,0 +1,186 @@
+use std::time::{Duration, Instant};
+
+use http::StatusCode;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{ResponseError, WebError};
+
+/// When the request must be answered by, set by `LimitsMiddleware` and `utils::Timeout`
+///
+/// Handlers read it through `req.deadline()` and `req.time_remaining()`,
+/// e.g. to pass what is left as the timeout of an upstream call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    /// Time left, zero once passed
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Splits the time left until a deadline across a handler's outbound calls
+///
+/// Each step gets an equal share of what is left for the steps still to
+/// come, so time a fast step doesn't use goes to the later ones. With a
+/// `min_step` floor a step gets at least that much while the budget lasts,
+/// and fails right away once less than the floor remains instead of starting
+/// a call that can't finish. Without a deadline, steps are not limited.
+///
+/// ```
+/// use pingora_web::{PingoraHttpRequest, TimeBudget};
+/// use std::time::Duration;
+///
+/// async fn page(req: &PingoraHttpRequest) -> Result<(), pingora_web::BudgetExceeded> {
+///     let budget = req.time_budget().min_step(Duration::from_millis(50));
+///     let _user = budget.run(3, async { "user" }).await?;
+///     let _orders = budget.run(2, async { "orders" }).await?;
+///     // The last step gets whatever is left
+///     let _recommendations = budget.run(1, async { "recs" }).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeBudget {
+    deadline: Option<Deadline>,
+    floor: Duration,
+}
+
+impl TimeBudget {
+    /// A budget of `total` from now
+    pub fn new(total: Duration) -> Self {
+        Self::until(Some(Deadline::after(total)))
+    }
+
+    /// A budget ending at `deadline`, unlimited for `None`
+    pub fn until(deadline: Option<Deadline>) -> Self {
+        Self {
+            deadline,
+            floor: Duration::ZERO,
+        }
+    }
+
+    /// Least time worth giving a step (default: zero)
+    pub fn min_step(mut self, floor: Duration) -> Self {
+        self.floor = floor;
+        self
+    }
+
+    /// Time left, `None` without a deadline
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|d| d.remaining())
+    }
+
+    /// Time for the next step when `steps_left` steps (this one included) remain
+    ///
+    /// `Err` when less than the floor is left. `None` without a deadline.
+    pub fn share(&self, steps_left: u32) -> Result<Option<Duration>, BudgetExceeded> {
+        let Some(remaining) = self.remaining() else {
+            return Ok(None);
+        };
+        if remaining.is_zero() || remaining < self.floor {
+            return Err(BudgetExceeded);
+        }
+        let share = remaining / steps_left.max(1);
+        Ok(Some(share.max(self.floor)))
+    }
+
+    /// Deadline for the next step, e.g. for `req.set_deadline` before calling
+    /// a handler such as `ProxyHandler`
+    pub fn step_deadline(&self, steps_left: u32) -> Result<Option<Deadline>, BudgetExceeded> {
+        Ok(self.share(steps_left)?.map(Deadline::after))
+    }
+
+    /// Run the next step within its share of the budget
+    pub async fn run<F: std::future::Future>(
+        &self,
+        steps_left: u32,
+        step: F,
+    ) -> Result<F::Output, BudgetExceeded> {
+        match self.share(steps_left)? {
+            Some(share) => tokio::time::timeout(share, step)
+                .await
+                .map_err(|_| BudgetExceeded),
+            None => Ok(step.await),
+        }
+    }
+}
+
+/// A `TimeBudget` step ran out of time, or had none left to start with (504)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BudgetExceeded;
+
+impl std::fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("time budget exceeded")
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+impl ResponseError for BudgetExceeded {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::GATEWAY_TIMEOUT
+    }
+}
+
+impl From<BudgetExceeded> for WebError {
+    #[track_caller]
+    fn from(err: BudgetExceeded) -> Self {
+        Self::new(err)
+    }
+}
+
+/// Fires when the client goes away before the response is written
+///
+/// Set by the App for requests from a listener; `req.cancellation()` hands
+/// out clones of the token.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ClientGone(pub(crate) CancellationToken);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::PingoraHttpRequest;
+    use http::Method;
+
+    #[test]
+    fn deadlines_only_tighten() {
+        let mut req = PingoraHttpRequest::new(Method::GET, "/");
+        assert_eq!(req.time_remaining(), None);
+        assert!(!req.cancellation().is_cancelled());
+
+        req.set_deadline(Deadline::after(Duration::from_secs(10)));
+        req.set_deadline(Deadline::after(Duration::from_secs(60)));
+        let remaining = req.time_remaining().unwrap();
+        assert!(remaining <= Duration::from_secs(10) && remaining > Duration::from_secs(9));
+
+        req.set_deadline(Deadline(Instant::now() - Duration::from_secs(1)));
+        assert_eq!(req.time_remaining(), Some(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn budgets_split_what_is_left() {
+        let budget =
+            TimeBudget::new(Duration::from_millis(900)).min_step(Duration::from_millis(100));
+        let first = budget.share(3).unwrap().unwrap();
+        assert!(first <= Duration::from_millis(300) && first > Duration::from_millis(250));
+
+        // A slow step is cut off at its share; the rest goes to later steps
+        let slow = budget.run(3, tokio::time::sleep(Duration::from_secs(5)));
+        assert_eq!(slow.await, Err(BudgetExceeded));
+        let last = budget.share(1).unwrap().unwrap();
+        assert!(last <= Duration::from_millis(600) && last > Duration::from_millis(500));
+
+        let spent = TimeBudget::new(Duration::from_millis(50)).min_step(Duration::from_millis(100));
+        assert_eq!(
+            spent.run(1, async { "never started" }).await,
+            Err(BudgetExceeded)
+        );
+        assert_eq!(TimeBudget::until(None).run(5, async { 1 }).await, Ok(1));
+    }
+}
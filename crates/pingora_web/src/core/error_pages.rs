@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use http::StatusCode;
+
+use crate::core::{Negotiate, PingoraHttpRequest, PingoraWebHttpResponse};
+
+/// Renders a response the App generates itself: 404 for unmatched paths and
+/// 405 for unsupported methods
+///
+/// Register one with `App::set_error_page`. The App still adds the `Allow`
+/// header to 405 responses. Closures taking the request and status work too.
+pub trait ErrorPage: Send + Sync {
+    fn render(&self, req: &PingoraHttpRequest, status: StatusCode) -> PingoraWebHttpResponse;
+}
+
+impl<F> ErrorPage for F
+where
+    F: Fn(&PingoraHttpRequest, StatusCode) -> PingoraWebHttpResponse + Send + Sync,
+{
+    fn render(&self, req: &PingoraHttpRequest, status: StatusCode) -> PingoraWebHttpResponse {
+        self(req, status)
+    }
+}
+
+/// Error page in the format the client asks for
+///
+/// JSON (`{"status": 405, "error": "Method Not Allowed"}`) for API clients,
+/// a small HTML page for browsers, and plain text otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NegotiatedErrorPage;
+
+impl ErrorPage for NegotiatedErrorPage {
+    fn render(&self, req: &PingoraHttpRequest, status: StatusCode) -> PingoraWebHttpResponse {
+        let reason = status.canonical_reason().unwrap_or("Error");
+        let res = Negotiate::new(req)
+            .on("text/plain", || {
+                PingoraWebHttpResponse::text(status, reason)
+            })
+            .on("application/json", || {
+                PingoraWebHttpResponse::json(
+                    status,
+                    serde_json::json!({ "status": status.as_u16(), "error": reason }),
+                )
+            })
+            .on("text/html", || {
+                let code = status.as_u16();
+                PingoraWebHttpResponse::html(
+                    status,
+                    format!(
+                        "<!DOCTYPE html><html><head><title>{code} {reason}</title></head>\
+                         <body><h1>{code} {reason}</h1></body></html>"
+                    ),
+                )
+            })
+            .respond();
+        // A client accepting none of them still learns the real status
+        if res.status == StatusCode::NOT_ACCEPTABLE && status != StatusCode::NOT_ACCEPTABLE {
+            return PingoraWebHttpResponse::text(status, reason).vary_append("Accept");
+        }
+        res
+    }
+}
+
+/// Error pages registered per status
+#[derive(Clone, Default)]
+pub(crate) struct ErrorPages {
+    pages: HashMap<StatusCode, Arc<dyn ErrorPage>>,
+}
+
+impl ErrorPages {
+    pub(crate) fn insert(&mut self, status: StatusCode, page: Arc<dyn ErrorPage>) {
+        self.pages.insert(status, page);
+    }
+
+    /// The registered page for `status`, or its plain-text reason phrase
+    pub(crate) fn render(
+        &self,
+        req: &PingoraHttpRequest,
+        status: StatusCode,
+    ) -> PingoraWebHttpResponse {
+        match self.pages.get(&status) {
+            Some(page) => page.render(req, status),
+            None => {
+                PingoraWebHttpResponse::text(status, status.canonical_reason().unwrap_or("Error"))
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// A one-time message carried across a redirect (post-redirect-get)
+///
+/// Set it on a response with `res.set_flash(...)` and read it on the next
+/// request with `req.take_flash()`. Requires `FlashMiddleware`, which stores
+/// the message in a signed cookie.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Flash {
+    /// Message category, e.g. "info", "success", "warning", "error"
+    pub level: String,
+    pub message: String,
+}
+
+impl Flash {
+    pub fn new(level: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            level: level.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn info(message: impl Into<String>) -> Self {
+        Self::new("info", message)
+    }
+
+    pub fn success(message: impl Into<String>) -> Self {
+        Self::new("success", message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new("warning", message)
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new("error", message)
+    }
+}
+
+/// Flash message received with the current request (request-level data)
+#[derive(Debug)]
+pub(crate) struct IncomingFlash {
+    pending: Mutex<Option<Flash>>,
+}
+
+impl IncomingFlash {
+    pub(crate) fn new(flash: Flash) -> Self {
+        Self {
+            pending: Mutex::new(Some(flash)),
+        }
+    }
+
+    pub(crate) fn take(&self) -> Option<Flash> {
+        self.pending.lock().ok()?.take()
+    }
+
+    /// Whether a handler consumed the message
+    pub(crate) fn is_taken(&self) -> bool {
+        self.pending.lock().map(|p| p.is_none()).unwrap_or(true)
+    }
+}
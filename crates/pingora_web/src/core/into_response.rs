@@ -0,0 +1,142 @@
+use bytes::Bytes;
+use http::StatusCode;
+
+use crate::core::PingoraWebHttpResponse;
+use crate::error::WebError;
+
+/// Conversion from handler return values into responses
+///
+/// Lets closure handlers return plain values instead of building a response:
+///
+/// ```
+/// use pingora_web::{App, Json, StatusCode};
+///
+/// let mut app = App::default();
+/// app.get_fn("/", |_req| "hello");
+/// app.get_fn("/health", |_req| StatusCode::NO_CONTENT);
+/// app.post_fn("/items", |_req| (StatusCode::CREATED, Json(vec![1, 2, 3])));
+/// ```
+pub trait IntoResponse {
+    fn into_response(self) -> PingoraWebHttpResponse;
+
+    /// Convert into a handler result. Errors stay `Err` so middleware can observe them.
+    fn into_result(self) -> Result<PingoraWebHttpResponse, WebError>
+    where
+        Self: Sized,
+    {
+        Ok(self.into_response())
+    }
+}
+
+/// JSON response body: `Json(value)` serializes `value` with `application/json`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+impl IntoResponse for PingoraWebHttpResponse {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        self
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        PingoraWebHttpResponse::text(StatusCode::OK, self)
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        PingoraWebHttpResponse::text(StatusCode::OK, self)
+    }
+}
+
+impl IntoResponse for Bytes {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        PingoraWebHttpResponse::bytes(StatusCode::OK, self)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        PingoraWebHttpResponse::bytes(StatusCode::OK, self)
+    }
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        PingoraWebHttpResponse::empty(self)
+    }
+}
+
+impl<T: serde::Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        PingoraWebHttpResponse::json(StatusCode::OK, self.0)
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        let mut res = self.1.into_response();
+        res.status = self.0;
+        res
+    }
+}
+
+// Only `WebError` is accepted as the error type so `Ok(...)` in closures infers
+// without annotations; `?` still converts other errors through `From`.
+impl<T: IntoResponse> IntoResponse for Result<T, WebError> {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+
+    fn into_result(self) -> Result<PingoraWebHttpResponse, WebError> {
+        self.map(IntoResponse::into_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::response::Body;
+
+    fn body(res: &PingoraWebHttpResponse) -> &[u8] {
+        match &res.body {
+            Body::Bytes(b) => b,
+            _ => panic!("expected bytes body"),
+        }
+    }
+
+    #[test]
+    fn plain_types_convert() {
+        let res = "hi".into_response();
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(body(&res), b"hi");
+
+        let res = StatusCode::ACCEPTED.into_response();
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+        assert!(body(&res).is_empty());
+
+        let res = (StatusCode::CREATED, Json(serde_json::json!({"id": 1}))).into_response();
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert_eq!(
+            res.headers.get(http::header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        assert_eq!(body(&res), br#"{"id":1}"#);
+    }
+
+    #[test]
+    fn results_keep_errors() {
+        let ok: Result<String, WebError> = Ok("x".to_string());
+        assert_eq!(ok.into_result().unwrap().status, StatusCode::OK);
+
+        let err: Result<String, WebError> = Err(crate::error::not_found("nope"));
+        assert!(err.into_result().is_err());
+
+        let err: Result<String, WebError> = Err(crate::error::not_found("nope"));
+        assert_eq!(err.into_response().status, StatusCode::NOT_FOUND);
+    }
+}
@@ -1,11 +1,63 @@
+pub mod annotations;
+pub mod body;
+pub mod connection;
 pub mod data;
+pub mod deadline;
+pub mod error_pages;
+pub mod flash;
+pub mod into_response;
+pub mod multipart;
+pub mod negotiate;
+#[cfg(feature = "openapi")]
+pub mod openapi;
+#[cfg(feature = "openapi")]
+pub mod openapi_routes;
+pub mod path_params;
+pub mod principal;
+pub mod readiness;
 pub mod request;
+pub mod request_metrics;
 pub mod response;
+pub mod route_table;
 pub(crate) mod router;
+pub mod shutdown;
+pub mod state;
+pub mod stats;
+pub mod summary;
+pub mod tasks;
+pub mod tenant;
+pub mod typed_header;
 // pingora ServeHttp is now implemented directly on App; no separate service module
 
+pub use annotations::{AnnotatedModule, Annotations};
+pub use body::{BodyError, BodySpool, SpooledBody};
+pub use connection::{ClientCert, ConnectionInfo, TlsInfo};
 pub use data::AppData;
+pub use deadline::{BudgetExceeded, Deadline, TimeBudget};
+pub use error_pages::{ErrorPage, NegotiatedErrorPage};
+pub use flash::Flash;
 pub use http::Method; // Use standard HTTP Method
+pub use into_response::{IntoResponse, Json};
+pub use multipart::{Multipart, MultipartError, Part, UploadPolicy};
+pub use negotiate::{Accept, Negotiate};
+#[cfg(feature = "openapi")]
+pub use openapi::{OpenApiInfo, Operation};
+#[cfg(feature = "openapi")]
+pub use openapi_routes::{OpenApiRoutes, OpenApiRoutesError};
+pub use path_params::PathParams;
+pub use principal::Principal;
+pub use readiness::{Dependency, DependencyStatus, HealthCheck, Readiness, ReadinessReport};
 pub use request::{FormParseError, PingoraHttpRequest};
-pub use response::PingoraWebHttpResponse;
-pub use router::Handler;
+pub use request_metrics::RequestMetrics;
+pub use response::{BodyAbort, BodyWriter, PingoraWebHttpResponse, Trailers};
+pub use route_table::{RouteInfo, RouteMeta, RouteTable};
+pub use router::{Guard, Handler, RouteError, Router, TrailingSlash};
+pub use shutdown::{ShutdownPhase, ShutdownReport};
+pub use state::State;
+pub use stats::StatsRegistry;
+pub use summary::StartupSummary;
+pub use tasks::{TaskScope, TaskTracker};
+pub use tenant::Tenant;
+pub use typed_header::{
+    Authorization, ContentType, IfModifiedSince, Range, RangeSpec, TypedHeader,
+};
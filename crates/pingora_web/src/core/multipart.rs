@@ -0,0 +1,385 @@
+use std::borrow::Cow;
+
+use bytes::Bytes;
+use http::StatusCode;
+
+use crate::error::ResponseError;
+
+/// Limits on the files of a `multipart/form-data` upload
+///
+/// `req.multipart()` applies the App's policy (`App::set_upload_policy`);
+/// routes with different needs pass their own to `req.multipart_with()`.
+///
+/// File types are detected from the content's magic bytes, not from the
+/// name or the declared type. Formats without a signature (plain text,
+/// CSV) are taken at their declared `text/*` type when the content is
+/// UTF-8 and are `application/octet-stream` otherwise.
+#[derive(Clone, Debug)]
+pub struct UploadPolicy {
+    /// Largest file in bytes (default: 10MB)
+    pub max_file_size: usize,
+    /// Most files per request (default: 10)
+    pub max_files: usize,
+    /// Accepted types like `image/png` or `image/*`; empty accepts any (default)
+    pub allowed_types: Vec<String>,
+}
+
+impl Default for UploadPolicy {
+    fn default() -> Self {
+        Self {
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 10,
+            allowed_types: Vec::new(),
+        }
+    }
+}
+
+impl UploadPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_file_size(mut self, bytes: usize) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    pub fn max_files(mut self, count: usize) -> Self {
+        self.max_files = count;
+        self
+    }
+
+    /// Accept files of `types` only, e.g. `["image/png", "image/jpeg"]`
+    pub fn allow_types<I, T>(mut self, types: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.allowed_types = types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    fn allows(&self, mime: &str) -> bool {
+        self.allowed_types.is_empty()
+            || self
+                .allowed_types
+                .iter()
+                .any(|allowed| match allowed.strip_suffix("/*") {
+                    Some(top) => mime.split('/').next() == Some(top),
+                    None => allowed.eq_ignore_ascii_case(mime),
+                })
+    }
+}
+
+/// One field or file of a multipart form
+#[derive(Clone, Debug)]
+pub struct Part {
+    pub name: String,
+    /// Set for files
+    pub filename: Option<String>,
+    /// Type the client declared
+    pub content_type: Option<String>,
+    /// Type detected from the content, for files
+    pub detected_type: Option<String>,
+    pub data: Bytes,
+}
+
+impl Part {
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+}
+
+/// A parsed `multipart/form-data` body, see `req.multipart()`
+#[derive(Clone, Debug, Default)]
+pub struct Multipart {
+    pub parts: Vec<Part>,
+}
+
+impl Multipart {
+    /// Value of the first non-file field `name`, when it is UTF-8
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.parts
+            .iter()
+            .find(|p| !p.is_file() && p.name == name)
+            .and_then(|p| std::str::from_utf8(&p.data).ok())
+    }
+
+    /// First file uploaded as `name`
+    pub fn file(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|p| p.is_file() && p.name == name)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &Part> {
+        self.parts.iter().filter(|p| p.is_file())
+    }
+}
+
+/// Why a multipart upload was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultipartError {
+    /// Not `multipart/form-data` with a boundary (415)
+    InvalidContentType(String),
+    /// The body doesn't follow the multipart format (400)
+    Malformed(&'static str),
+    /// A file is larger than the policy allows (413)
+    FileTooLarge { filename: String, limit: usize },
+    /// More files than the policy allows (422)
+    TooManyFiles(usize),
+    /// A file's detected type is not allowed (415)
+    TypeNotAllowed { filename: String, detected: String },
+}
+
+impl std::fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidContentType(ct) => write!(f, "Invalid content type: {ct}"),
+            Self::Malformed(why) => write!(f, "Malformed multipart body: {why}"),
+            Self::FileTooLarge { filename, limit } => {
+                write!(f, "File {filename} exceeds {limit} bytes")
+            }
+            Self::TooManyFiles(limit) => write!(f, "More than {limit} files"),
+            Self::TypeNotAllowed { filename, detected } => {
+                write!(f, "File {filename} has disallowed type {detected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {}
+
+impl ResponseError for MultipartError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidContentType(_) | Self::TypeNotAllowed { .. } => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            Self::Malformed(_) => StatusCode::BAD_REQUEST,
+            Self::FileTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::TooManyFiles(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn error_code(&self) -> Cow<'static, str> {
+        Cow::Borrowed(match self {
+            Self::InvalidContentType(_) => "invalid_content_type",
+            Self::Malformed(_) => "malformed_multipart",
+            Self::FileTooLarge { .. } => "file_too_large",
+            Self::TooManyFiles(_) => "too_many_files",
+            Self::TypeNotAllowed { .. } => "file_type_not_allowed",
+        })
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::FileTooLarge { filename, limit } => {
+                Some(serde_json::json!({ "filename": filename, "limit": limit }))
+            }
+            Self::TooManyFiles(limit) => Some(serde_json::json!({ "limit": limit })),
+            Self::TypeNotAllowed { filename, detected } => {
+                Some(serde_json::json!({ "filename": filename, "detected": detected }))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// `boundary` parameter of a `multipart/form-data` content type
+fn boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, v)| v.trim().trim_matches('"'))
+        .filter(|b| !b.is_empty() && b.len() <= 70)
+}
+
+/// Value of `param` in a `Content-Disposition` header
+fn disposition_param<'a>(disposition: &'a str, param: &str) -> Option<&'a str> {
+    disposition
+        .split(';')
+        .skip(1)
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case(param))
+        .map(|(_, v)| v.trim().trim_matches('"'))
+}
+
+fn detect_type(data: &[u8], declared: Option<&str>) -> String {
+    if let Some(kind) = infer::get(data) {
+        return kind.mime_type().to_string();
+    }
+    match declared {
+        Some(declared) if declared.starts_with("text/") && std::str::from_utf8(data).is_ok() => {
+            declared
+                .split(';')
+                .next()
+                .unwrap_or(declared)
+                .trim()
+                .to_string()
+        }
+        _ => "application/octet-stream".to_string(),
+    }
+}
+
+/// Parse and check a `multipart/form-data` body against `policy`
+pub(crate) fn parse(
+    content_type: &str,
+    body: &Bytes,
+    policy: &UploadPolicy,
+) -> Result<Multipart, MultipartError> {
+    let boundary = boundary(content_type)
+        .ok_or_else(|| MultipartError::InvalidContentType(content_type.to_string()))?;
+    let delimiter = format!("--{boundary}");
+    let next_part = format!("\r\n--{boundary}");
+
+    let mut pos = find(body, delimiter.as_bytes())
+        .ok_or(MultipartError::Malformed("missing boundary"))?
+        + delimiter.len();
+    let mut parts = Vec::new();
+    loop {
+        let rest = &body[pos..];
+        if rest.starts_with(b"--") {
+            break;
+        }
+        let rest = rest.strip_prefix(b"\r\n").ok_or(MultipartError::Malformed(
+            "missing line break after boundary",
+        ))?;
+        pos += 2;
+        let head_len = find(rest, b"\r\n\r\n")
+            .ok_or(MultipartError::Malformed("unterminated part headers"))?;
+        let head = std::str::from_utf8(&rest[..head_len])
+            .map_err(|_| MultipartError::Malformed("part headers are not UTF-8"))?;
+        let data_start = pos + head_len + 4;
+        let data_len = find(&body[data_start..], next_part.as_bytes())
+            .ok_or(MultipartError::Malformed("missing closing boundary"))?;
+
+        let mut disposition = None;
+        let mut declared = None;
+        for line in head.split("\r\n") {
+            if let Some((name, value)) = line.split_once(':') {
+                let name = name.trim();
+                if name.eq_ignore_ascii_case("content-disposition") {
+                    disposition = Some(value.trim());
+                } else if name.eq_ignore_ascii_case("content-type") {
+                    declared = Some(value.trim());
+                }
+            }
+        }
+        let disposition = disposition.ok_or(MultipartError::Malformed(
+            "part without Content-Disposition",
+        ))?;
+        let name = disposition_param(disposition, "name")
+            .ok_or(MultipartError::Malformed("part without a name"))?;
+        let filename = disposition_param(disposition, "filename");
+        let data = body.slice(data_start..data_start + data_len);
+
+        let detected_type = match filename {
+            Some(filename) => {
+                if parts.iter().filter(|p: &&Part| p.is_file()).count() == policy.max_files {
+                    return Err(MultipartError::TooManyFiles(policy.max_files));
+                }
+                if data.len() > policy.max_file_size {
+                    return Err(MultipartError::FileTooLarge {
+                        filename: filename.to_string(),
+                        limit: policy.max_file_size,
+                    });
+                }
+                let detected = detect_type(&data, declared);
+                if !policy.allows(&detected) {
+                    return Err(MultipartError::TypeNotAllowed {
+                        filename: filename.to_string(),
+                        detected,
+                    });
+                }
+                Some(detected)
+            }
+            None => None,
+        };
+        parts.push(Part {
+            name: name.to_string(),
+            filename: filename.map(str::to_string),
+            content_type: declared.map(str::to_string),
+            detected_type,
+            data,
+        });
+        pos = data_start + data_len + next_part.len();
+    }
+    Ok(Multipart { parts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PingoraHttpRequest;
+    use http::Method;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn upload(files: &[(&str, &[u8])]) -> PingoraHttpRequest {
+        let mut body = b"preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHoliday\r\n".to_vec();
+        for (filename, data) in files {
+            body.extend_from_slice(
+                format!(
+                    "--XyZ\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"{filename}\"\r\n\
+                     Content-Type: image/png\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--XyZ--\r\n");
+        PingoraHttpRequest::new(Method::POST, "/photos")
+            .header("content-type", "multipart/form-data; boundary=\"XyZ\"")
+            .with_body(body)
+    }
+
+    #[test]
+    fn enforces_the_upload_policy() {
+        let policy = UploadPolicy::new()
+            .max_files(2)
+            .max_file_size(64)
+            .allow_types(["image/*"]);
+
+        let form = upload(&[("a.png", PNG)]).multipart_with(&policy).unwrap();
+        assert_eq!(form.field("title"), Some("Holiday"));
+        let photo = form.file("photo").unwrap();
+        assert_eq!(photo.filename.as_deref(), Some("a.png"));
+        assert_eq!(photo.detected_type.as_deref(), Some("image/png"));
+        assert_eq!(&photo.data[..], PNG);
+
+        // Declared image/png, but the bytes say otherwise
+        let err = upload(&[("evil.png", b"MZ\x90\0\x03\0\0\0\x04\0\0\0\xff\xff")])
+            .multipart_with(&policy)
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let big = [PNG, &[0; 64]].concat();
+        let err = upload(&[("big.png", &big)])
+            .multipart_with(&policy)
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let err = upload(&[("1.png", PNG), ("2.png", PNG), ("3.png", PNG)])
+            .multipart_with(&policy)
+            .unwrap_err();
+        assert_eq!(err, MultipartError::TooManyFiles(2));
+        assert_eq!(err.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let req = PingoraHttpRequest::new(Method::POST, "/photos")
+            .header("content-type", "application/json")
+            .with_body("{}");
+        assert_eq!(
+            req.multipart().unwrap_err().status_code(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use http::StatusCode;
+
+use crate::core::{PingoraHttpRequest, PingoraWebHttpResponse};
+
+/// One media range of an `Accept` header, e.g. `text/*;q=0.5`
+#[derive(Debug, Clone, PartialEq)]
+struct MediaRange {
+    kind: String,
+    subtype: String,
+    /// Media type parameters before `q`, e.g. `level=1`
+    params: Vec<String>,
+    q: f32,
+}
+
+impl MediaRange {
+    /// 3 for an exact match with parameters, 2 without, 1 for `type/*`, 0 for
+    /// `*/*`; `None` when the range doesn't apply
+    fn specificity(&self, kind: &str, subtype: &str, params: &[String]) -> Option<u8> {
+        if !self.params.is_empty() && self.params != params {
+            return None;
+        }
+        match (self.kind.as_str(), self.subtype.as_str()) {
+            ("*", "*") => Some(0),
+            (k, "*") if k.eq_ignore_ascii_case(kind) => Some(1),
+            (k, s) if k.eq_ignore_ascii_case(kind) && s.eq_ignore_ascii_case(subtype) => {
+                Some(if self.params.is_empty() { 2 } else { 3 })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Lowercased `key=value` parameters, stopping at `q` (later ones are accept extensions)
+fn media_params<'a>(parts: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut params: Vec<String> = parts
+        .map(|p| p.trim().to_ascii_lowercase())
+        .take_while(|p| !p.starts_with("q="))
+        .filter(|p| !p.is_empty())
+        .collect();
+    params.sort();
+    params
+}
+
+/// A parsed `Accept` header
+///
+/// A missing or empty header accepts everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Accept {
+    ranges: Vec<MediaRange>,
+}
+
+impl Accept {
+    /// Parse a header value; malformed entries are skipped
+    pub fn parse(header: &str) -> Self {
+        let ranges = header
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+                let params = media_params(parts.clone());
+                let q = parts
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0)
+                    .clamp(0.0, 1.0);
+                Some(MediaRange {
+                    kind: kind.trim().to_string(),
+                    subtype: subtype.trim().to_string(),
+                    params,
+                    q,
+                })
+            })
+            .collect();
+        Self { ranges }
+    }
+
+    /// The request's `Accept` header (all of them, when repeated)
+    pub fn from_request(req: &PingoraHttpRequest) -> Self {
+        let values: Vec<&str> = req
+            .headers()
+            .get_all(http::header::ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect();
+        Self::parse(&values.join(","))
+    }
+
+    /// Quality (0.0 to 1.0) of `mime` according to the most specific matching range
+    pub fn quality(&self, mime: &str) -> f32 {
+        if self.ranges.is_empty() {
+            return 1.0;
+        }
+        let mut parts = mime.split(';');
+        let essence = parts.next().unwrap_or(mime).trim();
+        let Some((kind, subtype)) = essence.split_once('/') else {
+            return 0.0;
+        };
+        let params = media_params(parts);
+        self.ranges
+            .iter()
+            .filter_map(|r| r.specificity(kind, subtype, &params).map(|s| (s, r.q)))
+            .max_by_key(|(s, _)| *s)
+            .map_or(0.0, |(_, q)| q)
+    }
+
+    /// Whether `mime` is acceptable at all (quality above zero)
+    pub fn accepts(&self, mime: &str) -> bool {
+        self.quality(mime) > 0.0
+    }
+
+    /// The acceptable offer with the highest quality; ties go to the earlier offer
+    pub fn best<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+        let mut best: Option<(&str, f32)> = None;
+        for offer in offers {
+            let q = self.quality(offer);
+            if q > 0.0 && best.is_none_or(|(_, top)| q > top) {
+                best = Some((offer, q));
+            }
+        }
+        best.map(|(offer, _)| offer)
+    }
+}
+
+type Producer<'a> = Box<dyn FnOnce() -> PingoraWebHttpResponse + 'a>;
+
+/// Pick a response representation from the request's `Accept` header
+///
+/// Only the chosen producer runs. Its response gets the offered `Content-Type`
+/// unless it set one, and `Vary: Accept`; with no acceptable offer the
+/// answer is 406 Not Acceptable.
+///
+/// # Example
+/// ```
+/// use pingora_web::{Negotiate, PingoraHttpRequest, PingoraWebHttpResponse, StatusCode};
+///
+/// let req = PingoraHttpRequest::new(http::Method::GET, "/").header("accept", "text/html");
+/// let res = Negotiate::new(&req)
+///     .on("application/json", || PingoraWebHttpResponse::json(StatusCode::OK, [1, 2]))
+///     .on("text/html", || PingoraWebHttpResponse::html(StatusCode::OK, "<p>1, 2</p>"))
+///     .respond();
+/// assert_eq!(res.headers["content-type"], "text/html; charset=utf-8");
+/// ```
+pub struct Negotiate<'a> {
+    accept: Accept,
+    offers: Vec<(String, Producer<'a>)>,
+}
+
+impl<'a> Negotiate<'a> {
+    pub fn new(req: &PingoraHttpRequest) -> Self {
+        Self::with_accept(Accept::from_request(req))
+    }
+
+    pub fn with_accept(accept: Accept) -> Self {
+        Self {
+            accept,
+            offers: Vec::new(),
+        }
+    }
+
+    /// Offer a representation; earlier offers win ties
+    pub fn on<F>(mut self, mime: impl Into<String>, produce: F) -> Self
+    where
+        F: FnOnce() -> PingoraWebHttpResponse + 'a,
+    {
+        self.offers.push((mime.into(), Box::new(produce)));
+        self
+    }
+
+    pub fn respond(self) -> PingoraWebHttpResponse {
+        let mimes: Vec<&str> = self.offers.iter().map(|(m, _)| m.as_str()).collect();
+        let Some(best) = self.accept.best(&mimes).map(str::to_string) else {
+            return PingoraWebHttpResponse::text(StatusCode::NOT_ACCEPTABLE, "Not Acceptable")
+                .vary_append("Accept");
+        };
+        let (mime, produce) = self
+            .offers
+            .into_iter()
+            .find(|(m, _)| *m == best)
+            .expect("chosen offer exists");
+        let mut res = produce();
+        if !res.headers.contains_key(http::header::CONTENT_TYPE) {
+            res = res.content_type(mime);
+        }
+        res.vary_append("Accept")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_range_sets_quality() {
+        let accept = Accept::parse("text/*;q=0.3, text/html;q=0.7, text/html;level=1, */*;q=0.5");
+        assert_eq!(accept.quality("text/html"), 0.7);
+        assert_eq!(accept.quality("text/html; level=1"), 1.0);
+        assert_eq!(accept.quality("text/plain"), 0.3);
+        assert_eq!(accept.quality("image/png"), 0.5);
+
+        let accept = Accept::parse("application/json, image/webp;q=0");
+        assert!(!accept.accepts("image/webp"));
+        assert!(!accept.accepts("text/html"));
+        assert_eq!(
+            accept.best(&["text/html", "application/json"]),
+            Some("application/json")
+        );
+        assert!(Accept::parse("").accepts("anything/at-all"));
+    }
+
+    #[test]
+    fn respond_runs_only_the_chosen_producer() {
+        let req = PingoraHttpRequest::new(http::Method::GET, "/")
+            .header("accept", "text/csv;q=0.9, application/json");
+        let res = Negotiate::new(&req)
+            .on("text/csv", || panic!("not chosen"))
+            .on("application/json", || {
+                PingoraWebHttpResponse::bytes(StatusCode::OK, "[]")
+            })
+            .respond();
+        assert_eq!(res.headers["content-type"], "application/json");
+        assert_eq!(res.headers["vary"], "Accept");
+
+        let req = PingoraHttpRequest::new(http::Method::GET, "/").header("accept", "image/png");
+        let res = Negotiate::new(&req)
+            .on("text/plain", || PingoraWebHttpResponse::ok("hi"))
+            .respond();
+        assert_eq!(res.status, StatusCode::NOT_ACCEPTABLE);
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use http::{Method, StatusCode};
+use schemars::generate::SchemaSettings;
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use serde_json::{Map, Value, json};
+
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse, RouteTable};
+use crate::error::WebError;
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+fn schema_of<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    generator.subschema_for::<T>()
+}
+
+/// Title, version and description of the generated document
+#[derive(Clone, Debug)]
+pub struct OpenApiInfo {
+    pub title: String,
+    pub version: String,
+    pub description: Option<String>,
+}
+
+impl Default for OpenApiInfo {
+    fn default() -> Self {
+        Self::new("API", "0.1.0")
+    }
+}
+
+impl OpenApiInfo {
+    pub fn new(title: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            version: version.into(),
+            description: None,
+        }
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ResponseDoc {
+    description: String,
+    schema: Option<SchemaFn>,
+}
+
+/// OpenAPI operation details for one route (see `App::set_operation`)
+///
+/// Routes without one are still listed with their path parameters and a
+/// generic 200 response. Schemas come from `schemars::JsonSchema`.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, Method, Operation};
+///
+/// #[derive(schemars::JsonSchema)]
+/// struct User {
+///     id: u64,
+///     name: String,
+/// }
+///
+/// let mut app = App::default();
+/// app.get_fn("/users/{id}", |_| "user");
+/// app.set_operation(
+///     Method::GET,
+///     "/users/{id}",
+///     Operation::new()
+///         .summary("Fetch a user")
+///         .response::<User>(200, "The user")
+///         .no_content(404, "No such user"),
+/// );
+/// let spec = app.openapi_json();
+/// assert!(spec["components"]["schemas"]["User"].is_object());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Operation {
+    summary: Option<String>,
+    description: Option<String>,
+    tags: Vec<String>,
+    deprecated: bool,
+    request: Option<SchemaFn>,
+    responses: BTreeMap<u16, ResponseDoc>,
+}
+
+impl Operation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn deprecated(mut self) -> Self {
+        self.deprecated = true;
+        self
+    }
+
+    /// JSON request body described by `T`
+    pub fn request<T: JsonSchema>(mut self) -> Self {
+        self.request = Some(schema_of::<T>);
+        self
+    }
+
+    /// JSON response body described by `T`
+    pub fn response<T: JsonSchema>(mut self, status: u16, description: impl Into<String>) -> Self {
+        let doc = ResponseDoc {
+            description: description.into(),
+            schema: Some(schema_of::<T>),
+        };
+        self.responses.insert(status, doc);
+        self
+    }
+
+    /// Response without a body
+    pub fn no_content(mut self, status: u16, description: impl Into<String>) -> Self {
+        let doc = ResponseDoc {
+            description: description.into(),
+            schema: None,
+        };
+        self.responses.insert(status, doc);
+        self
+    }
+
+    fn document(
+        &self,
+        path_params: &[&str],
+        operation_id: Option<&str>,
+        generator: &mut SchemaGenerator,
+    ) -> Value {
+        let mut op = Map::new();
+        if let Some(id) = operation_id {
+            op.insert("operationId".into(), id.into());
+        }
+        if let Some(summary) = &self.summary {
+            op.insert("summary".into(), summary.as_str().into());
+        }
+        if let Some(description) = &self.description {
+            op.insert("description".into(), description.as_str().into());
+        }
+        if !self.tags.is_empty() {
+            op.insert("tags".into(), json!(self.tags));
+        }
+        if self.deprecated {
+            op.insert("deprecated".into(), true.into());
+        }
+        if !path_params.is_empty() {
+            let params: Vec<Value> = path_params
+                .iter()
+                .map(|name| {
+                    json!({
+                        "name": name,
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" },
+                    })
+                })
+                .collect();
+            op.insert("parameters".into(), params.into());
+        }
+        if let Some(schema) = self.request {
+            op.insert(
+                "requestBody".into(),
+                json!({
+                    "required": true,
+                    "content": { "application/json": { "schema": schema(generator) } },
+                }),
+            );
+        }
+        let mut responses = Map::new();
+        for (status, doc) in &self.responses {
+            let mut response = json!({ "description": doc.description });
+            if let Some(schema) = doc.schema {
+                response["content"] =
+                    json!({ "application/json": { "schema": schema(generator) } });
+            }
+            responses.insert(status.to_string(), response);
+        }
+        if responses.is_empty() {
+            responses.insert("200".into(), json!({ "description": "OK" }));
+        }
+        op.insert("responses".into(), responses.into());
+        op.into()
+    }
+}
+
+/// OpenAPI state kept by the App
+#[derive(Default)]
+pub(crate) struct OpenApiRegistry {
+    pub(crate) info: OpenApiInfo,
+    pub(crate) operations: HashMap<(Method, String), Operation>,
+    /// Paths of the spec and Swagger UI, left out of the document
+    pub(crate) spec_path: Option<String>,
+    pub(crate) ui_path: Option<String>,
+}
+
+impl OpenApiRegistry {
+    /// OpenAPI 3.0 document for the routes in `table`
+    pub(crate) fn document(&self, table: &RouteTable) -> Value {
+        let mut generator = SchemaSettings::openapi3().into_generator();
+        let mut paths: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+        for route in &table.routes {
+            let own_path = [&self.spec_path, &self.ui_path]
+                .iter()
+                .any(|path| path.as_deref() == Some(&*route.pattern));
+            let method = route.method.to_ascii_lowercase();
+            let known = [
+                "get", "put", "post", "delete", "options", "head", "patch", "trace",
+            ];
+            if own_path || !known.contains(&method.as_str()) {
+                continue;
+            }
+            let (path, params) = openapi_path(&route.pattern);
+            let entry = paths.entry(path).or_default();
+            // The same route on several virtual hosts is documented once
+            if entry.contains_key(&method) {
+                continue;
+            }
+            let key = (
+                route.method.parse().unwrap_or(Method::GET),
+                route.pattern.clone(),
+            );
+            let operation = self.operations.get(&key).cloned().unwrap_or_default();
+            let op = operation.document(&params, route.meta.name.as_deref(), &mut generator);
+            entry.insert(method, op);
+        }
+
+        let mut info = json!({ "title": self.info.title, "version": self.info.version });
+        if let Some(description) = &self.info.description {
+            info["description"] = description.as_str().into();
+        }
+        let mut doc = json!({ "openapi": "3.0.3", "info": info, "paths": paths });
+        let schemas = generator.take_definitions(true);
+        if !schemas.is_empty() {
+            doc["components"] = json!({ "schemas": schemas });
+        }
+        doc
+    }
+}
+
+/// Router pattern to OpenAPI path template plus its parameter names
+///
+/// `/files/{*path}` becomes `/files/{path}`.
+fn openapi_path(pattern: &str) -> (String, Vec<&str>) {
+    let mut params = Vec::new();
+    let segments: Vec<String> = pattern
+        .split('/')
+        .map(
+            |segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) => {
+                    let name = name.trim_start_matches('*');
+                    params.push(name);
+                    format!("{{{name}}}")
+                }
+                None => segment.to_string(),
+            },
+        )
+        .collect();
+    (segments.join("/"), params)
+}
+
+/// The generated document, attached by the App to requests for the spec route
+pub(crate) struct OpenApiDocument(pub(crate) Value);
+
+/// Serves the OpenAPI document the App attaches to requests for this route
+pub(crate) struct OpenApiHandler;
+
+#[async_trait]
+impl Handler for OpenApiHandler {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        Ok(match req.get_request_share_data::<OpenApiDocument>() {
+            Some(doc) => PingoraWebHttpResponse::json(StatusCode::OK, &doc.0),
+            None => PingoraWebHttpResponse::text(StatusCode::NOT_FOUND, "Not Found"),
+        })
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+/// Swagger UI page loading the spec from `spec_url`
+pub(crate) struct SwaggerUiHandler {
+    pub(crate) spec_url: String,
+}
+
+#[async_trait]
+impl Handler for SwaggerUiHandler {
+    async fn handle(&self, _req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let spec_url = serde_json::to_string(&self.spec_url).unwrap_or_default();
+        let html = format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>API documentation</title>\
+             <link rel=\"stylesheet\" href=\"https://unpkg.com/swagger-ui-dist@5/swagger-ui.css\">\
+             </head><body><div id=\"swagger-ui\"></div>\
+             <script src=\"https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js\"></script>\
+             <script>SwaggerUIBundle({{ url: {spec_url}, dom_id: \"#swagger-ui\" }});</script>\
+             </body></html>"
+        );
+        Ok(PingoraWebHttpResponse::html(StatusCode::OK, html))
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use crate::core::RouteMeta;
+
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    struct NewUser {
+        name: String,
+    }
+
+    #[tokio::test]
+    async fn spec_lists_routes_params_and_schemas() {
+        let mut app = App::default();
+        app.get_fn("/health", |_| "ok");
+        app.post_fn("/users", |_| "created");
+        app.get_fn("/files/{*path}", |_| "file");
+        app.set_operation(
+            Method::POST,
+            "/users",
+            Operation::new()
+                .summary("Create a user")
+                .tag("users")
+                .request::<NewUser>()
+                .no_content(201, "Created"),
+        );
+        app.set_route_meta(Method::POST, "/users", RouteMeta::new().name("create_user"));
+        app.serve_openapi("/openapi.json", OpenApiInfo::new("Users", "1.2.0"));
+        app.serve_swagger_ui("/docs", "/openapi.json");
+
+        let res = app.test_client().get("/openapi.json").send().await;
+        let spec: Value = serde_json::from_str(&res.text()).unwrap();
+        assert_eq!(spec["info"]["title"], "Users");
+        assert_eq!(
+            spec["paths"]["/health"]["get"]["responses"]["200"]["description"],
+            "OK"
+        );
+        let create = &spec["paths"]["/users"]["post"];
+        assert_eq!(create["operationId"], "create_user");
+        assert_eq!(create["tags"], json!(["users"]));
+        assert_eq!(
+            create["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/NewUser"
+        );
+        assert!(create["responses"]["201"].is_object());
+        assert_eq!(
+            spec["paths"]["/files/{path}"]["get"]["parameters"][0]["name"],
+            "path"
+        );
+        assert_eq!(
+            spec["components"]["schemas"]["NewUser"]["properties"]["name"]["type"],
+            "string"
+        );
+        assert!(spec["paths"].get("/openapi.json").is_none());
+        assert!(spec["paths"].get("/docs").is_none());
+
+        let res = app.test_client().get("/docs").send().await;
+        assert!(res.text().contains("url: \"/openapi.json\""));
+    }
+}
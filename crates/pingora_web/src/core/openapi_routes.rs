@@ -0,0 +1,312 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use http::Method;
+use serde_json::Value;
+
+use super::{Handler, Operation, RouteMeta};
+
+/// Why routes could not be loaded from an OpenAPI document
+#[derive(Debug)]
+pub enum OpenApiRoutesError {
+    /// The file could not be read
+    Io(String, std::io::Error),
+    /// The document is malformed
+    Parse(String),
+    /// The document and the registered handlers disagree, one line per problem
+    Mismatch(Vec<String>),
+}
+
+impl std::fmt::Display for OpenApiRoutesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "cannot read OpenAPI document {path}: {e}"),
+            Self::Parse(e) => write!(f, "invalid OpenAPI document: {e}"),
+            Self::Mismatch(problems) => {
+                write!(
+                    f,
+                    "OpenAPI document and handlers disagree: {}",
+                    problems.join("; ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for OpenApiRoutesError {}
+
+/// One operation of the document, ready to be routed
+pub(crate) struct SpecRoute {
+    pub(crate) method: Method,
+    pub(crate) path: String,
+    pub(crate) handler: Arc<dyn Handler>,
+    pub(crate) meta: RouteMeta,
+    pub(crate) operation: Operation,
+}
+
+/// Routes declared by an OpenAPI document, bound to handlers by `operationId`
+///
+/// The reverse of `App::serve_openapi`: the document is the source of truth
+/// and `App::mount_openapi` checks it against the handlers before adding any
+/// route, so the two can't drift apart silently.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, OpenApiRoutes};
+/// use pingora_web::utils::ProxyHandler;
+/// use std::sync::Arc;
+///
+/// let spec = r#"
+/// openapi: 3.0.3
+/// info: { title: Users, version: "1.0" }
+/// paths:
+///   /users/{id}:
+///     get:
+///       operationId: getUser
+///       parameters:
+///         - { name: id, in: path, required: true, schema: { type: string } }
+///       responses: { "200": { description: OK } }
+/// "#;
+/// let routes = OpenApiRoutes::from_yaml(spec)
+///     .unwrap()
+///     .handler("getUser", Arc::new(ProxyHandler::new(([127, 0, 0, 1], 8081))));
+/// let mut app = App::default();
+/// app.mount_openapi(routes).unwrap();
+/// ```
+pub struct OpenApiRoutes {
+    doc: Value,
+    handlers: HashMap<String, Arc<dyn Handler>>,
+}
+
+impl OpenApiRoutes {
+    pub fn from_json(text: &str) -> Result<Self, OpenApiRoutesError> {
+        let doc =
+            serde_json::from_str(text).map_err(|e| OpenApiRoutesError::Parse(e.to_string()))?;
+        Ok(Self::new(doc))
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Self, OpenApiRoutesError> {
+        let doc =
+            serde_yaml::from_str(text).map_err(|e| OpenApiRoutesError::Parse(e.to_string()))?;
+        Ok(Self::new(doc))
+    }
+
+    /// Load a `.json` or `.yaml`/`.yml` file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, OpenApiRoutesError> {
+        read_document(path.as_ref()).map(Self::new)
+    }
+
+    fn new(doc: Value) -> Self {
+        Self {
+            doc,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Handle the operation with this `operationId`
+    pub fn handler(mut self, operation_id: impl Into<String>, handler: Arc<dyn Handler>) -> Self {
+        self.handlers.insert(operation_id.into(), handler);
+        self
+    }
+
+    /// Every operation with its handler, or every problem found
+    pub(crate) fn resolve(self) -> Result<Vec<SpecRoute>, OpenApiRoutesError> {
+        let Some(paths) = self.doc.get("paths").and_then(Value::as_object) else {
+            return Err(OpenApiRoutesError::Parse("no `paths` object".into()));
+        };
+        let methods = [
+            "get", "put", "post", "delete", "options", "head", "patch", "trace",
+        ];
+        let mut problems = Vec::new();
+        let mut routes = Vec::new();
+        let mut used = BTreeSet::new();
+        for (path, item) in paths {
+            let template: BTreeSet<&str> = path
+                .split('/')
+                .filter_map(|s| s.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+                .collect();
+            let shared = self.path_params(item.get("parameters"));
+            for method in methods {
+                let Some(op) = item.get(method) else {
+                    continue;
+                };
+                let name = format!("{} {path}", method.to_ascii_uppercase());
+
+                let mut declared = shared.clone();
+                declared.extend(self.path_params(op.get("parameters")));
+                for param in template.difference(&declared) {
+                    problems.push(format!("{name}: path parameter `{param}` is not declared"));
+                }
+                for param in declared.difference(&template) {
+                    problems.push(format!(
+                        "{name}: declares path parameter `{param}` the path lacks"
+                    ));
+                }
+
+                let Some(id) = op.get("operationId").and_then(Value::as_str) else {
+                    problems.push(format!("{name}: no operationId"));
+                    continue;
+                };
+                if !used.insert(id) {
+                    problems.push(format!("{name}: operationId `{id}` is used twice"));
+                    continue;
+                }
+                let Some(handler) = self.handlers.get(id) else {
+                    problems.push(format!("{name}: no handler for `{id}`"));
+                    continue;
+                };
+                routes.push(SpecRoute {
+                    method: method.to_ascii_uppercase().parse().expect("known method"),
+                    path: path.clone(),
+                    handler: handler.clone(),
+                    meta: RouteMeta::new().name(id),
+                    operation: operation(op),
+                });
+            }
+        }
+        let mut unused: Vec<&String> = self
+            .handlers
+            .keys()
+            .filter(|id| !used.contains(id.as_str()))
+            .collect();
+        unused.sort();
+        for id in unused {
+            problems.push(format!("handler `{id}` matches no operation"));
+        }
+
+        if problems.is_empty() {
+            Ok(routes)
+        } else {
+            Err(OpenApiRoutesError::Mismatch(problems))
+        }
+    }
+
+    /// Names of the `in: path` entries of a `parameters` list, following local `$ref`s
+    fn path_params<'a>(&'a self, params: Option<&'a Value>) -> BTreeSet<&'a str> {
+        params
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|param| match param.get("$ref").and_then(Value::as_str) {
+                Some(reference) => reference
+                    .strip_prefix('#')
+                    .and_then(|pointer| self.doc.pointer(pointer)),
+                None => Some(param),
+            })
+            .filter(|param| param.get("in").and_then(Value::as_str) == Some("path"))
+            .filter_map(|param| param.get("name").and_then(Value::as_str))
+            .collect()
+    }
+}
+
+/// Parse a `.json` or `.yaml`/`.yml` OpenAPI file
+pub(crate) fn read_document(path: &Path) -> Result<Value, OpenApiRoutesError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| OpenApiRoutesError::Io(path.display().to_string(), e))?;
+    let parsed = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string()),
+        _ => serde_yaml::from_str(&text).map_err(|e| e.to_string()),
+    };
+    parsed.map_err(OpenApiRoutesError::Parse)
+}
+
+/// Summary, description, tags and deprecation carried over to the generated document
+fn operation(op: &Value) -> Operation {
+    let text = |key| op.get(key).and_then(Value::as_str);
+    let mut operation = Operation::new();
+    if let Some(summary) = text("summary") {
+        operation = operation.summary(summary);
+    }
+    if let Some(description) = text("description") {
+        operation = operation.description(description);
+    }
+    for tag in op
+        .get("tags")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        if let Some(tag) = tag.as_str() {
+            operation = operation.tag(tag);
+        }
+    }
+    if op.get("deprecated").and_then(Value::as_bool) == Some(true) {
+        operation = operation.deprecated();
+    }
+    operation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use crate::core::router::ResultClosure;
+    use crate::core::{IntoResponse, PingoraHttpRequest};
+
+    fn handler<R: IntoResponse + 'static>(
+        f: impl Fn(PingoraHttpRequest) -> R + Send + Sync + 'static,
+    ) -> Arc<dyn Handler> {
+        Arc::new(ResultClosure::new(f))
+    }
+
+    const SPEC: &str = r##"{
+        "openapi": "3.0.3",
+        "info": { "title": "Users", "version": "1.0" },
+        "components": {
+            "parameters": { "UserId": { "name": "id", "in": "path", "required": true } }
+        },
+        "paths": {
+            "/users": {
+                "post": { "operationId": "createUser", "summary": "Create a user" }
+            },
+            "/users/{id}": {
+                "parameters": [{ "$ref": "#/components/parameters/UserId" }],
+                "get": { "operationId": "getUser", "tags": ["users"] }
+            }
+        }
+    }"##;
+
+    #[tokio::test]
+    async fn mounts_operations_and_reports_mismatches() {
+        let mut app = App::default();
+        let routes = OpenApiRoutes::from_json(SPEC)
+            .unwrap()
+            .handler("createUser", handler(|_| "created"))
+            .handler(
+                "getUser",
+                handler(|req| req.param("id").unwrap().to_string()),
+            );
+        app.mount_openapi(routes).unwrap();
+        let client = app.test_client();
+        assert_eq!(client.get("/users/7").send().await.text(), "7");
+        assert_eq!(client.post("/users").send().await.text(), "created");
+        let spec = app.openapi_json();
+        assert_eq!(
+            spec["paths"]["/users/{id}"]["get"]["operationId"],
+            "getUser"
+        );
+        assert_eq!(spec["paths"]["/users"]["post"]["summary"], "Create a user");
+
+        let broken = SPEC
+            .replace("\"/users/{id}\"", "\"/users/{user_id}\"")
+            .replace("\"operationId\": \"createUser\", ", "");
+        let routes = OpenApiRoutes::from_json(&broken)
+            .unwrap()
+            .handler("getUser", handler(|_| "user"))
+            .handler("deleteUser", handler(|_| "gone"));
+        let Err(OpenApiRoutesError::Mismatch(problems)) = App::default().mount_openapi(routes)
+        else {
+            panic!("expected a mismatch");
+        };
+        assert_eq!(
+            problems,
+            [
+                "POST /users: no operationId",
+                "GET /users/{user_id}: path parameter `user_id` is not declared",
+                "GET /users/{user_id}: declares path parameter `id` the path lacks",
+                "handler `deleteUser` matches no operation",
+            ]
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use smallvec::SmallVec;
+
+/// Route parameters of a request, in the order they appear in the pattern
+///
+/// Kept inline for up to four parameters, so matching a route allocates
+/// nothing beyond the values; names are shared with the route.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathParams(SmallVec<[(Arc<str>, String); 4]>);
+
+impl PathParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| &**n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set `name`, replacing an earlier value
+    pub fn insert(&mut self, name: impl Into<Arc<str>>, value: impl Into<String>) {
+        let name = name.into();
+        let value = value.into();
+        match self.0.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.0.push((name, value)),
+        }
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(n, v)| (&**n, v.as_str()))
+    }
+
+    /// Push without checking for duplicates; route patterns can't repeat a name
+    pub(crate) fn push(&mut self, name: Arc<str>, value: String) {
+        self.0.push((name, value));
+    }
+}
+
+impl<K: Into<Arc<str>>, V: Into<String>> FromIterator<(K, V)> for PathParams {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut params = Self::new();
+        for (name, value) in iter {
+            params.insert(name, value);
+        }
+        params
+    }
+}
+
+impl From<HashMap<String, String>> for PathParams {
+    fn from(map: HashMap<String, String>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_and_replaces_by_name() {
+        let mut params: PathParams = [("user", "alice"), ("repo", "web")].into_iter().collect();
+        assert_eq!(params.get("repo"), Some("web"));
+        params.insert("repo", "core");
+        assert_eq!(params.len(), 2);
+        assert_eq!(
+            params.iter().collect::<Vec<_>>(),
+            [("user", "alice"), ("repo", "core")]
+        );
+        assert_eq!(params.get("missing"), None);
+        assert!(!params.0.spilled());
+    }
+}
@@ -0,0 +1,25 @@
+/// Authenticated identity attached to a request by auth middleware
+///
+/// Middleware stores it with `req.set_principal(...)`; handlers and the
+/// `log_req_*!` macros read it back with `req.principal()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Principal {
+    /// Stable identifier of the caller (user id, client id, subject claim, ...)
+    pub id: String,
+}
+
+impl Principal {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self { id: id.into() }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.id
+    }
+}
+
+impl std::fmt::Display for Principal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.id)
+    }
+}
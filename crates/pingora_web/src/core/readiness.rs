@@ -0,0 +1,373 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Health check for a dependency (database, cache, upstream, ...)
+///
+/// Implemented for async closures returning `Result<(), String>`:
+///
+/// ```
+/// use pingora_web::{App, Dependency};
+///
+/// let mut app = App::default();
+/// app.add_dependency(Dependency::new("cache", || async { Ok(()) }));
+/// app.readiness_endpoint("/readyz");
+/// ```
+#[async_trait]
+pub trait HealthCheck: Send + Sync + 'static {
+    /// `Err` carries a short reason shown in the readiness report
+    async fn check(&self) -> Result<(), String>;
+}
+
+#[async_trait]
+impl<F, Fut> HealthCheck for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), String>> + Send,
+{
+    async fn check(&self) -> Result<(), String> {
+        (self)().await
+    }
+}
+
+/// A registered dependency and its hysteresis thresholds
+pub struct Dependency {
+    name: String,
+    check: Arc<dyn HealthCheck>,
+    critical: bool,
+    failure_threshold: u32,
+    success_threshold: u32,
+    timeout: Duration,
+}
+
+impl Dependency {
+    /// Critical dependency; unhealthy after 3 failed checks, healthy again after 2 passing ones
+    pub fn new<C: HealthCheck>(name: impl Into<String>, check: C) -> Self {
+        Self {
+            name: name.into(),
+            check: Arc::new(check),
+            critical: true,
+            failure_threshold: 3,
+            success_threshold: 2,
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// Report this dependency's health without affecting readiness
+    pub fn non_critical(mut self) -> Self {
+        self.critical = false;
+        self
+    }
+
+    /// Consecutive failures before the dependency counts as unhealthy
+    pub fn failure_threshold(mut self, n: u32) -> Self {
+        self.failure_threshold = n.max(1);
+        self
+    }
+
+    /// Consecutive successes before an unhealthy dependency recovers
+    pub fn success_threshold(mut self, n: u32) -> Self {
+        self.success_threshold = n.max(1);
+        self
+    }
+
+    /// Time limit for a single check; a timeout counts as a failure
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[derive(Default)]
+struct DependencyState {
+    unhealthy: bool,
+    failures: u32,
+    successes: u32,
+    last_error: Option<String>,
+}
+
+struct Tracked {
+    dep: Dependency,
+    state: Mutex<DependencyState>,
+}
+
+impl Tracked {
+    fn record(&self, result: Result<(), String>) {
+        let mut state = self.state.lock().expect("DependencyState poisoned");
+        match result {
+            Ok(()) => {
+                state.failures = 0;
+                state.successes += 1;
+                if state.unhealthy && state.successes >= self.dep.success_threshold {
+                    state.unhealthy = false;
+                    state.last_error = None;
+                    tracing::info!(dependency = self.dep.name.as_str(), "Dependency recovered");
+                }
+            }
+            Err(e) => {
+                state.successes = 0;
+                state.failures += 1;
+                if !state.unhealthy && state.failures >= self.dep.failure_threshold {
+                    state.unhealthy = true;
+                    tracing::warn!(
+                        dependency = self.dep.name.as_str(),
+                        error = e.as_str(),
+                        "Dependency marked unhealthy"
+                    );
+                }
+                state.last_error = Some(e);
+            }
+        }
+    }
+
+    fn status(&self) -> DependencyStatus {
+        let state = self.state.lock().expect("DependencyState poisoned");
+        DependencyStatus {
+            name: self.dep.name.clone(),
+            healthy: !state.unhealthy,
+            critical: self.dep.critical,
+            error: state.last_error.clone(),
+        }
+    }
+}
+
+/// Health of one dependency in a `ReadinessReport`
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyStatus {
+    pub name: String,
+    pub healthy: bool,
+    pub critical: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a readiness check
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    /// `false` after `Readiness::set_ready(false)`, whatever the dependencies say
+    pub marked_ready: bool,
+    pub dependencies: Vec<DependencyStatus>,
+}
+
+/// Readiness gated on registered dependencies
+///
+/// Checks run when readiness is queried (e.g. by the `/readyz` probe), at most
+/// once per check interval; concurrent probes share one run. A critical
+/// dependency flips readiness only after repeated failures and recovers only
+/// after repeated successes, so a single blip doesn't cause flapping.
+///
+/// `set_ready(false)` takes the instance out of rotation right away, e.g.
+/// while a cache warms up or before planned maintenance.
+pub struct Readiness {
+    marked_ready: AtomicBool,
+    deps: RwLock<Vec<Arc<Tracked>>>,
+    check_interval: RwLock<Duration>,
+    last_run: tokio::sync::Mutex<Option<Instant>>,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self {
+            marked_ready: AtomicBool::new(true),
+            deps: RwLock::new(Vec::new()),
+            check_interval: RwLock::new(Duration::from_secs(5)),
+            last_run: tokio::sync::Mutex::new(None),
+        }
+    }
+}
+
+impl std::fmt::Debug for Readiness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Readiness").finish()
+    }
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a dependency
+    pub fn add(&self, dep: Dependency) {
+        let tracked = Arc::new(Tracked {
+            dep,
+            state: Mutex::new(DependencyState::default()),
+        });
+        self.deps.write().expect("Readiness poisoned").push(tracked);
+    }
+
+    /// Mark the instance ready or not, independently of its dependencies
+    pub fn set_ready(&self, ready: bool) {
+        if self.marked_ready.swap(ready, Ordering::SeqCst) != ready {
+            tracing::info!(ready, "Readiness set");
+        }
+    }
+
+    /// Minimum time between check runs (default 5s)
+    pub fn set_check_interval(&self, interval: Duration) {
+        *self.check_interval.write().expect("Readiness poisoned") = interval;
+    }
+
+    /// Run the checks if they are due and report the current readiness
+    pub async fn check(&self) -> ReadinessReport {
+        let deps: Vec<Arc<Tracked>> = self.deps.read().expect("Readiness poisoned").clone();
+        {
+            let mut last_run = self.last_run.lock().await;
+            let interval = *self.check_interval.read().expect("Readiness poisoned");
+            if last_run.is_none_or(|at| at.elapsed() >= interval) {
+                let runs = deps.iter().map(|t| async move {
+                    let result =
+                        match tokio::time::timeout(t.dep.timeout, t.dep.check.check()).await {
+                            Ok(result) => result,
+                            Err(_) => Err("health check timed out".to_string()),
+                        };
+                    t.record(result);
+                });
+                futures::future::join_all(runs).await;
+                *last_run = Some(Instant::now());
+            }
+        }
+        self.report(&deps)
+    }
+
+    fn report(&self, deps: &[Arc<Tracked>]) -> ReadinessReport {
+        let dependencies: Vec<DependencyStatus> = deps.iter().map(|t| t.status()).collect();
+        let marked_ready = self.marked_ready.load(Ordering::SeqCst);
+        ReadinessReport {
+            ready: marked_ready && dependencies.iter().all(|d| d.healthy || !d.critical),
+            marked_ready,
+            dependencies,
+        }
+    }
+}
+
+/// Handler serving the readiness report: 200 when ready, 503 otherwise
+pub(crate) struct ReadinessHandler(pub(crate) Arc<Readiness>);
+
+#[async_trait]
+impl Handler for ReadinessHandler {
+    async fn handle(&self, _req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let report = self.0.check().await;
+        let status = if report.ready {
+            http::StatusCode::OK
+        } else {
+            http::StatusCode::SERVICE_UNAVAILABLE
+        };
+        Ok(PingoraWebHttpResponse::json(status, report))
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+/// Handler for liveness probes: 200 as long as the process serves requests
+pub(crate) struct LivenessHandler;
+
+#[async_trait]
+impl Handler for LivenessHandler {
+    async fn handle(&self, _req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        Ok(PingoraWebHttpResponse::json(
+            http::StatusCode::OK,
+            serde_json::json!({ "alive": true }),
+        ))
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use http::StatusCode;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test]
+    async fn readiness_flips_with_hysteresis() {
+        let up = Arc::new(AtomicBool::new(true));
+        let db_up = up.clone();
+
+        let mut app = App::default();
+        app.add_dependency(
+            Dependency::new("db", move || {
+                let ok = db_up.load(Ordering::SeqCst);
+                async move {
+                    if ok {
+                        Ok(())
+                    } else {
+                        Err("refused".to_string())
+                    }
+                }
+            })
+            .failure_threshold(2)
+            .success_threshold(2),
+        );
+        app.add_dependency(
+            Dependency::new("cache", || async { Err("down".to_string()) }).non_critical(),
+        );
+        app.readiness().set_check_interval(Duration::ZERO);
+        app.readiness_endpoint("/readyz");
+
+        let client = app.test_client();
+        let probe = || async { client.get("/readyz").send().await.status() };
+
+        assert_eq!(probe().await, StatusCode::OK);
+        up.store(false, Ordering::SeqCst);
+        assert_eq!(probe().await, StatusCode::OK, "one failure is tolerated");
+        assert_eq!(probe().await, StatusCode::SERVICE_UNAVAILABLE);
+
+        up.store(true, Ordering::SeqCst);
+        assert_eq!(probe().await, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(probe().await, StatusCode::OK);
+
+        let report: serde_json::Value = client.get("/readyz").send().await.json().unwrap();
+        assert_eq!(report["ready"], true);
+        assert_eq!(report["dependencies"][1]["name"], "cache");
+        assert_eq!(report["dependencies"][1]["healthy"], false);
+    }
+    #[tokio::test]
+    async fn health_checks_serve_liveness_and_manual_readiness() {
+        let mut app = App::default();
+        app.with_health_checks();
+        app.add_dependency(
+            Dependency::new("slow", || async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .timeout(Duration::from_millis(10))
+            .failure_threshold(1),
+        );
+
+        let client = app.test_client();
+        let res = client.get("/livez").send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = client.get("/readyz").send().await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let report: serde_json::Value = res.json().unwrap();
+        assert_eq!(report["dependencies"][0]["error"], "health check timed out");
+
+        let mut app = App::default();
+        app.with_health_checks();
+        let client = app.test_client();
+        app.health().set_ready(false);
+        let res = client.get("/readyz").send().await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            res.json::<serde_json::Value>().unwrap()["marked_ready"],
+            false
+        );
+        app.health().set_ready(true);
+        assert_eq!(client.get("/readyz").send().await.status(), StatusCode::OK);
+        assert_eq!(client.get("/livez").send().await.status(), StatusCode::OK);
+    }
+}
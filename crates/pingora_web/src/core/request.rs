@@ -1,17 +1,35 @@
 use std::any::TypeId;
 use std::collections::HashMap;
 
+use crate::core::annotations::Annotations;
+use crate::core::body::{BodyError, BodySpool, PendingBody, ReadBody, SpooledBody};
+use crate::core::connection::ConnectionInfo;
 use crate::core::data::AppData;
+use crate::core::deadline::{ClientGone, Deadline, TimeBudget};
+use crate::core::flash::{Flash, IncomingFlash};
+use crate::core::multipart::{Multipart, MultipartError, UploadPolicy};
+use crate::core::negotiate::Accept;
+use crate::core::path_params::PathParams;
+use crate::core::principal::Principal;
+use crate::core::response::quote_etag;
+use crate::core::route_table::RouteInfo;
+use crate::core::tasks::{TaskScope, TaskTracker};
+use crate::core::tenant::Tenant;
+use crate::core::typed_header::TypedHeader;
+use crate::utils::request_id::RequestIdHeader;
 use bytes::Bytes;
 use http::{HeaderMap, HeaderName, HeaderValue, Method, Uri};
 use serde::de::DeserializeOwned;
+use tracing::Instrument;
 
 #[derive(Debug)]
 pub struct PingoraHttpRequest {
     pub inner: http::Request<Bytes>,
-    pub params: HashMap<String, String>,
+    pub params: PathParams,
     pub app_data: Option<std::sync::Arc<AppData>>, // App-level shared data
     pub extensions: HashMap<TypeId, std::sync::Arc<dyn std::any::Any + Send + Sync>>, // request-level data
+    pub(crate) pending_body: Option<PendingBody>, // body still on the connection
+    pub(crate) body_limit: usize,
 }
 
 impl PingoraHttpRequest {
@@ -24,12 +42,26 @@ impl PingoraHttpRequest {
 
         Self {
             inner,
-            params: HashMap::new(),
+            params: PathParams::new(),
             app_data: None,
             extensions: HashMap::new(),
+            pending_body: None,
+            body_limit: usize::MAX,
         }
     }
 
+    /// Build from a parsed Pingora request head
+    ///
+    /// Headers are taken over as they are: values share their bytes with the
+    /// head, repeated fields are kept, and non-UTF8 values survive intact.
+    pub fn from_request_header(head: &pingora_http::RequestHeader) -> Self {
+        let path = String::from_utf8_lossy(head.raw_path());
+        let mut req = Self::new(head.method.clone(), path);
+        *req.inner.version_mut() = head.version;
+        *req.inner.headers_mut() = head.headers.clone();
+        req
+    }
+
     /// Set a request header (simple string-based API)
     pub fn header(mut self, k: impl AsRef<str>, v: impl AsRef<str>) -> Self {
         if let (Ok(name), Ok(value)) = (
@@ -43,6 +75,13 @@ impl PingoraHttpRequest {
 
     pub fn with_body<B: Into<Bytes>>(mut self, body: B) -> Self {
         *self.inner.body_mut() = body.into();
+        self.pending_body = None;
+        self
+    }
+
+    /// Leave the body on the connection until `body_bytes()` asks for it
+    pub(crate) fn with_pending_body(mut self, pending: PendingBody) -> Self {
+        self.pending_body = Some(pending);
         self
     }
 
@@ -67,12 +106,159 @@ impl PingoraHttpRequest {
         self.inner.headers_mut()
     }
 
+    /// The buffered body; empty while it is still pending (see `body_bytes`)
     pub fn body(&self) -> &Bytes {
         self.inner.body()
     }
 
-    pub fn with_params(mut self, params: HashMap<String, String>) -> Self {
-        self.params = params;
+    /// Read the body from the connection if needed, enforcing the body limit
+    ///
+    /// The App buffers bodies before calling route handlers (unless a handler
+    /// opts out via `Handler::buffer_body`), so middleware and opted-out handlers
+    /// use this to read it on demand. Later calls return the buffered body.
+    pub async fn body_bytes(&mut self) -> Result<&Bytes, BodyError> {
+        if let Some(pending) = self.pending_body.take() {
+            *self.inner.body_mut() = match pending.read(self.body_limit, None).await? {
+                ReadBody::Memory(body) => body,
+                ReadBody::Spooled(_) => unreachable!("bodies are only spooled when asked to"),
+            };
+        } else if let Some(spooled) = self.spooled_body()
+            && self.inner.body().is_empty()
+        {
+            if spooled.len() > self.body_limit as u64 {
+                return Err(BodyError::TooLarge(self.body_limit));
+            }
+            let body = tokio::fs::read(spooled.path())
+                .await
+                .map_err(|e| BodyError::Spool(e.to_string()))?;
+            *self.inner.body_mut() = body.into();
+        } else if self.inner.body().len() > self.body_limit {
+            return Err(BodyError::TooLarge(self.body_limit));
+        }
+        Ok(self.inner.body())
+    }
+
+    /// Read the pending body before the route handler, spooling it when the App is set to
+    pub(crate) async fn buffer_body(&mut self) -> Result<(), BodyError> {
+        let Some(pending) = self.pending_body.take() else {
+            return self.body_bytes().await.map(|_| ());
+        };
+        let spool = self.get_request_share_data::<BodySpool>();
+        match pending.read(self.body_limit, spool).await? {
+            ReadBody::Memory(body) => *self.inner.body_mut() = body,
+            ReadBody::Spooled(spooled) => {
+                self.set_request_share_data(std::sync::Arc::new(spooled));
+            }
+        }
+        Ok(())
+    }
+
+    /// The body, when `App::set_body_spool` moved it to a temporary file
+    pub fn spooled_body(&self) -> Option<std::sync::Arc<SpooledBody>> {
+        self.get_request_share_data::<SpooledBody>()
+    }
+
+    /// A copy sharing params, app data and request data; `None` while the body is pending
+    pub(crate) fn try_clone(&self) -> Option<Self> {
+        if self.pending_body.is_some() {
+            return None;
+        }
+        let mut inner = http::Request::new(self.inner.body().clone());
+        *inner.method_mut() = self.inner.method().clone();
+        *inner.uri_mut() = self.inner.uri().clone();
+        *inner.version_mut() = self.inner.version();
+        *inner.headers_mut() = self.inner.headers().clone();
+        Some(Self {
+            inner,
+            params: self.params.clone(),
+            app_data: self.app_data.clone(),
+            extensions: self.extensions.clone(),
+            pending_body: None,
+            body_limit: self.body_limit,
+        })
+    }
+
+    /// `true` while the body has not been read from the connection yet
+    pub fn is_body_pending(&self) -> bool {
+        self.pending_body.is_some()
+    }
+
+    /// Maximum body size `body_bytes()` accepts (unlimited by default)
+    pub fn body_limit(&self) -> usize {
+        self.body_limit
+    }
+
+    /// Set the maximum body size, e.g. from `LimitsMiddleware`
+    pub fn set_body_limit(&mut self, limit: usize) {
+        self.body_limit = limit;
+    }
+
+    /// When the request must be answered by, if a timeout applies
+    pub fn deadline(&self) -> Option<Deadline> {
+        self.get_request_share_data::<Deadline>().map(|d| *d)
+    }
+
+    /// Time left until the deadline (zero once passed), `None` without one
+    pub fn time_remaining(&self) -> Option<std::time::Duration> {
+        self.deadline().map(|d| d.remaining())
+    }
+
+    /// Budget for splitting the time left across outbound calls, see `TimeBudget`
+    pub fn time_budget(&self) -> TimeBudget {
+        TimeBudget::until(self.deadline())
+    }
+
+    /// Set the deadline, unless an earlier one is already set
+    pub fn set_deadline(&mut self, deadline: Deadline) {
+        if self.deadline().is_none_or(|d| deadline.0 < d.0) {
+            self.set_request_share_data(std::sync::Arc::new(deadline));
+        }
+    }
+
+    /// Token cancelled when the client disconnects before the response is written
+    ///
+    /// Expensive handlers can select on `cancelled()` to stop early, and
+    /// streamed bodies can hold a clone to stop producing. Only
+    /// requests served from a listener are ever cancelled. On HTTP/1.1 only
+    /// connections that won't be kept alive are watched, once the request
+    /// body has been read, so pipelined requests are left unread.
+    pub fn cancellation(&self) -> tokio_util::sync::CancellationToken {
+        self.get_request_share_data::<ClientGone>()
+            .map(|c| c.0.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether the client has disconnected, see `cancellation`
+    pub fn is_cancelled(&self) -> bool {
+        self.get_request_share_data::<ClientGone>()
+            .is_some_and(|c| c.0.is_cancelled())
+    }
+
+    /// Get a cookie value by name from the `cookie` request header(s)
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .get_all(http::header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v)
+    }
+
+    /// The request id assigned by the App (from `x-request-id` unless
+    /// `App::set_request_id_header` chose another header)
+    pub fn request_id(&self) -> Option<&str> {
+        let header = self
+            .extensions
+            .get(&TypeId::of::<RequestIdHeader>())
+            .and_then(|h| h.downcast_ref::<RequestIdHeader>())
+            .map_or("x-request-id", |h| h.0.as_str());
+        self.headers().get(header).and_then(|v| v.to_str().ok())
+    }
+
+    pub fn with_params(mut self, params: impl Into<PathParams>) -> Self {
+        self.params = params.into();
         self
     }
 
@@ -82,7 +268,7 @@ impl PingoraHttpRequest {
     }
 
     pub fn param(&self, name: &str) -> Option<&str> {
-        self.params.get(name).map(|s| s.as_str())
+        self.params.get(name)
     }
 
     pub fn param_or<'a>(&'a self, name: &str, default: &'a str) -> &'a str {
@@ -127,8 +313,181 @@ impl PingoraHttpRequest {
 
     // (removed deprecated aliases)
 
+    /// Attach the authenticated caller to this request (used by auth middleware)
+    pub fn set_principal(&mut self, principal: Principal) {
+        self.set_request_share_data(std::sync::Arc::new(principal));
+    }
+
+    /// Take the flash message sent with this request (requires `FlashMiddleware`).
+    ///
+    /// Returns the message only once; taking it clears the flash cookie.
+    pub fn take_flash(&self) -> Option<Flash> {
+        self.get_request_share_data::<IncomingFlash>()?.take()
+    }
+
+    /// Issue a one-time form token (requires `FormTokenMiddleware`)
+    pub fn issue_form_token(&self) -> Option<String> {
+        Some(
+            self.get_request_share_data::<crate::middleware::FormTokens>()?
+                .issue(),
+        )
+    }
+
+    /// Transport details (peer/local address, TLS) of the connection, if any
+    pub fn connection(&self) -> Option<std::sync::Arc<ConnectionInfo>> {
+        self.get_request_share_data::<ConnectionInfo>()
+    }
+
+    /// Attach connection details (done by the App for requests from a listener)
+    pub fn with_connection(mut self, info: ConnectionInfo) -> Self {
+        self.set_request_share_data(std::sync::Arc::new(info));
+        self
+    }
+
+    /// Client address, when the request came over TCP
+    pub fn peer_addr(&self) -> Option<std::net::SocketAddr> {
+        self.connection().and_then(|c| c.peer_addr)
+    }
+
+    /// HTTP version the request was received with
+    pub fn version(&self) -> http::Version {
+        self.inner.version()
+    }
+
+    /// The authenticated caller, if auth middleware attached one
+    pub fn principal(&self) -> Option<std::sync::Arc<Principal>> {
+        self.get_request_share_data::<Principal>()
+    }
+
+    /// W3C trace id from the `traceparent` header (`TracingMiddleware` adds one when missing)
+    pub fn trace_id(&self) -> Option<&str> {
+        let value = self.headers().get("traceparent")?.to_str().ok()?;
+        let trace_id = value.split('-').nth(1)?;
+        (trace_id.len() == 32
+            && trace_id.bytes().all(|b| b.is_ascii_hexdigit())
+            && trace_id.bytes().any(|b| b != b'0'))
+        .then_some(trace_id)
+    }
+
+    /// Variables shared with bridged pingora HTTP modules (see `App::bridge_annotations`)
+    pub fn annotations(&self) -> Option<std::sync::Arc<Annotations>> {
+        self.get_request_share_data::<Annotations>()
+    }
+
+    /// Bytes read and written for this request (see `RequestMetrics`)
+    pub fn request_metrics(&self) -> Option<std::sync::Arc<crate::core::RequestMetrics>> {
+        self.get_request_share_data::<crate::core::RequestMetrics>()
+    }
+
+    /// Whether the `Accept` header allows `mime` (a missing header allows anything)
+    pub fn accepts(&self, mime: &str) -> bool {
+        Accept::from_request(self).accepts(mime)
+    }
+
+    /// The offered media type the client prefers, by `Accept` q-values
+    pub fn preferred_type<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+        Accept::from_request(self).best(offers)
+    }
+
+    /// Whether `If-None-Match` names `etag`, i.e. the client's copy is current
+    ///
+    /// Uses weak comparison; `*` matches any tag. `etag` may be bare or quoted.
+    pub fn if_none_match(&self, etag: &str) -> bool {
+        if_none_match(self.headers(), etag)
+    }
+
+    /// A header parsed into `H`, e.g. `req.typed_header::<Authorization>()`
+    ///
+    /// `None` when the header is absent or malformed.
+    pub fn typed_header<H: TypedHeader>(&self) -> Option<H> {
+        let values = self
+            .headers()
+            .get_all(H::name())
+            .iter()
+            .map(|v| v.to_str().ok())
+            .collect::<Option<Vec<_>>>()?;
+        if values.is_empty() {
+            return None;
+        }
+        H::decode(&values)
+    }
+
+    /// The matched route with its name and metadata (see `App::set_route_meta`)
+    ///
+    /// Set before any middleware runs, like `params`; `None` when no route matched.
+    pub fn route_info(&self) -> Option<std::sync::Arc<RouteInfo>> {
+        self.get_request_share_data::<RouteInfo>()
+    }
+
+    /// The tenant resolved by `TenantMiddleware`, if any
+    pub fn tenant(&self) -> Option<std::sync::Arc<Tenant>> {
+        self.get_request_share_data::<Tenant>()
+    }
+
+    /// Route template the request matched (e.g. `/hi/{name}`), `None` when no route matched
+    ///
+    /// Prefer this over `path()` for metric and log labels: it has bounded
+    /// cardinality no matter how many distinct ids appear in the path.
+    pub fn matched_route(&self) -> Option<&str> {
+        self.extensions
+            .get(&TypeId::of::<crate::core::router::MatchedRoute>())
+            .and_then(|v| v.downcast_ref::<crate::core::router::MatchedRoute>())
+            .map(|m| &*m.0)
+    }
+
+    // --- Background tasks ---
+
+    /// Spawn a background task from a handler
+    ///
+    /// The task runs inside the current tracing span, so its logs keep the
+    /// request id, and it is tracked so graceful shutdown waits for it.
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> tokio::task::JoinHandle<F::Output>
+    where
+        F: std::future::Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let future = future.instrument(tracing::Span::current());
+        match self.get_app_share_data::<TaskTracker>() {
+            Some(tracker) => {
+                let request_id = self.request_id().map(ToString::to_string);
+                tracker.spawn(future, request_id, std::panic::Location::caller())
+            }
+            None => tokio::spawn(future),
+        }
+    }
+
+    /// Spawn subtasks that are cancelled with this request, see `TaskScope`
+    ///
+    /// Requests served from a listener end their scope once the response is
+    /// written or the client goes away; elsewhere it ends when `App::handle`
+    /// returns. A request built by hand gets a scope only `cancel()` ends.
+    pub fn task_scope(&self) -> TaskScope {
+        self.get_request_share_data::<TaskScope>()
+            .map(|scope| (*scope).clone())
+            .unwrap_or_else(|| TaskScope::child_of(&self.cancellation()))
+    }
+
     // --- Form data parsing ---
 
+    /// Parse a `multipart/form-data` body, checking files against the App's `UploadPolicy`
+    pub fn multipart(&self) -> Result<Multipart, MultipartError> {
+        match self.get_request_share_data::<UploadPolicy>() {
+            Some(policy) => self.multipart_with(&policy),
+            None => self.multipart_with(&UploadPolicy::default()),
+        }
+    }
+
+    /// Like `multipart`, with a policy for this route instead of the App's
+    pub fn multipart_with(&self, policy: &UploadPolicy) -> Result<Multipart, MultipartError> {
+        let content_type = self
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        crate::core::multipart::parse(content_type, self.body(), policy)
+    }
+
     /// Parse form data as application/x-www-form-urlencoded
     pub fn parse_form<T>(&self) -> Result<T, FormParseError>
     where
@@ -150,6 +509,21 @@ impl PingoraHttpRequest {
     }
 }
 
+/// Whether the `If-None-Match` values in `headers` name `etag` (weak comparison)
+pub(crate) fn if_none_match(headers: &HeaderMap<HeaderValue>, etag: &str) -> bool {
+    let Some(etag) = quote_etag(etag) else {
+        return false;
+    };
+    let opaque = |t: &str| t.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(&etag);
+    headers
+        .get_all(http::header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.trim() == "*" || opaque(t) == etag)
+}
+
 /// Form data parsing errors
 #[derive(Debug)]
 pub enum FormParseError {
@@ -235,4 +609,54 @@ mod tests {
         assert_eq!(form.get("message"), Some(&"Hello World!".to_string()));
         assert_eq!(form.get("symbol"), Some(&"&=?".to_string()));
     }
+
+    #[test]
+    fn request_id_and_principal() {
+        let mut req = PingoraHttpRequest::new(Method::GET, "/me").header("x-request-id", "abc-123");
+        assert_eq!(req.request_id(), Some("abc-123"));
+        assert!(req.principal().is_none());
+
+        req.set_principal(Principal::new("user-7"));
+        assert_eq!(req.principal().unwrap().as_str(), "user-7");
+
+        // Logging macros accept the request plus optional fields
+        crate::log_req_info!(req, "loaded profile", cached = true);
+        crate::log_req_warn!(req, "no fields");
+    }
+
+    #[test]
+    fn connection_details() {
+        let req = PingoraHttpRequest::new(Method::GET, "/");
+        assert!(req.connection().is_none());
+        assert_eq!(req.version(), http::Version::HTTP_11);
+
+        let req = req.with_connection(ConnectionInfo {
+            peer_addr: Some("10.0.0.7:5123".parse().unwrap()),
+            ..Default::default()
+        });
+        assert_eq!(req.peer_addr(), Some("10.0.0.7:5123".parse().unwrap()));
+        assert!(req.connection().unwrap().tls.is_none());
+    }
+
+    #[test]
+    fn keeps_raw_header_bytes() {
+        let mut head = pingora_http::RequestHeader::build("GET", b"/users", None).unwrap();
+        head.append_header("x-name", HeaderValue::from_bytes(b"Jos\xe9").unwrap())
+            .unwrap();
+        head.append_header("accept", "text/html").unwrap();
+        head.append_header("accept", "*/*").unwrap();
+        let req = PingoraHttpRequest::from_request_header(&head);
+        assert_eq!(req.path(), "/users");
+        assert_eq!(req.headers()["x-name"].as_bytes(), b"Jos\xe9");
+        assert_eq!(req.headers().get_all("accept").iter().count(), 2);
+    }
+
+    #[test]
+    fn cookie_lookup() {
+        let req =
+            PingoraHttpRequest::new(Method::GET, "/").header("cookie", "a=1; session=xyz;b=2");
+        assert_eq!(req.cookie("session"), Some("xyz"));
+        assert_eq!(req.cookie("b"), Some("2"));
+        assert_eq!(req.cookie("missing"), None);
+    }
 }
@@ -0,0 +1,127 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use http::HeaderMap;
+
+/// Bytes read and written for one request, counted by the App
+///
+/// Get it with `req.request_metrics()` before calling `next` and read it
+/// afterwards: the request side is complete once the handler returned, the
+/// response side once `Annotations::on_complete` callbacks run. Header sizes
+/// are those of the HTTP/1.1 encoding, also for HTTP/2 (which compresses
+/// headers). Response bodies count what was sent, i.e. after compression;
+/// request bodies count their decoded bytes, without chunk framing.
+/// Requests driven through `App::handle` directly carry no metrics.
+#[derive(Debug, Default)]
+pub struct RequestMetrics {
+    header_bytes_read: AtomicU64,
+    body_bytes_read: AtomicU64,
+    header_bytes_written: AtomicU64,
+    body_bytes_written: AtomicU64,
+}
+
+impl RequestMetrics {
+    pub fn header_bytes_read(&self) -> u64 {
+        self.header_bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn body_bytes_read(&self) -> u64 {
+        self.body_bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Request head plus body
+    pub fn bytes_read(&self) -> u64 {
+        self.header_bytes_read() + self.body_bytes_read()
+    }
+
+    pub fn header_bytes_written(&self) -> u64 {
+        self.header_bytes_written.load(Ordering::Relaxed)
+    }
+
+    pub fn body_bytes_written(&self) -> u64 {
+        self.body_bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Response head plus body
+    pub fn bytes_written(&self) -> u64 {
+        self.header_bytes_written() + self.body_bytes_written()
+    }
+
+    pub(crate) fn set_header_bytes_read(&self, bytes: u64) {
+        self.header_bytes_read.store(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_body_bytes_read(&self, bytes: u64) {
+        self.body_bytes_read.store(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_header_bytes_written(&self, bytes: u64) {
+        self.header_bytes_written.store(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_body_bytes_written(&self, bytes: u64) {
+        self.body_bytes_written.store(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Size of an HTTP/1.1 message head: start line, header lines and blank line
+pub(crate) fn head_size(start_line: usize, headers: &HeaderMap) -> u64 {
+    let fields: usize = headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + 2 + value.len() + 2)
+        .sum();
+    (start_line + 2 + fields + 2) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+    use crate::error::WebError;
+    use crate::middleware::Middleware;
+    use async_trait::async_trait;
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps the request's metrics once the response went out
+    struct Recorder(Arc<Mutex<Option<Arc<RequestMetrics>>>>);
+
+    #[async_trait]
+    impl Middleware for Recorder {
+        async fn handle(
+            &self,
+            req: PingoraHttpRequest,
+            next: Arc<dyn Handler>,
+        ) -> Result<PingoraWebHttpResponse, WebError> {
+            let metrics = req.request_metrics().unwrap();
+            let annotations = req.annotations().unwrap();
+            let res = next.handle(req).await?;
+            assert_eq!(metrics.body_bytes_read(), 5);
+            let seen = self.0.clone();
+            annotations.on_complete(move |_| *seen.lock().unwrap() = Some(metrics));
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn counts_heads_and_bodies_on_the_connection() {
+        let seen = Arc::new(Mutex::new(None));
+        let mut app = App::default();
+        app.post_fn("/echo", |req| req.body().to_vec());
+        app.use_middleware(Recorder(seen.clone()));
+        let server = crate::test::spawn_server(app);
+
+        let request = "POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\
+                       Connection: close\r\n\r\nhello";
+        let mut conn = std::net::TcpStream::connect(server.addr()).unwrap();
+        conn.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        conn.read_to_end(&mut response).unwrap();
+        drop(server);
+
+        let metrics = seen.lock().unwrap().take().unwrap();
+        assert_eq!(metrics.bytes_read(), request.len() as u64);
+        assert_eq!(metrics.body_bytes_written(), 5);
+        assert_eq!(metrics.bytes_written(), response.len() as u64);
+    }
+}
@@ -1,12 +1,25 @@
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures::stream::BoxStream;
+use futures::{Sink, StreamExt};
 use http::{HeaderMap, HeaderValue, StatusCode};
-use tokio::io::AsyncReadExt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+use crate::core::Flash;
+
+/// Default read size of `stream_file`
+pub(crate) const FILE_READ_SIZE: usize = 64 * 1024;
 
 pub struct PingoraWebHttpResponse {
     pub status: StatusCode,
     pub headers: HeaderMap,
     pub body: Body,
+    /// Typed data for middleware further up the chain (not sent to the client)
+    pub extensions: http::Extensions,
+    /// Trailer fields sent after the body, declared with `trailers()`
+    pub trailers: Option<Trailers>,
 }
 
 impl PingoraWebHttpResponse {
@@ -15,6 +28,8 @@ impl PingoraWebHttpResponse {
             status,
             headers: HeaderMap::new(),
             body: Body::Bytes(Bytes::new()),
+            extensions: http::Extensions::new(),
+            trailers: None,
         }
     }
 
@@ -79,28 +94,26 @@ impl PingoraWebHttpResponse {
 
     /// Construct a streaming file response. Will not buffer the entire file in memory.
     pub fn stream_file<P: AsRef<std::path::Path>>(status: StatusCode, path: P) -> Self {
-        let mut res = Self::new(status);
-        let ct = mime_guess::from_path(path.as_ref()).first_or_octet_stream();
-        let _ = res.headers.insert(
-            http::header::CONTENT_TYPE,
-            HeaderValue::from_str(ct.as_ref())
-                .unwrap_or(HeaderValue::from_static("application/octet-stream")),
-        );
+        Self::stream_file_with_read_size(status, path, FILE_READ_SIZE)
+    }
 
-        // For files, we can set content-length if we know the file size
-        if let Ok(meta) = std::fs::metadata(path.as_ref()) {
-            let len_s = meta.len().to_string();
-            let _ = res.headers.insert(
-                http::header::CONTENT_LENGTH,
-                HeaderValue::from_str(&len_s).unwrap_or(HeaderValue::from_static("0")),
-            );
-        }
+    /// Like `stream_file`, reading the file `read_size` bytes at a time
+    ///
+    /// Larger reads mean fewer syscalls and body writes for big files, at the
+    /// cost of that much memory per response in flight.
+    pub fn stream_file_with_read_size<P: AsRef<std::path::Path>>(
+        status: StatusCode,
+        path: P,
+        read_size: usize,
+    ) -> Self {
+        let mut res = Self::file_headers(status, path.as_ref());
+        let read_size = read_size.max(1);
 
         // Build an async stream that reads the file chunk by chunk
         let pathbuf = path.as_ref().to_path_buf();
         let stream = futures::stream::unfold(
             Some((None::<tokio::fs::File>, pathbuf)),
-            |state| async move {
+            move |state| async move {
                 let (opt_file, path) = state?;
                 // Open file lazily on first pull
                 let mut file = match opt_file {
@@ -110,13 +123,11 @@ impl PingoraWebHttpResponse {
                         Err(_) => return None,
                     },
                 };
-                let mut buf = vec![0u8; 64 * 1024];
-                match file.read(&mut buf).await {
+                // Read into spare capacity: no zeroing, and freezing doesn't copy
+                let mut buf = BytesMut::with_capacity(read_size);
+                match file.read_buf(&mut buf).await {
                     Ok(0) => None,
-                    Ok(n) => {
-                        buf.truncate(n);
-                        Some((Bytes::from(buf), Some((Some(file), path))))
-                    }
+                    Ok(_) => Some((buf.freeze(), Some((Some(file), path)))),
                     Err(_) => None,
                 }
             },
@@ -125,6 +136,51 @@ impl PingoraWebHttpResponse {
         res
     }
 
+    /// Serve a file from a memory map instead of reading it
+    ///
+    /// The body is one `Bytes` over the mapping, so segments written to the
+    /// client are slices of the page cache rather than copies. The file must
+    /// not be truncated or modified while the response is alive: on most
+    /// systems, reading a truncated mapping kills the process with `SIGBUS`.
+    /// Use it for immutable files (hashed assets, `FsBlobStore` blobs).
+    #[cfg(feature = "mmap")]
+    pub fn mmap_file<P: AsRef<std::path::Path>>(
+        status: StatusCode,
+        path: P,
+    ) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path.as_ref())?;
+        let len = file.metadata()?.len();
+        let mut res = Self::file_headers(status, path.as_ref());
+        // Empty files can't be mapped
+        if len > 0 {
+            // SAFETY: callers promise the file isn't changed while mapped (see above)
+            let map = unsafe { memmap2::Mmap::map(&file)? };
+            res.body = Body::Bytes(Bytes::from_owner(map));
+        }
+        Ok(res)
+    }
+
+    /// `Content-Type` from the extension and `Content-Length` from the file size
+    fn file_headers(status: StatusCode, path: &std::path::Path) -> Self {
+        let mut res = Self::new(status);
+        let ct = mime_guess::from_path(path).first_or_octet_stream();
+        let _ = res.headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_str(ct.as_ref())
+                .unwrap_or(HeaderValue::from_static("application/octet-stream")),
+        );
+
+        // For files, we can set content-length if we know the file size
+        if let Ok(meta) = std::fs::metadata(path) {
+            let len_s = meta.len().to_string();
+            let _ = res.headers.insert(
+                http::header::CONTENT_LENGTH,
+                HeaderValue::from_str(&len_s).unwrap_or(HeaderValue::from_static("0")),
+            );
+        }
+        res
+    }
+
     /// Construct a streaming response from a boxed stream of Bytes chunks
     pub fn stream(status: StatusCode, stream: BoxStream<'static, Bytes>) -> Self {
         let mut res = Self::new(status);
@@ -132,6 +188,76 @@ impl PingoraWebHttpResponse {
         res
     }
 
+    /// Stream `items` as newline-delimited JSON (`application/x-ndjson`)
+    ///
+    /// Items are serialized one line each as the client reads, so large exports
+    /// are never buffered whole; items already available are sent together.
+    /// An item that fails to serialize ends the body early and is logged.
+    ///
+    /// # Example
+    /// ```
+    /// use pingora_web::{PingoraWebHttpResponse, StatusCode};
+    ///
+    /// let rows = futures::stream::iter((1..=3).map(|id| serde_json::json!({ "id": id })));
+    /// let res = PingoraWebHttpResponse::ndjson(StatusCode::OK, rows);
+    /// assert_eq!(res.headers["content-type"], "application/x-ndjson");
+    /// ```
+    pub fn ndjson<St>(status: StatusCode, items: St) -> Self
+    where
+        St: futures::Stream + Send + 'static,
+        St::Item: serde::Serialize,
+    {
+        let lines = items
+            .ready_chunks(64)
+            .scan(false, |failed, batch| {
+                if *failed {
+                    return futures::future::ready(None);
+                }
+                let mut buf = Vec::new();
+                for item in batch {
+                    match serde_json::to_vec(&item) {
+                        Ok(line) => {
+                            buf.extend_from_slice(&line);
+                            buf.push(b'\n');
+                        }
+                        Err(e) => {
+                            tracing::error!(error = %e, "Ending NDJSON stream at unserializable item");
+                            *failed = true;
+                            break;
+                        }
+                    }
+                }
+                futures::future::ready(Some(Bytes::from(buf)))
+            })
+            .filter(|chunk| futures::future::ready(!chunk.is_empty()));
+        let mut res = Self::stream(status, Box::pin(lines));
+        res.headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-ndjson"),
+        );
+        res
+    }
+
+    /// Render a template registered with `App::set_templates`
+    ///
+    /// Errors (including no registry) answer 500; see `utils::Templates::render`.
+    #[cfg(feature = "templates")]
+    pub fn render(
+        req: &crate::core::PingoraHttpRequest,
+        template: &str,
+        context: impl serde::Serialize,
+    ) -> Result<Self, crate::error::WebError> {
+        let templates = req
+            .get_app_share_data::<crate::utils::Templates>()
+            .ok_or_else(|| {
+                crate::error::WebError::wrap(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "no templates registered with App::set_templates",
+                )
+            })?;
+        templates.render(template, context)
+    }
+
     pub fn set_header<K, V>(&mut self, k: K, v: V)
     where
         K: TryInto<http::HeaderName>,
@@ -155,6 +281,93 @@ impl PingoraWebHttpResponse {
         self
     }
 
+    /// Change the status in place, e.g. from middleware
+    pub fn status_mut(&mut self) -> &mut StatusCode {
+        &mut self.status
+    }
+
+    // ===== Typed headers =====
+    // Invalid values are dropped with a warning instead of producing a broken header.
+
+    /// Set `Content-Type`; `mime` must look like `type/subtype[; params]`
+    pub fn content_type(mut self, mime: impl AsRef<str>) -> Self {
+        let mime = mime.as_ref().trim();
+        let essence = mime.split(';').next().unwrap_or_default().trim();
+        let valid = essence
+            .split_once('/')
+            .is_some_and(|(t, s)| is_token(t) && is_token(s));
+        match HeaderValue::from_str(mime) {
+            Ok(value) if valid => {
+                self.headers.insert(http::header::CONTENT_TYPE, value);
+            }
+            _ => tracing::warn!("Ignoring invalid Content-Type {mime:?}"),
+        }
+        self
+    }
+
+    /// Replace `Cache-Control` with `directives`, e.g. `"public, max-age=600"`
+    ///
+    /// See also `cache_for`, `no_store`, `public` and `private`.
+    pub fn cache_control(mut self, directives: impl AsRef<str>) -> Self {
+        let directives = directives.as_ref().trim();
+        let valid = directives.split(',').all(|d| {
+            let name = d.split('=').next().unwrap_or_default().trim();
+            is_token(name)
+        });
+        match HeaderValue::from_str(directives) {
+            Ok(value) if valid => {
+                self.headers.insert(http::header::CACHE_CONTROL, value);
+            }
+            _ => tracing::warn!("Ignoring invalid Cache-Control {directives:?}"),
+        }
+        self
+    }
+
+    /// Set `Location` to an absolute URL or a path
+    pub fn location(mut self, url: impl AsRef<str>) -> Self {
+        let url = url.as_ref();
+        match (url.parse::<http::Uri>(), HeaderValue::from_str(url)) {
+            (Ok(_), Ok(value)) => {
+                self.headers.insert(http::header::LOCATION, value);
+            }
+            _ => tracing::warn!("Ignoring invalid Location {url:?}"),
+        }
+        self
+    }
+
+    /// Add `header` to `Vary`, keeping the names already listed
+    pub fn vary_append(mut self, header: impl AsRef<str>) -> Self {
+        self.add_vary(header.as_ref());
+        self
+    }
+
+    /// In-place form of `vary_append`
+    pub fn add_vary(&mut self, header: &str) {
+        let header = header.trim();
+        if header != "*" && http::HeaderName::from_bytes(header.as_bytes()).is_err() {
+            tracing::warn!("Ignoring invalid Vary entry {header:?}");
+            return;
+        }
+        let mut names: Vec<String> = self
+            .headers
+            .get_all(http::header::VARY)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .collect();
+        if names.iter().any(|n| n == "*") {
+            return;
+        }
+        if header == "*" {
+            names = vec!["*".to_string()];
+        } else if !names.iter().any(|n| n.eq_ignore_ascii_case(header)) {
+            names.push(header.to_string());
+        }
+        self.set_header(http::header::VARY, names.join(", "));
+    }
+
     // ===== Convenience methods like Express.js =====
 
     /// 200 OK with text
@@ -227,32 +440,519 @@ impl PingoraWebHttpResponse {
         )
     }
 
-    /// 301/302 Redirect to URL
-    pub fn redirect<S: Into<String>>(url: S, permanent: bool) -> Self {
-        let status = if permanent {
-            StatusCode::MOVED_PERMANENTLY
-        } else {
-            StatusCode::FOUND
-        };
-        Self::empty(status).header("Location", url.into())
+    /// Redirect to `location` with the given 3xx status
+    pub fn redirect<S: Into<String>>(status: StatusCode, location: S) -> Self {
+        Self::empty(status).location(location.into())
     }
 
     /// 302 Found (temporary redirect)
     pub fn redirect_to<S: Into<String>>(url: S) -> Self {
-        Self::redirect(url, false)
+        Self::redirect(StatusCode::FOUND, url)
     }
 
     /// 301 Moved Permanently (permanent redirect)
     pub fn redirect_permanent<S: Into<String>>(url: S) -> Self {
-        Self::redirect(url, true)
+        Self::redirect(StatusCode::MOVED_PERMANENTLY, url)
+    }
+
+    /// 308 Permanent Redirect
+    ///
+    /// Unlike 301, clients must repeat the original method and body, so it is
+    /// safe for moved POST endpoints.
+    pub fn permanent_redirect<S: Into<String>>(url: S) -> Self {
+        Self::redirect(StatusCode::PERMANENT_REDIRECT, url)
+    }
+
+    /// 303 See Other: the client follows up with a GET
+    pub fn see_other<S: Into<String>>(url: S) -> Self {
+        Self::redirect(StatusCode::SEE_OTHER, url)
+    }
+
+    /// Attach a flash message for the next request (requires `FlashMiddleware`)
+    pub fn set_flash(&mut self, flash: Flash) {
+        self.extensions.insert(flash);
+    }
+
+    /// Builder form of `set_flash`
+    pub fn with_flash(mut self, flash: Flash) -> Self {
+        self.set_flash(flash);
+        self
+    }
+
+    /// Leave `Content-Length` and `Transfer-Encoding` to the caller
+    ///
+    /// By default the App adds one of them from the body. Skip that for bodies
+    /// that carry their own framing, e.g. proxied responses. Without either
+    /// header an HTTP/1 body ends when the connection closes.
+    pub fn set_raw_framing(&mut self) {
+        self.extensions.insert(RawFraming);
+    }
+
+    /// Builder form of `set_raw_framing`
+    pub fn raw_framing(mut self) -> Self {
+        self.set_raw_framing();
+        self
+    }
+
+    /// Whether automatic framing headers are skipped for this response
+    pub fn has_raw_framing(&self) -> bool {
+        self.extensions.get::<RawFraming>().is_some()
+    }
+
+    /// The status line and headers as a Pingora response head
+    ///
+    /// Header values share their bytes with this response; nothing is
+    /// parsed or validated again.
+    pub fn to_response_header(&self) -> pingora_http::ResponseHeader {
+        let (mut parts, ()) = http::Response::new(()).into_parts();
+        parts.status = self.status;
+        parts.headers = self.headers.clone();
+        parts.into()
+    }
+
+    /// 303 See Other carrying a flash message (post-redirect-get)
+    ///
+    /// 303 makes browsers follow up with a GET, so reloading the result page
+    /// doesn't resubmit the form.
+    pub fn redirect_with_flash<S: Into<String>>(url: S, flash: Flash) -> Self {
+        Self::see_other(url).with_flash(flash)
+    }
+
+    // ===== Caching =====
+
+    /// 304 Not Modified (empty response)
+    pub fn not_modified() -> Self {
+        Self::empty(StatusCode::NOT_MODIFIED)
+    }
+
+    /// Let caches keep the response for `ttl` (`Cache-Control: max-age`)
+    ///
+    /// Replaces `no-store`/`no-cache` and any earlier `max-age`; `public` or
+    /// `private` set before or after are kept.
+    pub fn cache_for(mut self, ttl: std::time::Duration) -> Self {
+        self.edit_cache_control(
+            &["max-age", "no-store", "no-cache"],
+            Some(format!("max-age={}", ttl.as_secs())),
+        );
+        self
+    }
+
+    /// Forbid storing the response anywhere (`Cache-Control: no-store`)
+    pub fn no_store(mut self) -> Self {
+        self.headers.remove(http::header::CACHE_CONTROL);
+        self.edit_cache_control(&[], Some("no-store".to_string()));
+        self
+    }
+
+    /// Allow shared caches to store the response
+    pub fn public(mut self) -> Self {
+        self.edit_cache_control(&["public", "private"], Some("public".to_string()));
+        self
+    }
+
+    /// Restrict caching to the client's own cache
+    pub fn private(mut self) -> Self {
+        self.edit_cache_control(&["public", "private"], Some("private".to_string()));
+        self
+    }
+
+    /// Set the `ETag`, quoting `tag` unless it already is (`"v1"`, `W/"v1"`)
+    ///
+    /// Tags containing `"` or control characters are ignored.
+    pub fn etag(mut self, tag: &str) -> Self {
+        if let Some(tag) = quote_etag(tag) {
+            self.set_header(http::header::ETAG, tag);
+        }
+        self
+    }
+
+    /// Turn the response into 304 Not Modified when the request's
+    /// `If-None-Match` matches its `ETag`
+    ///
+    /// Headers are kept so the client can refresh its cached copy. Prefer
+    /// `req.if_none_match(tag)` before building an expensive body.
+    pub fn or_not_modified(mut self, req: &crate::core::PingoraHttpRequest) -> Self {
+        let matches = self
+            .headers
+            .get(http::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|tag| req.if_none_match(tag));
+        if matches && (self.status.is_success() || self.status == StatusCode::NOT_MODIFIED) {
+            self.make_not_modified();
+        }
+        self
+    }
+
+    /// Turn into 304 Not Modified, keeping the validators and caching headers
+    pub(crate) fn make_not_modified(&mut self) {
+        self.status = StatusCode::NOT_MODIFIED;
+        self.body = Body::Bytes(Bytes::new());
+        self.headers.remove(http::header::CONTENT_LENGTH);
+        self.headers.remove(http::header::CONTENT_TYPE);
+    }
+
+    /// Drop `Cache-Control` directives named in `remove`, then append `add`
+    fn edit_cache_control(&mut self, remove: &[&str], add: Option<String>) {
+        let mut directives: Vec<String> = self
+            .headers
+            .get_all(http::header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|d| d.trim().to_string())
+            .filter(|d| {
+                let name = d.split('=').next().unwrap_or_default().trim();
+                !d.is_empty() && !remove.iter().any(|r| name.eq_ignore_ascii_case(r))
+            })
+            .collect();
+        directives.extend(add);
+        self.set_header(http::header::CACHE_CONTROL, directives.join(", "));
+    }
+
+    // ===== Body collection =====
+
+    /// Buffer the whole body, draining a streaming body into memory.
+    ///
+    /// The body is replaced with the buffered bytes, so the response can still
+    /// be returned afterwards (useful for middleware that must inspect bodies).
+    pub async fn collect_body(&mut self) -> Bytes {
+        let body = std::mem::replace(&mut self.body, Body::Bytes(Bytes::new()));
+        let bytes = body.collect().await;
+        self.body = Body::Bytes(bytes.clone());
+        bytes
+    }
+
+    /// Consume the response and return its body as UTF-8 text
+    pub async fn into_text(self) -> Result<String, std::string::FromUtf8Error> {
+        String::from_utf8(self.body.collect().await.to_vec())
+    }
+
+    /// A 200 streaming response fed by a channel of up to `capacity` buffered chunks
+    ///
+    /// Spawn a producer that sends chunks into the returned sender. The body ends
+    /// when every sender is dropped. If the client disconnects, the body is dropped
+    /// and `send` fails, so producers can stop (or wait on `Sender::closed()`).
+    ///
+    /// ```
+    /// use pingora_web::PingoraWebHttpResponse;
+    ///
+    /// # async fn handler() -> PingoraWebHttpResponse {
+    /// let (tx, res) = PingoraWebHttpResponse::from_channel(16);
+    /// tokio::spawn(async move {
+    ///     for i in 0..3 {
+    ///         if tx.send(format!("event {i}\n").into()).await.is_err() {
+    ///             break; // client went away
+    ///         }
+    ///     }
+    /// });
+    /// res
+    /// # }
+    /// ```
+    pub fn from_channel(capacity: usize) -> (tokio::sync::mpsc::Sender<Bytes>, Self) {
+        let (tx, rx) = tokio::sync::mpsc::channel(capacity.max(1));
+        let stream = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (chunk, rx))
+        });
+        (tx, Self::stream(StatusCode::OK, stream.boxed()))
+    }
+
+    /// Handle for the producer of a streaming body to report that it failed
+    ///
+    /// When the stream ends after `abort()`, the body is not terminated
+    /// cleanly: the HTTP/1.1 connection is closed before the final chunk and
+    /// an HTTP/2 stream is reset, so clients can tell the response is
+    /// incomplete. The App reports it as a `WriteStage::Aborted` write failure.
+    ///
+    /// ```
+    /// use pingora_web::PingoraWebHttpResponse;
+    ///
+    /// # async fn handler() -> PingoraWebHttpResponse {
+    /// let (tx, mut res) = PingoraWebHttpResponse::from_channel(16);
+    /// let abort = res.abort_handle();
+    /// tokio::spawn(async move {
+    ///     let _ = tx.send("partial".into()).await;
+    ///     abort.abort(); // e.g. the upstream failed mid-body
+    /// });
+    /// res
+    /// # }
+    /// ```
+    pub fn abort_handle(&mut self) -> BodyAbort {
+        if let Some(abort) = self.extensions.get::<BodyAbort>() {
+            return abort.clone();
+        }
+        let abort = BodyAbort::default();
+        self.extensions.insert(abort.clone());
+        abort
+    }
+
+    /// A 200 streaming response whose body is written through an `AsyncWrite`
+    ///
+    /// Lets code that writes to an io sink (CSV writers, encoders, serializers)
+    /// stream a response. Writes are coalesced into chunks of about 8 KiB; call
+    /// `shutdown()` when done so the last chunk is sent. Writes fail with
+    /// `BrokenPipe` once the client disconnects.
+    ///
+    /// ```
+    /// use pingora_web::PingoraWebHttpResponse;
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// # async fn handler() -> PingoraWebHttpResponse {
+    /// let (mut writer, res) = PingoraWebHttpResponse::writer();
+    /// tokio::spawn(async move {
+    ///     for i in 0..1000 {
+    ///         writer.write_all(format!("{i},row\n").as_bytes()).await?;
+    ///     }
+    ///     writer.shutdown().await
+    /// });
+    /// res
+    /// # }
+    /// ```
+    pub fn writer() -> (BodyWriter, Self) {
+        let (tx, rx) = futures::channel::mpsc::channel(4);
+        let writer = BodyWriter {
+            tx,
+            buf: BytesMut::new(),
+        };
+        (writer, Self::stream(StatusCode::OK, rx.boxed()))
+    }
+
+    /// Declare trailer fields (`Trailer: grpc-status`) and get a handle to set them
+    ///
+    /// Values may be set until the body ends, e.g. by the producer of a streaming
+    /// body once a checksum is known; only declared names are sent. Trailers are
+    /// sent on HTTP/2 only: pingora's HTTP/1 writer does not support them yet, so
+    /// there they are dropped along with the `Trailer` header.
+    ///
+    /// ```
+    /// use pingora_web::{PingoraWebHttpResponse, StatusCode};
+    ///
+    /// let mut res = PingoraWebHttpResponse::bytes(StatusCode::OK, "payload");
+    /// let trailers = res.trailers(["grpc-status", "grpc-message"]);
+    /// trailers.set("grpc-status", "0");
+    /// ```
+    pub fn trailers<I, K>(&mut self, names: I) -> Trailers
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<str>,
+    {
+        let trailers = self.trailers.get_or_insert_with(Trailers::default).clone();
+        for name in names {
+            if let Ok(name) = http::HeaderName::from_bytes(name.as_ref().as_bytes()) {
+                trailers.declare(name);
+            }
+        }
+        let declared = trailers.declared().join(", ");
+        if let Ok(value) = HeaderValue::from_str(&declared) {
+            self.headers.insert(http::header::TRAILER, value);
+        }
+        trailers
+    }
+
+    /// Consume the response and deserialize its body as JSON
+    pub async fn into_json<T: serde::de::DeserializeOwned>(self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body.collect().await)
     }
 }
 
+/// Insert a header built by the framework, logging instead of panicking on an invalid value
+pub(crate) fn insert_header(headers: &mut HeaderMap, name: http::HeaderName, value: &str) -> bool {
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            headers.insert(name, value);
+            true
+        }
+        Err(_) => {
+            tracing::warn!(header = %name, "Dropping header with invalid value");
+            false
+        }
+    }
+}
+
+/// RFC 9110 token: header field names, media types, cache directives
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// `tag` as a quoted entity tag, `None` when it can't be one
+pub(crate) fn quote_etag(tag: &str) -> Option<String> {
+    let opaque = tag.strip_prefix("W/").unwrap_or(tag);
+    let quoted = opaque.len() >= 2 && opaque.starts_with('"') && opaque.ends_with('"');
+    let inner = if quoted {
+        &opaque[1..opaque.len() - 1]
+    } else {
+        tag
+    };
+    // etagc: %x21 / %x23-7E / obs-text
+    if inner.bytes().any(|b| b == b'"' || b < 0x21 || b == 0x7f) {
+        return None;
+    }
+    Some(if quoted {
+        tag.to_string()
+    } else {
+        format!("\"{tag}\"")
+    })
+}
+
+/// Extension marking a response whose framing headers are left alone
+#[derive(Clone, Copy)]
+struct RawFraming;
+
 pub enum Body {
     Bytes(Bytes),
     Stream(BoxStream<'static, Bytes>),
 }
 
+impl Body {
+    /// Buffer the body into a single `Bytes`, draining streams to the end
+    pub async fn collect(self) -> Bytes {
+        match self {
+            Body::Bytes(b) => b,
+            Body::Stream(mut s) => {
+                let mut buf = BytesMut::new();
+                while let Some(chunk) = s.next().await {
+                    buf.extend_from_slice(&chunk);
+                }
+                buf.freeze()
+            }
+        }
+    }
+}
+
+/// Lets the producer of a streaming body fail it, see
+/// `PingoraWebHttpResponse::abort_handle()`
+#[derive(Clone, Default)]
+pub struct BodyAbort(Arc<std::sync::atomic::AtomicBool>);
+
+impl BodyAbort {
+    /// Mark the body as incomplete; takes effect when its stream ends
+    pub fn abort(&self) {
+        self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+/// Shared trailer values of a response, see `PingoraWebHttpResponse::trailers()`
+#[derive(Clone, Default)]
+pub struct Trailers {
+    inner: Arc<Mutex<TrailerFields>>,
+}
+
+#[derive(Default)]
+struct TrailerFields {
+    declared: Vec<http::HeaderName>,
+    values: HeaderMap,
+}
+
+impl Trailers {
+    fn fields(&self) -> std::sync::MutexGuard<'_, TrailerFields> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn declare(&self, name: http::HeaderName) {
+        let mut fields = self.fields();
+        if !fields.declared.contains(&name) {
+            fields.declared.push(name);
+        }
+    }
+
+    fn declared(&self) -> Vec<String> {
+        self.fields()
+            .declared
+            .iter()
+            .map(|n| n.as_str().to_string())
+            .collect()
+    }
+
+    /// Set a trailer value; ignored unless the name was declared
+    pub fn set<K: AsRef<str>, V: AsRef<str>>(&self, name: K, value: V) {
+        let (Ok(name), Ok(value)) = (
+            http::HeaderName::from_bytes(name.as_ref().as_bytes()),
+            HeaderValue::from_str(value.as_ref()),
+        ) else {
+            return;
+        };
+        let mut fields = self.fields();
+        if fields.declared.contains(&name) {
+            fields.values.insert(name, value);
+        }
+    }
+
+    /// The trailer values set so far
+    pub fn values(&self) -> HeaderMap {
+        self.fields().values.clone()
+    }
+}
+
+impl std::fmt::Debug for Trailers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Trailers")
+            .field("declared", &self.declared())
+            .field("values", &self.values())
+            .finish()
+    }
+}
+
+/// Writer feeding a streaming response body, from `PingoraWebHttpResponse::writer()`
+pub struct BodyWriter {
+    tx: futures::channel::mpsc::Sender<Bytes>,
+    buf: BytesMut,
+}
+
+impl BodyWriter {
+    const CHUNK_SIZE: usize = 8 * 1024;
+
+    /// Send the buffered bytes as one chunk once the channel has room
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if self.buf.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(self.tx.poll_ready(cx)).map_err(|_| client_gone())?;
+        let chunk = self.buf.split().freeze();
+        self.tx.start_send(chunk).map_err(|_| client_gone())?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn client_gone() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "response body dropped")
+}
+
+impl AsyncWrite for BodyWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.tx.is_closed() {
+            return Poll::Ready(Err(client_gone()));
+        }
+        if self.buf.len() >= Self::CHUNK_SIZE {
+            ready!(self.poll_send(cx))?;
+        }
+        let n = data.len().min(Self::CHUNK_SIZE * 2 - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_send(cx))?;
+        Pin::new(&mut self.tx)
+            .poll_flush(cx)
+            .map_err(|_| client_gone())
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.tx.close_channel();
+        Poll::Ready(Ok(()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -326,6 +1026,87 @@ mod tests {
         assert!(!res.headers.contains_key(http::header::TRANSFER_ENCODING));
     }
 
+    #[tokio::test]
+    async fn from_channel_streams_until_senders_drop() {
+        let (tx, res) = PingoraWebHttpResponse::from_channel(1);
+        tokio::spawn(async move {
+            for chunk in ["a", "b", "c"] {
+                tx.send(Bytes::from(chunk)).await.unwrap();
+            }
+        });
+        assert_eq!(res.body.collect().await, Bytes::from_static(b"abc"));
+
+        // Dropping the body (client gone) cancels the producer
+        let (tx, res) = PingoraWebHttpResponse::from_channel(1);
+        drop(res);
+        assert!(tx.send(Bytes::from_static(b"x")).await.is_err());
+        tx.closed().await;
+    }
+
+    #[tokio::test]
+    async fn ndjson_serializes_items_lazily() {
+        let items = futures::stream::iter(0..3).map(|n| serde_json::json!({ "n": n }));
+        let res = PingoraWebHttpResponse::ndjson(StatusCode::OK, items);
+        assert_eq!(
+            res.body.collect().await,
+            Bytes::from_static(b"{\"n\":0}\n{\"n\":1}\n{\"n\":2}\n")
+        );
+
+        // Maps with non-string keys can't be JSON; the body ends there
+        let bad = std::collections::HashMap::from([((1, 2), 3)]);
+        let res = PingoraWebHttpResponse::ndjson(StatusCode::OK, futures::stream::iter([bad]));
+        assert!(res.body.collect().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn writer_streams_coalesced_chunks() {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut writer, res) = PingoraWebHttpResponse::writer();
+        let producer = tokio::spawn(async move {
+            for i in 0..5000 {
+                writer.write_all(format!("{i}\n").as_bytes()).await?;
+            }
+            writer.shutdown().await
+        });
+        let Body::Stream(stream) = res.body else {
+            panic!("expected stream body");
+        };
+        let chunks: Vec<Bytes> = stream.collect().await;
+        producer.await.unwrap().unwrap();
+
+        let expected: String = (0..5000).map(|i| format!("{i}\n")).collect();
+        assert_eq!(chunks.concat(), expected.as_bytes());
+        assert!(chunks.len() < 10);
+
+        // Writes fail once the body is dropped
+        let (mut writer, res) = PingoraWebHttpResponse::writer();
+        drop(res);
+        let err = writer.write_all(b"late").await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    #[tokio::test]
+    async fn trailers_are_set_while_streaming() {
+        let (tx, mut res) = PingoraWebHttpResponse::from_channel(1);
+        let trailers = res.trailers(["x-checksum"]);
+        res.trailers(["grpc-status"]);
+        assert_eq!(
+            res.headers.get(http::header::TRAILER).unwrap(),
+            "x-checksum, grpc-status"
+        );
+
+        tokio::spawn(async move {
+            tx.send(Bytes::from_static(b"abc")).await.unwrap();
+            trailers.set("x-checksum", "294");
+            trailers.set("x-undeclared", "1");
+        });
+        res.body.collect().await;
+        let values = res.trailers.unwrap().values();
+        assert_eq!(values.get("x-checksum").unwrap(), "294");
+        assert!(!values.contains_key("x-undeclared"));
+    }
+
     #[test]
     fn manual_headers_not_overridden() {
         // Test that manually set headers are preserved
@@ -363,5 +1144,128 @@ mod tests {
 
         let res = PingoraWebHttpResponse::redirect_permanent("/new-url");
         assert_eq!(res.status.as_u16(), 301);
+
+        let res = PingoraWebHttpResponse::permanent_redirect("/v2/items");
+        assert_eq!(res.status.as_u16(), 308);
+
+        let res = PingoraWebHttpResponse::see_other("/items/1");
+        assert_eq!(res.status.as_u16(), 303);
+        assert_eq!(res.headers.get("location").unwrap(), "/items/1");
+    }
+
+    #[tokio::test]
+    async fn files_stream_in_read_size_chunks() {
+        let path =
+            std::env::temp_dir().join(format!("pingora_web_file_{}.txt", std::process::id()));
+        std::fs::write(&path, "x".repeat(10_000)).unwrap();
+
+        let res = PingoraWebHttpResponse::stream_file_with_read_size(StatusCode::OK, &path, 4096);
+        assert_eq!(res.headers["content-length"], "10000");
+        assert_eq!(res.headers["content-type"], "text/plain");
+        let Body::Stream(stream) = res.body else {
+            panic!("expected stream body");
+        };
+        let sizes: Vec<usize> = stream.map(|chunk| chunk.len()).collect().await;
+        assert_eq!(sizes, [4096, 4096, 1808]);
+
+        #[cfg(feature = "mmap")]
+        {
+            let mut res = PingoraWebHttpResponse::mmap_file(StatusCode::OK, &path).unwrap();
+            assert_eq!(res.collect_body().await.len(), 10_000);
+        }
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn collect_body_drains_streams() {
+        let stream = futures::stream::iter(vec![
+            Bytes::from_static(b"{\"a\":"),
+            Bytes::from_static(b"1}"),
+        ]);
+        let mut res = PingoraWebHttpResponse::stream(StatusCode::OK, stream.boxed());
+        assert_eq!(res.collect_body().await.as_ref(), b"{\"a\":1}");
+        // Body stays available as buffered bytes
+        assert!(matches!(res.body, Body::Bytes(_)));
+
+        let v: serde_json::Value = res.into_json().await.unwrap();
+        assert_eq!(v, json!({"a": 1}));
+
+        let res = PingoraWebHttpResponse::text(StatusCode::OK, "plain");
+        assert_eq!(res.into_text().await.unwrap(), "plain");
+    }
+
+    #[test]
+    fn cache_helpers_compose_directives() {
+        let res = PingoraWebHttpResponse::ok("x")
+            .no_store()
+            .public()
+            .cache_for(std::time::Duration::from_secs(60))
+            .private()
+            .etag("v1");
+        assert_eq!(res.headers["cache-control"], "max-age=60, private");
+        assert_eq!(res.headers["etag"], "\"v1\"");
+        assert_eq!(
+            PingoraWebHttpResponse::ok("x").etag("W/\"v1\"").headers["etag"],
+            "W/\"v1\""
+        );
+        assert!(
+            !PingoraWebHttpResponse::ok("x")
+                .etag("a\"b")
+                .headers
+                .contains_key("etag")
+        );
+
+        let req = crate::core::PingoraHttpRequest::new(http::Method::GET, "/")
+            .header("if-none-match", "\"v0\", W/\"v1\"");
+        assert!(req.if_none_match("v1"));
+        assert!(!req.if_none_match("v2"));
+        let res = PingoraWebHttpResponse::ok("x")
+            .etag("v1")
+            .or_not_modified(&req);
+        assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers["etag"], "\"v1\"");
+        assert!(matches!(res.body, Body::Bytes(ref b) if b.is_empty()));
+    }
+
+    #[test]
+    fn typed_setters_validate_and_append() {
+        let res = PingoraWebHttpResponse::ok("x")
+            .content_type("application/vnd.api+json; charset=utf-8")
+            .content_type("not a mime")
+            .cache_control("public, max-age=600")
+            .cache_control("max age=1")
+            .location("/next?page=2")
+            .location("bad url")
+            .header("vary", "Accept")
+            .vary_append("accept")
+            .vary_append("Accept-Encoding")
+            .vary_append("bad header");
+        assert_eq!(
+            res.headers["content-type"],
+            "application/vnd.api+json; charset=utf-8"
+        );
+        assert_eq!(res.headers["cache-control"], "public, max-age=600");
+        assert_eq!(res.headers["location"], "/next?page=2");
+        assert_eq!(res.headers["vary"], "Accept, Accept-Encoding");
+
+        let mut res = PingoraWebHttpResponse::ok("x").vary_append("*");
+        res.add_vary("Cookie");
+        *res.status_mut() = StatusCode::ACCEPTED;
+        assert_eq!(res.headers["vary"], "*");
+        assert_eq!(res.status, StatusCode::ACCEPTED);
+    }
+
+    #[test]
+    fn converts_to_response_header() {
+        let mut res = PingoraWebHttpResponse::text(StatusCode::CREATED, "x");
+        res.headers
+            .append("set-cookie", HeaderValue::from_static("a=1"));
+        res.headers
+            .append("set-cookie", HeaderValue::from_static("b=2"));
+        let head = res.to_response_header();
+        assert_eq!(head.status, StatusCode::CREATED);
+        assert_eq!(head.version, http::Version::HTTP_11);
+        assert_eq!(head.headers.get_all("set-cookie").iter().count(), 2);
+        assert_eq!(head.headers["content-type"], res.headers["content-type"]);
     }
 }
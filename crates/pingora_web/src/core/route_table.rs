@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use http::StatusCode;
+use serde::Serialize;
+
+use crate::core::{Guard, Handler, Negotiate, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Descriptive data for a route, shown by the route table (see `App::set_route_meta`)
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RouteMeta {
+    pub name: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl RouteMeta {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Add a free-form entry, e.g. `("owner", "payments")`
+    pub fn meta(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// One registered method and pattern
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RouteInfo {
+    pub method: String,
+    pub pattern: String,
+    /// Virtual host the route belongs to; `None` for the default router
+    pub host: Option<String>,
+    /// Number of handlers registered with a `Guard`
+    pub guarded: usize,
+    #[serde(flatten)]
+    pub meta: RouteMeta,
+}
+
+/// Snapshot of an App's routes and middleware (see `App::route_table`)
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RouteTable {
+    pub routes: Vec<RouteInfo>,
+    /// Type names of the App-wide middleware, outermost last
+    pub middleware: Vec<String>,
+}
+
+impl RouteTable {
+    fn html(&self) -> String {
+        let mut rows = String::new();
+        for route in &self.routes {
+            let metadata: Vec<String> = route
+                .meta
+                .metadata
+                .iter()
+                .map(|(k, v)| format!("{}={}", escape(k), escape(v)))
+                .collect();
+            rows.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&route.method),
+                escape(&route.pattern),
+                escape(route.host.as_deref().unwrap_or("")),
+                escape(route.meta.name.as_deref().unwrap_or("")),
+                route.guarded,
+                metadata.join(", "),
+            ));
+        }
+        let middleware: Vec<String> = self.middleware.iter().map(|m| escape(m)).collect();
+        format!(
+            "<!DOCTYPE html><html><head><title>Routes</title></head><body>\
+             <h1>Routes</h1><table border=\"1\"><tr><th>Method</th><th>Pattern</th>\
+             <th>Host</th><th>Name</th><th>Guarded</th><th>Metadata</th></tr>{rows}</table>\
+             <h2>Middleware</h2><ol><li>{}</li></ol></body></html>",
+            middleware.join("</li><li>")
+        )
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Serves the route table as JSON or HTML; the App attaches the snapshot
+/// to requests for this route
+pub(crate) struct RouteTableHandler {
+    pub(crate) guard: Guard,
+}
+
+#[async_trait]
+impl Handler for RouteTableHandler {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        // Hide the endpoint from callers the guard rejects
+        let table = match req.get_request_share_data::<RouteTable>() {
+            Some(table) if self.guard.check(&req) => table,
+            _ => {
+                return Ok(PingoraWebHttpResponse::text(
+                    StatusCode::NOT_FOUND,
+                    "Not Found",
+                ));
+            }
+        };
+        Ok(Negotiate::new(&req)
+            .on("application/json", || {
+                PingoraWebHttpResponse::json(StatusCode::OK, &*table)
+            })
+            .on("text/html", || {
+                PingoraWebHttpResponse::html(StatusCode::OK, table.html())
+            })
+            .respond()
+            .no_store())
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use http::Method;
+
+    #[tokio::test]
+    async fn route_table_lists_routes_behind_its_guard() {
+        let mut app = App::default();
+        app.get_fn("/users/{id}", |_| "user");
+        app.add_guarded(
+            Method::POST,
+            "/users/{id}",
+            Guard::content_type("application/json"),
+            std::sync::Arc::new(crate::core::router::ResultClosure::new(|_| "json")),
+        );
+        app.set_route_meta(
+            Method::GET,
+            "/users/{id}",
+            RouteMeta::new().name("get_user").meta("owner", "accounts"),
+        );
+        app.serve_route_table("/_routes", Guard::secret_header("x-admin-token", "secret"));
+        let client = app.test_client();
+
+        let res = client.get("/_routes").send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = client
+            .get("/_routes")
+            .header("x-admin-token", "secret")
+            .header("accept", "application/json")
+            .send()
+            .await;
+        let table: serde_json::Value = serde_json::from_str(&res.text()).unwrap();
+        assert_eq!(
+            table["routes"][0],
+            serde_json::json!({
+                "method": "GET",
+                "pattern": "/users/{id}",
+                "host": null,
+                "guarded": 0,
+                "name": "get_user",
+                "metadata": { "owner": "accounts" },
+            })
+        );
+        assert_eq!(table["routes"][1]["guarded"], 1);
+        assert!(
+            table["middleware"][0]
+                .as_str()
+                .unwrap()
+                .ends_with("RequestId")
+        );
+
+        let res = client
+            .get("/_routes")
+            .header("x-admin-token", "secret")
+            .header("accept", "text/html")
+            .send()
+            .await;
+        assert!(res.text().contains("<td>/users/{id}</td>"));
+    }
+}
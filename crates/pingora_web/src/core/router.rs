@@ -1,6 +1,11 @@
-use crate::core::{Method, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::core::route_table::{RouteInfo, RouteMeta};
+use crate::core::{
+    IntoResponse, Method, PathParams, PingoraHttpRequest, PingoraWebHttpResponse, State,
+};
 use crate::error::WebError;
 use async_trait::async_trait;
+use http::{HeaderName, HeaderValue};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -8,64 +13,539 @@ use std::sync::Arc;
 pub trait Handler: Send + Sync + 'static {
     /// Process the request and return a response or error
     async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError>;
+
+    /// Whether the App reads the request body before calling this handler
+    ///
+    /// Return `false` to skip that and read it on demand with `req.body_bytes()`.
+    fn buffer_body(&self) -> bool {
+        true
+    }
+
+    /// Problems with this handler's configuration, reported by `App::check`
+    fn check(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
-/// Wrapper for simple closure-based handlers that return Result
-pub struct ResultClosure<F>
-where
-    F: Fn(PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> + Send + Sync + 'static,
-{
+/// Wrapper for simple closure-based handlers returning any `IntoResponse`
+pub struct ResultClosure<F> {
     closure: F,
 }
 
-impl<F> ResultClosure<F>
-where
-    F: Fn(PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> + Send + Sync + 'static,
-{
+impl<F> ResultClosure<F> {
     pub fn new(closure: F) -> Self {
         Self { closure }
     }
 }
 
 #[async_trait]
-impl<F> Handler for ResultClosure<F>
+impl<F, R> Handler for ResultClosure<F>
+where
+    F: Fn(PingoraHttpRequest) -> R + Send + Sync + 'static,
+    R: IntoResponse + 'static,
+{
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        (self.closure)(req).into_result()
+    }
+}
+
+/// Closure handler that also receives the App's typed state
+pub struct StateClosure<F, S> {
+    closure: F,
+    state: State<S>,
+}
+
+impl<F, S> StateClosure<F, S> {
+    pub fn new(closure: F, state: State<S>) -> Self {
+        Self { closure, state }
+    }
+}
+
+#[async_trait]
+impl<F, S, R> Handler for StateClosure<F, S>
 where
-    F: Fn(PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> + Send + Sync + 'static,
+    F: Fn(PingoraHttpRequest, State<S>) -> R + Send + Sync + 'static,
+    S: Send + Sync + 'static,
+    R: IntoResponse + 'static,
 {
     async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
-        (self.closure)(req)
+        (self.closure)(req, self.state.clone()).into_result()
+    }
+}
+
+/// Label used in place of a route template when no route matched
+pub(crate) const UNMATCHED_ROUTE: &str = "<unmatched>";
+
+/// Route pattern matched for a request (e.g. `/users/{id}`), kept in request data
+#[derive(Debug, Clone)]
+pub(crate) struct MatchedRoute(pub(crate) Arc<str>);
+
+/// A route lookup result: handler, path params, and the matched pattern
+pub type RouteMatch = (Arc<dyn Handler>, PathParams, Arc<str>);
+
+/// Extra condition a request must meet for a guarded route to be chosen
+///
+/// Several handlers can share a method and path when each carries a guard;
+/// the first one whose guard passes handles the request, and the unguarded
+/// handler (if any) is the fallback. Guards run before the request body is
+/// read, so they should look at the head only (method, path, headers).
+#[derive(Clone)]
+pub struct Guard(Arc<dyn Fn(&PingoraHttpRequest) -> bool + Send + Sync>);
+
+impl Guard {
+    pub fn new<F>(check: F) -> Self
+    where
+        F: Fn(&PingoraHttpRequest) -> bool + Send + Sync + 'static,
+    {
+        Self(Arc::new(check))
+    }
+
+    /// Header present with this value, compared case-insensitively (use `secret_header` for tokens)
+    pub fn header(name: impl Into<String>, value: impl Into<String>) -> Self {
+        let (name, value) = (name.into(), value.into());
+        Self::new(move |req| {
+            req.headers()
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.trim().eq_ignore_ascii_case(&value))
+        })
+    }
+
+    /// Header equal to `secret` byte for byte (e.g. an admin token), compared in constant time
+    pub fn secret_header(name: impl Into<String>, secret: impl AsRef<[u8]>) -> Self {
+        let name = name.into();
+        let expected = Sha256::digest(secret.as_ref());
+        Self::new(move |req| {
+            req.headers().get(name.as_str()).is_some_and(|v| {
+                // Comparing digests keeps the secret's length out of the timing too
+                let actual = Sha256::digest(v.as_bytes());
+                actual
+                    .iter()
+                    .zip(expected.iter())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+            })
+        })
+    }
+
+    /// Header present with any value
+    pub fn has_header(name: impl Into<String>) -> Self {
+        let name = name.into();
+        Self::new(move |req| req.headers().contains_key(name.as_str()))
+    }
+
+    /// `Content-Type` media type, ignoring parameters such as `charset`
+    pub fn content_type(mime: impl Into<String>) -> Self {
+        let mime = mime.into();
+        Self::new(move |req| {
+            req.headers()
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(';').next())
+                .is_some_and(|v| v.trim().eq_ignore_ascii_case(&mime))
+        })
+    }
+
+    /// Path equal to `prefix` or below it (`/api` matches `/api/users`, not `/apis`)
+    pub fn path_prefix(prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into().trim_end_matches('/').to_string();
+        Self::new(move |req| match req.path().strip_prefix(prefix.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        })
+    }
+
+    /// Method is one of `methods`
+    pub fn methods(methods: impl IntoIterator<Item = http::Method>) -> Self {
+        let methods: Vec<http::Method> = methods.into_iter().collect();
+        Self::new(move |req| methods.contains(req.method()))
+    }
+
+    /// Both guards must pass
+    pub fn and(self, other: Guard) -> Self {
+        Self::new(move |req| self.check(req) && other.check(req))
+    }
+
+    /// Either guard must pass
+    pub fn or(self, other: Guard) -> Self {
+        Self::new(move |req| self.check(req) || other.check(req))
+    }
+
+    pub fn check(&self, req: &PingoraHttpRequest) -> bool {
+        (self.0)(req)
+    }
+}
+
+/// Passes when the guard does not, e.g. `!Guard::path_prefix("/health")`
+impl std::ops::Not for Guard {
+    type Output = Guard;
+
+    fn not(self) -> Guard {
+        Guard::new(move |req| !self.check(req))
+    }
+}
+
+/// Handlers registered for one method and pattern
+struct RouteEntry {
+    method: Method,
+    pattern: Arc<str>,
+    /// Parameter names of the pattern, shared by every match
+    param_names: Vec<Arc<str>>,
+    guarded: Vec<(Guard, Arc<dyn Handler>)>,
+    fallback: Option<Arc<dyn Handler>>,
+    /// Shared with every request the route serves, rebuilt when the route changes
+    info: Arc<RouteInfo>,
+}
+
+impl RouteEntry {
+    fn select(&self, req: Option<&PingoraHttpRequest>) -> Option<&Arc<dyn Handler>> {
+        req.and_then(|req| {
+            self.guarded
+                .iter()
+                .find(|(guard, _)| guard.check(req))
+                .map(|(_, handler)| handler)
+        })
+        .or(self.fallback.as_ref())
     }
 }
 
+/// How a path differing from a route only by a trailing slash is handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// `/foo` and `/foo/` are distinct routes (the default)
+    #[default]
+    Strict,
+    /// Answer with a 308 redirect to the path the route was registered with
+    Redirect,
+    /// Serve the matching route directly as if the path were the same
+    Ignore,
+}
+
+/// Why a route could not be registered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// The pattern overlaps a route registered earlier for the same method
+    Conflict {
+        method: Method,
+        pattern: String,
+        existing: String,
+    },
+    /// The pattern is malformed; `position` is the byte offset of the bad segment
+    Invalid {
+        method: Method,
+        pattern: String,
+        position: usize,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::Conflict {
+                method,
+                pattern,
+                existing,
+            } => write!(
+                f,
+                "{method} {pattern} conflicts with existing route {method} {existing}"
+            ),
+            RouteError::Invalid {
+                method,
+                pattern,
+                position,
+                reason,
+            } => write!(
+                f,
+                "invalid route {method} {pattern} at byte {position} ({:?}): {reason}",
+                &pattern[*position..]
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+/// Byte offset of the first segment at which `pattern` stops being a valid route
+fn invalid_position(pattern: &str) -> usize {
+    let mut starts: Vec<usize> = pattern.match_indices('/').map(|(i, _)| i).collect();
+    starts.push(pattern.len());
+    for pair in starts.windows(2) {
+        let mut probe = matchit::Router::new();
+        if probe.insert(&pattern[..pair[1]], ()).is_err() {
+            return pair[0];
+        }
+    }
+    0
+}
+
 pub struct Router {
-    by_method: HashMap<String, matchit::Router<Arc<dyn Handler>>>,
+    /// Per method, maps paths to an index into `entries`
+    by_method: HashMap<String, matchit::Router<usize>>,
+    entries: Vec<RouteEntry>,
+    /// `(method, pattern)` to the index of its entry
+    index: HashMap<(String, String), usize>,
+    patterns: HashMap<String, Vec<String>>,
+    routes: Vec<(Method, String)>,
+    trailing_slash: TrailingSlash,
+    /// Path prefix, name and value, longest prefix first
+    default_headers: Vec<(String, HeaderName, HeaderValue)>,
+    /// Set once the router is mounted with `App::vhost`
+    host: Option<String>,
+    /// `(method, pattern)` to its metadata, kept for routes not registered yet
+    meta: HashMap<(String, String), RouteMeta>,
 }
 
 impl Router {
     pub fn new() -> Self {
         Self {
             by_method: HashMap::new(),
+            entries: Vec::new(),
+            index: HashMap::new(),
+            patterns: HashMap::new(),
+            routes: Vec::new(),
+            trailing_slash: TrailingSlash::default(),
+            default_headers: Vec::new(),
+            host: None,
+            meta: HashMap::new(),
         }
     }
 
+    /// Set the trailing-slash policy for paths that match no route as-is
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy;
+    }
+
+    /// Register a route, panicking with the `RouteError` message if it is rejected
     pub fn add<S: Into<String>>(&mut self, method: Method, path: S, handler: Arc<dyn Handler>) {
+        if let Err(e) = self.try_add(method, path, handler) {
+            panic!("{e}");
+        }
+    }
+
+    /// Register a route, reporting conflicting or malformed patterns
+    pub fn try_add<S: Into<String>>(
+        &mut self,
+        method: Method,
+        path: S,
+        handler: Arc<dyn Handler>,
+    ) -> Result<(), RouteError> {
+        self.insert(method, path.into(), None, handler)
+    }
+
+    /// Register a handler chosen only for requests passing `guard`
+    ///
+    /// Any number of guarded handlers can share a method and pattern, next to
+    /// one unguarded fallback. Guards are tried in registration order.
+    pub fn add_guarded<S: Into<String>>(
+        &mut self,
+        method: Method,
+        path: S,
+        guard: Guard,
+        handler: Arc<dyn Handler>,
+    ) {
+        if let Err(e) = self.insert(method, path.into(), Some(guard), handler) {
+            panic!("{e}");
+        }
+    }
+
+    fn insert(
+        &mut self,
+        method: Method,
+        path: String,
+        guard: Option<Guard>,
+        handler: Arc<dyn Handler>,
+    ) -> Result<(), RouteError> {
         let key = method.as_str().to_string();
-        let r = self.by_method.entry(key).or_default();
-        r.insert(path.into(), handler).expect("valid route");
+        if let Some(&i) = self.index.get(&(key.clone(), path.clone())) {
+            let entry = &mut self.entries[i];
+            match guard {
+                Some(guard) => entry.guarded.push((guard, handler)),
+                None if entry.fallback.is_some() => {
+                    return Err(RouteError::Conflict {
+                        method,
+                        existing: path.clone(),
+                        pattern: path,
+                    });
+                }
+                None => entry.fallback = Some(handler),
+            }
+            self.refresh_info(i);
+        } else {
+            let i = self.entries.len();
+            let r = self.by_method.entry(key.clone()).or_default();
+            r.insert(path.clone(), i).map_err(|e| match e {
+                matchit::InsertError::Conflict { with } => RouteError::Conflict {
+                    method: method.clone(),
+                    pattern: path.clone(),
+                    existing: with,
+                },
+                other => RouteError::Invalid {
+                    method: method.clone(),
+                    position: invalid_position(&path),
+                    pattern: path.clone(),
+                    reason: other.to_string(),
+                },
+            })?;
+            let (guarded, fallback) = match guard {
+                Some(guard) => (vec![(guard, handler)], None),
+                None => (Vec::new(), Some(handler)),
+            };
+            let param_names = path
+                .split('/')
+                .filter_map(|s| s.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+                .map(|name| Arc::from(name.trim_start_matches('*')))
+                .collect();
+            let info = self.route_info(&method, &path, guarded.len());
+            self.entries.push(RouteEntry {
+                method: method.clone(),
+                pattern: Arc::from(path.as_str()),
+                param_names,
+                guarded,
+                fallback,
+                info,
+            });
+            self.index.insert((key.clone(), path.clone()), i);
+            self.patterns.entry(key).or_default().push(path.clone());
+        }
+        self.routes.push((method, path));
+        Ok(())
+    }
+
+    /// Name a route and attach metadata for the route table and `route_info`
+    ///
+    /// May be called before or after the route is registered.
+    pub fn set_route_meta<P: Into<String>>(&mut self, method: Method, pattern: P, meta: RouteMeta) {
+        let key = (method.as_str().to_string(), pattern.into());
+        let index = self.index.get(&key).copied();
+        self.meta.insert(key, meta);
+        if let Some(i) = index {
+            self.refresh_info(i);
+        }
+    }
+
+    /// Record the host this router serves, shown in its routes' info
+    pub(crate) fn set_host(&mut self, host: String) {
+        self.host = Some(host);
+        for i in 0..self.entries.len() {
+            self.refresh_info(i);
+        }
+    }
+
+    fn refresh_info(&mut self, i: usize) {
+        let entry = &self.entries[i];
+        let info = self.route_info(&entry.method, &entry.pattern, entry.guarded.len());
+        self.entries[i].info = info;
+    }
+
+    fn route_info(&self, method: &Method, pattern: &str, guarded: usize) -> Arc<RouteInfo> {
+        let key = (method.as_str().to_string(), pattern.to_string());
+        Arc::new(RouteInfo {
+            method: key.0.clone(),
+            pattern: key.1.clone(),
+            host: self.host.clone(),
+            guarded,
+            meta: self.meta.get(&key).cloned().unwrap_or_default(),
+        })
+    }
+
+    /// Set `name: value` on every response from this router unless already present
+    pub fn default_header<K, V>(&mut self, name: K, value: V)
+    where
+        K: TryInto<HeaderName>,
+        V: TryInto<HeaderValue>,
+        K::Error: std::fmt::Debug,
+        V::Error: std::fmt::Debug,
+    {
+        self.default_header_under("/", name, value);
+    }
+
+    /// `default_header` for the routes at `prefix` or below, e.g. `/api/v2`
+    ///
+    /// Headers for longer prefixes win over shorter ones and router-wide ones.
+    pub fn default_header_under<K, V>(&mut self, prefix: impl Into<String>, name: K, value: V)
+    where
+        K: TryInto<HeaderName>,
+        V: TryInto<HeaderValue>,
+        K::Error: std::fmt::Debug,
+        V::Error: std::fmt::Debug,
+    {
+        let (name, value) = match (name.try_into(), value.try_into()) {
+            (Ok(name), Ok(value)) => (name, value),
+            (name, value) => {
+                tracing::warn!(?name, ?value, "Ignoring invalid default header");
+                return;
+            }
+        };
+        let prefix = prefix.into();
+        let prefix = match prefix.trim_end_matches('/') {
+            "" => "/".to_string(),
+            trimmed => trimmed.to_string(),
+        };
+        self.default_headers.push((prefix, name, value));
+        self.default_headers
+            .sort_by_key(|(prefix, ..)| std::cmp::Reverse(prefix.len()));
+    }
+
+    /// Default headers for a response to `pattern` (`None` when no route
+    /// matched), most specific first
+    pub(crate) fn default_headers<'a>(
+        &'a self,
+        pattern: Option<&'a str>,
+    ) -> impl Iterator<Item = (&'a HeaderName, &'a HeaderValue)> {
+        self.default_headers
+            .iter()
+            .filter(move |(prefix, ..)| {
+                prefix == "/"
+                    || pattern.is_some_and(|p| {
+                        p.strip_prefix(prefix.as_str())
+                            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+                    })
+            })
+            .map(|(_, name, value)| (name, value))
+    }
+
+    /// Every handler with the method and pattern it is registered for
+    pub(crate) fn handlers(&self) -> impl Iterator<Item = (&str, &str, &Arc<dyn Handler>)> {
+        self.index.iter().flat_map(|((method, pattern), &i)| {
+            let entry = &self.entries[i];
+            let guarded = entry.guarded.iter().map(|(_, handler)| handler);
+            guarded
+                .chain(entry.fallback.as_ref())
+                .map(move |handler| (method.as_str(), pattern.as_str(), handler))
+        })
+    }
+
+    /// Every registered method and pattern, in registration order
+    pub fn routes(&self) -> &[(Method, String)] {
+        &self.routes
+    }
+
+    /// Info for the route registered with `method` and `pattern`
+    pub(crate) fn info(&self, method: &Method, pattern: &str) -> Option<&Arc<RouteInfo>> {
+        self.index
+            .get(&(method.as_str().to_string(), pattern.to_string()))
+            .map(|&i| &self.entries[i].info)
+    }
+
+    /// Route patterns registered for a method, in registration order
+    pub fn patterns(&self, method: &Method) -> &[String] {
+        self.patterns
+            .get(method.as_str())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
     }
 
     pub fn get<S: Into<String>>(&mut self, path: S, handler: Arc<dyn Handler>) {
         self.add(Method::GET, path, handler)
     }
 
-    /// Add a GET route with a simple closure handler returning Result
-    pub fn get_fn<S, F>(&mut self, path: S, handler: F)
+    /// Add a GET route with a closure handler returning any `IntoResponse`
+    pub fn get_fn<S, F, R>(&mut self, path: S, handler: F)
     where
         S: Into<String>,
-        F: Fn(PingoraHttpRequest) -> Result<PingoraWebHttpResponse, crate::error::WebError>
-            + Send
-            + Sync
-            + 'static,
+        F: Fn(PingoraHttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse + 'static,
     {
         self.add(Method::GET, path, Arc::new(ResultClosure::new(handler)))
     }
@@ -74,14 +554,12 @@ impl Router {
         self.add(Method::POST, path, handler)
     }
 
-    /// Add a POST route with a simple closure handler returning Result
-    pub fn post_fn<S, F>(&mut self, path: S, handler: F)
+    /// Add a POST route with a closure handler returning any `IntoResponse`
+    pub fn post_fn<S, F, R>(&mut self, path: S, handler: F)
     where
         S: Into<String>,
-        F: Fn(PingoraHttpRequest) -> Result<PingoraWebHttpResponse, crate::error::WebError>
-            + Send
-            + Sync
-            + 'static,
+        F: Fn(PingoraHttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse + 'static,
     {
         self.add(Method::POST, path, Arc::new(ResultClosure::new(handler)))
     }
@@ -94,42 +572,96 @@ impl Default for Router {
 }
 
 impl Router {
-    pub fn find(
+    /// Look up a route by method and path, ignoring guarded handlers
+    pub fn find(&self, method: &Method, path: &str) -> Option<RouteMatch> {
+        self.find_with(method, path, None)
+    }
+
+    /// Look up the route for a request, evaluating route guards against it
+    pub fn find_request(&self, req: &PingoraHttpRequest) -> Option<RouteMatch> {
+        self.find_with(req.method(), req.path(), Some(req))
+    }
+
+    /// `find_request` plus the info of the route as registered, e.g. the GET
+    /// route serving a HEAD request
+    pub(crate) fn find_route(
+        &self,
+        req: &PingoraHttpRequest,
+    ) -> Option<(RouteMatch, Arc<RouteInfo>)> {
+        let (entry, handler, params) = self.find_entry(req.method(), req.path(), Some(req))?;
+        let found = (Arc::clone(handler), params, Arc::clone(&entry.pattern));
+        Some((found, Arc::clone(&entry.info)))
+    }
+
+    fn find_with(
         &self,
         method: &Method,
         path: &str,
-    ) -> Option<(Arc<dyn Handler>, HashMap<String, String>)> {
-        // Try exact method first
-        if let Some(r) = self.by_method.get(method.as_str())
-            && let Ok(m) = r.at(path)
-        {
-            let mut params = HashMap::new();
-            for (k, v) in m.params.iter() {
-                params.insert(k.to_string(), v.to_string());
-            }
-            return Some((Arc::clone(m.value), params));
-        }
+        req: Option<&PingoraHttpRequest>,
+    ) -> Option<RouteMatch> {
+        let (entry, handler, params) = self.find_entry(method, path, req)?;
+        Some((Arc::clone(handler), params, Arc::clone(&entry.pattern)))
+    }
 
-        // Per RFC, HEAD should behave like GET without body if no explicit HEAD route is present
-        if *method == Method::HEAD
-            && let Some(rget) = self.by_method.get(Method::GET.as_str())
-            && let Ok(m) = rget.at(path)
-        {
-            let mut params = HashMap::new();
-            for (k, v) in m.params.iter() {
-                params.insert(k.to_string(), v.to_string());
+    fn find_entry(
+        &self,
+        method: &Method,
+        path: &str,
+        req: Option<&PingoraHttpRequest>,
+    ) -> Option<(&RouteEntry, &Arc<dyn Handler>, PathParams)> {
+        let (entry, params) = self.lookup(method, path).or_else(|| {
+            if self.trailing_slash != TrailingSlash::Ignore {
+                return None;
             }
-            return Some((Arc::clone(m.value), params));
+            self.lookup(method, &toggle_trailing_slash(path)?)
+        })?;
+        Some((entry, entry.select(req)?, params))
+    }
+
+    /// The path with its trailing slash added or removed, if that one has a route
+    ///
+    /// Returns `None` under `TrailingSlash::Strict`.
+    pub fn alternate_path(&self, method: &Method, path: &str) -> Option<String> {
+        if self.trailing_slash == TrailingSlash::Strict {
+            return None;
         }
+        let alt = toggle_trailing_slash(path)?;
+        self.lookup(method, &alt).map(|_| alt)
+    }
 
-        None
+    fn lookup(&self, method: &Method, path: &str) -> Option<(&RouteEntry, PathParams)> {
+        let matched = self
+            .by_method
+            .get(method.as_str())
+            .and_then(|r| r.at(path).ok())
+            // Per RFC, HEAD should behave like GET without body if no explicit HEAD route is present
+            .or_else(|| {
+                if *method != Method::HEAD {
+                    return None;
+                }
+                self.by_method.get(Method::GET.as_str())?.at(path).ok()
+            })?;
+        let entry = &self.entries[*matched.value];
+        let mut params = PathParams::new();
+        for (name, value) in matched.params.iter() {
+            let name = entry
+                .param_names
+                .iter()
+                .find(|n| &***n == name)
+                .cloned()
+                .unwrap_or_else(|| Arc::from(name));
+            params.push(name, value.to_string());
+        }
+        Some((entry, params))
     }
 
     /// Return a list of methods that match the given path pattern (for 405 responses)
     pub fn allowed_methods(&self, path: &str) -> Vec<String> {
+        let alt =
+            toggle_trailing_slash(path).filter(|_| self.trailing_slash == TrailingSlash::Ignore);
         let mut methods = Vec::new();
         for (m, r) in &self.by_method {
-            if r.at(path).is_ok() {
+            if r.at(path).is_ok() || alt.as_deref().is_some_and(|alt| r.at(alt).is_ok()) {
                 methods.push(m.clone());
             }
         }
@@ -137,6 +669,18 @@ impl Router {
     }
 }
 
+/// `/foo/` -> `/foo` and `/foo` -> `/foo/`; the root path has no alternative
+fn toggle_trailing_slash(path: &str) -> Option<String> {
+    if path == "/" || path.is_empty() {
+        return None;
+    }
+    match path.strip_suffix('/') {
+        Some(trimmed) if !trimmed.ends_with('/') => Some(trimmed.to_string()),
+        Some(_) => None,
+        None => Some(format!("{path}/")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -163,7 +707,8 @@ mod tests {
         let mut r = Router::new();
         r.get("/hi/{name}", Arc::new(HelloHandler));
 
-        let (h, params) = r.find(&Method::GET, "/hi/alice").expect("found");
+        let (h, params, pattern) = r.find(&Method::GET, "/hi/alice").expect("found");
+        assert_eq!(&*pattern, "/hi/{name}");
         let req = PingoraHttpRequest::new(Method::GET, "/hi/alice").with_params(params);
         let res = h.handle(req).await.expect("handler success");
         match res.body {
@@ -173,4 +718,116 @@ mod tests {
             _ => panic!("unexpected streaming body"),
         }
     }
+
+    #[test]
+    fn trailing_slash_policies() {
+        let mut r = Router::new();
+        r.get_fn("/users", |_req| "list");
+        r.get_fn("/docs/", |_req| "docs");
+
+        assert!(r.find(&Method::GET, "/users/").is_none());
+        assert_eq!(r.alternate_path(&Method::GET, "/users/"), None);
+
+        r.set_trailing_slash(TrailingSlash::Redirect);
+        assert!(r.find(&Method::GET, "/users/").is_none());
+        assert_eq!(
+            r.alternate_path(&Method::GET, "/users/").as_deref(),
+            Some("/users")
+        );
+        assert_eq!(
+            r.alternate_path(&Method::GET, "/docs").as_deref(),
+            Some("/docs/")
+        );
+        assert_eq!(r.alternate_path(&Method::GET, "/users//"), None);
+
+        r.set_trailing_slash(TrailingSlash::Ignore);
+        let (_, _, pattern) = r.find(&Method::GET, "/users/").expect("found");
+        assert_eq!(&*pattern, "/users");
+        assert!(r.find(&Method::HEAD, "/docs").is_some());
+    }
+
+    #[test]
+    fn rejected_routes_explain_why() {
+        let mut r = Router::new();
+        r.get_fn("/users/{id}", |_req| "user");
+        r.post_fn("/users/{id}", |_req| "update");
+
+        let err = r
+            .try_add(Method::GET, "/users/{name}", Arc::new(HelloHandler))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            RouteError::Conflict {
+                method: Method::GET,
+                pattern: "/users/{name}".into(),
+                existing: "/users/{id}".into(),
+            }
+        );
+
+        let err = r
+            .try_add(Method::GET, "/files/{*rest}/meta", Arc::new(HelloHandler))
+            .unwrap_err();
+        assert!(
+            matches!(err, RouteError::Invalid { position: 14, .. }),
+            "{err}"
+        );
+        let err = r
+            .try_add(Method::GET, "/a/{x}-{y}", Arc::new(HelloHandler))
+            .unwrap_err();
+        assert!(
+            matches!(err, RouteError::Invalid { position: 2, .. }),
+            "{err}"
+        );
+
+        // Failed registrations leave no trace
+        let routes: Vec<_> = r
+            .routes()
+            .iter()
+            .map(|(m, p)| (m.as_str(), p.as_str()))
+            .collect();
+        assert_eq!(routes, [("GET", "/users/{id}"), ("POST", "/users/{id}")]);
+    }
+
+    #[test]
+    fn guards_pick_among_handlers_on_one_path() {
+        let mut r = Router::new();
+        r.add_guarded(
+            Method::POST,
+            "/items",
+            Guard::content_type("application/json"),
+            Arc::new(ResultClosure::new(|_req| "json")),
+        );
+        r.add_guarded(
+            Method::POST,
+            "/items",
+            Guard::header("x-api-version", "2").and(Guard::new(|req| req.uri().query().is_none())),
+            Arc::new(ResultClosure::new(|_req| "v2")),
+        );
+        let pick = |r: &Router, req: PingoraHttpRequest| r.find_request(&req).is_some();
+        let post = || PingoraHttpRequest::new(Method::POST, "/items");
+
+        assert!(pick(
+            &r,
+            post().header("content-type", "application/json; charset=utf-8")
+        ));
+        assert!(pick(&r, post().header("x-api-version", "2")));
+        let with_query = PingoraHttpRequest::new(Method::POST, "/items?dry=1");
+        assert!(!pick(&r, with_query.header("x-api-version", "2")));
+        assert!(!pick(&r, post()));
+
+        let admin = Guard::secret_header("x-admin-token", "s3cret");
+        let get = || PingoraHttpRequest::new(Method::GET, "/");
+        assert!(admin.check(&get().header("x-admin-token", "s3cret")));
+        assert!(!admin.check(&get().header("x-admin-token", "S3CRET")));
+        assert!(!admin.check(&get().header("x-admin-token", "s3cret ")));
+        assert!(!admin.check(&get()));
+
+        // An unguarded handler becomes the fallback, but only one is allowed
+        r.post_fn("/items", |_req| "form");
+        assert!(pick(&r, post()));
+        assert!(matches!(
+            r.try_add(Method::POST, "/items", Arc::new(HelloHandler)),
+            Err(RouteError::Conflict { .. })
+        ));
+    }
 }
@@ -0,0 +1,155 @@
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+
+/// Summary of a graceful shutdown, logged and passed to `App::on_shutdown_report`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Requests completed after the shutdown signal
+    pub requests_drained: u64,
+    /// Streaming responses cut off after the shutdown signal
+    pub streams_aborted: u64,
+    /// Background tasks that finished while shutdown waited for them
+    pub tasks_finished: usize,
+    /// Background tasks still running when the drain timeout elapsed
+    pub tasks_stopped: usize,
+    /// Shutdown steps cut off by their timeout, by name
+    pub steps_timed_out: Vec<String>,
+    /// Time from the first observed shutdown signal to the end of cleanup
+    pub duration: Duration,
+}
+
+impl ShutdownReport {
+    /// `true` when nothing was cut off
+    pub fn is_clean(&self) -> bool {
+        self.streams_aborted == 0 && self.tasks_stopped == 0 && self.steps_timed_out.is_empty()
+    }
+}
+
+/// When a step added with `App::on_shutdown` runs
+///
+/// Phases run in this order once the listeners stopped accepting requests;
+/// the steps of one phase run concurrently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShutdownPhase {
+    /// Stop taking on new work, e.g. pause a job scheduler
+    StopIntake,
+    /// Let in-flight work finish, alongside the wait for `req.spawn()` tasks
+    Drain,
+    /// Deliver what was buffered, e.g. usage records or metrics
+    Flush,
+}
+
+type StepFn = Box<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// A named piece of shutdown work with its own time limit
+pub(crate) struct ShutdownStep {
+    pub(crate) phase: ShutdownPhase,
+    name: String,
+    timeout: Duration,
+    run: StepFn,
+}
+
+impl ShutdownStep {
+    pub(crate) fn new(phase: ShutdownPhase, name: String, timeout: Duration, run: StepFn) -> Self {
+        Self {
+            phase,
+            name,
+            timeout,
+            run,
+        }
+    }
+}
+
+/// Run the steps of `phase` concurrently, returning the names of those that timed out
+pub(crate) async fn run_phase(steps: &[ShutdownStep], phase: ShutdownPhase) -> Vec<String> {
+    let runs = steps.iter().filter(|s| s.phase == phase).map(|step| async {
+        let started = Instant::now();
+        match tokio::time::timeout(step.timeout, (step.run)()).await {
+            Ok(()) => {
+                tracing::debug!(
+                    step = step.name.as_str(),
+                    duration_ms = started.elapsed().as_millis() as u64,
+                    "Shutdown step finished"
+                );
+                None
+            }
+            Err(_) => {
+                tracing::warn!(
+                    step = step.name.as_str(),
+                    timeout_ms = step.timeout.as_millis() as u64,
+                    "Shutdown step timed out"
+                );
+                Some(step.name.clone())
+            }
+        }
+    });
+    futures::future::join_all(runs)
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Counters recorded while the server drains
+#[derive(Debug, Default)]
+pub(crate) struct ShutdownStats {
+    started: OnceLock<Instant>,
+    requests_drained: AtomicU64,
+    streams_aborted: AtomicU64,
+}
+
+impl ShutdownStats {
+    /// Note that shutdown has begun (only the first call counts)
+    pub(crate) fn mark_started(&self) {
+        self.started.get_or_init(Instant::now);
+    }
+
+    pub(crate) fn request_drained(&self) {
+        self.mark_started();
+        self.requests_drained.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stream_aborted(&self) {
+        self.mark_started();
+        self.streams_aborted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn report(
+        &self,
+        tasks_finished: usize,
+        tasks_stopped: usize,
+        steps_timed_out: Vec<String>,
+    ) -> ShutdownReport {
+        ShutdownReport {
+            requests_drained: self.requests_drained.load(Ordering::Relaxed),
+            streams_aborted: self.streams_aborted.load(Ordering::Relaxed),
+            tasks_finished,
+            tasks_stopped,
+            steps_timed_out,
+            duration: self.started.get().map(Instant::elapsed).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_counts_drain_activity() {
+        let stats = ShutdownStats::default();
+        assert_eq!(stats.report(0, 0, Vec::new()), ShutdownReport::default());
+
+        stats.request_drained();
+        stats.request_drained();
+        stats.stream_aborted();
+        let report = stats.report(3, 1, Vec::new());
+        assert_eq!(report.requests_drained, 2);
+        assert_eq!(report.streams_aborted, 1);
+        assert_eq!(report.tasks_finished, 3);
+        assert!(!report.is_clean());
+    }
+}
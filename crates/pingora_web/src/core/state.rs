@@ -0,0 +1,35 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Typed application state handed to handlers registered on an `App<S>`
+///
+/// Unlike `req.get_app_share_data::<T>()`, which returns an `Option` at request
+/// time, state is fixed when the App is built with `App::with_state`, so a
+/// handler asking for the wrong type fails to compile.
+///
+/// ```
+/// use pingora_web::{App, State};
+///
+/// struct Config {
+///     greeting: String,
+/// }
+///
+/// let mut app = App::with_state(Config { greeting: "hi".into() });
+/// app.get_with_state("/", |_req, State(cfg)| cfg.greeting.clone());
+/// ```
+#[derive(Debug, Default)]
+pub struct State<S>(pub Arc<S>);
+
+impl<S> Clone for State<S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<S> Deref for State<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.0
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Thread-safe registry of labeled counters shared across the App
+///
+/// Each metric is a set of counters keyed by a label (usually a route). The App
+/// provides one registry through app data, so middleware can record into it with
+/// `req.get_app_share_data::<StatsRegistry>()`, and `App::stats()` reads it back.
+#[derive(Default)]
+pub struct StatsRegistry {
+    counters: RwLock<HashMap<String, HashMap<String, u64>>>,
+}
+
+impl std::fmt::Debug for StatsRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StatsRegistry").finish()
+    }
+}
+
+impl StatsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `n` to the counter for `metric` and `label`
+    pub fn add(&self, metric: &str, label: &str, n: u64) {
+        let mut map = self.counters.write().expect("StatsRegistry poisoned");
+        let counters = map.entry(metric.to_string()).or_default();
+        *counters.entry(label.to_string()).or_default() += n;
+    }
+
+    /// Increment the counter for `metric` and `label` by one
+    pub fn incr(&self, metric: &str, label: &str) {
+        self.add(metric, label, 1)
+    }
+
+    /// Current value of a single counter (0 when never recorded)
+    pub fn get(&self, metric: &str, label: &str) -> u64 {
+        let map = self.counters.read().expect("StatsRegistry poisoned");
+        map.get(metric)
+            .and_then(|counters| counters.get(label))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// All counters of a metric, sorted by label
+    pub fn snapshot(&self, metric: &str) -> Vec<(String, u64)> {
+        let map = self.counters.read().expect("StatsRegistry poisoned");
+        let mut out: Vec<(String, u64)> = map
+            .get(metric)
+            .map(|counters| counters.iter().map(|(k, v)| (k.clone(), *v)).collect())
+            .unwrap_or_default();
+        out.sort();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_accumulate_per_label() {
+        let stats = StatsRegistry::new();
+        stats.incr("hits", "/a");
+        stats.incr("hits", "/a");
+        stats.add("hits", "/b", 5);
+
+        assert_eq!(stats.get("hits", "/a"), 2);
+        assert_eq!(stats.get("hits", "/missing"), 0);
+        assert_eq!(
+            stats.snapshot("hits"),
+            vec![("/a".to_string(), 2), ("/b".to_string(), 5)]
+        );
+        assert!(stats.snapshot("other").is_empty());
+    }
+}
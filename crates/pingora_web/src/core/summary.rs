@@ -0,0 +1,96 @@
+use async_trait::async_trait;
+use http::StatusCode;
+use serde::Serialize;
+
+use crate::core::{Guard, Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Effective configuration of an App, logged when it starts serving
+///
+/// Get it with `App::startup_summary`, or serve it with
+/// `App::serve_startup_summary` to check a deployment remotely.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StartupSummary {
+    /// pingora_web version
+    pub version: &'static str,
+    /// Addresses passed to `listen`, `listen_tls*` or `run`; TLS ones end in ` (tls)`
+    pub listeners: Vec<String>,
+    pub routes: usize,
+    pub vhosts: usize,
+    /// App-wide middleware, outermost last
+    pub middleware: Vec<String>,
+    pub max_header_bytes: usize,
+    pub max_header_count: usize,
+    pub keep_alive: bool,
+    pub keep_alive_idle_timeout_secs: Option<u64>,
+    pub keep_alive_max_requests: Option<u32>,
+    pub response_chunk_size: usize,
+    /// Level set with `App::enable_compression` or `AppConfig`; modules added
+    /// directly with `add_http_module` are not reflected
+    pub compression_level: Option<u32>,
+    pub h2c: bool,
+}
+
+impl StartupSummary {
+    /// Log the summary as structured fields plus the JSON document
+    pub fn log(&self) {
+        tracing::info!(
+            version = self.version,
+            listeners = ?self.listeners,
+            routes = self.routes,
+            vhosts = self.vhosts,
+            middleware = ?self.middleware,
+            compression_level = ?self.compression_level,
+            summary = %serde_json::to_string(self).unwrap_or_default(),
+            "pingora_web starting"
+        );
+    }
+}
+
+/// Serves the startup summary the App attaches to requests for this route
+pub(crate) struct StartupSummaryHandler {
+    pub(crate) guard: Guard,
+}
+
+#[async_trait]
+impl Handler for StartupSummaryHandler {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let summary = req.get_request_share_data::<StartupSummary>();
+        Ok(match summary {
+            Some(summary) if self.guard.check(&req) => {
+                PingoraWebHttpResponse::json(StatusCode::OK, &*summary).no_store()
+            }
+            _ => PingoraWebHttpResponse::text(StatusCode::NOT_FOUND, "Not Found"),
+        })
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    #[tokio::test]
+    async fn summary_reports_effective_configuration() {
+        let mut app = App::default();
+        app.get_fn("/", |_| "home");
+        app.enable_compression(6);
+        app.serve_startup_summary("/_summary", Guard::has_header("x-admin"));
+        let client = app.test_client();
+
+        let res = client.get("/_summary").send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = client.get("/_summary").header("x-admin", "1").send().await;
+        let summary: serde_json::Value = serde_json::from_str(&res.text()).unwrap();
+        assert_eq!(summary["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(summary["routes"], 2);
+        assert_eq!(summary["compression_level"], 6);
+        assert_eq!(summary["keep_alive_idle_timeout_secs"], 60);
+        assert_eq!(app.startup_summary().middleware.len(), 1);
+    }
+}
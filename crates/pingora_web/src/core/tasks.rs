@@ -0,0 +1,240 @@
+use std::future::Future;
+use std::panic::Location;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+/// Tracks background tasks spawned by handlers (see `PingoraHttpRequest::spawn`)
+///
+/// The App waits for tracked tasks to finish during graceful shutdown. In debug
+/// builds it also remembers where each task was spawned so tasks still running
+/// at shutdown can be reported.
+#[derive(Default)]
+pub struct TaskTracker {
+    active: AtomicUsize,
+    idle: Notify,
+    #[cfg(debug_assertions)]
+    live: std::sync::Mutex<std::collections::HashMap<u64, LiveTask>>,
+    #[cfg(debug_assertions)]
+    next_id: std::sync::atomic::AtomicU64,
+}
+
+#[cfg(debug_assertions)]
+struct LiveTask {
+    request_id: Option<String>,
+    location: &'static Location<'static>,
+    started: std::time::Instant,
+}
+
+impl std::fmt::Debug for TaskTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskTracker")
+            .field("active", &self.active())
+            .finish()
+    }
+}
+
+/// Decrements the active count when a tracked task completes or is aborted
+struct TaskGuard {
+    tracker: Arc<TaskTracker>,
+    #[cfg(debug_assertions)]
+    id: u64,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        if let Ok(mut live) = self.tracker.live.lock() {
+            live.remove(&self.id);
+        }
+        if self.tracker.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.tracker.idle.notify_waiters();
+        }
+    }
+}
+
+impl TaskTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of tracked tasks still running
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Spawn a tracked task on the current Tokio runtime
+    pub fn spawn<F>(
+        self: &Arc<Self>,
+        future: F,
+        request_id: Option<String>,
+        location: &'static Location<'static>,
+    ) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.active.fetch_add(1, Ordering::AcqRel);
+        let guard = TaskGuard {
+            tracker: Arc::clone(self),
+            #[cfg(debug_assertions)]
+            id: self.register(request_id, location),
+        };
+        #[cfg(not(debug_assertions))]
+        let _ = (request_id, location);
+
+        tokio::spawn(async move {
+            let _guard = guard;
+            future.await
+        })
+    }
+
+    #[cfg(debug_assertions)]
+    fn register(&self, request_id: Option<String>, location: &'static Location<'static>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut live) = self.live.lock() {
+            live.insert(
+                id,
+                LiveTask {
+                    request_id,
+                    location,
+                    started: std::time::Instant::now(),
+                },
+            );
+        }
+        id
+    }
+
+    /// Wait until all tracked tasks have finished or the timeout elapses.
+    ///
+    /// Returns `true` if every task finished in time.
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        let drained = async {
+            loop {
+                let notified = self.idle.notified();
+                let mut notified = std::pin::pin!(notified);
+                notified.as_mut().enable();
+                if self.active() == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, drained).await.is_ok()
+    }
+
+    /// Log tasks that are still running (debug builds only)
+    pub fn report_leaks(&self) {
+        #[cfg(debug_assertions)]
+        if let Ok(live) = self.live.lock() {
+            for task in live.values() {
+                tracing::warn!(
+                    request_id = task.request_id.as_deref().unwrap_or(""),
+                    spawned_at = %task.location,
+                    running_ms = task.started.elapsed().as_millis() as u64,
+                    "Background task still running at shutdown",
+                );
+            }
+        }
+    }
+}
+
+/// Subtasks tied to one request (see `PingoraHttpRequest::task_scope`)
+///
+/// Unlike `req.spawn()`, scoped tasks don't outlive the request: the App
+/// cancels the scope once the response is written or the client goes away,
+/// dropping every subtask still running. Clones share the scope.
+#[derive(Clone, Debug, Default)]
+pub struct TaskScope {
+    token: CancellationToken,
+}
+
+impl TaskScope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A scope that also ends when `parent` is cancelled
+    pub(crate) fn child_of(parent: &CancellationToken) -> Self {
+        Self {
+            token: parent.child_token(),
+        }
+    }
+
+    /// Spawn a subtask in the current tracing span
+    ///
+    /// The handle yields `None` when the scope ended before the task did.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<Option<F::Output>>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let token = self.token.clone();
+        let future = future.instrument(tracing::Span::current());
+        tokio::spawn(async move { token.run_until_cancelled(future).await })
+    }
+
+    /// End the scope, dropping its running subtasks
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Wait until the scope ends
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_returns_after_tasks_finish() {
+        let tracker = Arc::new(TaskTracker::new());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let handle = tracker.spawn(
+            async move {
+                let _ = rx.await;
+                7
+            },
+            Some("rid".to_string()),
+            Location::caller(),
+        );
+        assert_eq!(tracker.active(), 1);
+        assert!(!tracker.wait(Duration::from_millis(20)).await);
+
+        tx.send(()).unwrap();
+        assert_eq!(handle.await.unwrap(), 7);
+        assert!(tracker.wait(Duration::from_millis(100)).await);
+        assert_eq!(tracker.active(), 0);
+    }
+
+    #[tokio::test]
+    async fn scopes_drop_subtasks_when_cancelled() {
+        let parent = CancellationToken::new();
+        let scope = TaskScope::child_of(&parent);
+        let done = scope.spawn(async { 7 });
+        assert_eq!(done.await.unwrap(), Some(7));
+
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let pending = scope.spawn(async move {
+            let _tx = tx;
+            std::future::pending::<()>().await
+        });
+        parent.cancel();
+        assert!(scope.is_cancelled());
+        assert_eq!(pending.await.unwrap(), None);
+        // The subtask's future was dropped along with its sender
+        assert!(rx.await.is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::core::data::AppData;
+
+/// Per-tenant configuration attached to a request by `TenantMiddleware`
+///
+/// Read it with `req.tenant()`. Limits and feature flags are plain values the
+/// app interprets; `data` holds tenant-scoped handles such as a database pool.
+#[derive(Debug, Default)]
+pub struct Tenant {
+    pub id: String,
+    /// Named numeric limits (e.g. `"max_upload_bytes"`)
+    pub limits: HashMap<String, u64>,
+    /// Enabled feature flags
+    pub features: HashSet<String>,
+    data: AppData,
+}
+
+impl Tenant {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn limit(mut self, name: impl Into<String>, value: u64) -> Self {
+        self.limits.insert(name.into(), value);
+        self
+    }
+
+    pub fn feature(mut self, name: impl Into<String>) -> Self {
+        self.features.insert(name.into());
+        self
+    }
+
+    /// Attach a tenant-scoped handle, read back with `data::<T>()`
+    pub fn with_data<T: Send + Sync + 'static>(self, value: Arc<T>) -> Self {
+        self.data.provide_arc(value);
+        self
+    }
+
+    pub fn get_limit(&self, name: &str) -> Option<u64> {
+        self.limits.get(name).copied()
+    }
+
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features.contains(name)
+    }
+
+    pub fn data<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.data.get::<T>()
+    }
+}
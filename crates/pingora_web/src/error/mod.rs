@@ -1,7 +1,9 @@
 mod response_error;
+mod validation;
 mod web_error;
 
 pub use response_error::ResponseError;
+pub use validation::{Validate, ValidationError};
 pub use web_error::WebError;
 
 use http::StatusCode;
@@ -1,5 +1,6 @@
 use crate::core::PingoraWebHttpResponse;
 use http::StatusCode;
+use std::borrow::Cow;
 
 /// Trait for converting errors into HTTP responses
 ///
@@ -13,14 +14,77 @@ pub trait ResponseError: std::error::Error + Send + Sync {
         StatusCode::INTERNAL_SERVER_ERROR
     }
 
+    /// Stable, machine-readable code for clients, e.g. `invalid_token`
+    ///
+    /// The default is derived from the status, e.g. `not_found`.
+    fn error_code(&self) -> Cow<'static, str> {
+        let reason = self.status_code().canonical_reason().unwrap_or("error");
+        Cow::Owned(reason.to_ascii_lowercase().replace([' ', '-'], "_"))
+    }
+
+    /// Extra data for clients, e.g. which fields failed validation
+    fn details(&self) -> Option<serde_json::Value> {
+        None
+    }
+
     /// Generate an HTTP response for this error.
     ///
-    /// The default implementation creates a simple JSON response.
+    /// The default implementation answers with JSON:
+    /// `{"error": {"code", "message", "request_id", "details"}}`. The App fills
+    /// in `request_id` when it converts the error.
     fn error_response(&self) -> PingoraWebHttpResponse {
-        let error_body = serde_json::json!({
-            "error": self.to_string()
+        ErrorBody {
+            status: self.status_code(),
+            code: self.error_code().into_owned(),
+            message: self.to_string(),
+            request_id: None,
+            details: self.details(),
+        }
+        .into_response()
+    }
+}
+
+/// The default error body, kept in the response extensions so the App can
+/// add the request id (and `WebError` its context) before sending it
+#[derive(Clone, Debug)]
+pub(crate) struct ErrorBody {
+    status: StatusCode,
+    code: String,
+    message: String,
+    request_id: Option<String>,
+    pub(crate) details: Option<serde_json::Value>,
+}
+
+impl ErrorBody {
+    fn into_response(self) -> PingoraWebHttpResponse {
+        let body = serde_json::json!({
+            "error": {
+                "code": self.code,
+                "message": self.message,
+                "request_id": self.request_id,
+                "details": self.details,
+            }
         });
+        let mut res = PingoraWebHttpResponse::json(self.status, body);
+        res.extensions.insert(self);
+        res
+    }
+
+    /// Re-render `res` after `edit` if it carries the default error body
+    pub(crate) fn edit(res: &mut PingoraWebHttpResponse, edit: impl FnOnce(&mut ErrorBody)) {
+        let Some(mut body) = res.extensions.remove::<ErrorBody>() else {
+            return;
+        };
+        edit(&mut body);
+        let rendered = body.into_response();
+        res.body = rendered.body;
+        res.extensions.extend(rendered.extensions);
+    }
 
-        PingoraWebHttpResponse::json(self.status_code(), &error_body)
+    /// Fill in the request id of a default error body
+    pub(crate) fn set_request_id(res: &mut PingoraWebHttpResponse, request_id: Option<&str>) {
+        if let Some(id) = request_id {
+            Self::edit(res, |body| body.request_id = Some(id.to_string()));
+        }
     }
 }
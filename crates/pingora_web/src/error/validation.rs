@@ -0,0 +1,167 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use http::StatusCode;
+
+use super::{ResponseError, WebError};
+
+/// Invalid input, as messages per field
+///
+/// Answers 422 with code `validation_failed` and the messages as `details`:
+/// `{"error": {"code": "validation_failed", ..., "details": {"name": ["must not be empty"]}}}`.
+///
+/// # Example
+/// ```
+/// use pingora_web::error::{Validate, ValidationError};
+///
+/// struct Signup {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// impl Validate for Signup {
+///     fn validate(&self) -> Result<(), ValidationError> {
+///         let mut errors = ValidationError::new();
+///         if self.name.is_empty() {
+///             errors.add("name", "must not be empty");
+///         }
+///         if self.age < 18 {
+///             errors.add("age", "must be at least 18");
+///         }
+///         errors.into_result()
+///     }
+/// }
+///
+/// let err = Signup { name: String::new(), age: 30 }.validate().unwrap_err();
+/// assert_eq!(err.messages("name"), ["must not be empty"]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationError {
+    fields: BTreeMap<String, Vec<String>>,
+}
+
+impl ValidationError {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A single failed field
+    pub fn field(field: impl Into<String>, message: impl Into<String>) -> Self {
+        let mut errors = Self::new();
+        errors.add(field, message);
+        errors
+    }
+
+    /// Record a message for `field`
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.fields
+            .entry(field.into())
+            .or_default()
+            .push(message.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Messages recorded for `field`
+    pub fn messages(&self, field: &str) -> &[String] {
+        self.fields
+            .get(field)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn fields(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.fields
+    }
+
+    /// `Ok(())` when no field failed, so checks can end with `errors.into_result()`
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("validation failed")?;
+        for (i, (field, messages)) in self.fields.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { "; " };
+            write!(f, "{sep}{field} {}", messages.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl ResponseError for ValidationError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+
+    fn error_code(&self) -> Cow<'static, str> {
+        Cow::Borrowed("validation_failed")
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(&self.fields).ok()
+    }
+}
+
+impl From<ValidationError> for WebError {
+    #[track_caller]
+    fn from(err: ValidationError) -> Self {
+        Self::new(err)
+    }
+}
+
+/// Input that can check itself, so handlers can call `payload.validate()?`
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    struct Name(String);
+
+    impl Validate for Name {
+        fn validate(&self) -> Result<(), ValidationError> {
+            if self.0.trim().is_empty() {
+                return Err(ValidationError::field("name", "must not be empty"));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_validation_answers_422_with_field_messages() {
+        let mut app = App::default();
+        app.post_fn("/names", |req| -> Result<&'static str, WebError> {
+            let name = Name(String::from_utf8_lossy(req.body()).into_owned());
+            name.validate()?;
+            Ok("saved")
+        });
+        let client = app.test_client();
+
+        assert_eq!(
+            client.post("/names").body("ada").send().await.text(),
+            "saved"
+        );
+        let res = client.post("/names").body(" ").send().await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = serde_json::from_str(&res.text()).unwrap();
+        assert_eq!(body["error"]["code"], "validation_failed");
+        assert_eq!(
+            body["error"]["details"],
+            serde_json::json!({ "name": ["must not be empty"] })
+        );
+        assert_eq!(
+            body["error"]["message"],
+            "validation failed: name must not be empty"
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use super::ResponseError;
+use super::response_error::ErrorBody;
 use crate::core::PingoraWebHttpResponse;
+use std::borrow::Cow;
 
 /// Main error type for the web framework, similar to actix_web::Error
 ///
@@ -8,6 +10,7 @@ use crate::core::PingoraWebHttpResponse;
 #[derive(Debug)]
 pub struct WebError {
     inner: Box<dyn ResponseError>,
+    context: Vec<(String, String)>,
 }
 
 impl WebError {
@@ -16,9 +19,38 @@ impl WebError {
     pub fn new<T: ResponseError + 'static>(err: T) -> Self {
         Self {
             inner: Box::new(err),
+            context: Vec::new(),
         }
     }
 
+    /// Wrap any error to answer with `status`
+    ///
+    /// For 5xx statuses clients only see the reason phrase (e.g. "Internal
+    /// Server Error"); the wrapped error is logged with its source chain.
+    #[track_caller]
+    pub fn wrap<E>(status: http::StatusCode, err: E) -> Self
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        Self::new(WrappedError {
+            status,
+            error: err.into(),
+        })
+    }
+
+    /// Attach a key/value pair for logs and the error body's `details`
+    ///
+    /// Context is added to `details` when that is an object or absent.
+    pub fn with_context(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        self.context.push((key.into(), value.to_string()));
+        self
+    }
+
+    /// Key/value pairs attached with `with_context`
+    pub fn context(&self) -> &[(String, String)] {
+        &self.context
+    }
+
     /// Get a reference to the underlying ResponseError
     pub fn as_response_error(&self) -> &dyn ResponseError {
         &*self.inner
@@ -26,15 +58,77 @@ impl WebError {
 
     /// Convert this error into an HTTP response
     pub fn into_response(self) -> PingoraWebHttpResponse {
+        self.into_response_with_id(None)
+    }
+
+    /// Convert into a response whose default error body names `request_id`
+    pub(crate) fn into_response_with_id(self, request_id: Option<&str>) -> PingoraWebHttpResponse {
         // Log the error
         tracing::error!(
             status_code = %self.inner.status_code(),
-            error = %self.inner,
+            error = %error_chain(&*self.inner),
+            context = ?self.context,
             "Web error occurred",
         );
 
         // Generate the response
-        self.inner.error_response()
+        let mut res = self.error_response();
+        ErrorBody::set_request_id(&mut res, request_id);
+        res
+    }
+}
+
+/// `err` followed by its sources, separated by `: `
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut chain = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        chain.push_str(": ");
+        chain.push_str(&err.to_string());
+        source = err.source();
+    }
+    chain
+}
+
+/// An arbitrary error answered with a chosen status (see `WebError::wrap`)
+#[derive(Debug)]
+struct WrappedError {
+    status: http::StatusCode,
+    error: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl std::fmt::Display for WrappedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.status.is_server_error() {
+            // Keep internals out of the response; logs get them as the source
+            f.write_str(self.status.canonical_reason().unwrap_or("Server Error"))
+        } else {
+            self.error.fmt(f)
+        }
+    }
+}
+
+impl std::error::Error for WrappedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        if self.status.is_server_error() {
+            Some(&*self.error)
+        } else {
+            self.error.source()
+        }
+    }
+}
+
+impl ResponseError for WrappedError {
+    fn status_code(&self) -> http::StatusCode {
+        self.status
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for WebError {
+    #[track_caller]
+    fn from(err: anyhow::Error) -> Self {
+        Self::wrap(http::StatusCode::INTERNAL_SERVER_ERROR, err)
     }
 }
 
@@ -58,6 +152,20 @@ impl From<crate::core::request::FormParseError> for WebError {
     }
 }
 
+impl From<crate::core::MultipartError> for WebError {
+    #[track_caller]
+    fn from(err: crate::core::MultipartError) -> Self {
+        Self::new(err)
+    }
+}
+
+impl From<crate::core::BodyError> for WebError {
+    #[track_caller]
+    fn from(err: crate::core::BodyError) -> Self {
+        Self::new(err)
+    }
+}
+
 impl From<std::io::Error> for WebError {
     #[track_caller]
     fn from(err: std::io::Error) -> Self {
@@ -85,8 +193,33 @@ impl ResponseError for WebError {
         self.inner.status_code()
     }
 
+    fn error_code(&self) -> Cow<'static, str> {
+        self.inner.error_code()
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        let details = self.inner.details();
+        if self.context.is_empty() {
+            return details;
+        }
+        let mut map = match details {
+            None => serde_json::Map::new(),
+            Some(serde_json::Value::Object(map)) => map,
+            Some(other) => return Some(other),
+        };
+        for (key, value) in &self.context {
+            map.entry(key.clone())
+                .or_insert_with(|| serde_json::Value::String(value.clone()));
+        }
+        Some(serde_json::Value::Object(map))
+    }
+
     fn error_response(&self) -> PingoraWebHttpResponse {
-        self.inner.error_response()
+        let mut res = self.inner.error_response();
+        if !self.context.is_empty() {
+            ErrorBody::edit(&mut res, |body| body.details = self.details());
+        }
+        res
     }
 }
 
@@ -120,4 +253,58 @@ mod tests {
             StatusCode::BAD_REQUEST
         );
     }
+
+    #[test]
+    fn wrapped_server_errors_hide_details_from_clients() {
+        let io = std::io::Error::other("disk on fire");
+        let err = WebError::wrap(StatusCode::SERVICE_UNAVAILABLE, io);
+        assert_eq!(err.to_string(), "Service Unavailable");
+        assert_eq!(
+            error_chain(err.as_response_error()),
+            "Service Unavailable: disk on fire"
+        );
+
+        let err = WebError::wrap(StatusCode::CONFLICT, "name taken");
+        assert_eq!(err.as_response_error().status_code(), StatusCode::CONFLICT);
+        assert_eq!(err.to_string(), "name taken");
+    }
+
+    #[cfg(feature = "anyhow")]
+    #[test]
+    fn anyhow_errors_become_500() {
+        fn load() -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("connection refused")).map_err(|e| e.context("loading user"))
+        }
+        let err: WebError = load().unwrap_err().into();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            error_chain(err.as_response_error()),
+            "Internal Server Error: loading user: connection refused"
+        );
+    }
+
+    #[test]
+    fn error_body_has_a_stable_schema() {
+        let err = crate::error::not_found("no such user").with_context("user_id", 42);
+        let res = err.into_response_with_id(Some("req-1"));
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        let crate::core::response::Body::Bytes(bytes) = res.body else {
+            panic!("error bodies are buffered");
+        };
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {
+                    "code": "not_found",
+                    "message": "no such user",
+                    "request_id": "req-1",
+                    "details": { "user_id": "42" },
+                }
+            })
+        );
+    }
 }
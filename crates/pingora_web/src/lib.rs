@@ -1,6 +1,15 @@
+#[cfg(all(feature = "openssl", feature = "boringssl"))]
+compile_error!("features `openssl` and `boringssl` are mutually exclusive; enable one TLS backend");
+
+#[macro_use]
+mod macros;
+
+pub mod cli;
 pub mod core;
 pub mod error;
 pub mod middleware;
+pub mod server;
+pub mod test;
 pub mod utils;
 
 // Re-export commonly used types at the crate root
@@ -8,12 +17,18 @@ pub use core::*;
 pub use error::{ResponseError, WebError};
 pub use http::StatusCode;
 pub use middleware::*;
+pub use pingora_core::apps::HttpServerOptions;
 pub use pingora_core::modules::http::compression::ResponseCompressionBuilder;
 pub use pingora_core::modules::http::{HttpModule, ModuleBuilder};
+pub use pingora_core::protocols::http::v2::server::H2Options;
+pub use server::*;
 
-use crate::core::router::Router;
+#[doc(hidden)]
+pub use tracing as __tracing;
+
+use crate::core::annotations::Exchange;
 use async_trait::async_trait;
-use http::Response as HttpResponse;
+use pingora_core::modules::http::compression::ResponseCompression;
 use std::sync::Arc;
 // use pingora::apps::http_app::ServeHttp; // no longer used; we implement HttpServerApp
 use pingora::protocols::http::ServerSession;
@@ -22,23 +37,127 @@ use pingora_core::modules::http::HttpModules;
 // use tokio::time::{timeout, Duration};
 
 /// The main application: holds router and middleware.
-pub struct App {
+///
+/// `S` is optional typed state set with `App::with_state`; handlers registered
+/// through `get_with_state`/`post_with_state` receive it as `State<S>`.
+pub struct App<S = ()> {
     router: Router,
+    vhosts: std::collections::HashMap<String, Router>,
     pub(crate) middlewares: Vec<Arc<dyn Middleware>>,
     pub(crate) app_data: Arc<core::AppData>,
     pub(crate) http_modules: HttpModules,
+    pub(crate) parser_limits: ParserLimits,
+    keep_alive: KeepAlive,
+    response_chunk_size: usize,
+    chunk_coalescing: ChunkCoalescing,
+    request_id_header: http::HeaderName,
+    request_id_policy: utils::RequestIdPolicy,
+    request_id_generator: utils::RequestIdGenerator,
+    connection_requests: server::keep_alive::ConnectionRequests,
+    pub(crate) tasks: Arc<core::TaskTracker>,
+    pub(crate) stats: Arc<core::StatsRegistry>,
+    pub(crate) readiness: Arc<core::Readiness>,
+    state: State<S>,
+    warmup_paths: Vec<String>,
+    shutdown_stats: core::shutdown::ShutdownStats,
+    on_shutdown_report: Option<ShutdownCallback>,
+    shutdown_steps: Vec<core::shutdown::ShutdownStep>,
+    graceful_shutdown_timeout: Option<std::time::Duration>,
+    on_write_failure: Option<WriteFailureCallback>,
+    truncation_trailer: bool,
+    error_pages: Arc<core::error_pages::ErrorPages>,
+    route_table_path: Option<String>,
+    summary_path: Option<String>,
+    #[cfg(feature = "openapi")]
+    openapi: core::openapi::OpenApiRegistry,
+    compression_level: Option<u32>,
+    serving_on: Vec<String>,
+    listeners: Vec<server::Listener>,
+    server_options: HttpServerOptions,
+    h2_options: Option<H2Options>,
+    module_bridges: Vec<core::annotations::ModuleBridge>,
+    default_headers: http::HeaderMap,
+    upload_policy: Option<Arc<core::UploadPolicy>>,
+    body_spool: Option<Arc<core::BodySpool>>,
 }
 
-/// Default 404 handler
-struct NotFoundHandler;
+type ShutdownCallback = Arc<dyn Fn(&core::ShutdownReport) + Send + Sync>;
+type WriteFailureCallback = Arc<dyn Fn(&server::WriteFailure) + Send + Sync>;
+
+/// Header set on synthetic warmup requests so handlers can tell them apart
+pub const WARMUP_HEADER: &str = "x-warmup";
+
+/// How long graceful shutdown waits for background tasks spawned via `req.spawn()`
+const TASK_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Trailer added by `App::set_truncation_trailer`
+const TRUNCATED_TRAILER: &str = "x-response-truncated";
+
+/// Default segment size for writing in-memory response bodies
+const RESPONSE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Default 404 handler, rendering the registered 404 page if any
+struct NotFoundHandler(Arc<core::error_pages::ErrorPages>);
 
 #[async_trait]
 impl core::Handler for NotFoundHandler {
-    async fn handle(&self, _req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
-        Ok(PingoraWebHttpResponse::text(
-            StatusCode::NOT_FOUND,
-            "Not Found",
-        ))
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        Ok(self.0.render(&req, StatusCode::NOT_FOUND))
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+/// Read the request body from the session, stopping as soon as it exceeds `limit` bytes
+async fn read_request_body(
+    http: &mut ServerSession,
+    limit: usize,
+    spool: Option<&core::BodySpool>,
+) -> Result<core::body::ReadBody, core::BodyError> {
+    let declared = http
+        .req_header()
+        .headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > limit as u64) {
+        return Err(core::BodyError::TooLarge(limit));
+    }
+
+    let mut body = core::body::BodyCollector::new(spool);
+    loop {
+        match http.read_request_body().await {
+            // Chunked uploads carry no declared length; check every chunk
+            Ok(Some(chunk)) if body.len() + chunk.len() > limit => {
+                return Err(core::BodyError::TooLarge(limit));
+            }
+            Ok(Some(chunk)) => body.push(&chunk).await?,
+            Ok(None) => return body.finish().await,
+            Err(e) => return Err(core::BodyError::Read(e.to_string())),
+        }
+    }
+}
+
+/// Lowercase host without port or trailing dot
+fn normalize_host(host: &str) -> String {
+    let authority = host.parse::<http::uri::Authority>().ok();
+    let host = authority.as_ref().map_or(host, |a| a.host());
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Reads a pending request body before calling a handler that wants it buffered
+struct BufferBody(Arc<dyn Handler>);
+
+#[async_trait]
+impl core::Handler for BufferBody {
+    async fn handle(
+        &self,
+        mut req: PingoraHttpRequest,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        req.buffer_body().await?;
+        self.0.handle(req).await
     }
 }
 
@@ -46,12 +165,85 @@ impl App {
     /// Internal constructor with a Router. External users should use `App::default()`
     /// and the route methods on `App`.
     pub(crate) fn new(router: Router) -> Self {
+        Self::with_router_and_state(router, ())
+    }
+
+    /// Create an App with typed state passed to `*_with_state` handlers
+    ///
+    /// The state is also provided as app data, so middleware can still reach it
+    /// with `req.get_app_share_data::<S>()`.
+    pub fn with_state<S: Send + Sync + 'static>(state: S) -> App<S> {
+        App::with_router_and_state(Router::new(), state)
+    }
+
+    /// Create an App with the settings of `config` applied (see `apply_config`)
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pingora_web::{App, AppConfig};
+    /// let config = AppConfig::from_file("pingora_web.yaml")
+    ///     .and_then(AppConfig::with_env)
+    ///     .unwrap();
+    /// let app = App::from_config(&config);
+    /// // app.get("/", ...);
+    /// // app.run().unwrap();
+    /// ```
+    pub fn from_config(config: &server::AppConfig) -> Self {
+        let mut app = Self::default();
+        app.apply_config(config);
+        app
+    }
+}
+
+impl<S: Send + Sync + 'static> App<S> {
+    fn with_router_and_state(router: Router, state: S) -> Self {
         let mut s = Self {
             router,
+            vhosts: Default::default(),
             middlewares: Vec::new(),
             app_data: Arc::new(AppData::new()),
             http_modules: HttpModules::new(),
+            parser_limits: ParserLimits::default(),
+            keep_alive: KeepAlive::default(),
+            response_chunk_size: RESPONSE_CHUNK_SIZE,
+            chunk_coalescing: ChunkCoalescing::default(),
+            request_id_header: http::HeaderName::from_static("x-request-id"),
+            request_id_policy: utils::RequestIdPolicy::default(),
+            request_id_generator: utils::RequestIdGenerator::default(),
+            connection_requests: Default::default(),
+            tasks: Arc::new(core::TaskTracker::new()),
+            stats: Arc::new(core::StatsRegistry::new()),
+            readiness: Arc::new(core::Readiness::new()),
+            state: State(Arc::new(state)),
+            warmup_paths: Vec::new(),
+            shutdown_stats: Default::default(),
+            on_shutdown_report: None,
+            shutdown_steps: Vec::new(),
+            graceful_shutdown_timeout: None,
+            on_write_failure: None,
+            truncation_trailer: false,
+            error_pages: Default::default(),
+            route_table_path: None,
+            summary_path: None,
+            #[cfg(feature = "openapi")]
+            openapi: Default::default(),
+            compression_level: None,
+            serving_on: Vec::new(),
+            listeners: Vec::new(),
+            server_options: HttpServerOptions::default(),
+            h2_options: None,
+            // No-op unless the compression module is added
+            module_bridges: vec![core::annotations::bridge::<ResponseCompression>],
+            default_headers: http::HeaderMap::new(),
+            upload_policy: None,
+            body_spool: None,
         };
+        // Make the task tracker reachable from requests for `req.spawn()`
+        s.app_data.provide_arc(s.tasks.clone());
+        // Middleware records counters into the shared stats registry
+        s.app_data.provide_arc(s.stats.clone());
+        s.app_data.provide_arc(s.readiness.clone());
+        s.app_data.provide_arc(s.state.0.clone());
         // Install request-id middleware by default
         s.use_middleware(RequestId::default());
         s
@@ -63,61 +255,763 @@ impl App {
         self.middlewares.push(Arc::new(middleware));
     }
 
+    /// Accept HTTP/2 over plaintext connections (h2c prior knowledge), e.g. for gRPC
+    ///
+    /// HTTP/1.1 clients are still served on the same listener.
+    pub fn enable_h2c(&mut self) {
+        self.server_options.h2c = true;
+    }
+
+    /// Replace pingora's downstream HTTP server options (default: h2c off)
+    pub fn set_server_options(&mut self, options: HttpServerOptions) {
+        self.server_options = options;
+    }
+
+    /// Tune downstream HTTP/2 connections (window sizes, concurrent streams, ...)
+    ///
+    /// # Example
+    /// ```
+    /// use pingora_web::{App, H2Options};
+    ///
+    /// let mut app = App::default();
+    /// let mut h2 = H2Options::new();
+    /// h2.max_concurrent_streams(256).initial_window_size(1 << 20);
+    /// app.set_h2_options(h2);
+    /// ```
+    pub fn set_h2_options(&mut self, options: H2Options) {
+        self.h2_options = Some(options);
+    }
+
+    /// Compress responses at `level` (1-9) with pingora's compression module
+    pub fn enable_compression(&mut self, level: u32) {
+        self.add_http_module(ResponseCompressionBuilder::enable(level));
+        self.compression_level = Some(level);
+    }
+
+    /// Set `name: value` on every response unless already present, e.g. `Server`
+    ///
+    /// Also applies to 404s, redirects and error responses. Headers set with
+    /// `Router::default_header` or `default_header_under` take precedence.
+    pub fn default_header<K, V>(&mut self, name: K, value: V)
+    where
+        K: TryInto<http::HeaderName>,
+        V: TryInto<http::HeaderValue>,
+        K::Error: std::fmt::Debug,
+        V::Error: std::fmt::Debug,
+    {
+        match (name.try_into(), value.try_into()) {
+            (Ok(name), Ok(value)) => {
+                self.default_headers.insert(name, value);
+            }
+            (name, value) => tracing::warn!(?name, ?value, "Ignoring invalid default header"),
+        }
+    }
+
+    /// Default header for the App's routes at `prefix` or below, e.g. an API
+    /// version on `/api/v2` (see `Router::default_header_under` for vhosts)
+    pub fn default_header_under<K, V>(&mut self, prefix: impl Into<String>, name: K, value: V)
+    where
+        K: TryInto<http::HeaderName>,
+        V: TryInto<http::HeaderValue>,
+        K::Error: std::fmt::Debug,
+        V::Error: std::fmt::Debug,
+    {
+        self.router.default_header_under(prefix, name, value);
+    }
+
+    /// Choose how paths differing from a route only by a trailing slash are handled
+    ///
+    /// Defaults to `TrailingSlash::Strict`, where `/foo` and `/foo/` are distinct.
+    pub fn trailing_slash(&mut self, policy: core::TrailingSlash) {
+        self.router.set_trailing_slash(policy);
+    }
+
+    /// Serve requests for `host` from their own router
+    ///
+    /// The host is matched against the `Host` header (`:authority` on HTTP/2),
+    /// falling back to the TLS SNI, ignoring case and port. A leading `*.` matches
+    /// any subdomain, e.g. `*.example.com`. Requests for other hosts use the App's
+    /// own routes; middleware is shared by all hosts.
+    pub fn vhost(&mut self, host: impl AsRef<str>, mut router: Router) {
+        let host = normalize_host(host.as_ref());
+        router.set_host(host.clone());
+        self.vhosts.insert(host, router);
+    }
+
+    /// Router serving the request's host
+    fn router_for(&self, req: &PingoraHttpRequest) -> &Router {
+        if self.vhosts.is_empty() {
+            return &self.router;
+        }
+        let host = req
+            .headers()
+            .get(http::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| req.uri().authority().map(|a| a.as_str()))
+            .map(normalize_host)
+            .or_else(|| {
+                let tls = req.connection()?.tls.clone()?;
+                tls.sni.map(|sni| normalize_host(&sni))
+            });
+        let Some(host) = host else {
+            return &self.router;
+        };
+        if let Some(router) = self.vhosts.get(&host) {
+            return router;
+        }
+        // Most specific wildcard first: a.b.example.com tries *.b.example.com, then *.example.com
+        let mut rest = host.as_str();
+        while let Some((_, parent)) = rest.split_once('.') {
+            if let Some(router) = self.vhosts.get(&format!("*.{parent}")) {
+                return router;
+            }
+            rest = parent;
+        }
+        &self.router
+    }
+
     /// Add HTTP module to this App
     pub fn add_http_module(&mut self, module: ModuleBuilder) {
         self.http_modules.add_module(module)
     }
 
+    /// Let module `M` exchange values with request annotations (`req.annotations()`)
+    ///
+    /// The module itself is still added with `add_http_module`.
+    pub fn bridge_annotations<M: core::AnnotatedModule>(&mut self) {
+        self.module_bridges.push(core::annotations::bridge::<M>);
+    }
+
+    /// Run every module bridge in one direction
+    fn exchange_annotations(
+        &self,
+        ctx: &mut pingora_core::modules::http::HttpModuleCtx,
+        annotations: &core::Annotations,
+        exchange: core::annotations::Exchange,
+    ) {
+        for bridge in &self.module_bridges {
+            bridge(ctx, annotations, exchange);
+        }
+    }
+
+    /// Set header limits checked as soon as Pingora has parsed the request head
+    ///
+    /// Requests exceeding these limits are rejected with 431 before any routing,
+    /// HTTP module, or middleware work happens. They can only tighten Pingora's
+    /// own caps (see `ParserLimits`).
+    pub fn set_parser_limits(&mut self, limits: ParserLimits) {
+        self.parser_limits = limits;
+    }
+
+    /// Apply deployment settings loaded into an `AppConfig`
+    ///
+    /// Sets parser limits, keep-alive and the response chunk size, adds response
+    /// compression and `LimitsMiddleware` when configured, and replaces the
+    /// listeners for `run()` when the config names any. `log_level` is left
+    /// to your tracing subscriber.
+    pub fn apply_config(&mut self, config: &server::AppConfig) {
+        self.set_parser_limits(config.parser_limits());
+        self.set_keep_alive(config.keep_alive());
+        if let Some(size) = config.response_chunk_size {
+            self.set_response_chunk_size(size);
+        }
+        if let Some(level) = config.compression_level {
+            self.enable_compression(level);
+        }
+        if let Some(limits) = config.limits_middleware() {
+            self.use_middleware(LimitsMiddleware::with_config(limits));
+        }
+        if let Some(secs) = config.graceful_shutdown_timeout_secs {
+            self.set_graceful_shutdown_timeout(std::time::Duration::from_secs(secs));
+        }
+        let listeners = config.listeners();
+        if !listeners.is_empty() {
+            self.listeners = listeners;
+        }
+    }
+
+    /// Grace period `run()` gives in-flight requests and `on_shutdown` steps
+    /// after a graceful shutdown signal (default: Pingora's 5 minutes)
+    ///
+    /// Pingora waits out the whole period before stopping its runtimes; work
+    /// still running then is cut off.
+    pub fn set_graceful_shutdown_timeout(&mut self, timeout: std::time::Duration) {
+        self.graceful_shutdown_timeout = Some(timeout);
+    }
+
+    /// Configure HTTP/1.1 connection reuse (idle timeout, request cap, draining)
+    pub fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        self.keep_alive = keep_alive;
+    }
+
+    /// Write in-memory response bodies in segments of at most `size` bytes (default: 64KB)
+    ///
+    /// The connection task yields between segments, so one huge response
+    /// doesn't hold up the runtime or fill socket buffers in one go. `0`
+    /// writes each body in a single call.
+    pub fn set_response_chunk_size(&mut self, size: usize) {
+        self.response_chunk_size = size;
+    }
+
+    /// Merge small chunks of streamed response bodies into fewer writes (default: off)
+    ///
+    /// Helps generators that yield many tiny `Bytes`; only chunks the stream
+    /// already has ready are merged, see `ChunkCoalescing`.
+    ///
+    /// # Example
+    /// ```
+    /// use pingora_web::{App, ChunkCoalescing};
+    ///
+    /// let mut app = App::default();
+    /// app.set_chunk_coalescing(ChunkCoalescing::new(16 * 1024).max_chunks(256));
+    /// ```
+    pub fn set_chunk_coalescing(&mut self, coalescing: ChunkCoalescing) {
+        self.chunk_coalescing = coalescing;
+    }
+
+    /// Limits `req.multipart()` applies to uploaded files (default: `UploadPolicy::default()`)
+    pub fn set_upload_policy(&mut self, policy: core::UploadPolicy) {
+        self.upload_policy = Some(Arc::new(policy));
+    }
+
+    /// Write request bodies larger than `spool.threshold` to temporary files
+    ///
+    /// Applies to bodies the App buffers before route handlers; handlers
+    /// find them with `req.spooled_body()` while `req.body()` stays empty,
+    /// and `req.multipart()` does not parse them. `req.body_bytes()` still
+    /// works, loading the file into memory.
+    ///
+    /// # Example
+    /// ```
+    /// use pingora_web::{App, BodySpool};
+    ///
+    /// let mut app = App::default();
+    /// app.set_body_spool(BodySpool::new(4 * 1024 * 1024).dir("/var/tmp/uploads"));
+    /// ```
+    pub fn set_body_spool(&mut self, spool: core::BodySpool) {
+        self.body_spool = Some(Arc::new(spool));
+    }
+
+    /// Choose which client-sent `x-request-id` values are kept (default: validated)
+    pub fn set_request_id_policy(&mut self, policy: utils::RequestIdPolicy) {
+        self.request_id_policy = policy;
+    }
+
+    /// Render the App's own 404 or 405 responses with `page` instead of plain text
+    ///
+    /// # Example
+    /// ```
+    /// use pingora_web::{App, NegotiatedErrorPage, PingoraWebHttpResponse, StatusCode};
+    ///
+    /// let mut app = App::default();
+    /// app.set_error_page(StatusCode::METHOD_NOT_ALLOWED, NegotiatedErrorPage);
+    /// app.set_error_page(StatusCode::NOT_FOUND, |_req: &_, status| {
+    ///     PingoraWebHttpResponse::html(status, "<h1>Nothing here</h1>")
+    /// });
+    /// ```
+    pub fn set_error_page(&mut self, status: StatusCode, page: impl core::ErrorPage + 'static) {
+        Arc::make_mut(&mut self.error_pages).insert(status, Arc::new(page));
+    }
+
+    /// Read and echo request ids in `header` instead of `x-request-id`
+    ///
+    /// `req.request_id()` and the default `RequestId` middleware follow it.
+    pub fn set_request_id_header(&mut self, header: http::HeaderName) {
+        self.request_id_header = header;
+    }
+
+    /// Choose how request ids are generated (default: timestamp and counter)
+    pub fn set_request_id_generator(&mut self, generator: utils::RequestIdGenerator) {
+        self.request_id_generator = generator;
+    }
+
+    /// GET paths requested once at startup, before the listener accepts traffic
+    ///
+    /// Use this to populate caches, prime connection pools, or exercise cold code
+    /// paths. Warmup requests go through the full middleware stack and carry the
+    /// `x-warmup: 1` header. `run()` and `listen()` send them on the server's
+    /// runtime before binding the listeners; with `to_service()` call
+    /// `run_warmup()` yourself. There are no separate startup hooks, so do any
+    /// setup warmup relies on before calling `run()`.
+    pub fn warmup<I, T>(&mut self, paths: I)
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.warmup_paths.extend(paths.into_iter().map(Into::into));
+    }
+
+    /// Run the configured warmup requests and return each path's status
+    pub async fn run_warmup(&self) -> Vec<(String, StatusCode)> {
+        let mut results = Vec::with_capacity(self.warmup_paths.len());
+        for path in &self.warmup_paths {
+            let start = std::time::Instant::now();
+            let req = PingoraHttpRequest::new(Method::GET, path).header(WARMUP_HEADER, "1");
+            let res = self.handle(req).await;
+            if res.status.is_success() {
+                tracing::info!(
+                    path = path.as_str(),
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                    "Warmup request done"
+                );
+            } else {
+                tracing::warn!(
+                    path = path.as_str(),
+                    status = res.status.as_u16(),
+                    "Warmup request failed"
+                );
+            }
+            results.push((path.clone(), res.status));
+        }
+        results
+    }
+
     // ===== Route registration (App-level wrappers over Router) =====
 
-    pub fn add<S: Into<String>>(
+    pub fn add<P: Into<String>>(
         &mut self,
         method: core::Method,
-        path: S,
+        path: P,
         handler: Arc<dyn core::Handler>,
     ) {
         self.router.add(method, path, handler)
     }
 
-    pub fn get<S: Into<String>>(&mut self, path: S, handler: Arc<dyn core::Handler>) {
+    /// Like `add`, but returns a `RouteError` instead of panicking on a bad pattern
+    pub fn try_add<P: Into<String>>(
+        &mut self,
+        method: core::Method,
+        path: P,
+        handler: Arc<dyn core::Handler>,
+    ) -> Result<(), core::RouteError> {
+        self.router.try_add(method, path, handler)
+    }
+
+    /// Every registered method and route pattern, in registration order (vhosts excluded)
+    pub fn routes(&self) -> &[(core::Method, String)] {
+        self.router.routes()
+    }
+
+    /// Register a handler chosen only for requests passing `guard` (see `Guard`)
+    pub fn add_guarded<P: Into<String>>(
+        &mut self,
+        method: core::Method,
+        path: P,
+        guard: core::Guard,
+        handler: Arc<dyn core::Handler>,
+    ) {
+        self.router.add_guarded(method, path, guard, handler)
+    }
+
+    pub fn get<P: Into<String>>(&mut self, path: P, handler: Arc<dyn core::Handler>) {
         self.router.get(path, handler)
     }
 
-    pub fn post<S: Into<String>>(&mut self, path: S, handler: Arc<dyn core::Handler>) {
+    pub fn post<P: Into<String>>(&mut self, path: P, handler: Arc<dyn core::Handler>) {
         self.router.post(path, handler)
     }
 
     // For other HTTP methods, use `add(Method::X, ...)` for simplicity.
 
-    /// Closure handler: GET (returns Result)
-    pub fn get_fn<S, F>(&mut self, path: S, handler: F)
+    /// Closure handler: GET (returns any `IntoResponse`, e.g. `&str`, `Json<T>`, or `Result`)
+    pub fn get_fn<P, F, R>(&mut self, path: P, handler: F)
     where
-        S: Into<String>,
-        F: Fn(PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError>
-            + Send
-            + Sync
-            + 'static,
+        P: Into<String>,
+        F: Fn(PingoraHttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse + 'static,
     {
         self.router.get_fn(path, handler)
     }
 
-    /// Closure handler: POST (returns Result)
-    pub fn post_fn<S, F>(&mut self, path: S, handler: F)
+    /// Closure handler: POST (returns any `IntoResponse`)
+    pub fn post_fn<P, F, R>(&mut self, path: P, handler: F)
     where
-        S: Into<String>,
-        F: Fn(PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError>
-            + Send
-            + Sync
-            + 'static,
+        P: Into<String>,
+        F: Fn(PingoraHttpRequest) -> R + Send + Sync + 'static,
+        R: IntoResponse + 'static,
     {
         self.router.post_fn(path, handler)
     }
 
+    /// Closure handler with typed state: GET
+    pub fn get_with_state<P, F, R>(&mut self, path: P, handler: F)
+    where
+        P: Into<String>,
+        F: Fn(PingoraHttpRequest, State<S>) -> R + Send + Sync + 'static,
+        R: IntoResponse + 'static,
+    {
+        self.add_with_state(Method::GET, path, handler)
+    }
+
+    /// Closure handler with typed state: POST
+    pub fn post_with_state<P, F, R>(&mut self, path: P, handler: F)
+    where
+        P: Into<String>,
+        F: Fn(PingoraHttpRequest, State<S>) -> R + Send + Sync + 'static,
+        R: IntoResponse + 'static,
+    {
+        self.add_with_state(Method::POST, path, handler)
+    }
+
+    /// Closure handler with typed state for any method
+    pub fn add_with_state<P, F, R>(&mut self, method: core::Method, path: P, handler: F)
+    where
+        P: Into<String>,
+        F: Fn(PingoraHttpRequest, State<S>) -> R + Send + Sync + 'static,
+        R: IntoResponse + 'static,
+    {
+        let handler = core::router::StateClosure::new(handler, self.state.clone());
+        self.router.add(method, path, Arc::new(handler))
+    }
+
+    /// The typed state set with `App::with_state`
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
     // --- App-level shared data API (single choice) ---
     pub fn set_app_share_data<T: Send + Sync + 'static>(&self, value: Arc<T>) -> Option<Arc<T>> {
         self.app_data.provide_arc(value)
     }
 
+    /// Templates for `PingoraWebHttpResponse::render`, replacing any set before
+    #[cfg(feature = "templates")]
+    pub fn set_templates(&mut self, templates: utils::Templates) {
+        self.app_data.provide_arc(Arc::new(templates));
+    }
+
+    /// Counters recorded by middleware (e.g. `DuplicateRequestIdMiddleware`)
+    pub fn stats(&self) -> &core::StatsRegistry {
+        &self.stats
+    }
+
+    /// Register a dependency whose health gates readiness
+    pub fn add_dependency(&mut self, dependency: core::Dependency) {
+        self.readiness.add(dependency)
+    }
+
+    /// Serve the readiness report at `path` (200 when ready, 503 otherwise)
+    pub fn readiness_endpoint<P: Into<String>>(&mut self, path: P) {
+        let handler = core::readiness::ReadinessHandler(self.readiness.clone());
+        self.router.get(path, Arc::new(handler))
+    }
+
+    /// Name a route and attach metadata for the route table
+    ///
+    /// Applies to the App's own routes; use `Router::set_route_meta` for vhosts.
+    pub fn set_route_meta<P: Into<String>>(
+        &mut self,
+        method: core::Method,
+        pattern: P,
+        meta: core::RouteMeta,
+    ) {
+        self.router.set_route_meta(method, pattern, meta);
+    }
+
+    /// Every route (vhosts included) with its metadata, plus the middleware
+    pub fn route_table(&self) -> core::RouteTable {
+        let mut seen = std::collections::HashSet::new();
+        let hosts = std::iter::once((None, &self.router)).chain(
+            self.vhosts
+                .iter()
+                .map(|(host, router)| (Some(host.as_str()), router)),
+        );
+        let mut routes = Vec::new();
+        for (host, router) in hosts {
+            for (method, pattern) in router.routes() {
+                // Guarded handlers register their pattern once each
+                if !seen.insert((host, method, pattern)) {
+                    continue;
+                }
+                if let Some(info) = router.info(method, pattern) {
+                    routes.push(core::RouteInfo::clone(info));
+                }
+            }
+        }
+        core::RouteTable {
+            routes,
+            middleware: self
+                .middlewares
+                .iter()
+                .map(|m| m.name().to_string())
+                .collect(),
+        }
+    }
+
+    /// Validate the configuration without binding sockets, e.g. as a CI preflight
+    ///
+    /// Reports every problem found: listener addresses that don't resolve or
+    /// repeat, unreadable TLS files, warmup paths no route answers, and what
+    /// middleware and handlers find wrong with their options (such as a
+    /// `ServeDir` root that doesn't exist). Conflicting routes are already
+    /// rejected when they are added.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pingora_web::App;
+    ///
+    /// let mut app = App::default();
+    /// app.add_listener(pingora_web::Listener::new("0.0.0.0:8080"));
+    /// if std::env::args().any(|arg| arg == "--check") {
+    ///     match app.check() {
+    ///         Ok(()) => println!("configuration ok"),
+    ///         Err(e) => {
+    ///             eprintln!("{e}");
+    ///             std::process::exit(1);
+    ///         }
+    ///     }
+    ///     return;
+    /// }
+    /// app.run().unwrap();
+    /// ```
+    pub fn check(&self) -> Result<(), server::CheckError> {
+        let mut problems = Vec::new();
+        server::check::check_listeners(&self.listeners, &mut problems);
+
+        let hosts = std::iter::once((None, &self.router)).chain(
+            self.vhosts
+                .iter()
+                .map(|(host, router)| (Some(host.as_str()), router)),
+        );
+        let mut routes = Vec::new();
+        for (host, router) in hosts {
+            for (method, pattern, handler) in router.handlers() {
+                let route = match host {
+                    Some(host) => format!("{method} {host}{pattern}"),
+                    None => format!("{method} {pattern}"),
+                };
+                for problem in handler.check() {
+                    routes.push(format!("route {route}: {problem}"));
+                }
+            }
+        }
+        // Map iteration order varies; keep reports stable
+        routes.sort();
+        problems.extend(routes);
+
+        for path in &self.warmup_paths {
+            let route_path = path.split('?').next().unwrap_or_default();
+            if self.router.find(&Method::GET, route_path).is_none() {
+                problems.push(format!("warmup path {path} matches no GET route"));
+            }
+        }
+        for middleware in &self.middlewares {
+            for problem in middleware.check() {
+                problems.push(format!("middleware {}: {problem}", middleware.name()));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(server::CheckError(problems))
+        }
+    }
+
+    /// Serve the route table at `path` as JSON or HTML, for debugging
+    ///
+    /// Only requests passing `guard` see it; others get 404. App middleware
+    /// (e.g. authentication) applies as for any route.
+    ///
+    /// # Example
+    /// ```
+    /// use pingora_web::{App, Guard};
+    ///
+    /// let mut app = App::default();
+    /// app.serve_route_table("/_routes", Guard::secret_header("x-admin-token", "secret"));
+    /// ```
+    pub fn serve_route_table<P: Into<String>>(&mut self, path: P, guard: core::Guard) {
+        let path = path.into();
+        let handler = core::route_table::RouteTableHandler { guard };
+        self.router.get(path.clone(), Arc::new(handler));
+        self.route_table_path = Some(path);
+    }
+
+    /// Effective configuration: listeners, routes, middleware, limits, compression
+    pub fn startup_summary(&self) -> core::StartupSummary {
+        let keep_alive = &self.keep_alive;
+        core::StartupSummary {
+            version: env!("CARGO_PKG_VERSION"),
+            listeners: self.serving_on.clone(),
+            routes: self.route_table().routes.len(),
+            vhosts: self.vhosts.len(),
+            middleware: self
+                .middlewares
+                .iter()
+                .map(|m| m.name().to_string())
+                .collect(),
+            max_header_bytes: self.parser_limits.max_header_bytes,
+            max_header_count: self.parser_limits.max_header_count,
+            keep_alive: keep_alive.enabled,
+            keep_alive_idle_timeout_secs: keep_alive.idle_timeout.map(|t| t.as_secs()),
+            keep_alive_max_requests: keep_alive.max_requests,
+            response_chunk_size: self.response_chunk_size,
+            compression_level: self.compression_level,
+            h2c: self.server_options.h2c,
+        }
+    }
+
+    /// Serve `startup_summary()` as JSON at `path` to requests passing `guard`
+    ///
+    /// Others get 404, like `serve_route_table`.
+    pub fn serve_startup_summary<P: Into<String>>(&mut self, path: P, guard: core::Guard) {
+        let path = path.into();
+        let handler = core::summary::StartupSummaryHandler { guard };
+        self.router.get(path.clone(), Arc::new(handler));
+        self.summary_path = Some(path);
+    }
+
+    /// Describe a route in the OpenAPI document
+    #[cfg(feature = "openapi")]
+    pub fn set_operation<P: Into<String>>(
+        &mut self,
+        method: core::Method,
+        pattern: P,
+        operation: core::Operation,
+    ) {
+        self.openapi
+            .operations
+            .insert((method, pattern.into()), operation);
+    }
+
+    /// OpenAPI 3.0 document for every route, with the operations set through
+    /// `set_operation` and route names (`set_route_meta`) as operation ids
+    #[cfg(feature = "openapi")]
+    pub fn openapi_json(&self) -> serde_json::Value {
+        self.openapi.document(&self.route_table())
+    }
+
+    /// Add the routes of an OpenAPI document, each handled by the handler
+    /// registered for its `operationId`
+    ///
+    /// Nothing is added unless every operation has an id and a handler, every
+    /// handler an operation, and the path parameters of each operation match
+    /// its path template. Operation ids become route names, and summaries and
+    /// tags carry over to `openapi_json()`.
+    #[cfg(feature = "openapi")]
+    pub fn mount_openapi(
+        &mut self,
+        routes: core::OpenApiRoutes,
+    ) -> Result<(), core::OpenApiRoutesError> {
+        for route in routes.resolve()? {
+            self.add(route.method.clone(), route.path.clone(), route.handler);
+            self.set_route_meta(route.method.clone(), route.path.clone(), route.meta);
+            self.set_operation(route.method, route.path, route.operation);
+        }
+        Ok(())
+    }
+
+    /// Serve `openapi_json()` at `path`, titled by `info`
+    #[cfg(feature = "openapi")]
+    pub fn serve_openapi<P: Into<String>>(&mut self, path: P, info: core::OpenApiInfo) {
+        let path = path.into();
+        self.router
+            .get(path.clone(), Arc::new(core::openapi::OpenApiHandler));
+        self.openapi.spec_path = Some(path);
+        self.openapi.info = info;
+    }
+
+    /// Serve a Swagger UI page at `path` for the document at `spec_url`
+    ///
+    /// The page loads Swagger UI from the unpkg CDN.
+    #[cfg(feature = "openapi")]
+    pub fn serve_swagger_ui<P: Into<String>>(&mut self, path: P, spec_url: impl Into<String>) {
+        let path = path.into();
+        let handler = core::openapi::SwaggerUiHandler {
+            spec_url: spec_url.into(),
+        };
+        self.router.get(path.clone(), Arc::new(handler));
+        self.openapi.ui_path = Some(path);
+    }
+
+    /// Serve liveness at `/livez` and readiness at `/readyz`
+    ///
+    /// `/livez` answers 200 while the process serves requests. `/readyz` serves
+    /// the readiness report: dependencies added with `add_dependency` and the
+    /// flag set through `health().set_ready(..)`.
+    pub fn with_health_checks(&mut self) {
+        self.router
+            .get("/livez", Arc::new(core::readiness::LivenessHandler));
+        self.readiness_endpoint("/readyz");
+    }
+
+    /// Call `callback` with the shutdown summary once graceful shutdown completes
+    ///
+    /// The summary is always logged via tracing; use this to export it elsewhere
+    /// (e.g. to verify clean shutdowns after a deploy).
+    pub fn on_shutdown_report<F>(&mut self, callback: F)
+    where
+        F: Fn(&core::ShutdownReport) + Send + Sync + 'static,
+    {
+        self.on_shutdown_report = Some(Arc::new(callback));
+    }
+
+    /// Run `step` during graceful shutdown in `phase`, giving up on it after `timeout`
+    ///
+    /// Shutdown completes only after every phase ran: `StopIntake`, then
+    /// `Drain` alongside the wait for `req.spawn()` tasks, then `Flush`.
+    /// Steps cut off by their timeout are named in the shutdown report.
+    ///
+    /// ```
+    /// use pingora_web::{App, FileSink, ShutdownPhase, UsageMiddleware};
+    /// use std::time::Duration;
+    ///
+    /// let usage = UsageMiddleware::new(FileSink::new("usage.jsonl"));
+    /// let pipeline = usage.pipeline();
+    /// let mut app = App::default();
+    /// app.use_middleware(usage);
+    /// app.on_shutdown(ShutdownPhase::Flush, "usage", Duration::from_secs(5), move || {
+    ///     let pipeline = pipeline.clone();
+    ///     async move { pipeline.flush().await }
+    /// });
+    /// ```
+    pub fn on_shutdown<F, Fut>(
+        &mut self,
+        phase: core::ShutdownPhase,
+        name: impl Into<String>,
+        timeout: std::time::Duration,
+        step: F,
+    ) where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let run = Box::new(move || Box::pin(step()) as futures::future::BoxFuture<'static, ()>);
+        self.shutdown_steps.push(core::shutdown::ShutdownStep::new(
+            phase,
+            name.into(),
+            timeout,
+            run,
+        ));
+    }
+
+    /// Call `callback` for every response that could not be written completely
+    ///
+    /// Failures are always logged and set the `response.write_failure`
+    /// annotation, which `MetricsMiddleware` and `TracingMiddleware` record.
+    pub fn on_write_failure<F>(&mut self, callback: F)
+    where
+        F: Fn(&server::WriteFailure) + Send + Sync + 'static,
+    {
+        self.on_write_failure = Some(Arc::new(callback));
+    }
+
+    /// End HTTP/2 responses cut short by a failing HTTP module with an
+    /// `x-response-truncated` trailer naming the failed stage (default: off)
+    pub fn set_truncation_trailer(&mut self, enabled: bool) {
+        self.truncation_trailer = enabled;
+    }
+
+    /// Dependency-gated readiness state
+    pub fn readiness(&self) -> &core::Readiness {
+        &self.readiness
+    }
+
+    /// Shared handle to the readiness state, e.g. to call `set_ready` from a
+    /// background task after the App was handed to the server
+    pub fn health(&self) -> Arc<core::Readiness> {
+        self.readiness.clone()
+    }
+
     /// Listen on the given address and start the server (beginner-friendly method)
     ///
     /// This is a convenience method that handles all the Pingora server setup internally.
@@ -131,20 +1025,118 @@ impl App {
     /// // app.get("/", ...);
     /// // app.listen("0.0.0.0:8080").unwrap();
     /// ```
-    pub fn listen(self, addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn listen(mut self, addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.listeners = vec![server::Listener::new(addr)];
+        self.run()
+    }
+
+    /// Add a listener with socket options for `run()`
+    pub fn add_listener(&mut self, listener: server::Listener) {
+        self.listeners.push(listener);
+    }
+
+    /// Serve on the listeners added with `add_listener` or from the `AppConfig`
+    /// passed to `from_config`/`apply_config`
+    ///
+    /// TLS listeners require the `openssl` or `boringssl` feature. In worker
+    /// processes (see `Workers`) every listener is bound with `SO_REUSEPORT`.
+    pub fn run(mut self) -> Result<(), Box<dyn std::error::Error>> {
         use pingora::server::Server;
-        use pingora::services::listening::Service;
 
-        let mut server = Server::new(None)?;
-        server.bootstrap();
+        if self.listeners.is_empty() {
+            return Err("no listen addresses configured".into());
+        }
+        #[cfg(not(any(feature = "openssl", feature = "boringssl")))]
+        if self.listeners.iter().any(|l| l.tls.is_some()) {
+            return Err("TLS listeners need the `openssl` or `boringssl` feature".into());
+        }
 
-        let mut service = Service::new("pingora_web".to_string(), self);
-        service.add_tcp(addr);
-        server.add_services(vec![Box::new(service)]);
+        let mut listeners = std::mem::take(&mut self.listeners);
+        // Processes started by `Workers::spawn_processes` share the ports
+        if server::Workers::current().is_some() {
+            listeners = listeners.into_iter().map(|l| l.reuse_port(true)).collect();
+        }
+        self.serving_on = listeners.iter().map(|l| l.label()).collect();
+        let mut server = match self.graceful_shutdown_timeout {
+            Some(timeout) => {
+                let mut conf = pingora::server::configuration::ServerConf::new()
+                    .ok_or("cannot create the server configuration")?;
+                conf.grace_period_seconds = Some(timeout.as_secs());
+                Server::new_with_opt_and_conf(None, conf)
+            }
+            None => Server::new(None)?,
+        };
+        server.bootstrap();
+        self.startup_summary().log();
+
+        let tuning: Vec<_> = listeners
+            .iter()
+            .filter_map(|l| l.connection_tuning())
+            .collect();
+        let app = Arc::new(self);
+        let tuned = server::listener::Tuned::new(app.clone(), tuning);
+        let mut service = server::listener::listening_service(tuned, &listeners)?;
+        // Warm up on the runtime that will serve requests, so pools, timers
+        // and spawned tasks outlive it, before the listeners are bound
+        if !app.warmup_paths.is_empty() {
+            let warmup = async move {
+                app.run_warmup().await;
+            };
+            service = Box::new(server::listener::StartAfter::new(service, warmup));
+        }
+        server.add_services(vec![service]);
+        #[cfg(all(unix, feature = "systemd"))]
+        server.add_services(
+            server::systemd::Notifier::service(listeners.iter().map(|l| l.addr.clone()).collect())
+                .into_iter()
+                .collect(),
+        );
 
         server.run_forever()
     }
 
+    /// Listen for HTTPS on the given address using a PEM certificate and key
+    ///
+    /// Requires the `openssl` or `boringssl` feature. Use `listen_tls_with_config()`
+    /// to adjust ALPN, client certificate authentication, or the minimum TLS version.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pingora_web::App;
+    /// let app = App::default();
+    /// // app.listen_tls("0.0.0.0:8443", "cert.pem", "key.pem").unwrap();
+    /// ```
+    #[cfg(any(feature = "openssl", feature = "boringssl"))]
+    pub fn listen_tls(
+        self,
+        addr: &str,
+        cert_path: &str,
+        key_path: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.listen_tls_with_config(addr, TlsConfig::new(cert_path, key_path))
+    }
+
+    /// Listen for HTTPS on the given address with custom TLS settings
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pingora_web::{App, TlsConfig, TlsVersion};
+    /// let app = App::default();
+    /// let tls = TlsConfig::new("cert.pem", "key.pem")
+    ///     .enable_h2()
+    ///     .min_version(TlsVersion::Tls13);
+    /// // app.listen_tls_with_config("0.0.0.0:8443", tls).unwrap();
+    /// ```
+    #[cfg(any(feature = "openssl", feature = "boringssl"))]
+    pub fn listen_tls_with_config(
+        mut self,
+        addr: &str,
+        tls: TlsConfig,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.listeners = vec![server::Listener::new(addr).tls(tls)];
+        self.run()
+    }
+
     /// Convert this App into a Pingora Service (advanced users)
     ///
     /// This method gives you full control over the Service configuration,
@@ -158,7 +1150,8 @@ impl App {
     /// let app = App::default();
     /// let mut service = app.to_service("my-web-service");
     /// service.add_tcp("0.0.0.0:8080");
-    /// service.add_tcp("0.0.0.0:8443"); // Add HTTPS later
+    /// // With the `openssl` feature, add HTTPS via `TlsConfig::into_settings()`
+    /// // and `service.add_tls_with_settings(...)`
     ///
     /// let mut server = Server::new(None).unwrap();
     /// server.add_service(service);
@@ -172,96 +1165,202 @@ impl App {
         Service::new(name.into(), self)
     }
 
+    /// Create an in-process test client that sends requests through `handle()`
+    pub fn test_client(&self) -> utils::TestClient<'_, S> {
+        utils::TestClient::new(self)
+    }
+
+    /// Crawl GET routes from `seed_paths` and write the responses as a static site
+    ///
+    /// Internal links (`href`/`src`) in HTML pages are followed, HTML pages are
+    /// written as `path/index.html`, and other responses at their own path. The
+    /// report lists paths that answered with errors and GET routes no crawled
+    /// path reached. Requests carry no `Host` header, so only the App's own
+    /// routes are crawled; vhost routers are left out of the export and report.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # async fn export() -> std::io::Result<()> {
+    /// use pingora_web::App;
+    /// let app = App::default();
+    /// let report = app.export_static("public", ["/"]).await?;
+    /// for route in &report.unreachable {
+    ///     eprintln!("not linked from anywhere: {route}");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn export_static<I, T>(
+        &self,
+        output_dir: impl AsRef<std::path::Path>,
+        seed_paths: I,
+    ) -> std::io::Result<utils::ExportReport>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let seeds = seed_paths.into_iter().map(Into::into).collect();
+        utils::static_export::export(self, output_dir.as_ref(), seeds).await
+    }
+
     /// Handle a request end-to-end through middlewares and the router.
     pub async fn handle(&self, mut req: PingoraHttpRequest) -> PingoraWebHttpResponse {
-        // Ensure a request-id exists early, even if middlewares fail later
-        let request_id = req
-            .headers()
-            .get("x-request-id")
-            .and_then(|v| v.to_str().ok())
-            .filter(|s| !s.is_empty())
-            .map_or_else(crate::utils::request_id::generate, ToString::to_string);
-        // Put request-id into request headers if not already present
-        if !req.headers().contains_key("x-request-id") {
-            let _ = req.headers_mut().insert(
-                "x-request-id",
-                http::HeaderValue::from_str(&request_id).unwrap(),
+        // Ensure a request-id exists early, even if middlewares fail later;
+        // client ids the policy rejects are replaced
+        let header = &self.request_id_header;
+        let request_id = self
+            .request_id_policy
+            .resolve(req.headers().get(header), &self.request_id_generator);
+        response::insert_header(req.headers_mut(), header.clone(), &request_id);
+        req.set_request_share_data(Arc::new(utils::request_id::RequestIdHeader(header.clone())));
+        if req.annotations().is_none() {
+            req.set_request_share_data(Arc::new(core::Annotations::new()));
+        }
+        if let Some(policy) = &self.upload_policy {
+            req.set_request_share_data(policy.clone());
+        }
+        if let Some(spool) = &self.body_spool {
+            req.set_request_share_data(spool.clone());
+        }
+        // Requests from a listener get a scope that lasts until the response is written
+        let scope = match req.get_request_share_data::<core::TaskScope>() {
+            Some(_) => None,
+            None => {
+                let scope = core::TaskScope::child_of(&req.cancellation());
+                req.set_request_share_data(Arc::new(scope.clone()));
+                Some(scope)
+            }
+        };
+        let router = self.router_for(&req);
+        let (mut response, matched) = self.dispatch(req, router, &request_id).await;
+        if let Some(scope) = scope {
+            scope.cancel();
+        }
+
+        // Ensure response carries the request-id even on error paths
+        if !response.headers.contains_key(&self.request_id_header) {
+            response::insert_header(
+                &mut response.headers,
+                self.request_id_header.clone(),
+                &request_id,
             );
         }
+
+        // Default headers, then content-length or transfer-encoding if not already set
+        self.finalize_response_headers(&mut response, router, matched.as_deref());
+        response
+    }
+
+    /// Route the request and run it through the middlewares, returning the
+    /// response and the pattern of the matched route
+    async fn dispatch(
+        &self,
+        req: PingoraHttpRequest,
+        router: &Router,
+        request_id: &str,
+    ) -> (PingoraWebHttpResponse, Option<Arc<str>>) {
         // Route lookup using references to avoid cloning
-        let find_result = {
-            let method = req.method();
-            let path = req.path();
-            self.router.find(method, path)
-        };
-        let (handler, params): (Arc<dyn Handler>, std::collections::HashMap<String, String>) =
-            match find_result {
-                Some((h, p)) => (h, p),
-                None => {
-                    let path = req.path();
-                    let method = req.method();
-                    let mut allowed = self.router.allowed_methods(path);
-                    if *method == Method::OPTIONS {
-                        // For OPTIONS, respond with 204 No Content and Allow header when no explicit route
-                        allowed.push("OPTIONS".to_string());
-                        allowed.sort();
-                        allowed.dedup();
-                        let mut res = PingoraWebHttpResponse::text(StatusCode::NO_CONTENT, "");
-                        let allow_header = allowed.join(", ");
-                        res.headers.insert(
-                            http::header::ALLOW,
-                            http::HeaderValue::from_str(&allow_header).unwrap(),
-                        );
-                        return res;
-                    }
-                    // If a different method matches this path, return 405 with Allow header
-                    if !allowed.is_empty() {
-                        let allow_header = allowed.join(", ");
-                        let mut res = PingoraWebHttpResponse::text(
-                            StatusCode::METHOD_NOT_ALLOWED,
-                            "Method Not Allowed",
-                        );
-                        res.headers.insert(
-                            http::header::ALLOW,
-                            http::HeaderValue::from_str(&allow_header).unwrap(),
-                        );
-                        return res;
+        let find_result = router.find_route(&req);
+        let (handler, params, matched, info) = match find_result {
+            Some(((h, p, pattern), info)) => (h, p, Some(pattern), Some(info)),
+            None => {
+                let path = req.path();
+                let method = req.method();
+                if let Some(mut location) = router.alternate_path(method, path) {
+                    if let Some(query) = req.inner.uri().query() {
+                        location = format!("{location}?{query}");
                     }
-                    // Fallback 404 handler when no route matches
-                    let h: Arc<dyn Handler> = Arc::new(NotFoundHandler);
-                    (h, Default::default())
+                    return (PingoraWebHttpResponse::permanent_redirect(location), None);
                 }
-            };
+                let mut allowed = router.allowed_methods(path);
+                if *method == Method::OPTIONS {
+                    // For OPTIONS, respond with 204 No Content and Allow header when no explicit route
+                    allowed.push("OPTIONS".to_string());
+                    allowed.sort();
+                    allowed.dedup();
+                    let mut res = PingoraWebHttpResponse::text(StatusCode::NO_CONTENT, "");
+                    response::insert_header(
+                        &mut res.headers,
+                        http::header::ALLOW,
+                        &allowed.join(", "),
+                    );
+                    return (res, None);
+                }
+                // If a different method matches this path, return 405 with Allow header;
+                // when this method matches but its route guards rejected the request, 404
+                if !allowed.is_empty() && !allowed.iter().any(|m| m == method.as_str()) {
+                    let mut res = self
+                        .error_pages
+                        .render(&req, StatusCode::METHOD_NOT_ALLOWED);
+                    response::insert_header(
+                        &mut res.headers,
+                        http::header::ALLOW,
+                        &allowed.join(", "),
+                    );
+                    return (res, None);
+                }
+                // Fallback 404 handler when no route matches
+                let h: Arc<dyn Handler> = Arc::new(NotFoundHandler(self.error_pages.clone()));
+                (h, Default::default(), None, None)
+            }
+        };
 
         // Add route parameters and app-level data to request
-        let req_with_params = req.with_params(params).with_app_data(self.app_data.clone());
+        let mut req_with_params = req.with_params(params).with_app_data(self.app_data.clone());
+        if let Some(info) = info {
+            req_with_params.set_request_share_data(info);
+        }
+        if let Some(pattern) = matched.clone() {
+            if self.route_table_path.as_deref() == Some(&*pattern) {
+                req_with_params.set_request_share_data(Arc::new(self.route_table()));
+            }
+            if self.summary_path.as_deref() == Some(&*pattern) {
+                req_with_params.set_request_share_data(Arc::new(self.startup_summary()));
+            }
+            #[cfg(feature = "openapi")]
+            if self.openapi.spec_path.as_deref() == Some(&*pattern) {
+                let doc = core::openapi::OpenApiDocument(self.openapi_json());
+                req_with_params.set_request_share_data(Arc::new(doc));
+            }
+            req_with_params.set_request_share_data(Arc::new(core::router::MatchedRoute(pattern)));
+        }
+
+        // Middleware sees the body unread; buffer it just before the route handler
+        let handler = if handler.buffer_body() {
+            Arc::new(BufferBody(handler))
+        } else {
+            handler
+        };
 
         // Compose middlewares (onion model) around the route handler
         let entry = compose(&self.middlewares, handler);
 
         // Handle the request and convert any errors to responses
-        let mut response = match entry.handle(req_with_params).await {
+        let response = match entry.handle(req_with_params).await {
             Ok(response) => response,
-            Err(error) => error.into_response(),
+            Err(error) => error.into_response_with_id(Some(request_id)),
         };
+        (response, matched)
+    }
 
-        // Ensure response carries the request-id even on error paths
-        if !response.headers.contains_key("x-request-id") {
-            let _ = response.headers.insert(
-                "x-request-id",
-                http::HeaderValue::from_str(&request_id).unwrap(),
-            );
+    /// Stamp default headers missing from the response, most specific first,
+    /// then set content-length or transfer-encoding based on the body
+    fn finalize_response_headers(
+        &self,
+        response: &mut PingoraWebHttpResponse,
+        router: &Router,
+        matched: Option<&str>,
+    ) {
+        let defaults = router.default_headers(matched).chain(&self.default_headers);
+        for (name, value) in defaults {
+            if !response.headers.contains_key(name) {
+                response.headers.insert(name.clone(), value.clone());
+            }
         }
 
-        // Automatically set content-length or transfer-encoding if not already set
-        self.finalize_response_headers(&mut response);
-        response
-    }
-
-    /// Automatically set content-length or transfer-encoding headers based on response body
-    fn finalize_response_headers(&self, response: &mut PingoraWebHttpResponse) {
         // Only set headers if neither content-length nor transfer-encoding is already set
-        if response.headers.contains_key(http::header::CONTENT_LENGTH)
+        if response.has_raw_framing()
+            || response.headers.contains_key(http::header::CONTENT_LENGTH)
             || response
                 .headers
                 .contains_key(http::header::TRANSFER_ENCODING)
@@ -272,11 +1371,9 @@ impl App {
         match &response.body {
             response::Body::Bytes(bytes) => {
                 // Set content-length for byte bodies
-                let len_s = bytes.len().to_string();
-                let _ = response.headers.insert(
-                    http::header::CONTENT_LENGTH,
-                    http::HeaderValue::from_str(&len_s).unwrap(),
-                );
+                response
+                    .headers
+                    .insert(http::header::CONTENT_LENGTH, bytes.len().into());
             }
             response::Body::Stream(_) => {
                 // Set transfer-encoding for streaming bodies
@@ -297,11 +1394,10 @@ impl Default for App {
 
 use futures::StreamExt;
 use pingora::server::ShutdownWatch;
-use pingora_core::apps::{HttpPersistentSettings, HttpServerOptions, ReusedHttpStream};
-use pingora_http::ResponseHeader;
+use pingora_core::apps::{HttpPersistentSettings, ReusedHttpStream};
 
 #[async_trait]
-impl HttpServerApp for App {
+impl<S: Send + Sync + 'static> HttpServerApp for App<S> {
     async fn process_new_http(
         self: &Arc<Self>,
         mut http: ServerSession,
@@ -311,10 +1407,19 @@ impl HttpServerApp for App {
         if !(http.read_request().await.ok()?) {
             return None;
         }
-        if *shutdown.borrow() {
-            http.set_keepalive(None);
-        } else {
-            http.set_keepalive(Some(60));
+        let draining = *shutdown.borrow();
+        if draining {
+            self.shutdown_stats.mark_started();
+        }
+        self.connection_requests
+            .apply(&self.keep_alive, &mut http, draining);
+
+        // Reject oversized request heads before building anything from them
+        if !self.parser_limits.allows(http.req_header()) {
+            let _ = http
+                .respond_error(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE.as_u16())
+                .await;
+            return None;
         }
 
         // Build module context for HTTP modules
@@ -328,22 +1433,30 @@ impl HttpServerApp for App {
         {
             return None;
         }
+        let annotations = core::Annotations::new();
+        self.exchange_annotations(&mut module_ctx, &annotations, Exchange::Export);
 
         // Build our internal Request and read request body when present
         let reqh = http.req_header();
-        let path = String::from_utf8_lossy(reqh.raw_path()).to_string();
+        let metrics = Arc::new(core::RequestMetrics::default());
+        // "METHOD /path HTTP/1.1"
+        let request_line = reqh.method.as_str().len() + reqh.raw_path().len() + 10;
+        metrics.set_header_bytes_read(core::request_metrics::head_size(
+            request_line,
+            &reqh.headers,
+        ));
 
         // Only need a boolean for HEAD; avoid cloning the Method twice
         let is_head = reqh.method.as_str().eq_ignore_ascii_case("HEAD");
 
-        let mut req = PingoraHttpRequest::new(reqh.method.clone(), path);
-        for (name, value) in reqh.headers.iter() {
-            if let Ok(v) = value.to_str() {
-                req = req.header(name.as_str(), v);
-            }
-        }
+        let mut req = PingoraHttpRequest::from_request_header(reqh)
+            .with_connection(core::ConnectionInfo::from_session(&http));
+        req.set_request_share_data(Arc::new(annotations.clone()));
+        req.set_request_share_data(metrics.clone());
 
-        // Read request body only when hinted by headers (content-length > 0 or transfer-encoding present)
+        // The body stays on the connection until the request asks for it
+        // (only when hinted by headers: content-length > 0 or transfer-encoding present)
+        let mut demands = None;
         if req.method() != Method::HEAD {
             let has_te = req.headers().contains_key("transfer-encoding");
             let has_len = req
@@ -353,102 +1466,380 @@ impl HttpServerApp for App {
                 .and_then(|s| s.parse::<u64>().ok())
                 .unwrap_or(0)
                 > 0;
-            if (has_te || has_len)
-                && let Ok(Some(bytes)) = http.read_request_body().await
-            {
-                req = req.with_body(bytes);
+            if has_te || has_len {
+                let (pending, rx) = core::body::PendingBody::channel();
+                req = req.with_pending_body(pending);
+                demands = Some(rx);
+            }
+        }
+
+        // Route and produce Response (may be file for streaming), serving body
+        // reads meanwhile and watching for the client to go away
+        let client_gone = core::deadline::ClientGone::default();
+        req.set_request_share_data(Arc::new(client_gone.clone()));
+        let scope = core::TaskScope::child_of(&client_gone.0);
+        req.set_request_share_data(Arc::new(scope.clone()));
+        let mut body_read = demands.is_none();
+        let mut body_failed = false;
+        // Watching a reusable HTTP/1.1 connection would eat the first byte of
+        // a pipelined request, so only watch when it won't be reused
+        let mut watch_client = http.as_http1().is_none_or(|h1| !h1.will_keepalive());
+        let mut handling = std::pin::pin!(self.handle(req));
+        let res = loop {
+            // Reading the connection while a body is unread would eat it
+            let watching = watch_client && http.is_body_done();
+            let event = {
+                let demand = async {
+                    match demands.as_mut() {
+                        Some(demands) => demands.recv().await,
+                        None => futures::future::pending().await,
+                    }
+                };
+                let idle = async {
+                    if watching {
+                        http.read_body_or_idle(true).await
+                    } else {
+                        futures::future::pending().await
+                    }
+                };
+                let (demand, idle) = (std::pin::pin!(demand), std::pin::pin!(idle));
+                let waiting = futures::future::select(demand, idle);
+                match futures::future::select(handling.as_mut(), waiting).await {
+                    futures::future::Either::Left((res, _)) => Ok(res),
+                    futures::future::Either::Right((waited, _)) => Err(match waited {
+                        futures::future::Either::Left((demand, _)) => Ok(demand),
+                        futures::future::Either::Right((idle, _)) => Err(idle),
+                    }),
+                }
+            };
+            match event {
+                Ok(res) => break res,
+                Err(Ok(Some(demand))) => {
+                    body_read = true;
+                    let spool = demand.spool.as_deref();
+                    let body = read_request_body(&mut http, demand.limit, spool).await;
+                    metrics.set_body_bytes_read(http.body_bytes_read() as u64);
+                    body_failed = body.is_err();
+                    let _ = demand.reply.send(body);
+                }
+                // The request (and its body handle) is gone
+                Err(Ok(None)) => demands = None,
+                Err(Err(idle)) => {
+                    watch_client = false;
+                    match idle {
+                        // Data after the request on a connection that closes anyway
+                        Err(e) if e.etype() == &pingora::ErrorType::ConnectError => {
+                            http.set_keepalive(None);
+                        }
+                        _ => {
+                            tracing::debug!("Client went away before the response was written");
+                            client_gone.0.cancel();
+                        }
+                    }
+                }
+            }
+        };
+
+        // Don't drain a body we refused or failed to read; close the connection instead
+        if body_failed || (!body_read && res.status == StatusCode::PAYLOAD_TOO_LARGE) {
+            http.set_keepalive(None);
+        }
+
+        let status = res.status;
+        let written = self
+            .write_response(
+                &mut http,
+                &mut module_ctx,
+                &annotations,
+                res,
+                is_head,
+                shutdown,
+            )
+            .await;
+        scope.cancel();
+
+        // Head as filtered by modules, "HTTP/1.1 200 OK" and fields also for
+        // HTTP/2 which compresses them
+        if let Some(head) = http.response_written() {
+            let reason = head.status.canonical_reason().unwrap_or("");
+            let size = core::request_metrics::head_size(13 + reason.len(), &head.headers);
+            metrics.set_header_bytes_written(size);
+        }
+        // Body bytes as sent, i.e. after compression by modules; pingora counts
+        // the HTTP/1 head among them
+        let body_bytes = http.body_bytes_sent() as u64;
+        metrics.set_body_bytes_written(if http.is_http2() {
+            body_bytes
+        } else {
+            body_bytes.saturating_sub(metrics.header_bytes_written())
+        });
+        annotations.set(
+            core::annotations::RESPONSE_BODY_BYTES,
+            metrics.body_bytes_written().to_string(),
+        );
+        if let Err((stage, error)) = &written {
+            // Module failures leave the client connected
+            if !matches!(stage, WriteStage::HeaderFilter | WriteStage::BodyFilter) {
+                client_gone.0.cancel();
             }
+            self.write_failed(&mut http, &annotations, *stage, error, status)
+                .await;
+        }
+        self.exchange_annotations(&mut module_ctx, &annotations, Exchange::Export);
+        annotations.complete();
+        // Drop the connection so the client can tell the response is incomplete
+        written.ok()?;
+
+        if *shutdown.borrow() {
+            self.shutdown_stats.request_drained();
+        }
+
+        let persistent_settings = HttpPersistentSettings::for_session(&http);
+        match http.finish().await {
+            Ok(c) => c.map(|s| ReusedHttpStream::new(s, Some(persistent_settings))),
+            Err(_) => None,
         }
+    }
 
-        // Route and produce Response (may be file for streaming)
-        let res = self.handle(req).await;
+    fn h2_options(&self) -> Option<H2Options> {
+        self.h2_options.clone()
+    }
+    fn server_options(&self) -> Option<&HttpServerOptions> {
+        Some(&self.server_options)
+    }
+
+    async fn http_cleanup(&self) {
+        use core::ShutdownPhase;
+        use core::shutdown::run_phase;
+
+        self.shutdown_stats.mark_started();
+        let steps = &self.shutdown_steps;
+        let mut timed_out = run_phase(steps, ShutdownPhase::StopIntake).await;
+
+        let active = self.tasks.active();
+        let drain_tasks = async {
+            if active > 0 {
+                tracing::info!("Waiting for {} background task(s) to finish", active);
+                if !self.tasks.wait(TASK_DRAIN_TIMEOUT).await {
+                    tracing::warn!(
+                        "{} background task(s) still running after {}s",
+                        self.tasks.active(),
+                        TASK_DRAIN_TIMEOUT.as_secs()
+                    );
+                    self.tasks.report_leaks();
+                }
+            }
+        };
+        let (drained, ()) = futures::join!(run_phase(steps, ShutdownPhase::Drain), drain_tasks);
+        timed_out.extend(drained);
+        let stopped = self.tasks.active();
+
+        // Flush last so it sees what the drained work produced
+        timed_out.extend(run_phase(steps, ShutdownPhase::Flush).await);
+
+        let report = self
+            .shutdown_stats
+            .report(active.saturating_sub(stopped), stopped, timed_out);
+        tracing::info!(
+            requests_drained = report.requests_drained,
+            streams_aborted = report.streams_aborted,
+            tasks_finished = report.tasks_finished,
+            tasks_stopped = report.tasks_stopped,
+            steps_timed_out = report.steps_timed_out.len(),
+            duration_ms = report.duration.as_millis() as u64,
+            clean = report.is_clean(),
+            "Shutdown complete"
+        );
+        if let Some(callback) = &self.on_shutdown_report {
+            callback(&report);
+        }
+    }
+}
+
+impl<S: Send + Sync + 'static> App<S> {
+    /// Run the response through the HTTP modules and write it to the connection
+    async fn write_response(
+        &self,
+        http: &mut ServerSession,
+        module_ctx: &mut pingora_core::modules::http::HttpModuleCtx,
+        annotations: &core::Annotations,
+        res: PingoraWebHttpResponse,
+        is_head: bool,
+        shutdown: &ShutdownWatch,
+    ) -> Result<(), (WriteStage, String)> {
+        // Trailers need HTTP/2; pingora's HTTP/1 writer does not send them yet
+        let trailers = res.trailers.clone().filter(|_| http.is_http2() && !is_head);
+        let abort = res.extensions.get::<response::BodyAbort>().cloned();
 
         // Build and write response header
-        let mut builder = HttpResponse::builder().status(res.status);
-        for (k, v) in res.headers.iter() {
-            builder = builder.header(k, v);
+        let mut resp_header = res.to_response_header();
+        if trailers.is_none() {
+            resp_header.remove_header(&http::header::TRAILER);
         }
-        let (parts, _) = builder.body(Vec::<u8>::new()).unwrap().into_parts();
-        let mut resp_header: ResponseHeader = parts.into();
 
         // Apply response header filter from modules
+        self.exchange_annotations(module_ctx, annotations, Exchange::Import);
         let is_body_empty = matches!(res.body, response::Body::Bytes(ref b) if b.is_empty());
-        if module_ctx
+        module_ctx
             .response_header_filter(&mut resp_header, is_body_empty)
             .await
-            .is_err()
-        {
-            return None;
-        }
+            .map_err(|e| (WriteStage::HeaderFilter, e.to_string()))?;
 
-        if http
-            .write_response_header(Box::new(resp_header))
+        http.write_response_header(Box::new(resp_header))
             .await
-            .is_err()
-        {
-            return None;
-        }
+            .map_err(|e| (WriteStage::Header, e.to_string()))?;
 
         // Write body with streaming support; for HEAD, do not send a body
-        if !is_head {
-            match res.body {
-                response::Body::Bytes(bytes) => {
+        if is_head {
+            return Ok(());
+        }
+        match res.body {
+            response::Body::Bytes(mut bytes) => {
+                let chunk_size = match self.response_chunk_size {
+                    0 => bytes.len(),
+                    size => size,
+                };
+                loop {
+                    let chunk = bytes.split_to(chunk_size.min(bytes.len()));
+                    let end = bytes.is_empty();
                     // Apply response body filter from modules
-                    let mut body_opt = Some(bytes);
-                    if module_ctx
-                        .response_body_filter(&mut body_opt, true)
-                        .is_err()
-                    {
-                        return None;
-                    }
+                    let mut body_opt = Some(chunk);
+                    module_ctx
+                        .response_body_filter(&mut body_opt, end)
+                        .map_err(|e| (WriteStage::BodyFilter, e.to_string()))?;
                     if let Some(filtered_body) = body_opt {
-                        let _ = http.write_response_body(filtered_body, true).await;
+                        http.write_response_body(filtered_body, end && trailers.is_none())
+                            .await
+                            .map_err(|e| (WriteStage::Body, e.to_string()))?;
                     }
+                    if end {
+                        break;
+                    }
+                    // Let other tasks on this worker run between segments
+                    tokio::task::yield_now().await;
                 }
-                response::Body::Stream(mut s) => {
-                    while let Some(chunk) = s.next().await {
-                        // Apply body filter to each chunk
-                        let mut body_opt = Some(chunk);
-                        if module_ctx
-                            .response_body_filter(&mut body_opt, false)
-                            .is_err()
-                        {
-                            break;
+            }
+            response::Body::Stream(mut s) => {
+                // Stop pulling from the producer as soon as the client goes
+                // away rather than at the next failed write; dropping the
+                // stream ends generators waiting for data
+                let mut watch_client = http.is_body_done();
+                loop {
+                    let next = if watch_client {
+                        let event = {
+                            let next = std::pin::pin!(s.next());
+                            let idle = std::pin::pin!(http.read_body_or_idle(true));
+                            match futures::future::select(next, idle).await {
+                                futures::future::Either::Left((chunk, _)) => Ok(chunk),
+                                futures::future::Either::Right((idle, _)) => Err(idle),
+                            }
+                        };
+                        match event {
+                            Ok(chunk) => chunk,
+                            // A pipelined request started; its first byte was consumed
+                            Err(Err(e)) if e.etype() == &pingora::ErrorType::ConnectError => {
+                                watch_client = false;
+                                http.set_keepalive(None);
+                                continue;
+                            }
+                            Err(_) => {
+                                if *shutdown.borrow() {
+                                    self.shutdown_stats.stream_aborted();
+                                }
+                                return Err((WriteStage::Body, "client disconnected".to_string()));
+                            }
                         }
-                        if let Some(filtered_chunk) = body_opt
-                            && http
-                                .write_response_body(filtered_chunk, false)
-                                .await
-                                .is_err()
-                        {
-                            break;
+                    } else {
+                        s.next().await
+                    };
+                    let Some(chunk) = next else {
+                        break;
+                    };
+                    let (chunk, ended) = self.chunk_coalescing.coalesce(chunk, &mut s);
+                    // Apply body filter to each chunk
+                    let mut body_opt = Some(chunk);
+                    module_ctx
+                        .response_body_filter(&mut body_opt, false)
+                        .map_err(|e| (WriteStage::BodyFilter, e.to_string()))?;
+                    if let Some(filtered_chunk) = body_opt
+                        && let Err(e) = http.write_response_body(filtered_chunk, false).await
+                    {
+                        if *shutdown.borrow() {
+                            self.shutdown_stats.stream_aborted();
                         }
+                        return Err((WriteStage::Body, e.to_string()));
                     }
-                    // Final empty chunk to signal end
-                    let mut final_body = Some(bytes::Bytes::new());
-                    if module_ctx
-                        .response_body_filter(&mut final_body, true)
-                        .is_ok()
-                        && let Some(final_chunk) = final_body
-                    {
-                        let _ = http.write_response_body(final_chunk, true).await;
+                    if ended {
+                        break;
                     }
                 }
-            }
-        }
-
-        let persistent_settings = HttpPersistentSettings::for_session(&http);
-        match http.finish().await {
-            Ok(c) => c.map(|s| ReusedHttpStream::new(s, Some(persistent_settings))),
-            Err(_) => None,
+                // Close (HTTP/1.1) or reset (HTTP/2) rather than end a failed body
+                if abort.is_some_and(|abort| abort.is_aborted()) {
+                    http.shutdown().await;
+                    return Err((WriteStage::Aborted, "body aborted by its producer".into()));
+                }
+                // Final empty chunk to signal end
+                let mut final_body = Some(bytes::Bytes::new());
+                module_ctx
+                    .response_body_filter(&mut final_body, true)
+                    .map_err(|e| (WriteStage::BodyFilter, e.to_string()))?;
+                if let Some(final_chunk) = final_body {
+                    http.write_response_body(final_chunk, trailers.is_none())
+                        .await
+                        .map_err(|e| (WriteStage::Body, e.to_string()))?;
+                }
+            }
+        }
+        // Values are read only now, so producers can set them while streaming
+        if let Some(trailers) = trailers {
+            http.write_response_trailers(trailers.values())
+                .await
+                .map_err(|e| (WriteStage::Trailers, e.to_string()))?;
         }
+        Ok(())
     }
 
-    fn h2_options(&self) -> Option<pingora::protocols::http::v2::server::H2Options> {
-        None
-    }
-    fn server_options(&self) -> Option<&HttpServerOptions> {
-        None
+    /// Log, annotate and report a response that could not be written completely
+    async fn write_failed(
+        &self,
+        http: &mut ServerSession,
+        annotations: &core::Annotations,
+        stage: WriteStage,
+        error: &str,
+        status: StatusCode,
+    ) {
+        let reqh = http.req_header();
+        let failure = server::WriteFailure {
+            stage,
+            error: error.to_string(),
+            method: reqh.method.clone(),
+            path: String::from_utf8_lossy(reqh.raw_path()).to_string(),
+            status,
+            body_bytes_sent: http.body_bytes_sent(),
+        };
+        tracing::warn!(
+            stage = stage.as_str(),
+            error = failure.error.as_str(),
+            method = %failure.method,
+            path = failure.path.as_str(),
+            status = status.as_u16(),
+            body_bytes_sent = failure.body_bytes_sent,
+            "Response truncated"
+        );
+        annotations.set(core::annotations::RESPONSE_WRITE_FAILURE, stage.as_str());
+
+        // The stream is still usable when a module failed; say why it ends early
+        if self.truncation_trailer && http.is_http2() && stage == WriteStage::BodyFilter {
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert(
+                TRUNCATED_TRAILER,
+                http::HeaderValue::from_static(stage.as_str()),
+            );
+            let _ = http.write_response_trailers(trailers).await;
+        }
+        if let Some(callback) = &self.on_write_failure {
+            callback(&failure);
+        }
     }
 }
 
@@ -697,4 +2088,1126 @@ mod tests {
             Some("999")
         );
     }
+
+    #[tokio::test]
+    async fn rejected_client_request_ids_are_replaced() {
+        let mut app = App::default();
+        app.get_fn("/id", |req| {
+            req.request_id().unwrap_or_default().to_string()
+        });
+        let client = app.test_client();
+
+        let res = client
+            .get("/id")
+            .header("x-request-id", "abc-123")
+            .send()
+            .await;
+        assert_eq!(res.header("x-request-id"), Some("abc-123"));
+
+        let res = client
+            .get("/id")
+            .header("x-request-id", "\"><img src=x>")
+            .send()
+            .await;
+        let id = res.header("x-request-id").unwrap().to_string();
+        assert_ne!(id, "\"><img src=x>");
+        assert_eq!(res.text(), id);
+
+        app.set_request_id_policy(utils::RequestIdPolicy::Generate);
+        let res = app
+            .test_client()
+            .get("/id")
+            .header("x-request-id", "abc-123")
+            .send()
+            .await;
+        assert_ne!(res.header("x-request-id"), Some("abc-123"));
+    }
+
+    #[tokio::test]
+    async fn request_id_header_and_generator_are_configurable() {
+        let mut app = App::default();
+        app.set_request_id_header(http::HeaderName::from_static("x-correlation-id"));
+        app.set_request_id_generator(utils::RequestIdGenerator::custom(|| "gen-1".into()));
+        app.get_fn("/id", |req| {
+            req.request_id().unwrap_or_default().to_string()
+        });
+        let client = app.test_client();
+
+        let res = client.get("/id").send().await;
+        assert_eq!(res.header("x-correlation-id"), Some("gen-1"));
+        assert_eq!(res.header("x-request-id"), None);
+        assert_eq!(res.text(), "gen-1");
+
+        let res = client
+            .get("/id")
+            .header("x-correlation-id", "abc-123")
+            .send()
+            .await;
+        assert_eq!(res.text(), "abc-123");
+    }
+
+    #[tokio::test]
+    async fn raw_framing_skips_automatic_headers() {
+        let mut app = App::default();
+        app.get_fn("/raw", |_req| {
+            PingoraWebHttpResponse::bytes(StatusCode::OK, "hello").raw_framing()
+        });
+
+        let res = app
+            .handle(PingoraHttpRequest::new(Method::GET, "/raw"))
+            .await;
+        assert!(res.has_raw_framing());
+        assert!(!res.headers.contains_key(http::header::CONTENT_LENGTH));
+        assert!(!res.headers.contains_key(http::header::TRANSFER_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn spawned_tasks_are_tracked() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let rx = std::sync::Mutex::new(Some(rx));
+        let mut router = Router::new();
+        router.get_fn("/spawn", move |req| {
+            let rx = rx.lock().unwrap().take().unwrap();
+            req.spawn(async move {
+                let _ = rx.await;
+            });
+            Ok(PingoraWebHttpResponse::text(StatusCode::OK, "spawned"))
+        });
+        let app = App::new(router);
+
+        let res = app
+            .handle(PingoraHttpRequest::new(Method::GET, "/spawn"))
+            .await;
+        assert_eq!(res.status.as_u16(), 200);
+        assert_eq!(app.tasks.active(), 1);
+
+        tx.send(()).unwrap();
+        assert!(app.tasks.wait(std::time::Duration::from_secs(1)).await);
+    }
+
+    #[tokio::test]
+    async fn typed_state_reaches_handlers() {
+        struct Counter {
+            hits: std::sync::atomic::AtomicUsize,
+        }
+
+        let mut app = App::with_state(Counter {
+            hits: std::sync::atomic::AtomicUsize::new(0),
+        });
+        app.get_with_state("/hit", |_req, State(counter)| {
+            let n = counter
+                .hits
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            (n + 1).to_string()
+        });
+        // Middleware can still reach the state through app data
+        app.get_fn("/peek", |req| {
+            req.get_app_share_data::<Counter>()
+                .map(|c| c.hits.load(std::sync::atomic::Ordering::SeqCst).to_string())
+                .unwrap_or_default()
+        });
+
+        let client = app.test_client();
+        assert_eq!(client.get("/hit").send().await.text(), "1");
+        assert_eq!(client.get("/hit").send().await.text(), "2");
+        assert_eq!(client.get("/peek").send().await.text(), "2");
+        assert_eq!(
+            app.state().hits.load(std::sync::atomic::Ordering::SeqCst),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_redirects_to_registered_path() {
+        let mut app = App::default();
+        app.get_fn("/items", |_req| "items");
+        app.post_fn("/items", |_req| "created");
+
+        // Strict by default
+        let res = app.test_client().get("/items/").send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        app.trailing_slash(TrailingSlash::Redirect);
+        let client = app.test_client();
+        let res = client.get("/items/?page=2").send().await;
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(res.header("location").unwrap(), "/items?page=2");
+        let res = client.post("/items/").send().await;
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+
+        app.trailing_slash(TrailingSlash::Ignore);
+        assert_eq!(
+            app.test_client().get("/items/").send().await.text(),
+            "items"
+        );
+    }
+
+    #[tokio::test]
+    async fn vhosts_route_by_host_header() {
+        let mut api = Router::new();
+        api.get_fn("/", |_req| "api");
+        let mut tenants = Router::new();
+        tenants.get_fn("/", |_req| "tenant");
+
+        let mut app = App::default();
+        app.get_fn("/", |_req| "main");
+        app.vhost("API.example.com", api);
+        app.vhost("*.example.com", tenants);
+
+        let client = app.test_client();
+        let body = |host: &'static str| {
+            let client = &client;
+            async move { client.get("/").header("host", host).send().await.text() }
+        };
+        assert_eq!(body("api.example.com:8080").await, "api");
+        assert_eq!(body("acme.example.com").await, "tenant");
+        assert_eq!(body("a.b.example.com").await, "tenant");
+        assert_eq!(body("example.com").await, "main");
+        assert_eq!(body("other.test").await, "main");
+    }
+
+    #[test]
+    fn server_options_reach_pingora() {
+        let mut app = App::default();
+        assert!(!app.server_options().unwrap().h2c);
+        assert!(app.h2_options().is_none());
+
+        let mut options = HttpServerOptions::default();
+        options.h2c = true;
+        app.set_server_options(options);
+        app.set_h2_options(H2Options::new());
+        assert!(app.server_options().unwrap().h2c);
+        assert!(app.h2_options().is_some());
+        assert!(app.startup_summary().h2c);
+    }
+
+    #[tokio::test]
+    async fn default_headers_fill_in_most_specific_first() {
+        let mut app = App::default();
+        app.default_header("server", "pingora_web");
+        app.default_header("x-api-version", "1");
+        app.default_header_under("/api/v2", "x-api-version", "2");
+        app.default_header_under("/api/v2/", "x-frame-options", "DENY");
+        app.get_fn("/api/v1/items", |_req| "v1");
+        app.get_fn("/api/v2/items/{id}", |_req| "v2");
+        app.get_fn("/api/v2x", |_req| "not v2");
+        app.get_fn("/custom", |_req| {
+            PingoraWebHttpResponse::text(StatusCode::OK, "custom").header("server", "handler")
+        });
+        let client = app.test_client();
+
+        let res = client.get("/api/v1/items").send().await;
+        assert_eq!(res.header("server"), Some("pingora_web"));
+        assert_eq!(res.header("x-api-version"), Some("1"));
+        let res = client.get("/api/v2/items/7").send().await;
+        assert_eq!(res.header("x-api-version"), Some("2"));
+        assert_eq!(res.header("x-frame-options"), Some("DENY"));
+        let res = client.get("/api/v2x").send().await;
+        assert_eq!(res.header("x-api-version"), Some("1"));
+        let res = client.get("/custom").send().await;
+        assert_eq!(res.header("server"), Some("handler"));
+        let res = client.get("/missing").send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.header("server"), Some("pingora_web"));
+    }
+
+    #[test]
+    fn check_reports_all_problems() {
+        let mut app = App::default();
+        app.get(
+            "/assets/{path}",
+            Arc::new(utils::ServeDir::new("/missing/assets")),
+        );
+        app.get_fn("/", |_req| "home");
+        app.warmup(["/?cache=1", "/gone"]);
+        let limits = LimitsConfig::new().request_timeout(std::time::Duration::ZERO);
+        app.use_middleware(LimitsMiddleware::with_config(limits).when(Guard::path_prefix("/api")));
+        app.add_listener(server::Listener::new("bad address"));
+
+        let problems = app.check().unwrap_err().0;
+        assert_eq!(problems.len(), 4, "{problems:#?}");
+        assert!(problems[0].starts_with("listener bad address:"));
+        assert!(problems[1].starts_with("route GET /assets/{path}: root /missing/assets:"));
+        assert_eq!(problems[2], "warmup path /gone matches no GET route");
+        assert!(problems[3].ends_with("request_timeout is 0, every request would time out"));
+
+        let mut app = App::default();
+        app.get_fn("/", |_req| "home");
+        assert_eq!(app.check(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn scoped_tasks_end_with_the_request() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let tx = std::sync::Mutex::new(Some(tx));
+        let mut app = App::default();
+        app.get_fn("/work", move |req| {
+            let tx = tx.lock().unwrap().take();
+            // Left running on purpose: the scope must drop it
+            req.task_scope().spawn(async move {
+                let _tx = tx;
+                std::future::pending::<()>().await
+            });
+            "accepted"
+        });
+        let res = app.test_client().get("/work").send().await;
+        assert_eq!(res.text(), "accepted");
+        let wait = std::time::Duration::from_secs(2);
+        let dropped = tokio::time::timeout(wait, rx).await;
+        assert!(
+            matches!(dropped, Ok(Err(_))),
+            "subtask outlived the request"
+        );
+    }
+
+    #[tokio::test]
+    async fn guarded_routes_fall_through_to_not_found() {
+        let mut app = App::default();
+        let json: Arc<dyn Handler> = Arc::new(core::router::ResultClosure::new(
+            |req: PingoraHttpRequest| String::from_utf8_lossy(req.body()).to_string(),
+        ));
+        app.add_guarded(
+            Method::POST,
+            "/hook",
+            Guard::content_type("application/json"),
+            json,
+        );
+        app.get_fn("/hook", |_req| "ready");
+
+        let client = app.test_client();
+        let res = client
+            .post("/hook")
+            .header("content-type", "application/json")
+            .body("{}")
+            .send()
+            .await;
+        assert_eq!(res.text(), "{}");
+        let res = client.post("/hook").body("a=1").send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = client.request(Method::PUT, "/hook").send().await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn error_pages_negotiate_and_keep_allow() {
+        let mut app = App::default();
+        app.get_fn("/item", |_| "item");
+        app.post_fn("/item", |_| "created");
+        app.set_error_page(StatusCode::METHOD_NOT_ALLOWED, core::NegotiatedErrorPage);
+        app.set_error_page(StatusCode::NOT_FOUND, |req: &PingoraHttpRequest, status| {
+            PingoraWebHttpResponse::text(status, format!("no {}", req.path()))
+        });
+        let client = app.test_client();
+
+        let res = client
+            .request(Method::DELETE, "/item")
+            .header("accept", "application/json")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert!(res.header("allow").is_some_and(|a| a.contains("GET")));
+        assert_eq!(res.text(), r#"{"error":"Method Not Allowed","status":405}"#);
+
+        let res = client
+            .request(Method::DELETE, "/item")
+            .header("accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+            .send()
+            .await;
+        assert!(res.text().contains("<h1>405 Method Not Allowed</h1>"));
+
+        let res = client
+            .request(Method::DELETE, "/item")
+            .header("accept", "image/png")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let res = client.get("/nope").send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.text(), "no /nope");
+    }
+
+    #[test]
+    fn apply_config_keeps_listeners_unless_configured() {
+        let mut app = App::default();
+        app.add_listener(server::Listener::new("127.0.0.1:9000"));
+        app.apply_config(&server::AppConfig::default());
+        let addrs: Vec<&str> = app.listeners.iter().map(|l| l.addr()).collect();
+        assert_eq!(addrs, ["127.0.0.1:9000"]);
+
+        let config = server::AppConfig::from_yaml("listen: [\"127.0.0.1:9001\"]").unwrap();
+        app.apply_config(&config);
+        let addrs: Vec<&str> = app.listeners.iter().map(|l| l.addr()).collect();
+        assert_eq!(addrs, ["127.0.0.1:9001"]);
+    }
+
+    #[tokio::test]
+    async fn warmup_runs_configured_paths() {
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let seen = hits.clone();
+        let mut app = App::default();
+        app.get_fn("/cache", move |req| {
+            assert_eq!(req.headers().get(WARMUP_HEADER).unwrap(), "1");
+            seen.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            "warm"
+        });
+        app.warmup(["/cache", "/missing"]);
+
+        let results = app.run_warmup().await;
+        assert_eq!(
+            results,
+            vec![
+                ("/cache".to_string(), StatusCode::OK),
+                ("/missing".to_string(), StatusCode::NOT_FOUND),
+            ]
+        );
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn matched_route_is_visible_to_middleware_and_handlers() {
+        struct RouteHeader;
+        #[async_trait]
+        impl Middleware for RouteHeader {
+            async fn handle(
+                &self,
+                req: PingoraHttpRequest,
+                next: Arc<dyn Handler>,
+            ) -> Result<PingoraWebHttpResponse, WebError> {
+                let route = req.matched_route().unwrap_or("none").to_string();
+                let res = next.handle(req).await?;
+                Ok(res.header("x-route", route))
+            }
+        }
+
+        let mut app = App::default();
+        app.use_middleware(RouteHeader);
+        app.get_fn("/hi/{name}", |req| {
+            req.matched_route().unwrap_or_default().to_string()
+        });
+
+        let client = app.test_client();
+        let res = client.get("/hi/alice").send().await;
+        assert_eq!(res.text(), "/hi/{name}");
+        assert_eq!(res.header("x-route"), Some("/hi/{name}"));
+
+        let res = client.get("/nope").send().await;
+        assert_eq!(res.header("x-route"), Some("none"));
+    }
+
+    #[tokio::test]
+    async fn shutdown_report_reaches_callback() {
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        let mut app = App::default();
+        app.get_fn("/work", |req| {
+            req.spawn(tokio::time::sleep(std::time::Duration::from_millis(10)));
+            "queued"
+        });
+        app.on_shutdown_report(move |report| sink.lock().unwrap().push(report.clone()));
+        // Registered out of order; phases decide when each runs
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let short = std::time::Duration::from_millis(50);
+        for (phase, name) in [
+            (ShutdownPhase::Flush, "flush"),
+            (ShutdownPhase::StopIntake, "stop"),
+            (ShutdownPhase::Drain, "drain"),
+        ] {
+            let order = order.clone();
+            app.on_shutdown(phase, name, short, move || {
+                let order = order.clone();
+                async move { order.lock().unwrap().push(name) }
+            });
+        }
+        app.on_shutdown(ShutdownPhase::Drain, "stuck", short, || {
+            std::future::pending()
+        });
+
+        app.test_client().get("/work").send().await;
+        app.http_cleanup().await;
+
+        assert_eq!(*order.lock().unwrap(), ["stop", "drain", "flush"]);
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].tasks_finished, 1);
+        assert_eq!(reports[0].tasks_stopped, 0);
+        assert_eq!(reports[0].steps_timed_out, ["stuck"]);
+        assert!(!reports[0].is_clean());
+    }
+
+    #[tokio::test]
+    async fn keep_alive_policy() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let req = b"GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+
+        // Closes after max_requests
+        let mut app = App::default();
+        app.get_fn("/", |_req| "hi");
+        app.set_keep_alive(KeepAlive::new().max_requests(2));
+        let server = crate::test::spawn_server(app);
+
+        let mut client = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+        for close in [false, true] {
+            client.write_all(req).await.unwrap();
+            let res = read_response(&mut client).await.to_ascii_lowercase();
+            assert_eq!(res.contains("connection: close"), close, "{res}");
+        }
+
+        // Closes an idle connection after idle_timeout
+        let mut app = App::default();
+        app.get_fn("/", |_req| "hi");
+        let idle = std::time::Duration::from_secs(1);
+        app.set_keep_alive(KeepAlive::new().idle_timeout(Some(idle)));
+        let server = crate::test::spawn_server(app);
+
+        let mut client = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+        client.write_all(req).await.unwrap();
+        let res = read_response(&mut client).await.to_ascii_lowercase();
+        assert!(!res.contains("connection: close"), "{res}");
+        let wait = std::time::Duration::from_secs(3);
+        let read = tokio::time::timeout(wait, client.read(&mut [0u8; 16])).await;
+        assert_eq!(read.expect("closed while idle").unwrap_or(0), 0);
+
+        // Closes once draining, unless close_on_drain is turned off
+        for close_on_drain in [true, false] {
+            let mut app = App::default();
+            app.get_fn("/", |_req| "hi");
+            app.set_keep_alive(KeepAlive::new().close_on_drain(close_on_drain));
+            let app = Arc::new(app);
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (sock, _) = listener.accept().await.unwrap();
+                let stream = pingora::protocols::l4::stream::Stream::from(sock);
+                let session = ServerSession::new_http1(Box::new(stream));
+                let (_tx, shutdown) = tokio::sync::watch::channel(true);
+                app.process_new_http(session, &shutdown).await;
+            });
+
+            let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+            client.write_all(req).await.unwrap();
+            let res = read_response(&mut client).await.to_ascii_lowercase();
+            assert_eq!(res.contains("connection: close"), close_on_drain, "{res}");
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_pipelined_requests() {
+        use tokio::io::AsyncWriteExt;
+        struct Slow;
+
+        #[async_trait]
+        impl core::Handler for Slow {
+            async fn handle(
+                &self,
+                _req: PingoraHttpRequest,
+            ) -> Result<PingoraWebHttpResponse, error::WebError> {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                Ok(PingoraWebHttpResponse::text(StatusCode::OK, "slow"))
+            }
+        }
+
+        let mut app = App::default();
+        app.get("/slow", Arc::new(Slow));
+        let server = crate::test::spawn_server(app);
+
+        let mut client = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+        let req = b"GET /slow HTTP/1.1\r\nHost: x\r\n\r\n";
+        client.write_all(req).await.unwrap();
+        // The next request arrives while the first is still being handled
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        client.write_all(req).await.unwrap();
+        for _ in 0..2 {
+            let res = read_response(&mut client).await;
+            assert!(res.starts_with("HTTP/1.1 200"), "{res}");
+            assert!(res.ends_with("slow"), "{res}");
+        }
+    }
+
+    #[tokio::test]
+    async fn cancels_requests_when_the_client_leaves() {
+        use tokio::io::AsyncWriteExt;
+        struct Expensive(tokio::sync::mpsc::UnboundedSender<bool>);
+
+        #[async_trait]
+        impl core::Handler for Expensive {
+            async fn handle(
+                &self,
+                req: PingoraHttpRequest,
+            ) -> Result<PingoraWebHttpResponse, error::WebError> {
+                let token = req.cancellation();
+                let wait = std::time::Duration::from_secs(5);
+                let cancelled = tokio::time::timeout(wait, token.cancelled()).await;
+                let _ = self.0.send(cancelled.is_ok() && req.is_cancelled());
+                Ok(PingoraWebHttpResponse::text(StatusCode::OK, "late"))
+            }
+        }
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut app = App::default();
+        app.post("/report", Arc::new(Expensive(tx)));
+        let mut client = connect(Arc::new(app)).await;
+        // Only connections that won't be reused are watched on HTTP/1.1
+        let req = "POST /report HTTP/1.1\r\nHost: x\r\nConnection: close\r\n";
+        client
+            .write_all(format!("{req}Content-Length: 2\r\n\r\nhi").as_bytes())
+            .await
+            .unwrap();
+        drop(client);
+        assert_eq!(rx.recv().await, Some(true));
+    }
+
+    #[tokio::test]
+    async fn drops_streams_when_the_client_leaves() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        /// Signals when the stream holding it is dropped
+        struct Dropped(Option<tokio::sync::oneshot::Sender<()>>);
+        impl Drop for Dropped {
+            fn drop(&mut self) {
+                let _ = self.0.take().unwrap().send(());
+            }
+        }
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let tx = std::sync::Mutex::new(Some(tx));
+        let mut app = App::default();
+        app.get_fn("/events", move |_| {
+            let guard = Dropped(tx.lock().unwrap().take());
+            // One event, then nothing for a long time
+            let events = futures::stream::once(async { bytes::Bytes::from("event\n") })
+                .chain(futures::stream::pending())
+                .map(move |chunk| {
+                    let _ = &guard;
+                    chunk
+                });
+            PingoraWebHttpResponse::stream(StatusCode::OK, Box::pin(events))
+        });
+        let mut client = connect(Arc::new(app)).await;
+        client
+            .write_all(b"GET /events HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 256];
+        let mut seen = Vec::new();
+        while !String::from_utf8_lossy(&seen).contains("event") {
+            let n = client.read(&mut buf).await.unwrap();
+            seen.extend_from_slice(&buf[..n]);
+        }
+        drop(client);
+        let dropped = tokio::time::timeout(std::time::Duration::from_secs(5), rx).await;
+        assert!(dropped.is_ok(), "stream kept running after the client left");
+    }
+
+    /// Serve one client connection with `app` through `process_new_http`
+    async fn connect(app: Arc<App>) -> tokio::net::TcpStream {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (sock, _) = listener.accept().await.unwrap();
+            let stream = pingora::protocols::l4::stream::Stream::from(sock);
+            let session = ServerSession::new_http1(Box::new(stream));
+            let (_tx, shutdown) = tokio::sync::watch::channel(false);
+            app.process_new_http(session, &shutdown).await;
+        });
+        tokio::net::TcpStream::connect(addr).await.unwrap()
+    }
+
+    /// Read one response with a content-length body
+    async fn read_response(client: &mut tokio::net::TcpStream) -> String {
+        use tokio::io::AsyncReadExt;
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        loop {
+            let n =
+                tokio::time::timeout(std::time::Duration::from_secs(2), client.read(&mut chunk))
+                    .await
+                    .expect("response in time")
+                    .unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buf).to_string();
+            let complete = text.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                head.lines()
+                    .find_map(|l| {
+                        l.to_ascii_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .is_some_and(|len| body.len() >= len)
+            });
+            if n == 0 || complete {
+                return text;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn trailers_follow_body_on_http2() {
+        let mut app = App::default();
+        app.get_fn("/grpc", |_req| {
+            let (tx, mut res) = PingoraWebHttpResponse::from_channel(1);
+            let trailers = res.trailers(["grpc-status"]);
+            tokio::spawn(async move {
+                tx.send(bytes::Bytes::from("message")).await.unwrap();
+                trailers.set("grpc-status", "0");
+            });
+            res
+        });
+        let app = Arc::new(app);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use pingora::protocols::http::v2::server;
+            let (sock, _) = listener.accept().await.unwrap();
+            let stream = pingora::protocols::l4::stream::Stream::from(sock);
+            let mut conn = server::handshake(Box::new(stream), None).await.unwrap();
+            let digest = Arc::new(pingora::protocols::Digest::default());
+            while let Ok(Some(h2)) =
+                server::HttpSession::from_h2_conn(&mut conn, digest.clone()).await
+            {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    let (_tx, shutdown) = tokio::sync::watch::channel(false);
+                    app.process_new_http(ServerSession::new_http2(h2), &shutdown)
+                        .await;
+                });
+            }
+        });
+
+        let sock = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (client, conn) = h2::client::handshake(sock).await.unwrap();
+        tokio::spawn(conn);
+        let mut client = client.ready().await.unwrap();
+        let req = http::Request::get(format!("http://{addr}/grpc"))
+            .body(())
+            .unwrap();
+        let (res, _) = client.send_request(req, true).unwrap();
+        let res = res.await.unwrap();
+        assert_eq!(res.headers().get("trailer").unwrap(), "grpc-status");
+
+        let mut body = res.into_body();
+        let mut received = Vec::new();
+        while let Some(chunk) = body.data().await {
+            received.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(received, b"message");
+        let trailers = body.trailers().await.unwrap().expect("trailers");
+        assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+    }
+
+    #[tokio::test]
+    async fn channel_producer_stops_when_client_disconnects() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (stopped_tx, stopped) = tokio::sync::oneshot::channel::<usize>();
+        let stopped_tx = std::sync::Mutex::new(Some(stopped_tx));
+        let mut app = App::default();
+        app.get_fn("/events", move |_req| {
+            let (tx, res) = PingoraWebHttpResponse::from_channel(1);
+            let stopped_tx = stopped_tx.lock().unwrap().take();
+            tokio::spawn(async move {
+                let mut sent = 0;
+                while tx.send(bytes::Bytes::from("tick\n")).await.is_ok() {
+                    sent += 1;
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                }
+                if let Some(stopped_tx) = stopped_tx {
+                    let _ = stopped_tx.send(sent);
+                }
+            });
+            res
+        });
+
+        let mut client = connect(Arc::new(app)).await;
+        client
+            .write_all(b"GET /events HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0u8; 256];
+        let n = client.read(&mut buf).await.unwrap();
+        assert!(String::from_utf8_lossy(&buf[..n]).starts_with("HTTP/1.1 200"));
+        drop(client);
+
+        let sent = tokio::time::timeout(std::time::Duration::from_secs(5), stopped)
+            .await
+            .expect("producer cancelled")
+            .unwrap();
+        assert!(sent > 0);
+    }
+
+    #[tokio::test]
+    async fn request_body_is_read_on_demand() {
+        use tokio::io::AsyncWriteExt;
+
+        struct Peek;
+        #[async_trait::async_trait]
+        impl core::Handler for Peek {
+            async fn handle(
+                &self,
+                req: PingoraHttpRequest,
+            ) -> Result<PingoraWebHttpResponse, WebError> {
+                Ok(PingoraWebHttpResponse::ok(format!(
+                    "pending={}",
+                    req.is_body_pending()
+                )))
+            }
+
+            fn buffer_body(&self) -> bool {
+                false
+            }
+        }
+
+        let mut app = App::default();
+        app.use_middleware(LimitsMiddleware::with_config(
+            LimitsConfig::new().max_body_size(16),
+        ));
+        app.post("/peek", Arc::new(Peek));
+        app.post_fn("/echo", |req| {
+            String::from_utf8_lossy(req.body()).to_string()
+        });
+        let app = Arc::new(app);
+
+        // A handler that never reads the body answers without waiting for it
+        let mut client = connect(app.clone()).await;
+        client
+            .write_all(b"POST /peek HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_response(&mut client).await.ends_with("pending=true"));
+
+        let mut client = connect(app.clone()).await;
+        client
+            .write_all(b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello")
+            .await
+            .unwrap();
+        assert!(read_response(&mut client).await.ends_with("\r\n\r\nhello"));
+
+        // Oversized uploads are refused from the declared length, before any body is sent
+        let mut client = connect(app).await;
+        client
+            .write_all(b"POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 1000000\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_response(&mut client).await.starts_with("HTTP/1.1 413"));
+    }
+
+    #[tokio::test]
+    async fn multi_frame_uploads_are_read_completely() {
+        use tokio::io::AsyncWriteExt;
+
+        let mut app = App::default();
+        app.post_fn("/digest", |req| {
+            let body = req.body();
+            let sum: u64 = body.iter().map(|b| *b as u64).sum();
+            format!("{} {sum}", body.len())
+        });
+        let app = Arc::new(app);
+
+        // A content-length body larger than one read buffer, sent in pauses
+        let payload: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let expected = format!(
+            "{} {}",
+            payload.len(),
+            payload.iter().map(|b| *b as u64).sum::<u64>()
+        );
+        let mut client = connect(app.clone()).await;
+        let head = format!(
+            "POST /digest HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n",
+            payload.len()
+        );
+        client.write_all(head.as_bytes()).await.unwrap();
+        for part in payload.chunks(64 * 1024) {
+            client.write_all(part).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let res = read_response(&mut client).await;
+        assert!(res.ends_with(&format!("\r\n\r\n{expected}")), "{res}");
+
+        // Many small chunks of a chunked upload
+        let mut client = connect(app).await;
+        client
+            .write_all(b"POST /digest HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n")
+            .await
+            .unwrap();
+        for _ in 0..50 {
+            client
+                .write_all(b"4\r\n\x01\x01\x01\x01\r\n")
+                .await
+                .unwrap();
+        }
+        client.write_all(b"0\r\n\r\n").await.unwrap();
+        assert!(
+            read_response(&mut client)
+                .await
+                .ends_with("\r\n\r\n200 200")
+        );
+    }
+
+    #[tokio::test]
+    async fn annotations_reach_bridged_modules() {
+        use pingora_core::modules::http::{HttpModuleBuilder, Module};
+        use tokio::io::AsyncWriteExt;
+
+        /// Echoes the `tag` annotation as a header and publishes the request path
+        #[derive(Default)]
+        struct Tagger {
+            path: String,
+            tag: Option<String>,
+        }
+
+        #[async_trait]
+        impl HttpModule for Tagger {
+            fn as_any(&self) -> &dyn std::any::Any {
+                self
+            }
+            fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+                self
+            }
+            async fn request_header_filter(
+                &mut self,
+                req: &mut pingora_http::RequestHeader,
+            ) -> pingora::Result<()> {
+                self.path = req.uri.path().to_string();
+                Ok(())
+            }
+            async fn response_header_filter(
+                &mut self,
+                resp: &mut pingora_http::ResponseHeader,
+                _end_of_stream: bool,
+            ) -> pingora::Result<()> {
+                if let Some(tag) = &self.tag {
+                    resp.insert_header("x-tag", tag.as_str())?;
+                }
+                Ok(())
+            }
+        }
+
+        impl core::AnnotatedModule for Tagger {
+            fn import_annotations(&mut self, annotations: &core::Annotations) {
+                self.tag = annotations.get("tag");
+            }
+            fn export_annotations(&self, annotations: &core::Annotations) {
+                annotations.set("module.path", self.path.as_str());
+            }
+        }
+
+        struct TaggerBuilder;
+        impl HttpModuleBuilder for TaggerBuilder {
+            fn init(&self) -> Module {
+                Box::new(Tagger::default())
+            }
+        }
+
+        let mut app = App::default();
+        app.add_http_module(Box::new(TaggerBuilder));
+        app.bridge_annotations::<Tagger>();
+        app.get_fn("/tagged", |req| {
+            let annotations = req.annotations().unwrap();
+            annotations.set("tag", "blue");
+            annotations.get("module.path").unwrap_or_default()
+        });
+        let app = Arc::new(app);
+
+        let mut client = connect(app).await;
+        client
+            .write_all(b"GET /tagged HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let res = read_response(&mut client).await;
+        assert!(res.to_ascii_lowercase().contains("x-tag: blue"), "{res}");
+        assert!(res.ends_with("\r\n\r\n/tagged"), "{res}");
+    }
+
+    #[tokio::test]
+    async fn failed_body_filters_close_the_connection_and_are_reported() {
+        use pingora_core::modules::http::{HttpModule, HttpModuleBuilder, Module};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        /// Fails on the second body chunk
+        #[derive(Default)]
+        struct Flaky(usize);
+
+        #[async_trait]
+        impl HttpModule for Flaky {
+            fn as_any(&self) -> &dyn std::any::Any {
+                self
+            }
+            fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+                self
+            }
+            fn response_body_filter(
+                &mut self,
+                _body: &mut Option<bytes::Bytes>,
+                _end_of_stream: bool,
+            ) -> pingora::Result<()> {
+                self.0 += 1;
+                if self.0 > 1 {
+                    return pingora::Error::e_explain(pingora::ErrorType::InternalError, "flaky");
+                }
+                Ok(())
+            }
+        }
+
+        struct FlakyBuilder;
+        impl HttpModuleBuilder for FlakyBuilder {
+            fn init(&self) -> Module {
+                Box::new(Flaky::default())
+            }
+        }
+
+        let registry = prometheus::Registry::new();
+        let failures = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = failures.clone();
+        let mut app = App::default();
+        app.add_http_module(Box::new(FlakyBuilder));
+        app.use_middleware(MetricsMiddleware::with_registry(&registry).unwrap());
+        app.on_write_failure(move |f| sink.lock().unwrap().push(f.clone()));
+        app.get_fn("/feed", |_req| {
+            let chunks = [b"one\n", b"two\n"].map(|c| bytes::Bytes::from_static(c));
+            PingoraWebHttpResponse::stream(StatusCode::OK, futures::stream::iter(chunks).boxed())
+        });
+
+        let mut client = connect(Arc::new(app)).await;
+        client
+            .write_all(b"GET /feed HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(2),
+            client.read_to_end(&mut buf),
+        )
+        .await
+        .expect("connection closed")
+        .unwrap();
+        let res = String::from_utf8_lossy(&buf);
+        assert!(res.contains("one\n"), "{res}");
+        assert!(!res.ends_with("0\r\n\r\n"), "{res}");
+
+        let failures = failures.lock().unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].stage, WriteStage::BodyFilter);
+        assert_eq!(failures[0].path, "/feed");
+
+        let families = registry.gather();
+        let counted = families
+            .iter()
+            .find(|f| f.get_name() == "pingora_web_response_write_failures_total")
+            .unwrap();
+        assert_eq!(counted.get_metric()[0].get_counter().get_value(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn large_bodies_are_written_in_segments() {
+        use tokio::io::AsyncWriteExt;
+
+        let body: String = (0..5000)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        let expected = body.clone();
+        let mut app = App::default();
+        app.set_response_chunk_size(1000);
+        app.get_fn("/big", move |_req| body.clone());
+
+        let mut client = connect(Arc::new(app)).await;
+        client
+            .write_all(b"GET /big HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let res = read_response(&mut client).await;
+        assert!(res.to_ascii_lowercase().contains("content-length: 5000"));
+        assert!(res.ends_with(&expected));
+    }
+
+    #[tokio::test]
+    async fn compressed_size_is_recorded_after_the_body_is_sent() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let registry = prometheus::Registry::new();
+        let mut app = App::default();
+        app.add_http_module(ResponseCompressionBuilder::enable(6));
+        app.use_middleware(MetricsMiddleware::with_registry(&registry).unwrap());
+        app.get_fn("/big", |_req| "pingora ".repeat(1000));
+        let app = Arc::new(app);
+
+        let mut client = connect(app).await;
+        client
+            .write_all(b"GET /big HTTP/1.1\r\nHost: x\r\nAccept-Encoding: gzip\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        while !buf.ends_with(b"0\r\n\r\n") {
+            let n =
+                tokio::time::timeout(std::time::Duration::from_secs(2), client.read(&mut chunk))
+                    .await
+                    .expect("response in time")
+                    .unwrap();
+            assert!(n > 0, "connection closed early");
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        let head = String::from_utf8_lossy(&buf).to_ascii_lowercase();
+        assert!(head.contains("content-encoding: gzip"), "{head}");
+
+        let families = registry.gather();
+        let sent = families
+            .iter()
+            .find(|f| f.get_name() == "pingora_web_response_body_bytes_sent")
+            .unwrap();
+        let metric = &sent.get_metric()[0];
+        assert!(
+            metric
+                .get_label()
+                .iter()
+                .any(|l| l.get_name() == "encoding" && l.get_value() == "gzip")
+        );
+        let bytes = metric.get_histogram().get_sample_sum();
+        assert!(bytes > 0.0 && bytes < 8000.0, "{bytes}");
+    }
+
+    #[tokio::test]
+    async fn chunked_upload_is_cut_off_at_body_limit() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut app = App::default();
+        app.use_middleware(LimitsMiddleware::with_config(
+            LimitsConfig::new().max_body_size(8),
+        ));
+        app.post_fn("/echo", |req| {
+            String::from_utf8_lossy(req.body()).to_string()
+        });
+        let app = Arc::new(app);
+
+        let mut client = connect(app.clone()).await;
+        client
+            .write_all(b"POST /echo HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n")
+            .await
+            .unwrap();
+        // Stream chunks without ever finishing; the server must answer once the limit is passed
+        client.write_all(b"6\r\nabcdef\r\n").await.unwrap();
+        client.write_all(b"6\r\nghijkl\r\n").await.unwrap();
+        let res = read_response(&mut client).await;
+        assert!(res.starts_with("HTTP/1.1 413"), "{res}");
+
+        // The connection is closed rather than drained
+        let mut rest = Vec::new();
+        let closed = tokio::time::timeout(
+            std::time::Duration::from_secs(2),
+            client.read_to_end(&mut rest),
+        )
+        .await;
+        assert!(closed.is_ok());
+
+        // Within the limit, chunks are joined
+        let mut client = connect(app).await;
+        client
+            .write_all(
+                b"POST /echo HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n",
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        client.write_all(b"2\r\nde\r\n0\r\n\r\n").await.unwrap();
+        assert!(read_response(&mut client).await.ends_with("\r\n\r\nabcde"));
+    }
 }
@@ -0,0 +1,70 @@
+/// Log an event with the request's id, method, path, route, and principal attached
+///
+/// Fields follow the message using `tracing` field syntax, so the event carries
+/// request context even when emitted outside `TracingMiddleware`'s span
+/// (e.g. from a spawned task).
+///
+/// # Example
+/// ```
+/// use pingora_web::{Method, PingoraHttpRequest, log_req_info};
+///
+/// let req = PingoraHttpRequest::new(Method::GET, "/users/7");
+/// log_req_info!(req, "user loaded", user_id = 7, cached = true);
+/// ```
+#[macro_export]
+macro_rules! log_req {
+    ($level:expr, $req:expr, $msg:literal $(, $($fields:tt)+)?) => {{
+        let __req: &$crate::PingoraHttpRequest = &$req;
+        let __principal = __req.principal();
+        $crate::__tracing::event!(
+            $level,
+            request_id = __req.request_id().unwrap_or(""),
+            method = __req.method().as_str(),
+            path = __req.path(),
+            route = __req.matched_route().unwrap_or(""),
+            principal = __principal.as_ref().map(|p| p.as_str()).unwrap_or(""),
+            $($($fields)+,)?
+            $msg
+        )
+    }};
+}
+
+/// `log_req!` at TRACE level
+#[macro_export]
+macro_rules! log_req_trace {
+    ($req:expr, $($arg:tt)+) => {
+        $crate::log_req!($crate::__tracing::Level::TRACE, $req, $($arg)+)
+    };
+}
+
+/// `log_req!` at DEBUG level
+#[macro_export]
+macro_rules! log_req_debug {
+    ($req:expr, $($arg:tt)+) => {
+        $crate::log_req!($crate::__tracing::Level::DEBUG, $req, $($arg)+)
+    };
+}
+
+/// `log_req!` at INFO level
+#[macro_export]
+macro_rules! log_req_info {
+    ($req:expr, $($arg:tt)+) => {
+        $crate::log_req!($crate::__tracing::Level::INFO, $req, $($arg)+)
+    };
+}
+
+/// `log_req!` at WARN level
+#[macro_export]
+macro_rules! log_req_warn {
+    ($req:expr, $($arg:tt)+) => {
+        $crate::log_req!($crate::__tracing::Level::WARN, $req, $($arg)+)
+    };
+}
+
+/// `log_req!` at ERROR level
+#[macro_export]
+macro_rules! log_req_error {
+    ($req:expr, $($arg:tt)+) => {
+        $crate::log_req!($crate::__tracing::Level::ERROR, $req, $($arg)+)
+    };
+}
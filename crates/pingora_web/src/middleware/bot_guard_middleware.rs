@@ -0,0 +1,422 @@
+use async_trait::async_trait;
+use http::{Method, StatusCode, header};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::Middleware;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+use crate::utils::Signer;
+
+/// Cookie set by the page `JsChallengeMiddleware` serves
+pub const JS_CHALLENGE_COOKIE: &str = "__pw_challenge";
+
+/// Events per key within a fixed window
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+/// Expiring entries that are pruned as the table grows
+struct Expiring<K> {
+    entries: HashMap<K, Instant>,
+    prune_at: usize,
+}
+
+impl<K: Eq + Hash> Default for Expiring<K> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            prune_at: 1024,
+        }
+    }
+}
+
+impl<K: Eq + Hash> Expiring<K> {
+    fn insert(&mut self, key: K, until: Instant) {
+        self.entries.insert(key, until);
+        if self.entries.len() > self.prune_at {
+            let now = Instant::now();
+            self.entries.retain(|_, until| *until > now);
+            self.prune_at = (self.entries.len() * 2).max(1024);
+        }
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.entries
+            .get(key)
+            .is_some_and(|until| *until > Instant::now())
+    }
+}
+
+#[derive(Default)]
+struct GuardState {
+    banned_ips: Mutex<Expiring<IpAddr>>,
+    banned_agents: Mutex<Expiring<String>>,
+    ip_anomalies: Mutex<HashMap<IpAddr, Window>>,
+    agent_anomalies: Mutex<HashMap<String, Window>>,
+}
+
+/// Count an event for `key`; true when it pushes the key over `limit`
+fn count<K: Eq + Hash>(
+    table: &Mutex<HashMap<K, Window>>,
+    key: K,
+    (limit, window): (u32, Duration),
+) -> bool {
+    let mut table = table.lock().unwrap();
+    let now = Instant::now();
+    if table.len() > 4096 {
+        table.retain(|_, w| now.duration_since(w.start) < window);
+    }
+    let entry = table.entry(key).or_insert(Window {
+        start: now,
+        count: 0,
+    });
+    if now.duration_since(entry.start) >= window {
+        *entry = Window {
+            start: now,
+            count: 0,
+        };
+    }
+    entry.count += 1;
+    entry.count > limit
+}
+
+/// Temporary bans for misbehaving clients, fed by honeypots and anomaly counters
+///
+/// The guard is a middleware answering banned clients with 403 before any
+/// handler runs. Clients get banned by requesting a `honeypot()` route, or
+/// once their anomalies within a window exceed a limit: unknown paths (404s)
+/// count by default, and handlers report others (failed logins, invalid
+/// tokens) with `record_anomaly`. Clients are told apart by peer IP and,
+/// when `user_agent_anomalies` is set, by `User-Agent`.
+///
+/// Clones share their bans and counters. Behind a proxy the peer is the
+/// proxy, so only use the guard on directly exposed listeners.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, BotGuard};
+/// use std::time::Duration;
+///
+/// let guard = BotGuard::new()
+///     .ban_for(Duration::from_secs(3600))
+///     .ip_anomalies(20, Duration::from_secs(60));
+/// let mut app = App::default();
+/// app.get("/wp-login.php", guard.honeypot());
+/// app.use_middleware(guard);
+/// ```
+#[derive(Clone)]
+pub struct BotGuard {
+    ban_for: Duration,
+    ip_limit: Option<(u32, Duration)>,
+    agent_limit: Option<(u32, Duration)>,
+    state: Arc<GuardState>,
+}
+
+impl Default for BotGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BotGuard {
+    /// Ban for 10 minutes after more than 50 anomalies per IP in a minute
+    pub fn new() -> Self {
+        Self {
+            ban_for: Duration::from_secs(600),
+            ip_limit: Some((50, Duration::from_secs(60))),
+            agent_limit: None,
+            state: Arc::default(),
+        }
+    }
+
+    /// Set how long bans last
+    pub fn ban_for(mut self, duration: Duration) -> Self {
+        self.ban_for = duration;
+        self
+    }
+
+    /// Ban an IP after more than `limit` anomalies within `window`
+    pub fn ip_anomalies(mut self, limit: u32, window: Duration) -> Self {
+        self.ip_limit = Some((limit, window));
+        self
+    }
+
+    /// Block a `User-Agent` after more than `limit` anomalies within `window`,
+    /// across all IPs (off by default; agents are shared by many clients)
+    pub fn user_agent_anomalies(mut self, limit: u32, window: Duration) -> Self {
+        self.agent_limit = Some((limit, window));
+        self
+    }
+
+    pub fn ban(&self, ip: IpAddr) {
+        let until = Instant::now() + self.ban_for;
+        self.state.banned_ips.lock().unwrap().insert(ip, until);
+    }
+
+    pub fn unban(&self, ip: IpAddr) {
+        self.state.banned_ips.lock().unwrap().entries.remove(&ip);
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.state.banned_ips.lock().unwrap().contains(&ip)
+    }
+
+    /// Count a suspicious request, banning its client when over a limit
+    pub fn record_anomaly(&self, req: &PingoraHttpRequest) {
+        self.record(req.peer_addr().map(|a| a.ip()), user_agent(req));
+    }
+
+    fn record(&self, ip: Option<IpAddr>, agent: Option<String>) {
+        if let (Some(ip), Some(limit)) = (ip, self.ip_limit)
+            && count(&self.state.ip_anomalies, ip, limit)
+        {
+            tracing::warn!(%ip, "Banning client over its anomaly limit");
+            self.ban(ip);
+        }
+        if let (Some(agent), Some(limit)) = (agent, self.agent_limit)
+            && count(&self.state.agent_anomalies, agent.clone(), limit)
+        {
+            tracing::warn!(user_agent = %agent, "Blocking user agent over its anomaly limit");
+            let until = Instant::now() + self.ban_for;
+            self.state
+                .banned_agents
+                .lock()
+                .unwrap()
+                .insert(agent, until);
+        }
+    }
+
+    fn is_blocked(&self, req: &PingoraHttpRequest) -> bool {
+        if let Some(peer) = req.peer_addr()
+            && self.is_banned(peer.ip())
+        {
+            return true;
+        }
+        self.agent_limit.is_some()
+            && user_agent(req)
+                .is_some_and(|a| self.state.banned_agents.lock().unwrap().contains(&a))
+    }
+
+    /// A handler banning whoever requests it, for routes no real user visits
+    ///
+    /// Answers 404 so scanners can't tell the route is a trap.
+    pub fn honeypot(&self) -> Arc<dyn Handler> {
+        Arc::new(Honeypot(self.clone()))
+    }
+}
+
+fn user_agent(req: &PingoraHttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+struct Honeypot(BotGuard);
+
+#[async_trait]
+impl Handler for Honeypot {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        if let Some(peer) = req.peer_addr() {
+            tracing::warn!(ip = %peer.ip(), path = req.path(), "Honeypot hit, banning client");
+            self.0.ban(peer.ip());
+        }
+        Ok(PingoraWebHttpResponse::text(
+            StatusCode::NOT_FOUND,
+            "Not Found",
+        ))
+    }
+}
+
+#[async_trait]
+impl Middleware for BotGuard {
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        if self.is_blocked(&req) {
+            return Ok(PingoraWebHttpResponse::text(
+                StatusCode::FORBIDDEN,
+                "Forbidden",
+            ));
+        }
+        let ip = req.peer_addr().map(|a| a.ip());
+        let agent = user_agent(&req);
+        let res = next.handle(req).await?;
+        if res.status == StatusCode::NOT_FOUND {
+            self.record(ip, agent);
+        }
+        Ok(res)
+    }
+}
+
+/// Lets through only clients that run JavaScript
+///
+/// Requests without a valid `__pw_challenge` cookie get a small page whose
+/// script sets the cookie and reloads; GET and HEAD only, other methods are
+/// refused with 403. The cookie is signed and bound to the client IP. This
+/// stops simple scripted clients, not headless browsers or bots that parse
+/// the page for the token.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, JsChallengeMiddleware};
+///
+/// let mut app = App::default();
+/// app.use_middleware(JsChallengeMiddleware::new(b"at least 32 bytes of random secret"));
+/// ```
+pub struct JsChallengeMiddleware {
+    signer: Signer,
+    ttl: Duration,
+}
+
+impl JsChallengeMiddleware {
+    /// Sign challenge cookies with `secret`, valid for a day
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            signer: Signer::new(secret),
+            ttl: Duration::from_secs(24 * 3600),
+        }
+    }
+
+    /// Set how long a passed challenge stays valid
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn client(req: &PingoraHttpRequest) -> String {
+        req.peer_addr()
+            .map(|a| a.ip().to_string())
+            .unwrap_or_default()
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    fn passed(&self, req: &PingoraHttpRequest) -> bool {
+        let Some(payload) = req
+            .cookie(JS_CHALLENGE_COOKIE)
+            .and_then(|token| self.signer.verify(token))
+        else {
+            return false;
+        };
+        let payload = String::from_utf8_lossy(&payload);
+        let Some((client, expires)) = payload.rsplit_once('|') else {
+            return false;
+        };
+        client == Self::client(req) && expires.parse().is_ok_and(|e: u64| e > Self::now())
+    }
+
+    fn challenge(&self, req: &PingoraHttpRequest) -> PingoraWebHttpResponse {
+        let expires = Self::now() + self.ttl.as_secs();
+        let token = self
+            .signer
+            .sign(format!("{}|{expires}", Self::client(req)).as_bytes());
+        let page = format!(
+            "<!doctype html><html><head><meta name=\"robots\" content=\"noindex\"></head>\
+             <body><noscript>Please enable JavaScript to continue.</noscript><script>\
+             document.cookie=\"{JS_CHALLENGE_COOKIE}={token}; path=/; max-age={}; SameSite=Lax\";\
+             location.reload();</script></body></html>",
+            self.ttl.as_secs()
+        );
+        let mut res = PingoraWebHttpResponse::html(StatusCode::FORBIDDEN, page);
+        res.set_header(header::CACHE_CONTROL, "no-store");
+        res
+    }
+}
+
+#[async_trait]
+impl Middleware for JsChallengeMiddleware {
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        if self.passed(&req) {
+            return next.handle(req).await;
+        }
+        if matches!(*req.method(), Method::GET | Method::HEAD) {
+            Ok(self.challenge(&req))
+        } else {
+            Ok(PingoraWebHttpResponse::text(
+                StatusCode::FORBIDDEN,
+                "Forbidden",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use std::net::SocketAddr;
+
+    #[tokio::test]
+    async fn bans_scanners_and_challenges_clients() {
+        let scanner: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+        let user: SocketAddr = "198.51.100.2:5000".parse().unwrap();
+        let guard = BotGuard::new().ip_anomalies(2, Duration::from_secs(60));
+        let mut app = App::default();
+        app.get("/.env", guard.honeypot());
+        app.get_fn("/", |_| "home");
+        app.use_middleware(guard.clone());
+        let client = app.test_client();
+
+        // Unknown paths count; the third one within the window bans
+        for status in [
+            StatusCode::NOT_FOUND,
+            StatusCode::NOT_FOUND,
+            StatusCode::NOT_FOUND,
+        ] {
+            let res = client.get("/admin").peer_addr(scanner).send().await;
+            assert_eq!(res.status(), status);
+        }
+        let res = client.get("/").peer_addr(scanner).send().await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = client.get("/.env").peer_addr(user).send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(guard.is_banned(user.ip()));
+        guard.unban(user.ip());
+        let res = client.get("/").peer_addr(user).send().await;
+        assert_eq!(res.text(), "home");
+
+        let mut app = App::default();
+        app.use_middleware(JsChallengeMiddleware::new("secret"));
+        app.get_fn("/", |_| "home");
+        let client = app.test_client();
+        let res = client.get("/").peer_addr(user).send().await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let page = res.text();
+        let start = page.find(JS_CHALLENGE_COOKIE).unwrap();
+        let cookie = &page[start..page[start..].find(';').unwrap() + start];
+
+        let res = client
+            .get("/")
+            .peer_addr(user)
+            .header("cookie", cookie)
+            .send()
+            .await;
+        assert_eq!(res.text(), "home");
+        // Bound to the IP it was issued to
+        let res = client
+            .get("/")
+            .peer_addr(scanner)
+            .header("cookie", cookie)
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+}
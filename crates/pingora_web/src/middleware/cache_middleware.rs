@@ -0,0 +1,501 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Middleware;
+use crate::core::response::Body;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Response header telling whether the cache answered: `HIT` or `MISS`
+pub const X_CACHE: &str = "x-cache";
+
+/// Statuses cacheable without explicit freshness (RFC 9110, section 15.1)
+const CACHEABLE_STATUSES: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// In-memory cache for GET and HEAD responses
+///
+/// Entries are keyed by method, host, path and query, plus the request
+/// headers named in the response's `Vary`. The response's `Cache-Control`
+/// decides what is stored: `no-store`, `no-cache` and `private` skip the
+/// cache, `s-maxage`/`max-age` replace the default TTL. Responses setting
+/// cookies, streaming bodies and answers to requests carrying `Authorization`
+/// or `Cookie` (unless marked `public`) are never stored, and such requests
+/// are only answered from `public` entries.
+///
+/// Served responses carry `X-Cache: HIT` or `MISS`; hits also get `Age`.
+/// When full, the oldest entries are evicted first.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, CacheMiddleware};
+/// use std::time::Duration;
+///
+/// let mut app = App::default();
+/// app.use_middleware(
+///     CacheMiddleware::new()
+///         .ttl(Duration::from_secs(30))
+///         .max_bytes(64 * 1024 * 1024),
+/// );
+/// ```
+pub struct CacheMiddleware {
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    store: Mutex<CacheStore>,
+}
+
+#[derive(Default)]
+struct CacheStore {
+    /// Request headers named by `Vary` and the number of stored variants,
+    /// per method + host + path; dropped with the last variant
+    vary: HashMap<String, (Vec<HeaderName>, usize)>,
+    entries: HashMap<String, CachedResponse>,
+    /// Keys in insertion order with the sequence number of their entry
+    order: VecDeque<(String, u64)>,
+    bytes: usize,
+    seq: u64,
+}
+
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    ttl: Duration,
+    size: usize,
+    seq: u64,
+    /// Marked `public`, so it may answer requests with credentials
+    public: bool,
+}
+
+impl CacheStore {
+    fn remove(&mut self, key: &str) {
+        let Some(old) = self.entries.remove(key) else {
+            return;
+        };
+        self.bytes -= old.size;
+        // Variant keys extend the base key with `\n`-separated header values
+        let base = key.split('\n').next().unwrap_or(key);
+        if let Some((_, variants)) = self.vary.get_mut(base) {
+            *variants -= 1;
+            if *variants == 0 {
+                self.vary.remove(base);
+            }
+        }
+    }
+
+    /// Drop `order` pairs whose entry was replaced or removed, once they
+    /// outnumber the live ones
+    fn compact(&mut self) {
+        if self.order.len() <= 2 * self.entries.len() + 16 {
+            return;
+        }
+        let entries = &self.entries;
+        self.order
+            .retain(|(key, seq)| entries.get(key).is_some_and(|e| e.seq == *seq));
+    }
+}
+
+impl CacheMiddleware {
+    /// Cache up to 1000 responses (16MB) for 60 seconds each
+    pub fn new() -> Self {
+        Self {
+            ttl: Duration::from_secs(60),
+            max_entries: 1000,
+            max_bytes: 16 * 1024 * 1024,
+            store: Mutex::new(CacheStore::default()),
+        }
+    }
+
+    /// Set how long responses without `max-age` stay fresh
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the maximum number of cached responses
+    pub fn max_entries(mut self, count: usize) -> Self {
+        self.max_entries = count;
+        self
+    }
+
+    /// Set the maximum total size of cached bodies and headers
+    pub fn max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = bytes;
+        self
+    }
+
+    /// Drop every cached response
+    pub fn clear(&self) {
+        *self.store.lock().unwrap() = CacheStore::default();
+    }
+
+    /// Method, host, path and query
+    fn base_key(req: &PingoraHttpRequest) -> String {
+        let host = req
+            .headers()
+            .get(http::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let target = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.path(), |p| p.as_str());
+        format!("{} {}{}", req.method(), host.to_ascii_lowercase(), target)
+    }
+
+    /// `base` extended with the request's values for the `Vary` headers
+    fn variant_key(base: &str, vary: &[HeaderName], req: &PingoraHttpRequest) -> String {
+        let mut key = base.to_string();
+        for name in vary {
+            key.push('\n');
+            key.push_str(name.as_str());
+            for value in req.headers().get_all(name) {
+                key.push(':');
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+        key
+    }
+
+    fn lookup(&self, req: &PingoraHttpRequest) -> Option<PingoraWebHttpResponse> {
+        let mut store = self.store.lock().unwrap();
+        let base = Self::base_key(req);
+        let (vary, _) = store.vary.get(&base)?;
+        let key = Self::variant_key(&base, vary, req);
+        let entry = store.entries.get(&key)?;
+        if has_credentials(req) && !entry.public {
+            return None;
+        }
+        let age = entry.stored_at.elapsed();
+        if age >= entry.ttl {
+            store.remove(&key);
+            return None;
+        }
+
+        let mut res = PingoraWebHttpResponse::new(entry.status);
+        res.headers = entry.headers.clone();
+        res.body = Body::Bytes(entry.body.clone());
+        res.set_header(http::header::AGE, age.as_secs().to_string());
+        res.set_header(X_CACHE, "HIT");
+        Some(res)
+    }
+
+    /// How long `res` may be cached and whether it is `public`, `None` when it
+    /// must not be stored
+    fn freshness(
+        &self,
+        req: &PingoraHttpRequest,
+        res: &PingoraWebHttpResponse,
+    ) -> Option<(Duration, bool)> {
+        if !CACHEABLE_STATUSES.contains(&res.status.as_u16())
+            || res.trailers.is_some()
+            || res.headers.contains_key(http::header::SET_COOKIE)
+            || res
+                .headers
+                .get_all(http::header::VARY)
+                .iter()
+                .any(|v| v.as_bytes().contains(&b'*'))
+        {
+            return None;
+        }
+
+        let mut ttl = self.ttl;
+        let mut max_age = None;
+        let mut public = false;
+        let directives = res
+            .headers
+            .get_all(http::header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|d| d.trim().to_ascii_lowercase());
+        for directive in directives {
+            let (name, value) = directive
+                .split_once('=')
+                .map_or((directive.as_str(), None), |(n, v)| {
+                    (n.trim(), Some(v.trim().trim_matches('"')))
+                });
+            match (name, value.and_then(|v| v.parse::<u64>().ok())) {
+                ("no-store" | "no-cache" | "private", _) => return None,
+                ("public", _) => public = true,
+                ("s-maxage", Some(secs)) => max_age = Some(Duration::from_secs(secs)),
+                ("max-age", Some(secs)) => {
+                    max_age.get_or_insert(Duration::from_secs(secs));
+                }
+                _ => {}
+            }
+        }
+        if let Some(max_age) = max_age {
+            ttl = max_age;
+        }
+
+        // Answers to requests with credentials may be personalized
+        if has_credentials(req) && !public {
+            return None;
+        }
+        (!ttl.is_zero()).then_some((ttl, public))
+    }
+
+    fn insert(
+        &self,
+        req: &PingoraHttpRequest,
+        res: &PingoraWebHttpResponse,
+        ttl: Duration,
+        public: bool,
+    ) {
+        let Body::Bytes(body) = &res.body else {
+            return;
+        };
+        let mut headers = res.headers.clone();
+        headers.remove("x-request-id");
+        let size = body.len()
+            + headers
+                .iter()
+                .map(|(k, v)| k.as_str().len() + v.len())
+                .sum::<usize>();
+        if size > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+
+        let vary: Vec<HeaderName> = res
+            .headers
+            .get_all(http::header::VARY)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+            .collect();
+
+        let mut store = self.store.lock().unwrap();
+        let base = Self::base_key(req);
+        let key = Self::variant_key(&base, &vary, req);
+        store.remove(&key);
+
+        // Evict the oldest entries until the new one fits
+        while store.entries.len() >= self.max_entries || store.bytes + size > self.max_bytes {
+            let Some((old, seq)) = store.order.pop_front() else {
+                break;
+            };
+            if store.entries.get(&old).is_some_and(|e| e.seq == seq) {
+                store.remove(&old);
+            }
+        }
+
+        let row = store.vary.entry(base).or_default();
+        row.0 = vary;
+        row.1 += 1;
+        store.seq += 1;
+        let seq = store.seq;
+        store.bytes += size;
+        store.order.push_back((key.clone(), seq));
+        store.entries.insert(
+            key,
+            CachedResponse {
+                status: res.status,
+                headers,
+                body: body.clone(),
+                stored_at: Instant::now(),
+                ttl,
+                size,
+                seq,
+                public,
+            },
+        );
+        store.compact();
+    }
+}
+
+fn has_credentials(req: &PingoraHttpRequest) -> bool {
+    let headers = req.headers();
+    headers.contains_key(http::header::AUTHORIZATION) || headers.contains_key(http::header::COOKIE)
+}
+
+impl Default for CacheMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Middleware for CacheMiddleware {
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return next.handle(req).await;
+        }
+        if let Some(res) = self.lookup(&req) {
+            return Ok(res);
+        }
+
+        // Keep what the key needs; the request itself moves into the handler
+        let mut key_req = PingoraHttpRequest::new(req.method().clone(), req.uri().to_string());
+        *key_req.headers_mut() = req.headers().clone();
+
+        let mut res = next.handle(req).await?;
+        if let Some((ttl, public)) = self.freshness(&key_req, &res) {
+            self.insert(&key_req, &res, ttl, public);
+        }
+        res.headers
+            .insert(X_CACHE, HeaderValue::from_static("MISS"));
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counting_app(cache: CacheMiddleware) -> (App, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let seen = calls.clone();
+        let mut app = App::default();
+        app.use_middleware(cache);
+        app.get_fn("/report", move |req| {
+            let n = seen.fetch_add(1, Ordering::SeqCst) + 1;
+            let lang = req
+                .headers()
+                .get("accept-language")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("en")
+                .to_string();
+            PingoraWebHttpResponse::text(StatusCode::OK, format!("{lang} {n}"))
+                .header("vary", "Accept-Language")
+        });
+        app.get_fn("/live", |_req| {
+            PingoraWebHttpResponse::ok("now").header("cache-control", "no-store")
+        });
+        (app, calls)
+    }
+
+    #[tokio::test]
+    async fn serves_hits_per_vary_variant() {
+        let (app, calls) = counting_app(CacheMiddleware::new());
+        let client = app.test_client();
+
+        let res = client.get("/report").send().await;
+        assert_eq!(res.header(X_CACHE), Some("MISS"));
+        let res = client.get("/report").send().await;
+        assert_eq!(res.header(X_CACHE), Some("HIT"));
+        assert_eq!(res.header("age"), Some("0"));
+        assert_eq!(res.text(), "en 1");
+
+        let res = client
+            .get("/report")
+            .header("accept-language", "fr")
+            .send()
+            .await;
+        assert_eq!(res.text(), "fr 2");
+        let res = client.get("/report?page=2").send().await;
+        assert_eq!(res.text(), "en 3");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        for _ in 0..2 {
+            let res = client.get("/live").send().await;
+            assert_eq!(res.header(X_CACHE), Some("MISS"));
+        }
+    }
+
+    #[tokio::test]
+    async fn expires_and_evicts_oldest() {
+        let cache = CacheMiddleware::new()
+            .ttl(Duration::from_millis(20))
+            .max_entries(2);
+        let (app, calls) = counting_app(cache);
+        let client = app.test_client();
+
+        client.get("/report").send().await;
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let res = client.get("/report").send().await;
+        assert_eq!(res.header(X_CACHE), Some("MISS"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let cache = CacheMiddleware::new().max_entries(2);
+        let (app, calls) = counting_app(cache);
+        let client = app.test_client();
+        for path in [
+            "/report?a",
+            "/report?b",
+            "/report?c",
+            "/report?c",
+            "/report?a",
+        ] {
+            client.get(path).send().await;
+        }
+        // "a" was evicted when "c" arrived, "c" was a hit
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn bookkeeping_stays_bounded() {
+        let cache = CacheMiddleware::new().max_entries(2);
+        let res = PingoraWebHttpResponse::ok("x");
+        let ttl = Duration::from_secs(60);
+        for i in 0..100 {
+            let req = PingoraHttpRequest::new(Method::GET, format!("/x?a={i}"));
+            cache.insert(&req, &res, ttl, false);
+        }
+        {
+            let store = cache.store.lock().unwrap();
+            assert_eq!(store.entries.len(), 2);
+            assert_eq!(store.vary.len(), 2);
+        }
+
+        // Replacing the same entry doesn't pile up stale order pairs
+        let req = PingoraHttpRequest::new(Method::GET, "/x?a=0");
+        for _ in 0..100 {
+            cache.insert(&req, &res, ttl, false);
+        }
+        let store = cache.store.lock().unwrap();
+        assert!(store.order.len() <= 2 * store.entries.len() + 16);
+        assert_eq!(store.vary.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn requests_with_cookies_skip_private_entries() {
+        let (mut app, calls) = counting_app(CacheMiddleware::new());
+        app.get_fn("/shared", |_req| {
+            PingoraWebHttpResponse::ok("shared").header("cache-control", "public")
+        });
+        let client = app.test_client();
+
+        // Neither stored for, nor served to, a session
+        for _ in 0..2 {
+            let res = client
+                .get("/report")
+                .header("cookie", "session=alice")
+                .send()
+                .await;
+            assert_eq!(res.header(X_CACHE), Some("MISS"));
+        }
+        client.get("/report").send().await;
+        let res = client
+            .get("/report")
+            .header("cookie", "session=bob")
+            .send()
+            .await;
+        assert_eq!(res.header(X_CACHE), Some("MISS"));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        client
+            .get("/shared")
+            .header("cookie", "session=alice")
+            .send()
+            .await;
+        let res = client
+            .get("/shared")
+            .header("cookie", "session=bob")
+            .send()
+            .await;
+        assert_eq!(res.header(X_CACHE), Some("HIT"));
+    }
+}
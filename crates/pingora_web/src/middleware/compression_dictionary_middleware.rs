@@ -0,0 +1,443 @@
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::Bytes;
+use http::{HeaderValue, Method, StatusCode, header};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::Middleware;
+use crate::core::annotations::COMPRESSION_LEVEL;
+use crate::core::response::Body;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Request header naming the dictionary the client holds, as `:base64(sha256):`
+pub const AVAILABLE_DICTIONARY: &str = "available-dictionary";
+/// Response header marking a response as a dictionary for matching URLs
+pub const USE_AS_DICTIONARY: &str = "use-as-dictionary";
+
+/// Magic bytes starting a `dcb` (Brotli with dictionary) body, before the hash
+const DCB_MAGIC: [u8; 4] = [0xff, 0x44, 0x43, 0x42];
+/// Magic bytes starting a `dcz` (Zstandard with dictionary) body, before the hash
+const DCZ_MAGIC: [u8; 8] = [0x5e, 0x2a, 0x4d, 0x18, 0x20, 0x00, 0x00, 0x00];
+
+/// How long clients keep a served dictionary
+const DICTIONARY_MAX_AGE: u32 = 30 * 24 * 60 * 60;
+
+struct Dictionary {
+    path: String,
+    match_pattern: String,
+    bytes: Bytes,
+    hash: [u8; 32],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Dcb,
+    Dcz,
+}
+
+impl Encoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Dcb => "dcb",
+            Encoding::Dcz => "dcz",
+        }
+    }
+}
+
+/// Compression Dictionary Transport (RFC 9842): `dcb` and `dcz` encodings
+///
+/// Each dictionary is served at its own path with `Use-As-Dictionary`, and
+/// responses under its match pattern link to it. Clients that stored it send
+/// its hash in `Available-Dictionary`; their buffered 200 responses are then
+/// compressed against it, Zstandard (`dcz`) preferred over Brotli (`dcb`).
+/// Typical dictionaries are the previous release of a script bundle or a
+/// sample of API responses.
+///
+/// Match patterns are paths where `*` matches any characters. Streamed
+/// bodies, responses that already carry a `Content-Encoding` and those
+/// below `min_size` are left to the regular compression module. Browsers
+/// only announce dictionaries over HTTPS (and on localhost).
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, CompressionDictionaryMiddleware};
+///
+/// let dictionary = b"{\"id\":0,\"name\":\"\",\"email\":\"\"}".to_vec();
+/// let mut app = App::default();
+/// app.use_middleware(
+///     CompressionDictionaryMiddleware::new().dictionary("/dictionaries/api", "/api/*", dictionary),
+/// );
+/// ```
+pub struct CompressionDictionaryMiddleware {
+    dictionaries: Vec<Arc<Dictionary>>,
+    by_hash: HashMap<[u8; 32], Arc<Dictionary>>,
+    min_size: usize,
+    brotli_quality: u32,
+    zstd_level: i32,
+}
+
+impl Default for CompressionDictionaryMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompressionDictionaryMiddleware {
+    /// No dictionaries yet; Brotli quality 5, Zstandard level 3, 256 byte minimum
+    pub fn new() -> Self {
+        Self {
+            dictionaries: Vec::new(),
+            by_hash: HashMap::new(),
+            min_size: 256,
+            brotli_quality: 5,
+            zstd_level: 3,
+        }
+    }
+
+    /// Serve `bytes` at `path` as the dictionary for URLs matching `match_pattern`
+    pub fn dictionary(
+        mut self,
+        path: impl Into<String>,
+        match_pattern: impl Into<String>,
+        bytes: impl Into<Bytes>,
+    ) -> Self {
+        let bytes = bytes.into();
+        let dictionary = Arc::new(Dictionary {
+            path: path.into(),
+            match_pattern: match_pattern.into(),
+            hash: Sha256::digest(&bytes).into(),
+            bytes,
+        });
+        self.by_hash.insert(dictionary.hash, dictionary.clone());
+        self.dictionaries.push(dictionary);
+        self
+    }
+
+    /// Leave smaller bodies uncompressed
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Brotli quality for `dcb` (0-11)
+    pub fn brotli_quality(mut self, quality: u32) -> Self {
+        self.brotli_quality = quality.min(11);
+        self
+    }
+
+    /// Zstandard level for `dcz`
+    pub fn zstd_level(mut self, level: i32) -> Self {
+        self.zstd_level = level;
+        self
+    }
+
+    fn serve(dictionary: &Dictionary) -> PingoraWebHttpResponse {
+        let pattern = serde_json::to_string(&dictionary.match_pattern).unwrap_or_default();
+        let content_type = mime_guess::from_path(&dictionary.path)
+            .first_raw()
+            .unwrap_or("application/octet-stream");
+        PingoraWebHttpResponse::bytes(StatusCode::OK, dictionary.bytes.clone())
+            .content_type(content_type)
+            .cache_control(format!("public, max-age={DICTIONARY_MAX_AGE}"))
+            .header(USE_AS_DICTIONARY, format!("match={pattern}"))
+    }
+
+    /// The stored dictionary the client announced and the encoding to use with it
+    fn negotiate(&self, req: &PingoraHttpRequest) -> Option<(Arc<Dictionary>, Encoding)> {
+        let announced = req.headers().get(AVAILABLE_DICTIONARY)?.to_str().ok()?;
+        let hash = STANDARD
+            .decode(announced.trim().strip_prefix(':')?.strip_suffix(':')?)
+            .ok()?;
+        let dictionary = self.by_hash.get(hash.as_slice())?.clone();
+        let accepted = |name: &str| {
+            req.headers()
+                .get_all(header::ACCEPT_ENCODING)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .any(|coding| {
+                    let mut parts = coding.split(';');
+                    let accepted_name = parts.next().unwrap_or("").trim();
+                    let refused = parts.any(|p| {
+                        p.trim()
+                            .strip_prefix("q=")
+                            .and_then(|q| q.parse::<f32>().ok())
+                            == Some(0.0)
+                    });
+                    accepted_name.eq_ignore_ascii_case(name) && !refused
+                })
+        };
+        let encoding = if accepted("dcz") {
+            Encoding::Dcz
+        } else if accepted("dcb") {
+            Encoding::Dcb
+        } else {
+            return None;
+        };
+        Some((dictionary, encoding))
+    }
+
+    async fn compress(
+        &self,
+        res: &mut PingoraWebHttpResponse,
+        dictionary: Arc<Dictionary>,
+        encoding: Encoding,
+    ) -> std::io::Result<()> {
+        let Body::Bytes(body) = &res.body else {
+            return Ok(());
+        };
+        let body = body.clone();
+        let (quality, level) = (self.brotli_quality, self.zstd_level);
+        let encoded = tokio::task::spawn_blocking(move || match encoding {
+            Encoding::Dcb => dcb(&dictionary, &body, quality),
+            Encoding::Dcz => dcz(&dictionary, &body, level),
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+
+        res.body = Body::Bytes(encoded.into());
+        res.headers.remove(header::CONTENT_LENGTH);
+        res.set_header(header::CONTENT_ENCODING, encoding.as_str());
+        // The bytes differ from the identity representation
+        if let Some(etag) = res.headers.get(header::ETAG).cloned() {
+            if etag.as_bytes().starts_with(b"\"") {
+                let weak = [b"W/", etag.as_bytes()].concat();
+                if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                    res.headers.insert(header::ETAG, weak);
+                }
+            } else if !etag.as_bytes().starts_with(b"W/") {
+                res.headers.remove(header::ETAG);
+            }
+        }
+        res.add_vary("accept-encoding");
+        res.add_vary(AVAILABLE_DICTIONARY);
+        Ok(())
+    }
+}
+
+/// `dcb` body: magic, dictionary hash, then a Brotli stream using the dictionary
+fn dcb(dictionary: &Dictionary, body: &[u8], quality: u32) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(body.len() / 2 + 36);
+    out.extend_from_slice(&DCB_MAGIC);
+    out.extend_from_slice(&dictionary.hash);
+    let params = brotli::enc::BrotliEncoderParams {
+        quality: quality as i32,
+        // Window large enough to reach back into the dictionary (16MB at most)
+        lgwin: window_log(dictionary.bytes.len() + body.len(), 24),
+        ..Default::default()
+    };
+    let mut input_buffer = [0u8; 4096];
+    let mut output_buffer = [0u8; 4096];
+    brotli::BrotliCompressCustomIoCustomDict(
+        &mut brotli::IoReaderWrapper(&mut &body[..]),
+        &mut brotli::IoWriterWrapper(&mut out),
+        &mut input_buffer,
+        &mut output_buffer,
+        &params,
+        brotli::enc::StandardAlloc::default(),
+        &mut |_, _, _, _| (),
+        &dictionary.bytes,
+        std::io::Error::from(std::io::ErrorKind::UnexpectedEof),
+    )?;
+    Ok(out)
+}
+
+/// `dcz` body: magic, dictionary hash, then a Zstandard frame using the dictionary
+fn dcz(dictionary: &Dictionary, body: &[u8], level: i32) -> std::io::Result<Vec<u8>> {
+    let mut compressor = zstd::bulk::Compressor::with_dictionary(level, &dictionary.bytes)?;
+    // Clients must support windows up to 8MB
+    let window = window_log(dictionary.bytes.len() + body.len(), 23);
+    compressor.set_parameter(zstd::zstd_safe::CParameter::WindowLog(window as u32))?;
+    let frame = compressor.compress(body)?;
+    let mut out = Vec::with_capacity(frame.len() + 40);
+    out.extend_from_slice(&DCZ_MAGIC);
+    out.extend_from_slice(&dictionary.hash);
+    out.extend_from_slice(&frame);
+    Ok(out)
+}
+
+/// Base-2 log of the window covering `len` bytes, between 10 and `max`
+fn window_log(len: usize, max: i32) -> i32 {
+    let bits = usize::BITS - len.max(1).next_power_of_two().leading_zeros() - 1;
+    (bits as i32).clamp(10, max)
+}
+
+/// `*` matches any run of characters, everything else itself
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[async_trait]
+impl Middleware for CompressionDictionaryMiddleware {
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let is_get = matches!(*req.method(), Method::GET | Method::HEAD);
+        if is_get && let Some(d) = self.dictionaries.iter().find(|d| d.path == req.path()) {
+            return Ok(Self::serve(d));
+        }
+
+        let negotiated = if *req.method() == Method::HEAD {
+            None
+        } else {
+            self.negotiate(&req)
+        };
+        let linked: Vec<String> = self
+            .dictionaries
+            .iter()
+            .filter(|d| matches(&d.match_pattern, req.path()))
+            .filter(|d| negotiated.as_ref().is_none_or(|(n, _)| n.hash != d.hash))
+            .map(|d| format!("<{}>; rel=\"compression-dictionary\"", d.path))
+            .collect();
+        let annotations = req.annotations();
+
+        let mut res = next.handle(req).await?;
+        if res.status != StatusCode::OK {
+            return Ok(res);
+        }
+        for link in linked {
+            if let Ok(value) = HeaderValue::from_str(&link) {
+                res.headers.append(header::LINK, value);
+            }
+        }
+        let Some((dictionary, encoding)) = negotiated else {
+            return Ok(res);
+        };
+        let eligible = matches!(&res.body, Body::Bytes(b) if b.len() >= self.min_size)
+            && !res.headers.contains_key(header::CONTENT_ENCODING)
+            && !res.has_raw_framing();
+        if !eligible {
+            return Ok(res);
+        }
+        match self.compress(&mut res, dictionary, encoding).await {
+            // Keep the compression module from encoding the body again
+            Ok(()) => {
+                if let Some(annotations) = annotations {
+                    annotations.set(COMPRESSION_LEVEL, "0");
+                }
+            }
+            Err(e) => tracing::warn!(error = %e, "Dictionary compression failed"),
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use std::io::Read;
+
+    #[test]
+    fn match_patterns() {
+        assert!(matches("/api/*", "/api/users"));
+        assert!(matches("/static/app.*.js", "/static/app.1f2e.js"));
+        assert!(!matches("/static/app.*.js", "/static/app.1f2e.css"));
+        assert!(matches("/exact", "/exact"));
+        assert!(!matches("/exact", "/exact/more"));
+    }
+
+    #[tokio::test]
+    async fn compresses_against_the_announced_dictionary() {
+        let dictionary = "{\"users\":[{\"name\":\"Ada Lovelace\",\"role\":\"admin\"}]}".repeat(8);
+        let body = "{\"users\":[{\"name\":\"Ada Lovelace\",\"role\":\"admin\"}]}".repeat(10);
+        let hash = STANDARD.encode(Sha256::digest(dictionary.as_bytes()));
+        let mut app = App::default();
+        let expected = body.clone();
+        app.get_fn("/api/users", move |_| {
+            PingoraWebHttpResponse::bytes(StatusCode::OK, expected.clone())
+                .content_type("application/json")
+                .header(header::ETAG, "\"v1\"")
+        });
+        app.use_middleware(CompressionDictionaryMiddleware::new().dictionary(
+            "/dictionaries/api",
+            "/api/*",
+            dictionary.clone(),
+        ));
+        let client = app.test_client();
+
+        let res = client.get("/dictionaries/api").send().await;
+        assert_eq!(res.header(USE_AS_DICTIONARY), Some("match=\"/api/*\""));
+        assert_eq!(res.text(), dictionary);
+
+        // Without the dictionary: a hint where to get it
+        let res = client.get("/api/users").send().await;
+        assert_eq!(
+            res.header("link"),
+            Some("</dictionaries/api>; rel=\"compression-dictionary\"")
+        );
+        assert_eq!(res.header("content-encoding"), None);
+        let plain = res.text();
+
+        let announced = format!(":{hash}:");
+        let res = client
+            .get("/api/users")
+            .header(AVAILABLE_DICTIONARY, announced.as_str())
+            .header("accept-encoding", "gzip, br, dcb")
+            .send()
+            .await;
+        assert_eq!(res.header("content-encoding"), Some("dcb"));
+        assert_eq!(res.header("etag"), Some("W/\"v1\""));
+        assert_eq!(
+            res.header("vary"),
+            Some("accept-encoding, available-dictionary")
+        );
+        let encoded = res.bytes().to_vec();
+        assert_eq!(&encoded[..4], DCB_MAGIC);
+        assert_eq!(STANDARD.encode(&encoded[4..36]), hash);
+        assert!(encoded.len() < plain.len() / 4);
+        let mut decoded = Vec::new();
+        brotli::reader::Decompressor::new_with_custom_dict(
+            &encoded[36..],
+            4096,
+            dictionary.as_bytes().to_vec().into(),
+        )
+        .read_to_end(&mut decoded)
+        .unwrap();
+        assert_eq!(decoded, plain.as_bytes());
+
+        let res = client
+            .get("/api/users")
+            .header(AVAILABLE_DICTIONARY, announced.as_str())
+            .header("accept-encoding", "dcb, dcz")
+            .send()
+            .await;
+        assert_eq!(res.header("content-encoding"), Some("dcz"));
+        let encoded = res.bytes().to_vec();
+        assert_eq!(&encoded[..8], DCZ_MAGIC);
+        let mut decompressor =
+            zstd::bulk::Decompressor::with_dictionary(dictionary.as_bytes()).unwrap();
+        let decoded = decompressor.decompress(&encoded[40..], 1 << 20).unwrap();
+        assert_eq!(decoded, plain.as_bytes());
+
+        // Unknown dictionaries fall back to regular responses
+        let res = client
+            .get("/api/users")
+            .header(AVAILABLE_DICTIONARY, ":AAAA:")
+            .header("accept-encoding", "dcb, dcz")
+            .send()
+            .await;
+        assert_eq!(res.header("content-encoding"), None);
+    }
+}
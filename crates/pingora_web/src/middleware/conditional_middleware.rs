@@ -0,0 +1,125 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use super::Middleware;
+use crate::core::{Guard, Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Runs the wrapped middleware only for requests its guard passes
+///
+/// Other requests go straight to `next`. Build it with `MiddlewareExt::when`.
+///
+/// ```
+/// use pingora_web::{App, Guard, LimitsMiddleware, MiddlewareExt};
+/// use http::Method;
+///
+/// let uploads = Guard::path_prefix("/upload").and(Guard::methods([Method::POST, Method::PUT]));
+/// let mut app = App::default();
+/// app.use_middleware(LimitsMiddleware::new().when(uploads));
+/// ```
+pub struct ConditionalMiddleware<M> {
+    inner: M,
+    guard: Guard,
+}
+
+impl<M: Middleware> ConditionalMiddleware<M> {
+    pub fn new(inner: M, guard: Guard) -> Self {
+        Self { inner, guard }
+    }
+}
+
+#[async_trait]
+impl<M: Middleware> Middleware for ConditionalMiddleware<M> {
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        if self.guard.check(&req) {
+            self.inner.handle(req, next).await
+        } else {
+            next.handle(req).await
+        }
+    }
+
+    fn convert_errors(&self) -> bool {
+        self.inner.convert_errors()
+    }
+
+    fn check(&self) -> Vec<String> {
+        self.inner.check()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+}
+
+/// Combinators available on every middleware
+pub trait MiddlewareExt: Middleware + Sized {
+    /// Run this middleware only for requests `guard` passes
+    fn when(self, guard: Guard) -> ConditionalMiddleware<Self> {
+        ConditionalMiddleware::new(self, guard)
+    }
+
+    /// Run this middleware only when `check` returns `true`
+    fn when_fn<F>(self, check: F) -> ConditionalMiddleware<Self>
+    where
+        F: Fn(&PingoraHttpRequest) -> bool + Send + Sync + 'static,
+    {
+        self.when(Guard::new(check))
+    }
+}
+
+impl<M: Middleware> MiddlewareExt for M {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use http::Method;
+
+    /// Marks responses with a header
+    struct Tag(&'static str);
+
+    #[async_trait]
+    impl Middleware for Tag {
+        async fn handle(
+            &self,
+            req: PingoraHttpRequest,
+            next: Arc<dyn Handler>,
+        ) -> Result<PingoraWebHttpResponse, WebError> {
+            Ok(next.handle(req).await?.header(self.0, "1"))
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_requests_the_guard_rejects() {
+        let mut app = App::default();
+        let writes = Guard::path_prefix("/api/").and(Guard::methods([Method::POST]));
+        app.use_middleware(Tag("x-api").when(writes));
+        app.use_middleware(Tag("x-debug").when_fn(|req| req.headers().contains_key("x-debug")));
+        app.post_fn("/api/items", |_req| "ok");
+        app.use_middleware(Tag("x-not-health").when(!Guard::path_prefix("/health")));
+        app.post_fn("/apis", |_req| "ok");
+        app.get_fn("/api/items", |_req| "ok");
+
+        let client = app.test_client();
+        let res = client.post("/api/items").send().await;
+        assert_eq!(res.header("x-api"), Some("1"));
+        assert_eq!(res.header("x-debug"), None);
+        assert_eq!(client.post("/apis").send().await.header("x-api"), None);
+        let res = client
+            .get("/api/items")
+            .header("x-debug", "on")
+            .send()
+            .await;
+        assert_eq!(res.header("x-api"), None);
+        assert_eq!(res.header("x-debug"), Some("1"));
+        assert_eq!(res.header("x-not-health"), Some("1"));
+        assert_eq!(
+            client.get("/health").send().await.header("x-not-health"),
+            None
+        );
+    }
+}
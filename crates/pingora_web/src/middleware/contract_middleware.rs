@@ -0,0 +1,589 @@
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use http::StatusCode;
+use serde_json::{Value, json};
+
+use crate::core::response::Body;
+use crate::core::{Handler, OpenApiRoutesError, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::{ResponseError, WebError};
+use crate::middleware::Middleware;
+
+/// Which side of an exchange broke the API contract
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContractSide {
+    Request,
+    Response,
+}
+
+/// A request or response that does not match the OpenAPI document
+///
+/// Answers 400 for requests and 500 for responses, with the problems in
+/// `details`.
+#[derive(Debug)]
+pub struct ContractViolation {
+    pub side: ContractSide,
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for ContractViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let side = match self.side {
+            ContractSide::Request => "request",
+            ContractSide::Response => "response",
+        };
+        write!(
+            f,
+            "{side} does not match the API contract: {}",
+            self.problems.join("; ")
+        )
+    }
+}
+
+impl std::error::Error for ContractViolation {}
+
+impl ResponseError for ContractViolation {
+    fn status_code(&self) -> StatusCode {
+        match self.side {
+            ContractSide::Request => StatusCode::BAD_REQUEST,
+            ContractSide::Response => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_code(&self) -> Cow<'static, str> {
+        Cow::Borrowed("contract_violation")
+    }
+
+    fn details(&self) -> Option<Value> {
+        Some(json!({ "problems": self.problems }))
+    }
+}
+
+/// Check requests and responses against an OpenAPI document
+///
+/// Meant for development and staging, to catch drift between the code and
+/// its contract. Operations are found by the matched route; for them the
+/// middleware checks path, query and header parameters, JSON request
+/// bodies, response statuses and buffered JSON response bodies. Schemas
+/// support `type`, `nullable`, `enum`, `required`, `properties`,
+/// `additionalProperties`, `items`, length and range bounds, `allOf`,
+/// `anyOf`, `oneOf` and local `$ref`s; `pattern` and `format` are not
+/// checked. Request bodies the document describes are buffered to check
+/// them, even for handlers that stream their input.
+///
+/// Mismatches are logged as warnings; with `enforce()` they are answered
+/// with a `ContractViolation` instead.
+///
+/// # Example
+/// ```no_run
+/// use pingora_web::{App, ContractMiddleware};
+///
+/// let mut app = App::default();
+/// if std::env::var("APP_ENV").as_deref() != Ok("production") {
+///     app.use_middleware(ContractMiddleware::from_file("openapi.yaml").unwrap().enforce());
+/// }
+/// ```
+pub struct ContractMiddleware {
+    doc: Arc<Value>,
+    enforce: bool,
+}
+
+impl ContractMiddleware {
+    /// Check against `doc`, e.g. `app.openapi_json()` or a parsed document
+    pub fn new(doc: Value) -> Self {
+        Self {
+            doc: Arc::new(doc),
+            enforce: false,
+        }
+    }
+
+    /// Load a `.json` or `.yaml`/`.yml` document
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, OpenApiRoutesError> {
+        crate::core::openapi_routes::read_document(path.as_ref()).map(Self::new)
+    }
+
+    /// Reject mismatches instead of only logging them
+    pub fn enforce(mut self) -> Self {
+        self.enforce = true;
+        self
+    }
+
+    /// The operation for a route pattern, with its path item
+    fn operation(&self, method: &str, pattern: &str) -> Option<(&Value, &Value)> {
+        let template: Vec<String> = pattern
+            .split('/')
+            .map(|segment| segment.replacen("{*", "{", 1))
+            .collect();
+        let item = self.doc.get("paths")?.get(template.join("/"))?;
+        Some((item, item.get(method.to_ascii_lowercase())?))
+    }
+
+    /// Follow a local `$ref`, if `value` is one
+    fn resolve<'a>(&'a self, value: &'a Value) -> &'a Value {
+        match value.get("$ref").and_then(Value::as_str) {
+            Some(reference) => reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.doc.pointer(pointer))
+                .unwrap_or(value),
+            None => value,
+        }
+    }
+
+    async fn check_request(
+        &self,
+        req: &mut PingoraHttpRequest,
+        item: &Value,
+        op: &Value,
+    ) -> Result<Vec<String>, WebError> {
+        let mut problems = Vec::new();
+        let query: Vec<(String, String)> =
+            serde_urlencoded::from_str(req.uri().query().unwrap_or_default()).unwrap_or_default();
+        let params = [item.get("parameters"), op.get("parameters")];
+        for param in params
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_array)
+            .flatten()
+        {
+            let param = self.resolve(param);
+            let (Some(name), Some(location)) = (
+                param.get("name").and_then(Value::as_str),
+                param.get("in").and_then(Value::as_str),
+            ) else {
+                continue;
+            };
+            let value = match location {
+                "path" => req.param(name),
+                "query" => query
+                    .iter()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.as_str()),
+                "header" => req.headers().get(name).and_then(|v| v.to_str().ok()),
+                "cookie" => req.cookie(name),
+                _ => continue,
+            };
+            let required = location == "path" || param.get("required") == Some(&Value::Bool(true));
+            match (value, param.get("schema")) {
+                (None, _) if required => {
+                    problems.push(format!("missing required {location} parameter `{name}`"));
+                }
+                (Some(value), Some(schema)) => {
+                    let value = self.coerce(value, self.resolve(schema));
+                    let at = format!("{location} parameter `{name}`");
+                    self.validate(self.resolve(schema), &value, &at, &mut problems);
+                }
+                _ => {}
+            }
+        }
+
+        let Some(body) = op.get("requestBody").map(|b| self.resolve(b)) else {
+            return Ok(problems);
+        };
+        let bytes = req.body_bytes().await?.clone();
+        if bytes.is_empty() {
+            if body.get("required") == Some(&Value::Bool(true)) {
+                problems.push("missing required request body".to_string());
+            }
+            return Ok(problems);
+        }
+        let content_type = req
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        self.check_body(body, content_type, &bytes, "request body", &mut problems);
+        Ok(problems)
+    }
+
+    fn check_response(&self, op: &Value, res: &PingoraWebHttpResponse) -> Vec<String> {
+        let mut problems = Vec::new();
+        let Some(responses) = op.get("responses") else {
+            return problems;
+        };
+        let status = res.status.as_str();
+        let range = format!("{}XX", &status[..1]);
+        let Some(doc) = [status, &range, "default"]
+            .into_iter()
+            .find_map(|key| responses.get(key))
+        else {
+            problems.push(format!("undocumented response status {status}"));
+            return problems;
+        };
+        let doc = self.resolve(doc);
+        // Streamed bodies are not buffered just to check them
+        if let Body::Bytes(bytes) = &res.body
+            && !bytes.is_empty()
+            && res.status != StatusCode::NOT_MODIFIED
+        {
+            let content_type = res
+                .headers
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            self.check_body(doc, content_type, bytes, "response body", &mut problems);
+        }
+        problems
+    }
+
+    /// Check a body against the `content` of a request body or response object
+    fn check_body(
+        &self,
+        doc: &Value,
+        content_type: &str,
+        bytes: &[u8],
+        at: &str,
+        problems: &mut Vec<String>,
+    ) {
+        let Some(content) = doc.get("content").and_then(Value::as_object) else {
+            return;
+        };
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        let media = content.get(essence).or_else(|| {
+            let wildcard = format!("{}/*", essence.split('/').next().unwrap_or_default());
+            content.get(&wildcard).or_else(|| content.get("*/*"))
+        });
+        let Some(media) = media else {
+            problems.push(format!("{at}: undocumented content type `{essence}`"));
+            return;
+        };
+        let is_json = essence == "application/json" || essence.ends_with("+json");
+        let Some(schema) = media.get("schema").filter(|_| is_json) else {
+            return;
+        };
+        match serde_json::from_slice::<Value>(bytes) {
+            Ok(value) => self.validate(self.resolve(schema), &value, at, problems),
+            Err(e) => problems.push(format!("{at}: invalid JSON ({e})")),
+        }
+    }
+
+    /// Parameters arrive as text; read them as the type their schema asks for
+    fn coerce(&self, value: &str, schema: &Value) -> Value {
+        let parsed = match schema.get("type").and_then(Value::as_str) {
+            Some("integer") => value.parse::<i64>().ok().map(Value::from),
+            Some("number") => value.parse::<f64>().ok().map(Value::from),
+            Some("boolean") => value.parse::<bool>().ok().map(Value::from),
+            _ => None,
+        };
+        parsed.unwrap_or_else(|| Value::from(value))
+    }
+
+    fn validate(&self, schema: &Value, value: &Value, at: &str, problems: &mut Vec<String>) {
+        let schema = self.resolve(schema);
+        if value.is_null() && schema.get("nullable") == Some(&Value::Bool(true)) {
+            return;
+        }
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Value::String(t) => vec![t.as_str()],
+                Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+                problems.push(format!(
+                    "{at}: expected {}, got {}",
+                    types.join(" or "),
+                    type_name(value)
+                ));
+                return;
+            }
+        }
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array)
+            && !allowed.contains(value)
+        {
+            problems.push(format!(
+                "{at}: {value} is not one of {}",
+                Value::from(allowed.clone())
+            ));
+        }
+
+        match value {
+            Value::Object(object) => {
+                let properties = schema.get("properties").and_then(Value::as_object);
+                for name in schema
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    if let Some(name) = name.as_str()
+                        && !object.contains_key(name)
+                    {
+                        problems.push(format!("{at}: missing required property `{name}`"));
+                    }
+                }
+                for (name, field) in object {
+                    let at = format!("{at}.{name}");
+                    match properties.and_then(|p| p.get(name)) {
+                        Some(property) => self.validate(property, field, &at, problems),
+                        None => match schema.get("additionalProperties") {
+                            Some(Value::Bool(false)) => {
+                                problems.push(format!("{at}: unexpected property"));
+                            }
+                            Some(extra @ Value::Object(_)) => {
+                                self.validate(extra, field, &at, problems)
+                            }
+                            _ => {}
+                        },
+                    }
+                }
+            }
+            Value::Array(items) => {
+                let len = items.len() as f64;
+                bound(schema, "minItems", "maxItems", len, "items", at, problems);
+                if let Some(item) = schema.get("items") {
+                    for (i, value) in items.iter().enumerate() {
+                        self.validate(item, value, &format!("{at}[{i}]"), problems);
+                    }
+                }
+            }
+            Value::String(s) => {
+                let len = s.chars().count() as f64;
+                bound(
+                    schema,
+                    "minLength",
+                    "maxLength",
+                    len,
+                    "characters",
+                    at,
+                    problems,
+                );
+            }
+            Value::Number(n) => {
+                let n = n.as_f64().unwrap_or_default();
+                bound(schema, "minimum", "maximum", n, "", at, problems);
+            }
+            _ => {}
+        }
+
+        let matching = |key: &str| {
+            schema.get(key).and_then(Value::as_array).map(|schemas| {
+                schemas
+                    .iter()
+                    .map(|s| {
+                        let mut found = Vec::new();
+                        self.validate(s, value, at, &mut found);
+                        found
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        if let Some(results) = matching("allOf") {
+            problems.extend(results.into_iter().flatten());
+        }
+        if let Some(results) = matching("anyOf")
+            && !results.iter().any(Vec::is_empty)
+        {
+            problems.push(format!("{at}: matches none of anyOf"));
+        }
+        if let Some(results) = matching("oneOf") {
+            let count = results.iter().filter(|r| r.is_empty()).count();
+            if count != 1 {
+                problems.push(format!("{at}: matches {count} of oneOf instead of one"));
+            }
+        }
+    }
+}
+
+fn has_type(value: &Value, ty: &str) -> bool {
+    match ty {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Check `n` against the `min`/`max` keywords of `schema`
+fn bound(
+    schema: &Value,
+    min: &str,
+    max: &str,
+    n: f64,
+    unit: &str,
+    at: &str,
+    problems: &mut Vec<String>,
+) {
+    let unit = if unit.is_empty() {
+        String::new()
+    } else {
+        format!(" {unit}")
+    };
+    if let Some(min) = schema.get(min).and_then(Value::as_f64)
+        && n < min
+    {
+        problems.push(format!("{at}: {n}{unit} is below the minimum of {min}"));
+    }
+    if let Some(max) = schema.get(max).and_then(Value::as_f64)
+        && n > max
+    {
+        problems.push(format!("{at}: {n}{unit} is above the maximum of {max}"));
+    }
+}
+
+#[async_trait]
+impl Middleware for ContractMiddleware {
+    async fn handle(
+        &self,
+        mut req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let Some(pattern) = req.matched_route().map(str::to_string) else {
+            return next.handle(req).await;
+        };
+        let method = req.method().to_string();
+        let Some((item, op)) = self.operation(&method, &pattern) else {
+            tracing::warn!(method = %method, route = %pattern, "Operation missing from the API contract");
+            return next.handle(req).await;
+        };
+
+        let problems = self.check_request(&mut req, item, op).await?;
+        if !problems.is_empty() {
+            tracing::warn!(method = %method, route = %pattern, ?problems, "Request breaks the API contract");
+            if self.enforce {
+                let side = ContractSide::Request;
+                return Err(WebError::new(ContractViolation { side, problems }));
+            }
+        }
+
+        let res = next.handle(req).await?;
+        let problems = self.check_response(op, &res);
+        if !problems.is_empty() {
+            tracing::warn!(method = %method, route = %pattern, status = res.status.as_u16(), ?problems, "Response breaks the API contract");
+            if self.enforce {
+                let side = ContractSide::Response;
+                return Err(WebError::new(ContractViolation { side, problems }));
+            }
+        }
+        Ok(res)
+    }
+
+    // Undocumented error statuses are problems too
+    fn convert_errors(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    const SPEC: &str = r##"
+openapi: 3.0.3
+info: { title: Pets, version: "1.0" }
+components:
+  schemas:
+    Pet:
+      type: object
+      required: [name]
+      additionalProperties: false
+      properties:
+        name: { type: string, minLength: 1 }
+        age: { type: integer, minimum: 0 }
+        tag: { type: string, nullable: true }
+paths:
+  /pets/{id}:
+    get:
+      parameters:
+        - { name: id, in: path, required: true, schema: { type: integer } }
+        - { name: verbose, in: query, schema: { type: boolean } }
+      responses:
+        "200":
+          description: A pet
+          content:
+            application/json: { schema: { $ref: "#/components/schemas/Pet" } }
+  /pets:
+    post:
+      requestBody:
+        required: true
+        content:
+          application/json: { schema: { $ref: "#/components/schemas/Pet" } }
+      responses:
+        "201": { description: Created }
+"##;
+
+    #[tokio::test]
+    async fn enforces_the_contract_both_ways() {
+        let doc: Value = serde_yaml::from_str(SPEC).unwrap();
+        let mut app = App::default();
+        app.use_middleware(ContractMiddleware::new(doc).enforce());
+        app.get_fn("/pets/{id}", |req| match req.param("id") {
+            Some("1") => {
+                PingoraWebHttpResponse::json(StatusCode::OK, json!({ "name": "Rex", "tag": null }))
+            }
+            _ => PingoraWebHttpResponse::json(
+                StatusCode::OK,
+                json!({ "name": "", "age": -1, "color": "red" }),
+            ),
+        });
+        app.post_fn("/pets", |_| {
+            PingoraWebHttpResponse::text(StatusCode::CREATED, "")
+        });
+        let client = app.test_client();
+
+        let res = client.get("/pets/1?verbose=true").send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = client.get("/pets/one?verbose=maybe").send().await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = serde_json::from_str(&res.text()).unwrap();
+        assert_eq!(body["error"]["code"], "contract_violation");
+        assert_eq!(
+            body["error"]["details"]["problems"],
+            json!([
+                "path parameter `id`: expected integer, got string",
+                "query parameter `verbose`: expected boolean, got string",
+            ])
+        );
+
+        let res = client.get("/pets/2").send().await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: Value = serde_json::from_str(&res.text()).unwrap();
+        assert_eq!(
+            body["error"]["details"]["problems"],
+            json!([
+                "response body.age: -1 is below the minimum of 0",
+                "response body.color: unexpected property",
+                "response body.name: 0 characters is below the minimum of 1",
+            ])
+        );
+
+        let res = client
+            .post("/pets")
+            .header("content-type", "application/json")
+            .body(r#"{"age": 3}"#)
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(
+            res.text()
+                .contains("request body: missing required property `name`")
+        );
+        let res = client.post("/pets").send().await;
+        assert!(res.text().contains("missing required request body"));
+        let res = client
+            .post("/pets")
+            .header("content-type", "application/json")
+            .body(r#"{"name": "Tom"}"#)
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+}
@@ -0,0 +1,186 @@
+use async_trait::async_trait;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Middleware;
+use crate::core::router::UNMATCHED_ROUTE;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse, StatsRegistry};
+use crate::error::WebError;
+
+/// Stats metric that counts repeated request ids per route
+pub const DUPLICATE_REQUEST_IDS: &str = "duplicate_request_ids";
+
+/// Storage for recently seen request ids
+///
+/// Implement this to share duplicate detection across processes (e.g. backed by
+/// Redis). The default is an in-process `InMemorySeenIds`.
+#[async_trait]
+pub trait SeenIdStore: Send + Sync + 'static {
+    /// Record `id` and return `true` if it had already been seen
+    async fn check_and_insert(&self, id: &str) -> bool;
+
+    /// Forget `id` so it counts as unseen again (default: ids are kept)
+    async fn remove(&self, _id: &str) {}
+}
+
+/// Bounded in-memory store remembering ids for a time window
+pub struct InMemorySeenIds {
+    window: Duration,
+    capacity: usize,
+    state: Mutex<SeenState>,
+}
+
+#[derive(Default)]
+struct SeenState {
+    seen: HashSet<String>,
+    order: VecDeque<(String, Instant)>,
+}
+
+impl InMemorySeenIds {
+    /// Remember up to `capacity` ids for `window`
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            state: Mutex::new(SeenState::default()),
+        }
+    }
+}
+
+impl Default for InMemorySeenIds {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(300), 100_000)
+    }
+}
+
+#[async_trait]
+impl SeenIdStore for InMemorySeenIds {
+    async fn check_and_insert(&self, id: &str) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("InMemorySeenIds poisoned");
+
+        // Evict expired entries and keep the store within capacity
+        while let Some((_, at)) = state.order.front() {
+            if now.duration_since(*at) <= self.window && state.order.len() < self.capacity {
+                break;
+            }
+            if let Some((old, _)) = state.order.pop_front() {
+                state.seen.remove(&old);
+            }
+        }
+
+        // Ids are remembered from their first sighting
+        if state.seen.contains(id) {
+            return true;
+        }
+        state.seen.insert(id.to_string());
+        state.order.push_back((id.to_string(), now));
+        false
+    }
+
+    async fn remove(&self, id: &str) {
+        let mut state = self.state.lock().expect("InMemorySeenIds poisoned");
+        if state.seen.remove(id) {
+            state.order.retain(|(seen, _)| seen != id);
+        }
+    }
+}
+
+/// Middleware counting repeated `x-request-id` values (client retries) per route
+///
+/// Duplicates are recorded in the App's `StatsRegistry` under
+/// `DUPLICATE_REQUEST_IDS`, labeled `"METHOD /route/{template}"`, to help spot flaky clients
+/// and retry storms. Requests are never rejected.
+pub struct DuplicateRequestIdMiddleware {
+    store: Arc<dyn SeenIdStore>,
+}
+
+impl DuplicateRequestIdMiddleware {
+    /// Track duplicates with the default in-memory store
+    pub fn new() -> Self {
+        Self::with_store(InMemorySeenIds::default())
+    }
+
+    /// Track duplicates with a custom store
+    pub fn with_store<S: SeenIdStore>(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+}
+
+impl Default for DuplicateRequestIdMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Middleware for DuplicateRequestIdMiddleware {
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        if let Some(id) = req.request_id()
+            && self.store.check_and_insert(id).await
+        {
+            let route = format!(
+                "{} {}",
+                req.method(),
+                req.matched_route().unwrap_or(UNMATCHED_ROUTE)
+            );
+            tracing::debug!(
+                request_id = id,
+                route = route.as_str(),
+                "Duplicate request id"
+            );
+            if let Some(stats) = req.get_app_share_data::<StatsRegistry>() {
+                stats.incr(DUPLICATE_REQUEST_IDS, &route);
+            }
+        }
+        next.handle(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use http::StatusCode;
+
+    #[tokio::test]
+    async fn in_memory_store_expires_and_caps() {
+        let store = InMemorySeenIds::new(Duration::from_secs(60), 2);
+        assert!(!store.check_and_insert("a").await);
+        assert!(store.check_and_insert("a").await);
+        assert!(!store.check_and_insert("b").await);
+        assert!(!store.check_and_insert("c").await);
+        // "a" was evicted to stay within capacity
+        assert!(!store.check_and_insert("a").await);
+    }
+
+    #[tokio::test]
+    async fn counts_duplicates_per_route() {
+        let mut app = App::default();
+        app.use_middleware(DuplicateRequestIdMiddleware::new());
+        app.get_fn("/pay/{id}", |_req| {
+            Ok(PingoraWebHttpResponse::text(StatusCode::OK, "ok"))
+        });
+
+        let client = app.test_client();
+        for _ in 0..3 {
+            let res = client
+                .get("/pay/7")
+                .header("x-request-id", "retry-1")
+                .send()
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        // Generated ids are unique and never counted
+        client.get("/pay/7").send().await;
+
+        assert_eq!(app.stats().get(DUPLICATE_REQUEST_IDS, "GET /pay/{id}"), 2);
+    }
+}
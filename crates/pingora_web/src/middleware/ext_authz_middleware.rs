@@ -0,0 +1,389 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use http::{HeaderMap, HeaderName, Method, StatusCode};
+use pingora_core::connectors::http::Connector;
+use pingora_core::upstreams::peer::HttpPeer;
+use pingora_http::RequestHeader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::Middleware;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Request metadata sent to the authorization service
+#[derive(Debug, Clone)]
+pub struct AuthzRequest {
+    pub method: Method,
+    /// Path and query of the original request
+    pub path: String,
+    pub headers: HeaderMap,
+    pub peer_addr: Option<SocketAddr>,
+}
+
+/// Decision returned by the authorization service
+#[derive(Debug, Clone)]
+pub enum AuthzDecision {
+    /// Continue to the handler after merging `headers` into the request
+    Allow { headers: HeaderMap },
+    /// Answer the client directly
+    Deny {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+    },
+}
+
+/// External authorization backend (HTTP, gRPC, or in-process)
+#[async_trait]
+pub trait AuthzService: Send + Sync + 'static {
+    /// `Err` means the service could not be reached or answered garbage
+    async fn check(&self, req: &AuthzRequest) -> Result<AuthzDecision, String>;
+}
+
+/// Middleware delegating allow/deny decisions to an external service, like Envoy's ext_authz
+///
+/// Every request's metadata (method, path, headers, peer address) is sent to
+/// the `AuthzService`. On allow, returned headers are merged into the request
+/// (e.g. `x-user-id`); on deny, the service's status, headers, and body go back
+/// to the client. If the service fails, the request is rejected with 403 unless
+/// `failure_mode_allow(true)` is set.
+pub struct ExtAuthzMiddleware {
+    service: Arc<dyn AuthzService>,
+    failure_mode_allow: bool,
+    status_on_error: StatusCode,
+}
+
+impl ExtAuthzMiddleware {
+    pub fn new<S: AuthzService>(service: S) -> Self {
+        Self {
+            service: Arc::new(service),
+            failure_mode_allow: false,
+            status_on_error: StatusCode::FORBIDDEN,
+        }
+    }
+
+    /// Let requests through when the authorization service fails
+    pub fn failure_mode_allow(mut self, allow: bool) -> Self {
+        self.failure_mode_allow = allow;
+        self
+    }
+
+    /// Status returned when the service fails and failure mode denies (default 403)
+    pub fn status_on_error(mut self, status: StatusCode) -> Self {
+        self.status_on_error = status;
+        self
+    }
+}
+
+#[async_trait]
+impl Middleware for ExtAuthzMiddleware {
+    async fn handle(
+        &self,
+        mut req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let check = AuthzRequest {
+            method: req.method().clone(),
+            path: req
+                .inner
+                .uri()
+                .path_and_query()
+                .map_or_else(|| req.path().to_string(), ToString::to_string),
+            headers: req.headers().clone(),
+            peer_addr: req.peer_addr(),
+        };
+
+        match self.service.check(&check).await {
+            Ok(AuthzDecision::Allow { headers }) => {
+                for (name, value) in &headers {
+                    req.headers_mut().insert(name.clone(), value.clone());
+                }
+                next.handle(req).await
+            }
+            Ok(AuthzDecision::Deny {
+                status,
+                headers,
+                body,
+            }) => {
+                let mut res = PingoraWebHttpResponse::bytes(status, body);
+                for (name, value) in &headers {
+                    res.headers.insert(name.clone(), value.clone());
+                }
+                Ok(res)
+            }
+            Err(e) => {
+                tracing::warn!(error = e.as_str(), "Authorization service failed");
+                if self.failure_mode_allow {
+                    next.handle(req).await
+                } else {
+                    Ok(PingoraWebHttpResponse::text(
+                        self.status_on_error,
+                        self.status_on_error
+                            .canonical_reason()
+                            .unwrap_or("Forbidden"),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Connection and framing headers, never copied between the client, the authz service, and the app
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// `AuthzService` calling an HTTP authorization server
+///
+/// The original method and path (behind an optional prefix) and headers are
+/// sent without the body. A 2xx answer allows the request; anything else denies
+/// it with that response. Only headers named in `allowed_upstream_headers` are
+/// merged into allowed requests.
+pub struct HttpAuthzService {
+    connector: Connector,
+    peer: HttpPeer,
+    path_prefix: String,
+    allowed_upstream_headers: Vec<HeaderName>,
+    timeout: Duration,
+}
+
+impl HttpAuthzService {
+    /// Plain-HTTP authorization server at `addr` (e.g. `"127.0.0.1:9000"`)
+    pub fn new(addr: impl Into<String>) -> Self {
+        let addr = addr.into();
+        Self {
+            connector: Connector::new(None),
+            peer: HttpPeer::new(addr.as_str(), false, String::new()),
+            path_prefix: String::new(),
+            allowed_upstream_headers: Vec::new(),
+            timeout: Duration::from_millis(500),
+        }
+    }
+
+    /// Prefix prepended to the original path (e.g. `/authz`)
+    pub fn path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.path_prefix = prefix.into();
+        self
+    }
+
+    /// Response headers merged into allowed requests (default: none)
+    pub fn allowed_upstream_headers<I, N>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = N>,
+        N: AsRef<str>,
+    {
+        self.allowed_upstream_headers = names
+            .into_iter()
+            .filter_map(|n| HeaderName::from_bytes(n.as_ref().as_bytes()).ok())
+            .collect();
+        self
+    }
+
+    /// Time limit for one authorization call (default 500ms)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn call(&self, req: &AuthzRequest) -> pingora_core::Result<AuthzDecision> {
+        let path = format!("{}{}", self.path_prefix, req.path);
+        let mut header = RequestHeader::build(req.method.clone(), path.as_bytes(), None)?;
+        for (name, value) in &req.headers {
+            if !CONNECTION_HEADERS.contains(&name.as_str()) {
+                header.append_header(name.clone(), value.clone())?;
+            }
+        }
+        if let Some(peer) = req.peer_addr {
+            header.insert_header("x-forwarded-for", peer.ip().to_string())?;
+        }
+        header.insert_header("content-length", "0")?;
+
+        let (mut session, _reused) = self.connector.get_http_session(&self.peer).await?;
+        session.set_read_timeout(Some(self.timeout));
+        session.set_write_timeout(Some(self.timeout));
+        session.write_request_header(Box::new(header)).await?;
+        session.finish_request_body().await?;
+        session.read_response_header().await?;
+
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.read_response_body().await? {
+            body.extend_from_slice(&chunk);
+        }
+
+        let resp = session
+            .response_header()
+            .expect("response header was read")
+            .clone();
+        self.connector
+            .release_http_session(session, &self.peer, None)
+            .await;
+
+        let headers: HeaderMap = resp
+            .headers
+            .iter()
+            .filter(|(name, _)| !CONNECTION_HEADERS.contains(&name.as_str()))
+            .map(|(n, v)| (n.clone(), v.clone()))
+            .collect();
+
+        if resp.status.is_success() {
+            let headers = headers
+                .iter()
+                .filter(|(n, _)| self.allowed_upstream_headers.contains(n))
+                .map(|(n, v)| (n.clone(), v.clone()))
+                .collect();
+            Ok(AuthzDecision::Allow { headers })
+        } else {
+            Ok(AuthzDecision::Deny {
+                status: resp.status,
+                headers,
+                body: body.freeze(),
+            })
+        }
+    }
+}
+
+#[async_trait]
+impl AuthzService for HttpAuthzService {
+    async fn check(&self, req: &AuthzRequest) -> Result<AuthzDecision, String> {
+        match tokio::time::timeout(self.timeout, self.call(req)).await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(_) => Err("authorization request timed out".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn app(authz: ExtAuthzMiddleware) -> App {
+        let mut app = App::default();
+        app.use_middleware(authz);
+        app.get_fn("/me", |req| {
+            req.headers()
+                .get("x-user-id")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("anonymous")
+                .to_string()
+        });
+        app
+    }
+
+    struct TokenService;
+
+    #[async_trait]
+    impl AuthzService for TokenService {
+        async fn check(&self, req: &AuthzRequest) -> Result<AuthzDecision, String> {
+            match req.headers.get("authorization").map(|v| v.as_bytes()) {
+                Some(b"Bearer good") => {
+                    let mut headers = HeaderMap::new();
+                    headers.insert("x-user-id", "42".parse().unwrap());
+                    Ok(AuthzDecision::Allow { headers })
+                }
+                Some(b"Bearer broken") => Err("backend down".to_string()),
+                _ => Ok(AuthzDecision::Deny {
+                    status: StatusCode::UNAUTHORIZED,
+                    headers: HeaderMap::new(),
+                    body: Bytes::from_static(b"no token"),
+                }),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn enforces_decisions() {
+        let strict = app(ExtAuthzMiddleware::new(TokenService));
+        let client = strict.test_client();
+
+        let res = client
+            .get("/me")
+            .header("authorization", "Bearer good")
+            .send()
+            .await;
+        assert_eq!(res.text(), "42");
+
+        let res = client.get("/me").send().await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.text(), "no token");
+
+        let res = client
+            .get("/me")
+            .header("authorization", "Bearer broken")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let fail_open = app(ExtAuthzMiddleware::new(TokenService).failure_mode_allow(true));
+        let res = fail_open
+            .test_client()
+            .get("/me")
+            .header("authorization", "Bearer broken")
+            .send()
+            .await;
+        assert_eq!(res.text(), "anonymous");
+    }
+
+    #[tokio::test]
+    async fn http_service_round_trip() {
+        // Minimal authz server: allows requests carrying `authorization: ok`
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut sock, _)) = listener.accept().await else {
+                    return;
+                };
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 4096];
+                    let n = sock.read(&mut buf).await.unwrap_or(0);
+                    let head = String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase();
+                    let reply: &[u8] = if head.starts_with("get /authz/me ")
+                        && head.contains("authorization: ok")
+                    {
+                        b"HTTP/1.1 200 OK\r\nx-user-id: 7\r\nx-internal: 1\r\ncontent-length: 0\r\n\r\n"
+                    } else {
+                        b"HTTP/1.1 403 Forbidden\r\ncontent-length: 4\r\n\r\nnope"
+                    };
+                    let _ = sock.write_all(reply).await;
+                });
+            }
+        });
+
+        let service = HttpAuthzService::new(addr.to_string())
+            .path_prefix("/authz")
+            .allowed_upstream_headers(["x-user-id"]);
+        let allowlisted = app(ExtAuthzMiddleware::new(service));
+        let client = allowlisted.test_client();
+
+        let res = client.get("/me").header("authorization", "ok").send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text(), "7");
+
+        let res = client.get("/me").send().await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(res.text(), "nope");
+
+        // Without an allowlist nothing from the authz response reaches the app
+        let service = HttpAuthzService::new(addr.to_string()).path_prefix("/authz");
+        let open = app(ExtAuthzMiddleware::new(service));
+        let res = open
+            .test_client()
+            .get("/me")
+            .header("authorization", "ok")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text(), "anonymous");
+    }
+}
@@ -0,0 +1,129 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use super::Middleware;
+use crate::core::flash::IncomingFlash;
+use crate::core::{Flash, Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+use crate::utils::Signer;
+
+/// Middleware carrying flash messages between requests in a signed cookie
+///
+/// A `Flash` set on a response is signed and stored in the cookie; on the next
+/// request it is verified and made available via `req.take_flash()`. Once a
+/// handler takes the message the cookie is cleared. Tampered cookies are ignored.
+pub struct FlashMiddleware {
+    signer: Signer,
+    cookie_name: String,
+}
+
+impl FlashMiddleware {
+    /// Create flash middleware signing cookies with `secret`
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            signer: Signer::new(secret),
+            cookie_name: "_flash".to_string(),
+        }
+    }
+
+    /// Use a custom cookie name (default: `_flash`)
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Self {
+        self.cookie_name = name.into();
+        self
+    }
+
+    fn read(&self, req: &PingoraHttpRequest) -> Option<Flash> {
+        let token = req.cookie(&self.cookie_name)?;
+        let payload = self.signer.verify(token)?;
+        serde_json::from_slice(&payload).ok()
+    }
+
+    fn set_cookie(&self, res: &mut PingoraWebHttpResponse, value: &str, max_age: Option<u32>) {
+        let mut cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax",
+            self.cookie_name, value
+        );
+        if let Some(age) = max_age {
+            cookie.push_str(&format!("; Max-Age={age}"));
+        }
+        if let Ok(v) = http::HeaderValue::from_str(&cookie) {
+            res.headers.append(http::header::SET_COOKIE, v);
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for FlashMiddleware {
+    async fn handle(
+        &self,
+        mut req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let incoming = self.read(&req).map(|flash| {
+            let incoming = Arc::new(IncomingFlash::new(flash));
+            req.set_request_share_data(incoming.clone());
+            incoming
+        });
+
+        let mut res = next.handle(req).await?;
+
+        if let Some(flash) = res.extensions.remove::<Flash>() {
+            let payload = serde_json::to_vec(&flash)?;
+            let token = self.signer.sign(&payload);
+            self.set_cookie(&mut res, &token, None);
+        } else if incoming.is_some_and(|i| i.is_taken()) {
+            self.set_cookie(&mut res, "", Some(0));
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use http::StatusCode;
+
+    fn app() -> App {
+        let mut app = App::default();
+        app.use_middleware(FlashMiddleware::new("test-secret"));
+        app.post_fn("/save", |_req| {
+            PingoraWebHttpResponse::redirect_to("/").with_flash(Flash::success("Saved"))
+        });
+        app.get_fn("/", |req| match req.take_flash() {
+            Some(flash) => format!("{}: {}", flash.level, flash.message),
+            None => "no flash".to_string(),
+        });
+        app
+    }
+
+    #[tokio::test]
+    async fn flash_survives_redirect_once() {
+        let app = app();
+        let client = app.test_client();
+
+        let res = client.post("/save").send().await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        let cookie = res.header("set-cookie").unwrap().to_string();
+        assert!(cookie.starts_with("_flash="));
+        let pair = cookie.split(';').next().unwrap();
+
+        let res = client.get("/").header("cookie", pair).send().await;
+        assert_eq!(res.text(), "success: Saved");
+        // Taken flash is cleared
+        assert!(res.header("set-cookie").unwrap().contains("Max-Age=0"));
+    }
+
+    #[tokio::test]
+    async fn tampered_cookie_is_ignored() {
+        let app = app();
+        let res = app
+            .test_client()
+            .get("/")
+            .header("cookie", "_flash=eyJ4IjoxfQ.bad")
+            .send()
+            .await;
+        assert_eq!(res.text(), "no flash");
+        assert!(res.header("set-cookie").is_none());
+    }
+}
@@ -0,0 +1,254 @@
+use async_trait::async_trait;
+use http::{Method, StatusCode};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Middleware;
+use super::duplicate_request_id_middleware::{InMemorySeenIds, SeenIdStore};
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::{ResponseError, WebError};
+use crate::utils::Signer;
+
+/// Form field carrying the one-time token
+pub const FORM_TOKEN_FIELD: &str = "_form_token";
+/// Header alternative to the form field (for JS-submitted forms)
+pub const FORM_TOKEN_HEADER: &str = "x-form-token";
+
+/// Issues and redeems one-time form tokens (request-level data set by `FormTokenMiddleware`)
+pub struct FormTokens {
+    signer: Signer,
+    ttl: Duration,
+    used: Arc<dyn SeenIdStore>,
+}
+
+impl FormTokens {
+    /// Issue a fresh signed token to embed in a form
+    pub fn issue(&self) -> String {
+        let issued = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let nonce = crate::utils::request_id::generate();
+        self.signer.sign(format!("{nonce}:{issued}").as_bytes())
+    }
+
+    /// Validate a token and mark its nonce used
+    async fn redeem(&self, token: &str) -> Result<String, PingoraWebHttpResponse> {
+        let invalid =
+            || PingoraWebHttpResponse::text(StatusCode::BAD_REQUEST, "Invalid Form Token");
+        let payload = self.signer.verify(token).ok_or_else(invalid)?;
+        let payload = String::from_utf8(payload).map_err(|_| invalid())?;
+        let (nonce, issued) = payload.split_once(':').ok_or_else(invalid)?;
+        let issued: u64 = issued.parse().map_err(|_| invalid())?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if now.saturating_sub(issued) > self.ttl.as_secs() {
+            return Err(invalid());
+        }
+        if self.used.check_and_insert(nonce).await {
+            return Err(PingoraWebHttpResponse::text(
+                StatusCode::CONFLICT,
+                "Form Already Submitted",
+            ));
+        }
+        Ok(nonce.to_string())
+    }
+}
+
+/// Middleware preventing duplicate form submissions with one-time tokens
+///
+/// Handlers rendering a form call `req.issue_form_token()` and embed the value
+/// in a hidden `_form_token` field (or send it as `x-form-token`). When the form
+/// is submitted, the token is verified and burned; replaying the same
+/// submission gets `409 Conflict`, a forged or expired token `400 Bad Request`.
+/// If the handler rejects the submission with a 4xx (e.g. a validation error),
+/// the token is released so the corrected form can be sent again. Custom
+/// stores need `SeenIdStore::remove` for that.
+///
+/// Submissions without a token pass through unless `require_token()` is set.
+pub struct FormTokenMiddleware {
+    tokens: Arc<FormTokens>,
+    required: bool,
+    custom_store: bool,
+}
+
+impl FormTokenMiddleware {
+    /// Create middleware signing tokens with `secret`; tokens expire after one hour
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        let ttl = Duration::from_secs(3600);
+        Self {
+            tokens: Arc::new(FormTokens {
+                signer: Signer::new(secret),
+                ttl,
+                used: Arc::new(InMemorySeenIds::new(ttl, 100_000)),
+            }),
+            required: false,
+            custom_store: false,
+        }
+    }
+
+    /// Set how long issued tokens stay valid
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        if let Some(tokens) = Arc::get_mut(&mut self.tokens) {
+            tokens.ttl = ttl;
+            // Spent tokens must be remembered for as long as they are valid
+            if !self.custom_store {
+                tokens.used = Arc::new(InMemorySeenIds::new(ttl, 100_000));
+            }
+        }
+        self
+    }
+
+    /// Use a custom store for spent tokens (e.g. shared across processes)
+    pub fn with_store<S: SeenIdStore>(mut self, store: S) -> Self {
+        if let Some(tokens) = Arc::get_mut(&mut self.tokens) {
+            tokens.used = Arc::new(store);
+        }
+        self.custom_store = true;
+        self
+    }
+
+    /// Reject unsafe-method submissions that carry no token
+    pub fn require_token(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    fn submitted_token(req: &PingoraHttpRequest) -> Option<String> {
+        if let Some(v) = req
+            .headers()
+            .get(FORM_TOKEN_HEADER)
+            .and_then(|v| v.to_str().ok())
+        {
+            return Some(v.to_string());
+        }
+        let form: std::collections::HashMap<String, String> = req.parse_form().ok()?;
+        form.get(FORM_TOKEN_FIELD).cloned()
+    }
+}
+
+#[async_trait]
+impl Middleware for FormTokenMiddleware {
+    async fn handle(
+        &self,
+        mut req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let mut redeemed = None;
+        let unsafe_method = !matches!(
+            *req.method(),
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        );
+        if unsafe_method {
+            // The token may sit in a form body that hasn't been read yet
+            if !req.headers().contains_key(FORM_TOKEN_HEADER) {
+                req.body_bytes().await?;
+            }
+            match Self::submitted_token(&req) {
+                Some(token) => match self.tokens.redeem(&token).await {
+                    Ok(nonce) => redeemed = Some(nonce),
+                    Err(res) => return Ok(res),
+                },
+                None if self.required => {
+                    return Ok(PingoraWebHttpResponse::text(
+                        StatusCode::BAD_REQUEST,
+                        "Missing Form Token",
+                    ));
+                }
+                None => {}
+            }
+        }
+
+        req.set_request_share_data(self.tokens.clone());
+        let result = next.handle(req).await;
+        if let Some(nonce) = redeemed {
+            // The nonce is claimed up front so concurrent replays conflict, and
+            // handed back when the submission itself was rejected
+            let status = match &result {
+                Ok(res) => res.status,
+                Err(e) => e.status_code(),
+            };
+            if status.is_client_error() {
+                self.tokens.used.remove(&nonce).await;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use crate::core::Flash;
+
+    fn app(middleware: FormTokenMiddleware) -> App {
+        let mut app = App::default();
+        app.use_middleware(middleware);
+        app.get_fn("/form", |req| req.issue_form_token().unwrap_or_default());
+        app.post_fn("/form", |req| {
+            let form: std::collections::HashMap<String, String> =
+                req.parse_form().unwrap_or_default();
+            if form.get("name").is_some_and(|n| n.is_empty()) {
+                return PingoraWebHttpResponse::text(StatusCode::UNPROCESSABLE_ENTITY, "Name");
+            }
+            PingoraWebHttpResponse::redirect_with_flash("/done", Flash::success("Saved"))
+        });
+        app
+    }
+
+    #[tokio::test]
+    async fn token_can_only_be_used_once() {
+        let app = app(FormTokenMiddleware::new("secret"));
+        let client = app.test_client();
+        let token = client.get("/form").send().await.text();
+
+        let submit = || {
+            client
+                .post("/form")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .body(format!("name=a&{FORM_TOKEN_FIELD}={token}"))
+                .send()
+        };
+        assert_eq!(submit().await.status(), StatusCode::SEE_OTHER);
+        assert_eq!(submit().await.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn rejected_submissions_can_be_resent() {
+        let app = app(FormTokenMiddleware::new("secret"));
+        let client = app.test_client();
+        let token = client.get("/form").send().await.text();
+
+        let submit = |name: &str| {
+            client
+                .post("/form")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .body(format!("name={name}&{FORM_TOKEN_FIELD}={token}"))
+                .send()
+        };
+        assert_eq!(submit("").await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(submit("a").await.status(), StatusCode::SEE_OTHER);
+        assert_eq!(submit("a").await.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn forged_or_missing_tokens() {
+        let app = app(FormTokenMiddleware::new("secret").require_token());
+        let client = app.test_client();
+
+        let res = client
+            .post("/form")
+            .header(FORM_TOKEN_HEADER, "forged.token")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = client.post("/form").send().await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(res.text(), "Missing Form Token");
+    }
+}
@@ -0,0 +1,235 @@
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use http::{HeaderMap, Method, StatusCode, header};
+use prometheus::{IntCounterVec, Opts, Registry};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, OnceLock};
+
+use super::Middleware;
+use crate::core::request::if_none_match;
+use crate::core::response::Body;
+use crate::core::router::UNMATCHED_ROUTE;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+#[derive(Clone)]
+struct EtagMetrics {
+    revalidations: IntCounterVec,
+}
+
+impl EtagMetrics {
+    fn new() -> prometheus::Result<Self> {
+        Ok(Self {
+            revalidations: IntCounterVec::new(
+                Opts::new(
+                    "pingora_web_etag_revalidations_total",
+                    "Conditional GET requests by whether the client's copy was current",
+                ),
+                &["route", "result"],
+            )?,
+        })
+    }
+
+    fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.revalidations.clone()))
+    }
+}
+
+/// ETag metrics in the default registry, shared by every `JsonEtagMiddleware`
+fn default_metrics() -> &'static EtagMetrics {
+    static DEFAULT: OnceLock<EtagMetrics> = OnceLock::new();
+    DEFAULT.get_or_init(|| {
+        let metrics = EtagMetrics::new().expect("valid metric definitions");
+        if let Err(e) = metrics.register(prometheus::default_registry()) {
+            tracing::warn!("Failed to register pingora_web ETag metrics: {}", e);
+        }
+        metrics
+    })
+}
+
+/// Weak ETags and 304 answers for JSON API endpoints that clients poll
+///
+/// Buffered 200 JSON responses to GET and HEAD without an `ETag` get one
+/// derived from a hash of the body, e.g. `W/"lT3a..."`. When the request's
+/// `If-None-Match` names the response's tag (computed or set by the handler),
+/// the answer becomes a bodiless 304, saving the transfer but not the work
+/// of building the body; use `req.if_none_match` in handlers for that.
+///
+/// Conditional requests are counted in
+/// `pingora_web_etag_revalidations_total{route,result}` with `result` being
+/// `hit` (304) or `miss`; the 304 hit rate is `hit / (hit + miss)`.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, JsonEtagMiddleware, PingoraWebHttpResponse, StatusCode};
+///
+/// let mut app = App::default();
+/// app.use_middleware(JsonEtagMiddleware::new());
+/// app.get_fn("/jobs/{id}", |_| {
+///     PingoraWebHttpResponse::json(StatusCode::OK, serde_json::json!({ "state": "running" }))
+/// });
+/// ```
+pub struct JsonEtagMiddleware {
+    metrics: EtagMetrics,
+}
+
+impl Default for JsonEtagMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JsonEtagMiddleware {
+    /// Count revalidations in the default Prometheus registry
+    pub fn new() -> Self {
+        Self {
+            metrics: default_metrics().clone(),
+        }
+    }
+
+    /// Count revalidations in a custom registry
+    pub fn with_registry(mut self, registry: &Registry) -> prometheus::Result<Self> {
+        let metrics = EtagMetrics::new()?;
+        metrics.register(registry)?;
+        self.metrics = metrics;
+        Ok(self)
+    }
+}
+
+/// Weak tag from a hash of the serialized body
+fn weak_etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    format!("W/\"{}\"", URL_SAFE_NO_PAD.encode(&digest[..16]))
+}
+
+fn is_json(res: &PingoraWebHttpResponse) -> bool {
+    let Some(content_type) = res
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime == "application/json" || mime.ends_with("+json")
+}
+
+#[async_trait]
+impl Middleware for JsonEtagMiddleware {
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        if !matches!(*req.method(), Method::GET | Method::HEAD) {
+            return next.handle(req).await;
+        }
+        let mut conditions = HeaderMap::new();
+        for value in req.headers().get_all(header::IF_NONE_MATCH) {
+            conditions.append(header::IF_NONE_MATCH, value.clone());
+        }
+        let route = req.matched_route().unwrap_or(UNMATCHED_ROUTE).to_string();
+
+        let mut res = next.handle(req).await?;
+        if res.status != StatusCode::OK {
+            return Ok(res);
+        }
+        if !res.headers.contains_key(header::ETAG)
+            && is_json(&res)
+            && let Body::Bytes(body) = &res.body
+        {
+            let tag = weak_etag(body);
+            res.set_header(header::ETAG, tag);
+        }
+        let Some(tag) = res
+            .headers
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+        else {
+            return Ok(res);
+        };
+        if conditions.is_empty() {
+            return Ok(res);
+        }
+
+        let hit = if_none_match(&conditions, &tag);
+        let result = if hit { "hit" } else { "miss" };
+        self.metrics
+            .revalidations
+            .with_label_values(&[route.as_str(), result])
+            .inc();
+        if hit {
+            res.make_not_modified();
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[tokio::test]
+    async fn answers_polls_with_304_until_the_body_changes() {
+        let registry = Registry::new();
+        let version = Arc::new(AtomicU64::new(1));
+        let mut app = App::default();
+        app.use_middleware(JsonEtagMiddleware::new().with_registry(&registry).unwrap());
+        let current = version.clone();
+        app.get_fn("/jobs/{id}", move |_| {
+            let v = current.load(Ordering::Relaxed);
+            PingoraWebHttpResponse::json(StatusCode::OK, serde_json::json!({ "version": v }))
+        });
+        app.get_fn("/text", |_| "plain");
+        let client = app.test_client();
+
+        let res = client.get("/jobs/7").send().await;
+        let tag = res.header("etag").unwrap().to_string();
+        assert!(tag.starts_with("W/\""));
+
+        let res = client
+            .get("/jobs/7")
+            .header("if-none-match", &tag)
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert!(res.bytes().is_empty());
+        assert_eq!(res.header("etag"), Some(tag.as_str()));
+
+        version.store(2, Ordering::Relaxed);
+        let res = client
+            .get("/jobs/7")
+            .header("if-none-match", &tag)
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_ne!(res.header("etag"), Some(tag.as_str()));
+
+        let res = client.get("/text").send().await;
+        assert_eq!(res.header("etag"), None);
+
+        let count = |result: &str| {
+            registry
+                .gather()
+                .iter()
+                .flat_map(|f| f.get_metric().to_vec())
+                .find(|m| {
+                    m.get_label()
+                        .iter()
+                        .any(|l| l.get_name() == "result" && l.get_value() == result)
+                })
+                .map_or(0.0, |m| m.get_counter().get_value())
+        };
+        assert_eq!(count("hit"), 1.0);
+        assert_eq!(count("miss"), 1.0);
+    }
+}
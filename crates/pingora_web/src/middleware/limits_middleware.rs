@@ -5,7 +5,7 @@ use std::time::Duration;
 use tokio::time::timeout;
 
 use super::Middleware;
-use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::core::{Deadline, Handler, PingoraHttpRequest, PingoraWebHttpResponse};
 use crate::error::WebError;
 
 /// Configuration for timeout and size limits
@@ -135,7 +135,24 @@ impl LimitsMiddleware {
             }
         }
 
-        // Check body size
+        // Check body size: the declared length rejects uploads before they are read
+        let declared = req
+            .headers()
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        if declared > self.config.max_body_size {
+            tracing::warn!(
+                "Declared request body too large: {} > {}",
+                declared,
+                self.config.max_body_size
+            );
+            return Some(PingoraWebHttpResponse::text(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Payload Too Large",
+            ));
+        }
         if req.body().len() > self.config.max_body_size {
             tracing::warn!(
                 "Request body too large: {} > {}",
@@ -162,13 +179,16 @@ impl Default for LimitsMiddleware {
 impl Middleware for LimitsMiddleware {
     async fn handle(
         &self,
-        req: PingoraHttpRequest,
+        mut req: PingoraHttpRequest,
         next: Arc<dyn Handler>,
     ) -> Result<PingoraWebHttpResponse, WebError> {
         // First validate request limits
         if let Some(error_response) = self.validate_request(&req) {
             return Ok(error_response);
         }
+        // Bodies read later (e.g. chunked uploads) stop at the same limit
+        req.set_body_limit(req.body_limit().min(self.config.max_body_size));
+        req.set_deadline(Deadline::after(self.config.request_timeout));
 
         // Apply timeout to the entire request processing
         match timeout(self.config.request_timeout, next.handle(req)).await {
@@ -185,6 +205,24 @@ impl Middleware for LimitsMiddleware {
             }
         }
     }
+
+    fn check(&self) -> Vec<String> {
+        let config = &self.config;
+        let mut problems = Vec::new();
+        if config.request_timeout.is_zero() {
+            problems.push("request_timeout is 0, every request would time out".to_string());
+        }
+        for (name, value) in [
+            ("max_path_length", config.max_path_length),
+            ("max_headers", config.max_headers),
+            ("max_header_size", config.max_header_size),
+        ] {
+            if value == 0 {
+                problems.push(format!("{name} is 0, every request would be rejected"));
+            }
+        }
+        problems
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,327 @@
+use async_trait::async_trait;
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, exponential_buckets,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+use super::Middleware;
+use crate::core::annotations::{
+    COMPRESSION_ALGORITHM, RESPONSE_BODY_BYTES, RESPONSE_WRITE_FAILURE,
+};
+use crate::core::response::Body;
+use crate::core::router::UNMATCHED_ROUTE;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::{ResponseError, WebError};
+use crate::utils::metrics_endpoint::exemplars;
+
+const LATENCY_METRIC: &str = "pingora_web_request_duration_seconds";
+
+#[derive(Clone)]
+struct Metrics {
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    in_flight: IntGaugeVec,
+    response_size: HistogramVec,
+    body_bytes: HistogramVec,
+    write_failures: IntCounterVec,
+}
+
+impl Metrics {
+    /// Metric definitions, with a constant `worker` label when given
+    fn new(worker: Option<usize>) -> prometheus::Result<Self> {
+        let const_labels: HashMap<String, String> = worker
+            .map(|w| ("worker".to_string(), w.to_string()))
+            .into_iter()
+            .collect();
+        let opts =
+            |name: &str, help: &str| Opts::new(name, help).const_labels(const_labels.clone());
+        let histogram_opts = |name: &str, help: &str| {
+            HistogramOpts::new(name, help).const_labels(const_labels.clone())
+        };
+        let labels = &["method", "route", "status"];
+        Ok(Self {
+            requests: IntCounterVec::new(
+                opts("pingora_web_requests_total", "Total HTTP requests"),
+                labels,
+            )?,
+            latency: HistogramVec::new(
+                histogram_opts(LATENCY_METRIC, "HTTP request latency in seconds"),
+                labels,
+            )?,
+            in_flight: IntGaugeVec::new(
+                opts(
+                    "pingora_web_requests_in_flight",
+                    "HTTP requests currently being handled",
+                ),
+                &["method", "route"],
+            )?,
+            response_size: HistogramVec::new(
+                histogram_opts(
+                    "pingora_web_response_size_bytes",
+                    "HTTP response body size in bytes (buffered bodies only)",
+                )
+                .buckets(exponential_buckets(64.0, 4.0, 10)?),
+                labels,
+            )?,
+            body_bytes: HistogramVec::new(
+                histogram_opts(
+                    "pingora_web_response_body_bytes_sent",
+                    "HTTP response body bytes sent, after compression",
+                )
+                .buckets(exponential_buckets(64.0, 4.0, 10)?),
+                &["method", "route", "status", "encoding"],
+            )?,
+            write_failures: IntCounterVec::new(
+                opts(
+                    "pingora_web_response_write_failures_total",
+                    "HTTP responses cut short while being written",
+                ),
+                &["method", "route", "stage"],
+            )?,
+        })
+    }
+
+    fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.requests.clone()))?;
+        registry.register(Box::new(self.latency.clone()))?;
+        registry.register(Box::new(self.in_flight.clone()))?;
+        registry.register(Box::new(self.response_size.clone()))?;
+        registry.register(Box::new(self.body_bytes.clone()))?;
+        registry.register(Box::new(self.write_failures.clone()))?;
+        Ok(())
+    }
+}
+
+/// Metrics in the default registry, shared by every `MetricsMiddleware::new()`
+fn default_metrics() -> &'static Metrics {
+    static DEFAULT: OnceLock<Metrics> = OnceLock::new();
+    DEFAULT.get_or_init(|| {
+        let metrics = Metrics::new(None).expect("valid metric definitions");
+        if let Err(e) = metrics.register(prometheus::default_registry()) {
+            tracing::warn!("Failed to register pingora_web metrics: {}", e);
+        }
+        metrics
+    })
+}
+
+/// Prometheus metrics: request count, latency, in-flight requests, and response size
+///
+/// Labels are the method, the matched route pattern (e.g. `/users/{id}`, never
+/// the raw path), and the status class (`2xx`, `4xx`, ...). `new()` registers into
+/// the default registry, which `Service::prometheus_http_service()` exports.
+///
+/// Errors are recorded with the status of the response they turn into.
+///
+/// Once a response is written to the connection, the body bytes actually sent
+/// are recorded with the negotiated `encoding` (`identity` when uncompressed);
+/// responses cut short count toward `pingora_web_response_write_failures_total`
+/// by the failed `stage`.
+///
+/// Requests carrying a trace id (see `TracingMiddleware`) leave it as an exemplar
+/// on the latency histogram; serve them with `utils::MetricsEndpoint`, since
+/// Pingora's Prometheus service cannot expose exemplars.
+pub struct MetricsMiddleware {
+    metrics: Metrics,
+}
+
+impl MetricsMiddleware {
+    /// Record into the default Prometheus registry
+    pub fn new() -> Self {
+        Self {
+            metrics: default_metrics().clone(),
+        }
+    }
+
+    /// Record into the default registry with a constant `worker` label, so
+    /// scrapes of a port shared by `Workers` show which process answered
+    ///
+    /// A metric name cannot be registered both with and without the label, so
+    /// use this instead of `new()` in worker processes.
+    pub fn for_worker(worker: usize) -> Self {
+        static WORKERS: OnceLock<Mutex<HashMap<usize, Metrics>>> = OnceLock::new();
+        let mut workers = WORKERS.get_or_init(Default::default).lock().unwrap();
+        let metrics = workers.entry(worker).or_insert_with(|| {
+            let metrics = Metrics::new(Some(worker)).expect("valid metric definitions");
+            if let Err(e) = metrics.register(prometheus::default_registry()) {
+                tracing::warn!("Failed to register pingora_web worker metrics: {}", e);
+            }
+            metrics
+        });
+        Self {
+            metrics: metrics.clone(),
+        }
+    }
+
+    /// Record into a custom registry
+    pub fn with_registry(registry: &Registry) -> prometheus::Result<Self> {
+        let metrics = Metrics::new(None)?;
+        metrics.register(registry)?;
+        Ok(Self { metrics })
+    }
+}
+
+impl Default for MetricsMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Middleware for MetricsMiddleware {
+    fn convert_errors(&self) -> bool {
+        true
+    }
+
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let method = req.method().to_string();
+        let route = req.matched_route().unwrap_or(UNMATCHED_ROUTE).to_string();
+        let trace_id = req.trace_id().map(ToString::to_string);
+        let annotations = req.annotations();
+
+        let in_flight = self.metrics.in_flight.with_label_values(&[&method, &route]);
+        in_flight.inc();
+        let start = Instant::now();
+
+        let result = next.handle(req).await;
+
+        in_flight.dec();
+        let (status, size) = match &result {
+            Ok(res) => (
+                res.status,
+                match &res.body {
+                    Body::Bytes(b) => Some(b.len()),
+                    Body::Stream(_) => None,
+                },
+            ),
+            // Composed chains hand us error responses; this covers direct calls
+            Err(e) => (e.status_code(), None),
+        };
+        let class = format!("{}xx", status.as_u16() / 100);
+        let labels = [method.as_str(), route.as_str(), class.as_str()];
+
+        self.metrics.requests.with_label_values(&labels).inc();
+        let elapsed = start.elapsed().as_secs_f64();
+        self.metrics
+            .latency
+            .with_label_values(&labels)
+            .observe(elapsed);
+        if let Some(trace_id) = &trace_id {
+            exemplars::record(
+                LATENCY_METRIC,
+                &[("method", &method), ("route", &route), ("status", &class)],
+                prometheus::DEFAULT_BUCKETS,
+                elapsed,
+                trace_id,
+            );
+        }
+        if let Some(size) = size {
+            self.metrics
+                .response_size
+                .with_label_values(&labels)
+                .observe(size as f64);
+        }
+        if let Some(annotations) = annotations {
+            let body_bytes = self.metrics.body_bytes.clone();
+            let write_failures = self.metrics.write_failures.clone();
+            annotations.on_complete(move |a| {
+                if let Some(stage) = a.get(RESPONSE_WRITE_FAILURE) {
+                    write_failures
+                        .with_label_values(&[&method, &route, &stage])
+                        .inc();
+                }
+                let Some(sent) = a
+                    .get(RESPONSE_BODY_BYTES)
+                    .and_then(|b| b.parse::<f64>().ok())
+                else {
+                    return;
+                };
+                let encoding = a
+                    .get(COMPRESSION_ALGORITHM)
+                    .unwrap_or_else(|| "identity".to_string());
+                body_bytes
+                    .with_label_values(&[&method, &route, &class, &encoding])
+                    .observe(sent);
+            });
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    #[tokio::test]
+    async fn records_by_route_pattern() {
+        let registry = Registry::new();
+        let mut app = App::default();
+        app.use_middleware(MetricsMiddleware::with_registry(&registry).unwrap());
+        app.get_fn("/users/{id}", |req| {
+            format!("user {}", req.param("id").unwrap_or(""))
+        });
+
+        let client = app.test_client();
+        client.get("/users/1").send().await;
+        client.get("/users/2").send().await;
+        client.get("/nope").send().await;
+
+        let metrics = MetricsMiddleware::with_registry(&registry);
+        assert!(metrics.is_err(), "metrics are registered once per registry");
+
+        let families = registry.gather();
+        let requests = families
+            .iter()
+            .find(|f| f.get_name() == "pingora_web_requests_total")
+            .unwrap();
+        let count = |route: &str, status: &str| {
+            requests
+                .get_metric()
+                .iter()
+                .find(|m| {
+                    m.get_label().iter().any(|l| l.get_value() == route)
+                        && m.get_label().iter().any(|l| l.get_value() == status)
+                })
+                .map(|m| m.get_counter().get_value())
+                .unwrap_or(0.0)
+        };
+        assert_eq!(count("/users/{id}", "2xx"), 2.0);
+        assert_eq!(count(UNMATCHED_ROUTE, "4xx"), 1.0);
+
+        let sizes = families
+            .iter()
+            .find(|f| f.get_name() == "pingora_web_response_size_bytes")
+            .unwrap();
+        assert!(!sizes.get_metric().is_empty());
+    }
+    #[tokio::test]
+    async fn worker_metrics_carry_a_worker_label() {
+        // `for_worker` uses the default registry; a private one keeps this
+        // test independent of the others
+        let registry = Registry::new();
+        let metrics = Metrics::new(Some(41)).unwrap();
+        metrics.register(&registry).unwrap();
+        let mut app = App::default();
+        app.use_middleware(MetricsMiddleware { metrics });
+        app.get_fn("/", |_| "ok");
+        app.test_client().get("/").send().await;
+
+        let families = registry.gather();
+        let requests = families
+            .iter()
+            .find(|f| f.get_name() == "pingora_web_requests_total")
+            .unwrap();
+        let labels = requests.get_metric()[0].get_label();
+        assert!(
+            labels
+                .iter()
+                .any(|l| l.get_name() == "worker" && l.get_value() == "41")
+        );
+    }
+}
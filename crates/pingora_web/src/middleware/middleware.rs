@@ -5,6 +5,11 @@ use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
 use crate::error::WebError;
 
 /// Middleware trait for processing requests
+///
+/// The App routes a request before running middleware, so `handle` already
+/// sees `req.params`, `req.matched_route()` and `req.route_info()` of the
+/// route that will handle it; e.g. authorization can check an `{org_id}`
+/// path param. The body is still unread at this point.
 #[async_trait]
 pub trait Middleware: Send + Sync + 'static {
     /// Process the request, optionally calling the next handler
@@ -13,6 +18,27 @@ pub trait Middleware: Send + Sync + 'static {
         req: PingoraHttpRequest,
         next: Arc<dyn Handler>,
     ) -> Result<PingoraWebHttpResponse, WebError>;
+
+    /// Receive errors from `next` as the responses they turn into
+    ///
+    /// By default an `Err` from further in the chain reaches `handle` as is and
+    /// becomes a response only after the whole chain returned. Return `true`
+    /// when `handle` must see the final error response instead, e.g. to record
+    /// its exact status; middleware wrapping this one then see it as a
+    /// response too.
+    fn convert_errors(&self) -> bool {
+        false
+    }
+
+    /// Problems with this middleware's options, reported by `App::check`
+    fn check(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Name shown in the route table (default: the type name)
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
 }
 
 /// Wrapper that implements Handler for middleware composition
@@ -28,6 +54,21 @@ impl Handler for MiddlewareHandler {
     }
 }
 
+/// Turns errors from the wrapped handler into responses
+struct ErrorResponses(Arc<dyn Handler>);
+
+#[async_trait]
+impl Handler for ErrorResponses {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let request_id = req.request_id().map(ToString::to_string);
+        Ok(self
+            .0
+            .handle(req)
+            .await
+            .unwrap_or_else(|e| e.into_response_with_id(request_id.as_deref())))
+    }
+}
+
 /// Compose multiple middlewares around a final handler
 /// Creates an onion model where the last middleware wraps all previous ones
 pub fn compose(
@@ -39,7 +80,10 @@ pub fn compose(
     // 从后往前遍历中间件，让后注册的中间件在外层
     for i in (0..middlewares.len()).rev() {
         let middleware = Arc::clone(&middlewares[i]);
-        let next_handler = Arc::clone(&current_handler);
+        let mut next_handler = Arc::clone(&current_handler);
+        if middleware.convert_errors() {
+            next_handler = Arc::new(ErrorResponses(next_handler));
+        }
 
         // 创建一个新的处理器，将当前中间件包装在外层
         current_handler = Arc::new(MiddlewareHandler {
@@ -50,3 +94,128 @@ pub fn compose(
 
     current_handler
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use http::StatusCode;
+    use std::sync::Mutex;
+
+    /// Records what `next` returned: a status, or `None` for `Err`
+    struct Observer {
+        convert: bool,
+        seen: Arc<Mutex<Vec<Option<StatusCode>>>>,
+    }
+
+    #[async_trait]
+    impl Middleware for Observer {
+        async fn handle(
+            &self,
+            req: PingoraHttpRequest,
+            next: Arc<dyn Handler>,
+        ) -> Result<PingoraWebHttpResponse, WebError> {
+            let result = next.handle(req).await;
+            let status = result.as_ref().ok().map(|res| res.status);
+            self.seen.lock().unwrap().push(status);
+            result
+        }
+
+        fn convert_errors(&self) -> bool {
+            self.convert
+        }
+    }
+
+    #[tokio::test]
+    async fn converting_middleware_see_error_responses() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut app = App::default();
+        for convert in [false, true, false] {
+            app.use_middleware(Observer {
+                convert,
+                seen: seen.clone(),
+            });
+        }
+        app.get_fn("/fail", |_req| {
+            Err::<PingoraWebHttpResponse, _>(crate::error::unprocessable_entity("nope"))
+        });
+
+        let res = app.test_client().get("/fail").send().await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        // Innermost first: the error, then its response for the converting
+        // middleware and everything wrapping it
+        let status = Some(StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(*seen.lock().unwrap(), vec![None, status, status]);
+    }
+
+    /// Allows a request only for the org named in its path, per route scope
+    struct OrgAuth;
+
+    #[async_trait]
+    impl Middleware for OrgAuth {
+        async fn handle(
+            &self,
+            req: PingoraHttpRequest,
+            next: Arc<dyn Handler>,
+        ) -> Result<PingoraWebHttpResponse, WebError> {
+            let Some(route) = req.route_info() else {
+                return next.handle(req).await;
+            };
+            let scope = route.meta.metadata.get("scope").cloned();
+            let org = req.headers().get("x-org").and_then(|v| v.to_str().ok());
+            if scope.as_deref() == Some("org") && org != req.param("org_id") {
+                return Ok(PingoraWebHttpResponse::text(
+                    StatusCode::FORBIDDEN,
+                    route.pattern.as_str(),
+                ));
+            }
+            next.handle(req).await
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_see_route_params_and_metadata() {
+        let mut app = App::default();
+        app.use_middleware(OrgAuth);
+        app.get_fn("/orgs/{org_id}/bills", |_req| "bills");
+        let meta = crate::core::RouteMeta::new()
+            .name("bills")
+            .meta("scope", "org");
+        app.set_route_meta(http::Method::GET, "/orgs/{org_id}/bills", meta);
+        // Same pattern on another host, without the org scope
+        let mut other = crate::core::router::Router::new();
+        other.get_fn("/orgs/{org_id}/bills", |_req| "other bills");
+        app.vhost("other.test", other);
+
+        let client = app.test_client();
+        let res = client
+            .get("/orgs/7/bills")
+            .header("x-org", "7")
+            .send()
+            .await;
+        assert_eq!(res.text(), "bills");
+        let res = client
+            .get("/orgs/7/bills")
+            .header("x-org", "8")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(res.text(), "/orgs/{org_id}/bills");
+        // HEAD is served by the GET route and gets its metadata
+        let res = client
+            .head("/orgs/7/bills")
+            .header("x-org", "8")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = client
+            .get("/orgs/7/bills")
+            .header("host", "other.test")
+            .header("x-org", "8")
+            .send()
+            .await;
+        assert_eq!(res.text(), "other bills");
+        let res = client.get("/missing").send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
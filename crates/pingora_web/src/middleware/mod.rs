@@ -1,12 +1,69 @@
 #![allow(clippy::module_inception)]
+pub mod bot_guard_middleware;
+pub mod cache_middleware;
+#[cfg(feature = "compression-dictionary")]
+pub mod compression_dictionary_middleware;
+pub mod conditional_middleware;
+#[cfg(feature = "openapi")]
+pub mod contract_middleware;
+pub mod duplicate_request_id_middleware;
+pub mod ext_authz_middleware;
+pub mod flash_middleware;
+pub mod form_token_middleware;
+pub mod json_etag_middleware;
+#[cfg(feature = "jwt")]
+pub mod jwt_middleware;
 pub mod limits_middleware;
+pub mod metrics_middleware;
 pub mod middleware;
 pub mod panic_recovery_middleware;
 pub mod request_id_middleware;
+#[cfg(feature = "accounting")]
+pub mod resource_middleware;
+#[cfg(feature = "scripting")]
+pub mod script_middleware;
+pub mod tenant_middleware;
 pub mod tracing_middleware;
+pub mod usage_middleware;
+#[cfg(feature = "user-agent")]
+pub mod user_agent_middleware;
 
+pub use bot_guard_middleware::{BotGuard, JS_CHALLENGE_COOKIE, JsChallengeMiddleware};
+pub use cache_middleware::{CacheMiddleware, X_CACHE};
+#[cfg(feature = "compression-dictionary")]
+pub use compression_dictionary_middleware::{
+    AVAILABLE_DICTIONARY, CompressionDictionaryMiddleware, USE_AS_DICTIONARY,
+};
+pub use conditional_middleware::{ConditionalMiddleware, MiddlewareExt};
+#[cfg(feature = "openapi")]
+pub use contract_middleware::{ContractMiddleware, ContractSide, ContractViolation};
+pub use duplicate_request_id_middleware::{
+    DUPLICATE_REQUEST_IDS, DuplicateRequestIdMiddleware, InMemorySeenIds, SeenIdStore,
+};
+pub use ext_authz_middleware::{
+    AuthzDecision, AuthzRequest, AuthzService, ExtAuthzMiddleware, HttpAuthzService,
+};
+pub use flash_middleware::FlashMiddleware;
+pub use form_token_middleware::{
+    FORM_TOKEN_FIELD, FORM_TOKEN_HEADER, FormTokenMiddleware, FormTokens,
+};
+pub use json_etag_middleware::JsonEtagMiddleware;
+#[cfg(feature = "jwt")]
+pub use jwt_middleware::{Claims, JwtError, JwtMiddleware};
 pub use limits_middleware::{LimitsConfig, LimitsMiddleware};
+pub use metrics_middleware::MetricsMiddleware;
 pub use middleware::{Middleware, compose};
 pub use panic_recovery_middleware::PanicRecoveryMiddleware;
 pub use request_id_middleware::RequestId;
+#[cfg(feature = "accounting")]
+pub use resource_middleware::{CountingAllocator, ResourceMiddleware, ResourceUsage};
+#[cfg(feature = "scripting")]
+pub use script_middleware::{ScriptError, ScriptMiddleware};
+pub use tenant_middleware::{InMemoryTenants, TenantMiddleware, TenantSource, TenantStore};
 pub use tracing_middleware::TracingMiddleware;
+pub use usage_middleware::{
+    Backpressure, FileSink, HttpBatchSink, KafkaProducer, KafkaSink, UsageMiddleware,
+    UsagePipeline, UsageRecord, UsageSink,
+};
+#[cfg(feature = "user-agent")]
+pub use user_agent_middleware::{BotPolicy, Device, UserAgent, UserAgentMiddleware};
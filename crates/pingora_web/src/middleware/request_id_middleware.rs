@@ -1,22 +1,54 @@
+use crate::core::response::insert_header;
+use crate::utils::request_id::RequestIdHeader;
+use crate::utils::{RequestIdGenerator, RequestIdPolicy};
 use crate::{
     core::Handler,
     core::{PingoraHttpRequest, PingoraWebHttpResponse},
     error::WebError,
     middleware::Middleware,
 };
+use http::HeaderName;
 use std::sync::Arc;
 
-#[derive(Clone)]
+/// Middleware ensuring every request and response carries an `x-request-id`
+///
+/// Requests served by an `App` already have an id (see
+/// `App::set_request_id_policy`); this middleware reuses it and only copies it
+/// into its own header when one was set with `header()`. The policy and
+/// generator apply to requests handled without an App.
+#[derive(Clone, Debug)]
 pub struct RequestId {
-    header: &'static str,
+    header: Option<HeaderName>,
+    policy: RequestIdPolicy,
+    generator: RequestIdGenerator,
 }
 
 impl RequestId {
     pub fn new() -> Self {
         Self {
-            header: "x-request-id",
+            header: None,
+            policy: RequestIdPolicy::default(),
+            generator: RequestIdGenerator::default(),
         }
     }
+
+    /// Carry the id in `header` instead of the App's request id header
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = Some(header);
+        self
+    }
+
+    /// Choose which client-sent ids are kept
+    pub fn policy(mut self, policy: RequestIdPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Choose how missing or rejected ids are generated
+    pub fn generator(mut self, generator: RequestIdGenerator) -> Self {
+        self.generator = generator;
+        self
+    }
 }
 
 impl Default for RequestId {
@@ -32,29 +64,30 @@ impl Middleware for RequestId {
         mut req: PingoraHttpRequest,
         next: Arc<dyn Handler>,
     ) -> Result<PingoraWebHttpResponse, WebError> {
-        // Generate or use existing request ID
-        let request_id = req
-            .headers()
-            .get(self.header)
-            .and_then(|v| v.to_str().ok())
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string())
-            .unwrap_or_else(crate::utils::request_id::generate);
+        let assigned = req
+            .get_request_share_data::<RequestIdHeader>()
+            .map(|h| h.0.clone());
+        let header = self
+            .header
+            .clone()
+            .or_else(|| assigned.clone())
+            .unwrap_or(HeaderName::from_static("x-request-id"));
+        // Keep the App's id; otherwise use the client's when the policy accepts it
+        let request_id = match assigned.as_ref().and_then(|_| req.request_id()) {
+            Some(id) => id.to_string(),
+            None => self
+                .policy
+                .resolve(req.headers().get(&header), &self.generator),
+        };
 
         // Store request ID in request headers for later access
-        let _ = req.headers_mut().insert(
-            self.header,
-            http::HeaderValue::from_str(&request_id).unwrap(),
-        );
+        insert_header(req.headers_mut(), header.clone(), &request_id);
 
         let mut res = next.handle(req).await?;
 
         // Ensure response has the request ID header
-        if !res.headers.contains_key(self.header) {
-            let _ = res.headers.insert(
-                self.header,
-                http::HeaderValue::from_str(&request_id).unwrap(),
-            );
+        if !res.headers.contains_key(&header) {
+            insert_header(&mut res.headers, header, &request_id);
         }
         Ok(res)
     }
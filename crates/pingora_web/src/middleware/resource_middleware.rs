@@ -0,0 +1,287 @@
+use async_trait::async_trait;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use super::Middleware;
+use crate::core::router::UNMATCHED_ROUTE;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+thread_local! {
+    static ALLOCATED: Cell<u64> = const { Cell::new(0) };
+}
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+/// Bytes allocated on this thread so far, `None` without `CountingAllocator`
+fn thread_allocated() -> Option<u64> {
+    if !COUNTING.load(Ordering::Relaxed) {
+        return None;
+    }
+    ALLOCATED.try_with(Cell::get).ok()
+}
+
+/// Global allocator wrapper that lets `ResourceMiddleware` attribute allocations to requests
+///
+/// Counts bytes allocated per thread; install it in the binary to enable
+/// allocation accounting:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOC: pingora_web::CountingAllocator = pingora_web::CountingAllocator::system();
+/// ```
+pub struct CountingAllocator<A = System> {
+    inner: A,
+}
+
+impl CountingAllocator<System> {
+    pub const fn system() -> Self {
+        Self { inner: System }
+    }
+}
+
+impl<A> CountingAllocator<A> {
+    /// Count allocations made through another allocator
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    fn count(&self, size: usize) {
+        COUNTING.store(true, Ordering::Relaxed);
+        let _ = ALLOCATED.try_with(|n| n.set(n.get().wrapping_add(size as u64)));
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.count(layout.size());
+        unsafe { self.inner.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.count(layout.size());
+        unsafe { self.inner.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.count(new_size.saturating_sub(layout.size()));
+        unsafe { self.inner.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+}
+
+/// Resources a request's handler future used
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// Times the future was polled
+    pub polls: u64,
+    /// Total time spent inside `poll` (an approximation of CPU time)
+    pub busy: Duration,
+    /// Longest single poll; long polls block other requests on the worker thread
+    pub max_poll: Duration,
+    /// Bytes allocated while polling, `None` without `CountingAllocator`
+    pub allocated: Option<u64>,
+}
+
+/// Future wrapper recording poll durations and allocation deltas
+struct Measured<F> {
+    inner: Pin<Box<F>>,
+    usage: ResourceUsage,
+}
+
+impl<F: Future> Future for Measured<F> {
+    type Output = (F::Output, ResourceUsage);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let allocated_before = thread_allocated();
+        let start = Instant::now();
+        let poll = self.inner.as_mut().poll(cx);
+        let elapsed = start.elapsed();
+
+        // A task never moves threads during a poll, so the thread counter delta is ours
+        let usage = &mut self.usage;
+        usage.polls += 1;
+        usage.busy += elapsed;
+        usage.max_poll = usage.max_poll.max(elapsed);
+        if let (Some(before), Some(after)) = (allocated_before, thread_allocated()) {
+            *usage.allocated.get_or_insert(0) += after.wrapping_sub(before);
+        }
+
+        poll.map(|output| (output, std::mem::take(&mut self.usage)))
+    }
+}
+
+fn measure<F: Future>(future: F) -> Measured<F> {
+    Measured {
+        inner: Box::pin(future),
+        usage: ResourceUsage::default(),
+    }
+}
+
+/// Experimental: per-request poll time and allocation accounting
+///
+/// Samples requests, measures their handler future (time spent polling, the
+/// longest poll, and bytes allocated when `CountingAllocator` is installed) and
+/// logs a `tracing` warning for requests over any threshold. Streaming response
+/// bodies are polled after the handler returns and are not measured.
+///
+/// ```
+/// use pingora_web::{App, ResourceMiddleware};
+/// use std::time::Duration;
+///
+/// let mut app = App::default();
+/// app.use_middleware(
+///     ResourceMiddleware::new()
+///         .sample_every(10)
+///         .max_poll(Duration::from_millis(5)),
+/// );
+/// ```
+pub struct ResourceMiddleware {
+    sample_every: u64,
+    max_busy: Duration,
+    max_poll: Duration,
+    max_allocated: u64,
+    seen: AtomicU64,
+}
+
+impl ResourceMiddleware {
+    pub fn new() -> Self {
+        Self {
+            sample_every: 1,
+            max_busy: Duration::from_millis(50),
+            max_poll: Duration::from_millis(10),
+            max_allocated: 64 * 1024 * 1024,
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    /// Measure one request in every `n` (default: all)
+    pub fn sample_every(mut self, n: u64) -> Self {
+        self.sample_every = n.max(1);
+        self
+    }
+
+    /// Report requests that spend longer than this polling in total (default 50ms)
+    pub fn max_busy(mut self, busy: Duration) -> Self {
+        self.max_busy = busy;
+        self
+    }
+
+    /// Report requests with a single poll longer than this (default 10ms)
+    pub fn max_poll(mut self, poll: Duration) -> Self {
+        self.max_poll = poll;
+        self
+    }
+
+    /// Report requests allocating more than this many bytes (default 64 MiB)
+    pub fn max_allocated(mut self, bytes: u64) -> Self {
+        self.max_allocated = bytes;
+        self
+    }
+
+    fn is_outlier(&self, usage: &ResourceUsage) -> bool {
+        usage.busy > self.max_busy
+            || usage.max_poll > self.max_poll
+            || usage.allocated.is_some_and(|n| n > self.max_allocated)
+    }
+}
+
+impl Default for ResourceMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Middleware for ResourceMiddleware {
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        if !self
+            .seen
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample_every)
+        {
+            return next.handle(req).await;
+        }
+
+        let method = req.method().to_string();
+        let route = req.matched_route().unwrap_or(UNMATCHED_ROUTE).to_string();
+        let (result, usage) = measure(next.handle(req)).await;
+
+        if self.is_outlier(&usage) {
+            tracing::warn!(
+                method = %method,
+                route = %route,
+                polls = usage.polls,
+                busy_us = usage.busy.as_micros() as u64,
+                max_poll_us = usage.max_poll.as_micros() as u64,
+                allocated = usage.allocated,
+                "Request exceeded resource thresholds"
+            );
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    #[tokio::test]
+    async fn measures_polls_and_busy_time() {
+        let (output, usage) = measure(async {
+            std::thread::sleep(Duration::from_millis(20));
+            tokio::task::yield_now().await;
+            std::thread::sleep(Duration::from_millis(5));
+            7
+        })
+        .await;
+
+        assert_eq!(output, 7);
+        assert_eq!(usage.polls, 2);
+        assert!(usage.busy >= Duration::from_millis(25));
+        assert!(usage.max_poll >= Duration::from_millis(20));
+        assert!(usage.max_poll < usage.busy);
+
+        let middleware = ResourceMiddleware::new().max_poll(Duration::from_millis(10));
+        assert!(middleware.is_outlier(&usage));
+        assert!(!middleware.is_outlier(&ResourceUsage::default()));
+    }
+
+    #[test]
+    fn counts_allocations_per_thread() {
+        let alloc = CountingAllocator::system();
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        let before = thread_allocated().unwrap_or(0);
+        unsafe {
+            let ptr = alloc.alloc(layout);
+            let ptr = alloc.realloc(ptr, layout, 6144);
+            alloc.dealloc(ptr, Layout::from_size_align(6144, 8).unwrap());
+        }
+        assert_eq!(thread_allocated(), Some(before + 6144));
+    }
+
+    #[tokio::test]
+    async fn passes_responses_through() {
+        let mut app = App::default();
+        app.use_middleware(ResourceMiddleware::new().sample_every(2));
+        app.get_fn("/", |_req| "ok");
+        let client = app.test_client();
+        for _ in 0..3 {
+            assert_eq!(client.get("/").send().await.text(), "ok");
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use async_trait::async_trait;
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use rhai::{AST, Dynamic, Engine, EvalAltResult, FuncArgs, Scope};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::Middleware;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Error compiling or loading a script
+pub type ScriptError = Box<EvalAltResult>;
+
+/// Request view handed to scripts; clones share state so edits reach the host
+#[derive(Clone)]
+struct ScriptRequest(Arc<Mutex<RequestParts>>);
+
+struct RequestParts {
+    method: String,
+    path: String,
+    headers: HeaderMap,
+}
+
+/// Response handed to scripts, either the real one (`on_response`) or a
+/// short-circuit built with `respond()`/`redirect()`
+#[derive(Clone)]
+struct ScriptResponse(Arc<Mutex<ResponseParts>>);
+
+struct ResponseParts {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Option<String>,
+}
+
+impl ScriptResponse {
+    fn new(status: StatusCode, body: Option<String>) -> Self {
+        Self(Arc::new(Mutex::new(ResponseParts {
+            status,
+            headers: HeaderMap::new(),
+            body,
+        })))
+    }
+}
+
+fn get_header(headers: &HeaderMap, name: &str) -> Dynamic {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map_or(Dynamic::UNIT, |v| v.to_string().into())
+}
+
+fn set_header(headers: &mut HeaderMap, name: &str, value: &str) {
+    match (
+        HeaderName::from_bytes(name.as_bytes()),
+        HeaderValue::from_str(value),
+    ) {
+        (Ok(name), Ok(value)) => {
+            headers.insert(name, value);
+        }
+        _ => tracing::warn!(header = name, "Script set an invalid header"),
+    }
+}
+
+fn build_engine() -> Engine {
+    let mut engine = Engine::new();
+    // Keep misbehaving scripts from stalling requests
+    engine.set_max_operations(100_000);
+    engine.set_max_call_levels(16);
+    engine.set_max_string_size(64 * 1024);
+
+    engine
+        .register_type_with_name::<ScriptRequest>("Request")
+        .register_get("method", |r: &mut ScriptRequest| {
+            r.0.lock().expect("script request").method.clone()
+        })
+        .register_get("path", |r: &mut ScriptRequest| {
+            r.0.lock().expect("script request").path.clone()
+        })
+        .register_fn("header", |r: &mut ScriptRequest, name: &str| {
+            get_header(&r.0.lock().expect("script request").headers, name)
+        })
+        .register_fn(
+            "set_header",
+            |r: &mut ScriptRequest, name: &str, value: &str| {
+                set_header(
+                    &mut r.0.lock().expect("script request").headers,
+                    name,
+                    value,
+                )
+            },
+        )
+        .register_fn("remove_header", |r: &mut ScriptRequest, name: &str| {
+            r.0.lock().expect("script request").headers.remove(name);
+        });
+
+    engine
+        .register_type_with_name::<ScriptResponse>("Response")
+        .register_get_set(
+            "status",
+            |r: &mut ScriptResponse| r.0.lock().expect("script response").status.as_u16() as i64,
+            |r: &mut ScriptResponse, status: i64| {
+                if let Some(status) = u16::try_from(status)
+                    .ok()
+                    .and_then(|s| StatusCode::from_u16(s).ok())
+                {
+                    r.0.lock().expect("script response").status = status;
+                }
+            },
+        )
+        .register_fn("header", |r: &mut ScriptResponse, name: &str| {
+            get_header(&r.0.lock().expect("script response").headers, name)
+        })
+        .register_fn(
+            "set_header",
+            |r: &mut ScriptResponse, name: &str, value: &str| {
+                set_header(
+                    &mut r.0.lock().expect("script response").headers,
+                    name,
+                    value,
+                )
+            },
+        )
+        .register_fn("remove_header", |r: &mut ScriptResponse, name: &str| {
+            r.0.lock().expect("script response").headers.remove(name);
+        })
+        .register_fn("respond", |status: i64, body: &str| {
+            let status = u16::try_from(status)
+                .ok()
+                .and_then(|s| StatusCode::from_u16(s).ok())
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            ScriptResponse::new(status, Some(body.to_string()))
+        })
+        .register_fn("redirect", |location: &str| {
+            let res = ScriptResponse::new(StatusCode::FOUND, None);
+            set_header(
+                &mut res.0.lock().expect("script response").headers,
+                "location",
+                location,
+            );
+            res
+        });
+
+    engine
+}
+
+/// Middleware running Rhai scripts against requests and responses (feature `scripting`)
+///
+/// A script may define either hook:
+///
+/// ```rhai
+/// fn on_request(req) {
+///     if req.path == "/old" { return redirect("/new"); }
+///     if req.header("x-debug") == () { req.set_header("x-debug", "0"); }
+/// }
+///
+/// fn on_response(req, res) {
+///     res.set_header("x-served-by", "edge-1");
+/// }
+/// ```
+///
+/// `on_request` can edit request headers, or return `respond(status, body)` /
+/// `redirect(url)` to answer without calling the handler. `on_response` can edit
+/// the status and headers. Scripts are swapped at runtime with `reload()`; a
+/// script error is logged and the request continues unmodified.
+pub struct ScriptMiddleware {
+    engine: Engine,
+    ast: RwLock<Arc<AST>>,
+}
+
+impl ScriptMiddleware {
+    /// Compile a script from source
+    pub fn new(source: &str) -> Result<Self, ScriptError> {
+        let engine = build_engine();
+        let ast = engine.compile(source)?;
+        Ok(Self {
+            engine,
+            ast: RwLock::new(Arc::new(ast)),
+        })
+    }
+
+    /// Compile a script from a file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ScriptError> {
+        let source = std::fs::read_to_string(path.as_ref()).map_err(|e| e.to_string())?;
+        Self::new(&source)
+    }
+
+    /// Replace the running script; the old one stays active if compilation fails
+    pub fn reload(&self, source: &str) -> Result<(), ScriptError> {
+        let ast = self.engine.compile(source)?;
+        *self.ast.write().expect("ScriptMiddleware poisoned") = Arc::new(ast);
+        Ok(())
+    }
+
+    fn call(&self, ast: &AST, hook: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        if !ast.iter_functions().any(|f| f.name == hook) {
+            return None;
+        }
+        match self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), ast, hook, args)
+        {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::warn!(hook, error = %e, "Script hook failed");
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for ScriptMiddleware {
+    async fn handle(
+        &self,
+        mut req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let ast = self.ast.read().expect("ScriptMiddleware poisoned").clone();
+
+        let script_req = ScriptRequest(Arc::new(Mutex::new(RequestParts {
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            headers: req.headers().clone(),
+        })));
+
+        let mut early = None;
+        if let Some(result) = self.call(&ast, "on_request", (script_req.clone(),)) {
+            // Header edits only apply when the hook ran to completion
+            *req.headers_mut() = script_req.0.lock().expect("script request").headers.clone();
+            early = result.try_cast::<ScriptResponse>();
+        }
+
+        if let Some(early) = early {
+            let parts = early.0.lock().expect("script response");
+            let mut res =
+                PingoraWebHttpResponse::text(parts.status, parts.body.clone().unwrap_or_default());
+            for (name, value) in &parts.headers {
+                res.headers.insert(name.clone(), value.clone());
+            }
+            return Ok(res);
+        }
+
+        let mut res = next.handle(req).await?;
+
+        if ast.iter_functions().any(|f| f.name == "on_response") {
+            let script_res = ScriptResponse(Arc::new(Mutex::new(ResponseParts {
+                status: res.status,
+                headers: std::mem::take(&mut res.headers),
+                body: None,
+            })));
+            self.call(&ast, "on_response", (script_req, script_res.clone()));
+            let mut parts = script_res.0.lock().expect("script response");
+            res.status = parts.status;
+            res.headers = std::mem::take(&mut parts.headers);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    fn app(script: &str) -> (App, Arc<ScriptMiddleware>) {
+        struct Shared(Arc<ScriptMiddleware>);
+        #[async_trait]
+        impl Middleware for Shared {
+            async fn handle(
+                &self,
+                req: PingoraHttpRequest,
+                next: Arc<dyn Handler>,
+            ) -> Result<PingoraWebHttpResponse, WebError> {
+                self.0.handle(req, next).await
+            }
+        }
+
+        let scripts = Arc::new(ScriptMiddleware::new(script).unwrap());
+        let mut app = App::default();
+        app.use_middleware(Shared(scripts.clone()));
+        app.get_fn("/hello", |req| {
+            let tag = req
+                .headers()
+                .get("x-tag")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("none")
+                .to_string();
+            format!("hello {tag}")
+        });
+        (app, scripts)
+    }
+
+    #[tokio::test]
+    async fn rewrites_headers_and_short_circuits() {
+        let (app, _) = app(r#"
+            fn on_request(req) {
+                if req.path == "/old" { return redirect("/hello"); }
+                if req.header("x-block") != () { return respond(403, "blocked"); }
+                req.set_header("x-tag", "scripted");
+            }
+            fn on_response(req, res) {
+                res.set_header("x-script", req.method);
+                if res.status == 404 { res.status = 410; }
+            }
+        "#);
+        let client = app.test_client();
+
+        let res = client.get("/hello").send().await;
+        assert_eq!(res.text(), "hello scripted");
+        assert_eq!(res.header("x-script"), Some("GET"));
+
+        let res = client.get("/old").send().await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.header("location"), Some("/hello"));
+
+        let res = client.get("/hello").header("x-block", "1").send().await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(res.text(), "blocked");
+
+        let res = client.get("/gone").send().await;
+        assert_eq!(res.status(), StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn reload_swaps_script_and_errors_fail_open() {
+        let (app, scripts) = app(r#"fn on_request(req) { req.set_header("x-tag", "v1"); }"#);
+        let client = app.test_client();
+        assert_eq!(client.get("/hello").send().await.text(), "hello v1");
+
+        assert!(scripts.reload("fn on_request(req) {").is_err());
+        assert_eq!(client.get("/hello").send().await.text(), "hello v1");
+
+        scripts
+            .reload(
+                r#"fn on_request(req) { req.set_header("x-tag", "v2"); req.no_such_method(); }"#,
+            )
+            .unwrap();
+        assert_eq!(client.get("/hello").send().await.text(), "hello none");
+    }
+}
@@ -0,0 +1,335 @@
+use async_trait::async_trait;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Instant;
+
+use super::Middleware;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse, Tenant};
+use crate::error::{ResponseError, WebError};
+use http::StatusCode;
+
+/// Source of tenant configuration
+///
+/// Implement this to load tenants from a database or config service. The
+/// default is an in-process `InMemoryTenants`.
+#[async_trait]
+pub trait TenantStore: Send + Sync + 'static {
+    /// Configuration for `id`, or `None` if the tenant is unknown
+    async fn load(&self, id: &str) -> Option<Arc<Tenant>>;
+}
+
+/// Tenants kept in memory; can be updated while serving
+#[derive(Default)]
+pub struct InMemoryTenants {
+    tenants: RwLock<HashMap<String, Arc<Tenant>>>,
+}
+
+impl InMemoryTenants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace a tenant
+    pub fn insert(&self, tenant: Tenant) {
+        self.tenants
+            .write()
+            .expect("InMemoryTenants poisoned")
+            .insert(tenant.id.clone(), Arc::new(tenant));
+    }
+
+    pub fn remove(&self, id: &str) -> Option<Arc<Tenant>> {
+        self.tenants
+            .write()
+            .expect("InMemoryTenants poisoned")
+            .remove(id)
+    }
+}
+
+#[async_trait]
+impl TenantStore for InMemoryTenants {
+    async fn load(&self, id: &str) -> Option<Arc<Tenant>> {
+        self.tenants
+            .read()
+            .expect("InMemoryTenants poisoned")
+            .get(id)
+            .cloned()
+    }
+}
+
+#[async_trait]
+impl<T: TenantStore> TenantStore for Arc<T> {
+    async fn load(&self, id: &str) -> Option<Arc<Tenant>> {
+        (**self).load(id).await
+    }
+}
+
+/// Where the tenant id is read from
+#[derive(Debug, Clone)]
+pub enum TenantSource {
+    /// The full host, without port (`acme.example.com`)
+    Host,
+    /// The label in front of a base domain: `acme` for `acme.example.com` with base `example.com`
+    Subdomain(String),
+    /// A request header (e.g. `x-tenant-id`)
+    Header(String),
+    /// The first path segment: `acme` for `/acme/orders`
+    PathPrefix,
+}
+
+impl TenantSource {
+    fn resolve<'a>(&self, req: &'a PingoraHttpRequest) -> Option<&'a str> {
+        let host = || {
+            req.headers()
+                .get(http::header::HOST)
+                .and_then(|v| v.to_str().ok())
+                .or_else(|| req.inner.uri().host())
+                .map(|h| h.rsplit_once(':').map_or(h, |(host, _)| host))
+        };
+        let id = match self {
+            TenantSource::Host => host()?,
+            TenantSource::Subdomain(base) => host()?
+                .strip_suffix(base.as_str())?
+                .strip_suffix('.')
+                .filter(|sub| !sub.contains('.'))?,
+            TenantSource::Header(name) => req.headers().get(name.as_str())?.to_str().ok()?,
+            TenantSource::PathPrefix => req.path().trim_start_matches('/').split('/').next()?,
+        };
+        Some(id.trim()).filter(|id| !id.is_empty())
+    }
+}
+
+#[derive(Clone)]
+struct TenantMetrics {
+    requests: IntCounterVec,
+    latency: HistogramVec,
+}
+
+impl TenantMetrics {
+    fn new() -> prometheus::Result<Self> {
+        let labels = &["tenant", "status"];
+        Ok(Self {
+            requests: IntCounterVec::new(
+                Opts::new(
+                    "pingora_web_tenant_requests_total",
+                    "HTTP requests per tenant",
+                ),
+                labels,
+            )?,
+            latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "pingora_web_tenant_request_duration_seconds",
+                    "HTTP request latency per tenant in seconds",
+                ),
+                labels,
+            )?,
+        })
+    }
+
+    fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.requests.clone()))?;
+        registry.register(Box::new(self.latency.clone()))?;
+        Ok(())
+    }
+}
+
+/// Tenant metrics in the default registry, shared by every `TenantMiddleware`
+fn default_metrics() -> &'static TenantMetrics {
+    static DEFAULT: OnceLock<TenantMetrics> = OnceLock::new();
+    DEFAULT.get_or_init(|| {
+        let metrics = TenantMetrics::new().expect("valid metric definitions");
+        if let Err(e) = metrics.register(prometheus::default_registry()) {
+            tracing::warn!("Failed to register pingora_web tenant metrics: {}", e);
+        }
+        metrics
+    })
+}
+
+/// Multi-tenancy: resolve the tenant of each request and attach its configuration
+///
+/// Sources are tried in order until one yields an id, which is looked up in the
+/// `TenantStore`. Requests without a tenant get 400, unknown tenants 404. The
+/// tenant is available to handlers via `req.tenant()`, and requests are counted
+/// per tenant (`pingora_web_tenant_requests_total{tenant,status}`).
+///
+/// ```
+/// use pingora_web::{App, InMemoryTenants, Tenant, TenantMiddleware, TenantSource};
+/// use std::sync::Arc;
+///
+/// let tenants = Arc::new(InMemoryTenants::new());
+/// tenants.insert(Tenant::new("acme").limit("max_upload_bytes", 1 << 20).feature("beta"));
+///
+/// let mut app = App::default();
+/// app.use_middleware(
+///     TenantMiddleware::new(tenants)
+///         .source(TenantSource::Header("x-tenant-id".into()))
+///         .source(TenantSource::Subdomain("example.com".into())),
+/// );
+/// ```
+pub struct TenantMiddleware {
+    store: Arc<dyn TenantStore>,
+    sources: Vec<TenantSource>,
+    metrics: TenantMetrics,
+}
+
+impl TenantMiddleware {
+    /// Resolve tenants from `store`; add at least one `source`
+    pub fn new<S: TenantStore>(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+            sources: Vec::new(),
+            metrics: default_metrics().clone(),
+        }
+    }
+
+    /// Add a place to look for the tenant id (tried in the order added)
+    pub fn source(mut self, source: TenantSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Record tenant metrics into a custom registry
+    pub fn with_registry(mut self, registry: &Registry) -> prometheus::Result<Self> {
+        let metrics = TenantMetrics::new()?;
+        metrics.register(registry)?;
+        self.metrics = metrics;
+        Ok(self)
+    }
+}
+
+#[async_trait]
+impl Middleware for TenantMiddleware {
+    async fn handle(
+        &self,
+        mut req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let Some(id) = self.sources.iter().find_map(|s| s.resolve(&req)) else {
+            return Ok(PingoraWebHttpResponse::text(
+                StatusCode::BAD_REQUEST,
+                "Missing Tenant",
+            ));
+        };
+        let Some(tenant) = self.store.load(id).await else {
+            return Ok(PingoraWebHttpResponse::text(
+                StatusCode::NOT_FOUND,
+                "Unknown Tenant",
+            ));
+        };
+
+        let tenant_id = tenant.id.clone();
+        req.set_request_share_data(tenant);
+        let start = Instant::now();
+
+        let result = next.handle(req).await;
+
+        let status = match &result {
+            Ok(res) => res.status,
+            Err(e) => e.status_code(),
+        };
+        let class = format!("{}xx", status.as_u16() / 100);
+        let labels = [tenant_id.as_str(), class.as_str()];
+        self.metrics.requests.with_label_values(&labels).inc();
+        self.metrics
+            .latency
+            .with_label_values(&labels)
+            .observe(start.elapsed().as_secs_f64());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    struct Db(&'static str);
+
+    fn app(registry: &Registry) -> App {
+        let tenants = Arc::new(InMemoryTenants::new());
+        tenants.insert(
+            Tenant::new("acme")
+                .feature("beta")
+                .limit("max_items", 10)
+                .with_data(Arc::new(Db("acme-db"))),
+        );
+        tenants.insert(Tenant::new("globex"));
+
+        let mut app = App::default();
+        app.use_middleware(
+            TenantMiddleware::new(tenants)
+                .source(TenantSource::Header("x-tenant-id".into()))
+                .source(TenantSource::Subdomain("example.com".into()))
+                .with_registry(registry)
+                .unwrap(),
+        );
+        app.get_fn("/info", |req| {
+            let tenant = req.tenant().unwrap();
+            format!(
+                "{} beta={} max={:?} db={}",
+                tenant.id,
+                tenant.has_feature("beta"),
+                tenant.get_limit("max_items"),
+                tenant.data::<Db>().map_or("-", |db| db.0),
+            )
+        });
+        app
+    }
+
+    #[tokio::test]
+    async fn resolves_tenant_config() {
+        let registry = Registry::new();
+        let app = app(&registry);
+        let client = app.test_client();
+
+        let res = client
+            .get("/info")
+            .header("x-tenant-id", "acme")
+            .send()
+            .await;
+        assert_eq!(res.text(), "acme beta=true max=Some(10) db=acme-db");
+
+        let res = client
+            .get("/info")
+            .header("host", "globex.example.com:8080")
+            .send()
+            .await;
+        assert_eq!(res.text(), "globex beta=false max=None db=-");
+
+        let res = client
+            .get("/info")
+            .header("x-tenant-id", "initech")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = client
+            .get("/info")
+            .header("host", "example.com")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let families = registry.gather();
+        let requests = families
+            .iter()
+            .find(|f| f.get_name() == "pingora_web_tenant_requests_total")
+            .unwrap();
+        let tenants: Vec<_> = requests
+            .get_metric()
+            .iter()
+            .flat_map(|m| m.get_label().iter().filter(|l| l.get_name() == "tenant"))
+            .map(|l| l.get_value().to_string())
+            .collect();
+        assert_eq!(tenants.len(), 2);
+        assert!(tenants.contains(&"acme".to_string()));
+    }
+
+    #[test]
+    fn path_prefix_source() {
+        let req = PingoraHttpRequest::new(http::Method::GET, "/acme/orders");
+        assert_eq!(TenantSource::PathPrefix.resolve(&req), Some("acme"));
+        let req = PingoraHttpRequest::new(http::Method::GET, "/");
+        assert_eq!(TenantSource::PathPrefix.resolve(&req), None);
+    }
+}
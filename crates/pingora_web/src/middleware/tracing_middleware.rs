@@ -1,21 +1,51 @@
 use crate::core::Handler;
+use crate::core::annotations::{
+    COMPRESSION_ALGORITHM, RESPONSE_BODY_BYTES, RESPONSE_WRITE_FAILURE,
+};
 use crate::{
     core::{PingoraHttpRequest, PingoraWebHttpResponse},
     error::WebError,
     middleware::Middleware,
 };
 use async_trait::async_trait;
+use http::HeaderValue;
+use std::hash::BuildHasher;
 use std::sync::Arc;
-use tracing::{Instrument, info};
+use std::time::Duration;
+use tracing::{Instrument, info, warn};
 
 /// Tracing middleware that creates a span for each request with request_id context
 /// This ensures all tracing calls within the request have the request_id automatically included
+///
+/// Requests without a valid W3C `traceparent` header get a fresh one, so the
+/// span's `trace_id` is always set and inner middleware (e.g. metrics exemplars)
+/// can link back to it. Register it before `MetricsMiddleware`.
+///
+/// Once the response is written, `content_encoding` and `body_bytes` record what
+/// went over the wire, after the compression module ran; `write_failure` names
+/// the stage at which a truncated response failed. `bytes_read` and
+/// `bytes_written` hold the request's total size on the connection, heads
+/// included (see `RequestMetrics`).
+///
+/// With `slow_requests`, requests taking longer than the threshold log a
+/// warning with their details, plus the timings of the spans inside them when
+/// `utils::SpanTimingLayer` is installed (`span-timings` feature).
 #[derive(Clone)]
-pub struct TracingMiddleware;
+pub struct TracingMiddleware {
+    slow_threshold: Option<Duration>,
+}
 
 impl TracingMiddleware {
     pub fn new() -> Self {
-        Self
+        Self {
+            slow_threshold: None,
+        }
+    }
+
+    /// Warn about requests whose handling takes at least `threshold`
+    pub fn slow_requests(mut self, threshold: Duration) -> Self {
+        self.slow_threshold = Some(threshold);
+        self
     }
 }
 
@@ -27,30 +57,77 @@ impl Default for TracingMiddleware {
 
 #[async_trait]
 impl Middleware for TracingMiddleware {
+    fn convert_errors(&self) -> bool {
+        true
+    }
+
     async fn handle(
         &self,
-        req: PingoraHttpRequest,
+        mut req: PingoraHttpRequest,
         next: Arc<dyn Handler>,
     ) -> Result<PingoraWebHttpResponse, WebError> {
-        let request_id = req
-            .headers()
-            .get("x-request-id")
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("")
-            .to_string();
+        if req.trace_id().is_none() {
+            let traceparent = format!("00-{}-{:016x}-01", new_trace_id(), random_u64());
+            if let Ok(value) = HeaderValue::from_str(&traceparent) {
+                req.headers_mut().insert("traceparent", value);
+            }
+        }
+        let trace_id = req.trace_id().unwrap_or("").to_string();
+
+        let request_id = req.request_id().unwrap_or("").to_string();
         let method = req.method().as_str().to_string();
         let path = req.path().to_string();
+        let route = req.matched_route().unwrap_or("").to_string();
+        let slow = self.slow_threshold.map(|threshold| SlowRequest {
+            threshold,
+            uri: req.uri().to_string(),
+            version: req.version(),
+            peer: req.peer_addr(),
+            user_agent: header(&req, http::header::USER_AGENT),
+            content_length: header(&req, http::header::CONTENT_LENGTH),
+        });
 
         // Create a span for this request with structured fields
         let span = tracing::info_span!(
             "request",
             request_id = request_id.as_str(),
+            trace_id = trace_id.as_str(),
             method = method.as_str(),
             path = path,
+            route = route.as_str(),
             status = tracing::field::Empty,
             latency_ms = tracing::field::Empty,
+            content_encoding = tracing::field::Empty,
+            body_bytes = tracing::field::Empty,
+            write_failure = tracing::field::Empty,
+            bytes_read = tracing::field::Empty,
+            bytes_written = tracing::field::Empty,
         );
 
+        // Sizes are only known once the (possibly compressed) body went out
+        if let Some(annotations) = req.annotations() {
+            let span = span.clone();
+            let metrics = req.request_metrics();
+            annotations.on_complete(move |a| {
+                if let Some(encoding) = a.get(COMPRESSION_ALGORITHM) {
+                    span.record("content_encoding", encoding);
+                }
+                if let Some(bytes) = a
+                    .get(RESPONSE_BODY_BYTES)
+                    .and_then(|b| b.parse::<u64>().ok())
+                {
+                    span.record("body_bytes", bytes);
+                }
+                if let Some(stage) = a.get(RESPONSE_WRITE_FAILURE) {
+                    span.record("write_failure", stage);
+                }
+                if let Some(metrics) = &metrics {
+                    span.record("bytes_read", metrics.bytes_read());
+                    span.record("bytes_written", metrics.bytes_written());
+                }
+            });
+        }
+
         // Clone span for use in both the closure and instrument
         let span_for_record = span.clone();
 
@@ -63,12 +140,19 @@ impl Middleware for TracingMiddleware {
 
             let res = next.handle(req).await?;
 
-            let elapsed_ms = start_time.elapsed().as_millis();
+            let elapsed = start_time.elapsed();
+            let elapsed_ms = elapsed.as_millis();
 
             // Record the response status and latency in the span
             span_for_record.record("status", res.status.as_u16());
             span_for_record.record("latency_ms", elapsed_ms);
 
+            if let Some(slow) = slow
+                && elapsed >= slow.threshold
+            {
+                slow.log(&span_for_record, res.status, elapsed);
+            }
+
             // Log the request completion
             info!("Request completed");
 
@@ -78,3 +162,56 @@ impl Middleware for TracingMiddleware {
         .await
     }
 }
+
+/// Request details kept for the slow request warning
+struct SlowRequest {
+    threshold: Duration,
+    uri: String,
+    version: http::Version,
+    peer: Option<std::net::SocketAddr>,
+    user_agent: Option<String>,
+    content_length: Option<String>,
+}
+
+impl SlowRequest {
+    fn log(self, span: &tracing::Span, status: http::StatusCode, elapsed: Duration) {
+        #[cfg(feature = "span-timings")]
+        let span_timings = crate::utils::span_timings::request_timings(span);
+        #[cfg(not(feature = "span-timings"))]
+        let span_timings: Option<String> = {
+            let _ = span;
+            None
+        };
+        warn!(
+            uri = self.uri.as_str(),
+            version = ?self.version,
+            peer = self.peer.map(|p| p.to_string()),
+            user_agent = self.user_agent,
+            content_length = self.content_length,
+            status = status.as_u16(),
+            latency_ms = elapsed.as_millis(),
+            threshold_ms = self.threshold.as_millis(),
+            span_timings,
+            "Slow request"
+        );
+    }
+}
+
+fn header(req: &PingoraHttpRequest, name: http::header::HeaderName) -> Option<String> {
+    req.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+pub(crate) fn random_u64() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    // RandomState is seeded randomly per instance; hashing a counter keeps ids distinct
+    std::collections::hash_map::RandomState::new().hash_one(COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// 128-bit random trace id as 32 hex digits
+fn new_trace_id() -> String {
+    format!("{:016x}{:016x}", random_u64(), random_u64() | 1)
+}
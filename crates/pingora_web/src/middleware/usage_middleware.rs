@@ -0,0 +1,605 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use pingora_core::connectors::http::Connector;
+use pingora_core::upstreams::peer::HttpPeer;
+use pingora_http::RequestHeader;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+
+use super::Middleware;
+use crate::core::response::Body;
+use crate::core::router::UNMATCHED_ROUTE;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::{ResponseError, WebError};
+
+/// One metered request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Unix time in milliseconds when the request started
+    pub timestamp_ms: u64,
+    pub request_id: Option<String>,
+    pub tenant: Option<String>,
+    pub principal: Option<String>,
+    pub method: String,
+    /// Matched route template, or `<unmatched>`
+    pub route: String,
+    pub status: u16,
+    pub request_bytes: u64,
+    /// Body bytes sent; for streams, counted until the stream ends or the client leaves
+    pub response_bytes: u64,
+    pub duration_us: u64,
+}
+
+/// Destination for batches of usage records
+#[async_trait]
+pub trait UsageSink: Send + Sync + 'static {
+    /// Deliver a batch; an `Err` makes the pipeline retry it
+    async fn send(&self, records: &[UsageRecord]) -> Result<(), String>;
+}
+
+/// Appends records to a file as JSON lines (opened per batch, so rotation is safe)
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl UsageSink for FileSink {
+    async fn send(&self, records: &[UsageRecord]) -> Result<(), String> {
+        let mut buf = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut buf, record).map_err(|e| e.to_string())?;
+            buf.push(b'\n');
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| e.to_string())?;
+        file.write_all(&buf).await.map_err(|e| e.to_string())?;
+        file.flush().await.map_err(|e| e.to_string())
+    }
+}
+
+/// Minimal Kafka producer interface, implemented over the client library of your choice
+#[async_trait]
+pub trait KafkaProducer: Send + Sync + 'static {
+    async fn produce(&self, topic: &str, key: Option<&str>, payload: Vec<u8>)
+    -> Result<(), String>;
+}
+
+/// Publishes each record as a JSON message keyed by tenant
+pub struct KafkaSink<P> {
+    producer: P,
+    topic: String,
+}
+
+impl<P: KafkaProducer> KafkaSink<P> {
+    pub fn new(producer: P, topic: impl Into<String>) -> Self {
+        Self {
+            producer,
+            topic: topic.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl<P: KafkaProducer> UsageSink for KafkaSink<P> {
+    async fn send(&self, records: &[UsageRecord]) -> Result<(), String> {
+        for record in records {
+            let payload = serde_json::to_vec(record).map_err(|e| e.to_string())?;
+            self.producer
+                .produce(&self.topic, record.tenant.as_deref(), payload)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// POSTs each batch as a JSON array to an HTTP collector
+pub struct HttpBatchSink {
+    connector: Connector,
+    peer: HttpPeer,
+    path: String,
+    timeout: Duration,
+}
+
+impl HttpBatchSink {
+    /// Plain-HTTP collector at `addr` (e.g. `"127.0.0.1:9000"`) receiving POSTs on `path`
+    pub fn new(addr: impl Into<String>, path: impl Into<String>) -> Self {
+        let addr = addr.into();
+        Self {
+            connector: Connector::new(None),
+            peer: HttpPeer::new(addr.as_str(), false, String::new()),
+            path: path.into(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Time limit for one batch upload (default 5s)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn post(&self, body: Vec<u8>) -> pingora_core::Result<http::StatusCode> {
+        let mut header = RequestHeader::build(http::Method::POST, self.path.as_bytes(), None)?;
+        header.insert_header("content-type", "application/json")?;
+        header.insert_header("content-length", body.len().to_string())?;
+
+        let (mut session, _reused) = self.connector.get_http_session(&self.peer).await?;
+        session.set_read_timeout(Some(self.timeout));
+        session.set_write_timeout(Some(self.timeout));
+        session.write_request_header(Box::new(header)).await?;
+        session.write_request_body(Bytes::from(body), true).await?;
+        session.finish_request_body().await?;
+        session.read_response_header().await?;
+        while session.read_response_body().await?.is_some() {}
+
+        let status = session
+            .response_header()
+            .expect("response header was read")
+            .status;
+        self.connector
+            .release_http_session(session, &self.peer, None)
+            .await;
+        Ok(status)
+    }
+}
+
+#[async_trait]
+impl UsageSink for HttpBatchSink {
+    async fn send(&self, records: &[UsageRecord]) -> Result<(), String> {
+        let body = serde_json::to_vec(records).map_err(|e| e.to_string())?;
+        match tokio::time::timeout(self.timeout, self.post(body)).await {
+            Ok(Ok(status)) if status.is_success() => Ok(()),
+            Ok(Ok(status)) => Err(format!("collector answered {status}")),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("usage upload timed out".to_string()),
+        }
+    }
+}
+
+/// What to do when the pipeline's queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Hold the response until there is room (no record is lost)
+    #[default]
+    Wait,
+    /// Drop the record and count it in `UsagePipeline::dropped()`
+    DropNewest,
+}
+
+enum Message {
+    Record(UsageRecord),
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Clone, Copy)]
+struct PipelineConfig {
+    batch_size: usize,
+    flush_interval: Duration,
+    capacity: usize,
+    max_retries: u32,
+    backpressure: Backpressure,
+}
+
+#[derive(Default)]
+struct Shared {
+    sender: OnceLock<mpsc::Sender<Message>>,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Shared {
+    /// Queue without waiting (used where awaiting is impossible, e.g. `Drop`)
+    fn try_emit(&self, record: UsageRecord) {
+        if let Some(tx) = self.sender.get()
+            && tx.try_send(Message::Record(record)).is_err()
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Handle to a running usage pipeline, for flushing and health counters
+#[derive(Clone)]
+pub struct UsagePipeline {
+    shared: Arc<Shared>,
+}
+
+impl UsagePipeline {
+    /// Deliver everything queued so far (e.g. before shutting down)
+    pub async fn flush(&self) {
+        let Some(tx) = self.shared.sender.get() else {
+            return;
+        };
+        let (done, wait) = oneshot::channel();
+        if tx.send(Message::Flush(done)).await.is_ok() {
+            let _ = wait.await;
+        }
+    }
+
+    /// Records dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Records lost because the sink kept failing
+    pub fn failed(&self) -> u64 {
+        self.shared.failed.load(Ordering::Relaxed)
+    }
+}
+
+async fn run_pipeline(
+    mut rx: mpsc::Receiver<Message>,
+    sink: Arc<dyn UsageSink>,
+    config: PipelineConfig,
+    shared: Arc<Shared>,
+) {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    let mut deadline = tokio::time::Instant::now() + config.flush_interval;
+    loop {
+        match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Some(Message::Record(record))) => {
+                batch.push(record);
+                if batch.len() >= config.batch_size {
+                    deliver(&*sink, &mut batch, &config, &shared).await;
+                }
+            }
+            Ok(Some(Message::Flush(done))) => {
+                deliver(&*sink, &mut batch, &config, &shared).await;
+                let _ = done.send(());
+            }
+            Ok(None) => {
+                deliver(&*sink, &mut batch, &config, &shared).await;
+                return;
+            }
+            Err(_) => {
+                deliver(&*sink, &mut batch, &config, &shared).await;
+                deadline = tokio::time::Instant::now() + config.flush_interval;
+            }
+        }
+    }
+}
+
+/// Send a batch, retrying with exponential backoff
+async fn deliver(
+    sink: &dyn UsageSink,
+    batch: &mut Vec<UsageRecord>,
+    config: &PipelineConfig,
+    shared: &Shared,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    let records = std::mem::take(batch);
+    for attempt in 0..=config.max_retries {
+        match sink.send(&records).await {
+            Ok(()) => return,
+            Err(e) => {
+                tracing::warn!(
+                    attempt,
+                    records = records.len(),
+                    error = e.as_str(),
+                    "Usage sink failed"
+                );
+                if attempt < config.max_retries {
+                    tokio::time::sleep(Duration::from_millis(100 << attempt)).await;
+                }
+            }
+        }
+    }
+    tracing::error!(
+        records = records.len(),
+        "Dropping usage batch after retries"
+    );
+    shared
+        .failed
+        .fetch_add(records.len() as u64, Ordering::Relaxed);
+}
+
+/// Emits a `UsageRecord` per request to a `UsageSink`, for metering and billing
+///
+/// Records are queued, batched (`batch_size` or every `flush_interval`), and
+/// delivered by a background task with retries. Add it after `TenantMiddleware`
+/// and auth middleware so tenant and principal are known.
+///
+/// ```
+/// use pingora_web::{App, FileSink, UsageMiddleware};
+///
+/// let usage = UsageMiddleware::new(FileSink::new("usage.jsonl")).batch_size(500);
+/// // call `pipeline.flush().await` before exiting, e.g. from `App::on_shutdown`
+/// let pipeline = usage.pipeline();
+///
+/// let mut app = App::default();
+/// app.use_middleware(usage);
+/// ```
+pub struct UsageMiddleware {
+    sink: Arc<dyn UsageSink>,
+    config: PipelineConfig,
+    shared: Arc<Shared>,
+}
+
+impl UsageMiddleware {
+    pub fn new<S: UsageSink>(sink: S) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            config: PipelineConfig {
+                batch_size: 100,
+                flush_interval: Duration::from_secs(1),
+                capacity: 10_000,
+                max_retries: 3,
+                backpressure: Backpressure::Wait,
+            },
+            shared: Arc::new(Shared::default()),
+        }
+    }
+
+    /// Records per delivered batch (default 100)
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.config.batch_size = size.max(1);
+        self
+    }
+
+    /// Deliver a partial batch after this long (default 1s)
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.config.flush_interval = interval;
+        self
+    }
+
+    /// Records that may wait in the queue (default 10 000)
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.config.capacity = capacity.max(1);
+        self
+    }
+
+    /// Delivery attempts after the first failure (default 3)
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.config.max_retries = retries;
+        self
+    }
+
+    /// Behavior when the queue is full (default `Wait`)
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.config.backpressure = backpressure;
+        self
+    }
+
+    /// Handle for flushing and reading the drop/failure counters
+    pub fn pipeline(&self) -> UsagePipeline {
+        UsagePipeline {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Queue sender; the delivery task starts on the first request
+    fn sender(&self) -> &mpsc::Sender<Message> {
+        self.shared.sender.get_or_init(|| {
+            let (tx, rx) = mpsc::channel(self.config.capacity);
+            tokio::spawn(run_pipeline(
+                rx,
+                self.sink.clone(),
+                self.config,
+                self.shared.clone(),
+            ));
+            tx
+        })
+    }
+
+    async fn emit(&self, record: UsageRecord) {
+        let tx = self.sender();
+        let queued = match self.config.backpressure {
+            Backpressure::Wait => tx.send(Message::Record(record)).await.is_ok(),
+            Backpressure::DropNewest => tx.try_send(Message::Record(record)).is_ok(),
+        };
+        if !queued {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Counts streamed bytes and emits the record when the stream ends or is dropped
+struct MeteredStream {
+    inner: BoxStream<'static, Bytes>,
+    pending: Option<(UsageRecord, Instant)>,
+    shared: Arc<Shared>,
+}
+
+impl MeteredStream {
+    fn finish(&mut self) {
+        if let Some((mut record, start)) = self.pending.take() {
+            record.duration_us = start.elapsed().as_micros() as u64;
+            self.shared.try_emit(record);
+        }
+    }
+}
+
+impl Stream for MeteredStream {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        let poll = self.inner.poll_next_unpin(cx);
+        match &poll {
+            Poll::Ready(Some(chunk)) => {
+                if let Some((record, _)) = &mut self.pending {
+                    record.response_bytes += chunk.len() as u64;
+                }
+            }
+            Poll::Ready(None) => self.finish(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for MeteredStream {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[async_trait]
+impl Middleware for UsageMiddleware {
+    async fn handle(
+        &self,
+        req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let mut record = UsageRecord {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            request_id: req.request_id().map(ToString::to_string),
+            tenant: req.tenant().map(|t| t.id.clone()),
+            principal: req.principal().map(|p| p.id.clone()),
+            method: req.method().to_string(),
+            route: req.matched_route().unwrap_or(UNMATCHED_ROUTE).to_string(),
+            status: 0,
+            request_bytes: if req.is_body_pending() {
+                // Not read yet; trust the declared length
+                req.headers()
+                    .get(http::header::CONTENT_LENGTH)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0)
+            } else {
+                req.body().len() as u64
+            },
+            response_bytes: 0,
+            duration_us: 0,
+        };
+        let start = Instant::now();
+
+        let mut result = next.handle(req).await;
+
+        match &mut result {
+            Ok(res) => {
+                record.status = res.status.as_u16();
+                match std::mem::replace(&mut res.body, Body::Bytes(Bytes::new())) {
+                    Body::Bytes(b) => {
+                        record.response_bytes = b.len() as u64;
+                        res.body = Body::Bytes(b);
+                    }
+                    Body::Stream(inner) => {
+                        // Make sure the queue exists before the stream may finish
+                        self.sender();
+                        res.body = Body::Stream(Box::pin(MeteredStream {
+                            inner,
+                            pending: Some((record, start)),
+                            shared: self.shared.clone(),
+                        }));
+                        return result;
+                    }
+                }
+            }
+            Err(e) => record.status = e.status_code().as_u16(),
+        }
+        record.duration_us = start.elapsed().as_micros() as u64;
+        self.emit(record).await;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{App, InMemoryTenants, Tenant, TenantMiddleware, TenantSource};
+    use http::StatusCode;
+    use std::sync::Mutex;
+
+    #[derive(Default, Clone)]
+    struct Collect(Arc<Mutex<Vec<Vec<UsageRecord>>>>);
+
+    #[async_trait]
+    impl UsageSink for Collect {
+        async fn send(&self, records: &[UsageRecord]) -> Result<(), String> {
+            self.0.lock().unwrap().push(records.to_vec());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_records_per_request() {
+        let sink = Collect::default();
+        let tenants = Arc::new(InMemoryTenants::new());
+        tenants.insert(Tenant::new("acme"));
+        let usage = UsageMiddleware::new(sink.clone())
+            .batch_size(2)
+            .flush_interval(Duration::from_secs(60));
+        let pipeline = usage.pipeline();
+
+        let mut app = App::default();
+        app.use_middleware(
+            TenantMiddleware::new(tenants).source(TenantSource::Header("x-tenant".into())),
+        );
+        app.use_middleware(usage);
+        app.get_fn("/items/{id}", |_req| "item");
+        app.get_fn("/stream", |_req| {
+            let chunks = futures::stream::iter(vec![Bytes::from("ab"), Bytes::from("cde")]);
+            PingoraWebHttpResponse::stream(StatusCode::OK, chunks.boxed())
+        });
+
+        let client = app.test_client();
+        for path in ["/items/1", "/items/2", "/stream"] {
+            client.get(path).header("x-tenant", "acme").send().await;
+        }
+        pipeline.flush().await;
+
+        let batches = sink.0.lock().unwrap().clone();
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), [2, 1]);
+        let first = &batches[0][0];
+        assert_eq!(first.tenant.as_deref(), Some("acme"));
+        assert_eq!(first.route, "/items/{id}");
+        assert_eq!((first.status, first.response_bytes), (200, 4));
+        let stream = &batches[1][0];
+        assert_eq!(
+            (stream.route.as_str(), stream.response_bytes),
+            ("/stream", 5)
+        );
+        assert_eq!(pipeline.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn file_sink_appends_json_lines() {
+        let path =
+            std::env::temp_dir().join(format!("pw-usage-{}.jsonl", crate::utils::generate()));
+        let sink = FileSink::new(&path);
+        let record = UsageRecord {
+            timestamp_ms: 1,
+            request_id: None,
+            tenant: Some("acme".into()),
+            principal: None,
+            method: "GET".into(),
+            route: "/".into(),
+            status: 200,
+            request_bytes: 0,
+            response_bytes: 2,
+            duration_us: 10,
+        };
+        sink.send(std::slice::from_ref(&record)).await.unwrap();
+        sink.send(std::slice::from_ref(&record)).await.unwrap();
+
+        let lines: Vec<UsageRecord> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(lines, [record.clone(), record]);
+    }
+}
@@ -0,0 +1,199 @@
+use async_trait::async_trait;
+use http::{StatusCode, header};
+use std::sync::Arc;
+use woothee::parser::Parser;
+
+use super::{CacheMiddleware, Middleware};
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Kind of device a `User-Agent` belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Device {
+    Desktop,
+    /// Phones and tablets
+    Mobile,
+    /// Game consoles, TVs and other appliances
+    Appliance,
+    /// Crawlers and other automated clients
+    Bot,
+    /// Unrecognized or missing `User-Agent`
+    Unknown,
+}
+
+/// Classification of the request's `User-Agent`, available via
+/// `req.get_request_share_data::<UserAgent>()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserAgent {
+    pub device: Device,
+    /// Browser or crawler name, e.g. `Chrome` or `Googlebot`; empty when unknown
+    pub browser: String,
+    pub version: String,
+    /// Operating system, e.g. `Windows 10` or `iPhone`; empty when unknown
+    pub os: String,
+}
+
+impl UserAgent {
+    fn parse(parser: &Parser, agent: Option<&str>) -> Self {
+        let known = |s: &str| {
+            if s == woothee::woothee::VALUE_UNKNOWN {
+                String::new()
+            } else {
+                s.to_string()
+            }
+        };
+        match agent.and_then(|a| parser.parse(a)) {
+            Some(result) => Self {
+                device: match result.category {
+                    "pc" => Device::Desktop,
+                    "smartphone" | "mobilephone" => Device::Mobile,
+                    "appliance" => Device::Appliance,
+                    "crawler" => Device::Bot,
+                    _ => Device::Unknown,
+                },
+                browser: known(result.name),
+                version: known(result.version),
+                os: known(result.os),
+            },
+            None => Self {
+                device: Device::Unknown,
+                browser: String::new(),
+                version: String::new(),
+                os: String::new(),
+            },
+        }
+    }
+
+    pub fn is_bot(&self) -> bool {
+        self.device == Device::Bot
+    }
+}
+
+/// What `UserAgentMiddleware` does with requests from bots
+pub enum BotPolicy {
+    /// Only classify them, like every other request (default)
+    Tag,
+    /// Answer 403 without running the handler
+    Block,
+    /// Answer from a cache only bots use, so crawls of the same pages reach
+    /// handlers once per TTL; other clients are unaffected
+    Cache(CacheMiddleware),
+}
+
+/// Classifies each request's `User-Agent` into device, browser and OS
+///
+/// The result is stored as a `UserAgent` in the request data, for handlers
+/// that tailor responses or metrics per client. Known crawlers are handled
+/// per `BotPolicy`.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, BotPolicy, CacheMiddleware, UserAgentMiddleware};
+/// use std::time::Duration;
+///
+/// let mut app = App::default();
+/// app.use_middleware(UserAgentMiddleware::new().bots(BotPolicy::Cache(
+///     CacheMiddleware::new().ttl(Duration::from_secs(600)),
+/// )));
+/// ```
+pub struct UserAgentMiddleware {
+    parser: Parser,
+    bots: BotPolicy,
+}
+
+impl Default for UserAgentMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UserAgentMiddleware {
+    pub fn new() -> Self {
+        Self {
+            parser: Parser::new(),
+            bots: BotPolicy::Tag,
+        }
+    }
+
+    /// Set how requests from bots are handled
+    pub fn bots(mut self, policy: BotPolicy) -> Self {
+        self.bots = policy;
+        self
+    }
+}
+
+#[async_trait]
+impl Middleware for UserAgentMiddleware {
+    async fn handle(
+        &self,
+        mut req: PingoraHttpRequest,
+        next: Arc<dyn Handler>,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok());
+        let user_agent = UserAgent::parse(&self.parser, agent);
+        let is_bot = user_agent.is_bot();
+        req.set_request_share_data(Arc::new(user_agent));
+        if !is_bot {
+            return next.handle(req).await;
+        }
+        match &self.bots {
+            BotPolicy::Tag => next.handle(req).await,
+            BotPolicy::Block => Ok(PingoraWebHttpResponse::text(
+                StatusCode::FORBIDDEN,
+                "Forbidden",
+            )),
+            BotPolicy::Cache(cache) => cache.handle(req, next).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    const GOOGLEBOT: &str =
+        "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) \
+        AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1";
+
+    #[tokio::test]
+    async fn classifies_clients_and_serves_bots_from_cache() {
+        let renders = Arc::new(AtomicU64::new(0));
+        let mut app = App::default();
+        app.use_middleware(
+            UserAgentMiddleware::new().bots(BotPolicy::Cache(CacheMiddleware::new())),
+        );
+        let count = renders.clone();
+        app.get_fn("/", move |req| {
+            count.fetch_add(1, Ordering::Relaxed);
+            let ua = req.get_request_share_data::<UserAgent>().unwrap();
+            format!("{:?} {}", ua.device, ua.browser)
+        });
+        let client = app.test_client();
+
+        let res = client.get("/").header("user-agent", IPHONE).send().await;
+        assert_eq!(res.text(), "Mobile Safari");
+        let res = client.get("/").send().await;
+        assert_eq!(res.text(), "Unknown ");
+
+        for _ in 0..3 {
+            let res = client.get("/").header("user-agent", GOOGLEBOT).send().await;
+            assert_eq!(res.text(), "Bot Googlebot");
+        }
+        assert_eq!(renders.load(Ordering::Relaxed), 3);
+
+        let mut app = App::default();
+        app.use_middleware(UserAgentMiddleware::new().bots(BotPolicy::Block));
+        app.get_fn("/", |_| "page");
+        let client = app.test_client();
+        let res = client.get("/").header("user-agent", GOOGLEBOT).send().await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = client.get("/").header("user-agent", IPHONE).send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,96 @@
+use std::net::ToSocketAddrs;
+use std::path::Path;
+
+use super::{ClientAuth, Listener, TlsConfig};
+
+/// Problems found by `App::check`, one line each
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckError(pub Vec<String>);
+
+impl std::fmt::Display for CheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} configuration problem(s)", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CheckError {}
+
+/// Addresses that resolve and aren't repeated, and TLS files that can be read
+pub(crate) fn check_listeners(listeners: &[Listener], problems: &mut Vec<String>) {
+    let mut seen = std::collections::HashSet::new();
+    for listener in listeners {
+        let addr = listener.addr.as_str();
+        if !seen.insert(addr) {
+            problems.push(format!("listener {addr}: address used twice"));
+        }
+        if let Err(e) = addr.to_socket_addrs() {
+            problems.push(format!("listener {addr}: {e}"));
+        }
+        if let Some(tls) = &listener.tls {
+            #[cfg(not(any(feature = "openssl", feature = "boringssl")))]
+            problems.push(format!(
+                "listener {addr}: TLS needs the `openssl` or `boringssl` feature"
+            ));
+            check_tls(addr, tls, problems);
+        }
+    }
+}
+
+fn check_tls(addr: &str, tls: &TlsConfig, problems: &mut Vec<String>) {
+    let mut files = vec![("certificate", &tls.cert_path), ("key", &tls.key_path)];
+    if let ClientAuth::Optional(ca) | ClientAuth::Required(ca) = &tls.client_auth {
+        files.push(("client CA bundle", ca));
+    }
+    for (what, path) in files {
+        if let Err(e) = std::fs::File::open(path) {
+            problems.push(format!(
+                "listener {addr}: cannot read TLS {what} {path}: {e}"
+            ));
+        }
+    }
+}
+
+/// `path` exists and is a directory
+pub(crate) fn check_dir(what: &str, path: &Path) -> Option<String> {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_dir() => None,
+        Ok(_) => Some(format!("{what} {} is not a directory", path.display())),
+        Err(e) => Some(format!("{what} {}: {e}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_every_listener_problem() {
+        let dir = std::env::temp_dir();
+        let cert = dir.join(format!("pingora_web_check_{}.pem", std::process::id()));
+        std::fs::write(&cert, "cert").unwrap();
+        let cert = cert.to_str().unwrap();
+        let listeners = [
+            Listener::new("127.0.0.1:8080"),
+            Listener::new("127.0.0.1:8080"),
+            Listener::new("no-port"),
+            Listener::new("127.0.0.1:8443").tls(TlsConfig::new(cert, "/missing/key.pem")),
+        ];
+        let mut problems = Vec::new();
+        check_listeners(&listeners, &mut problems);
+        std::fs::remove_file(cert).ok();
+
+        assert!(problems[0].contains("8080: address used twice"));
+        assert!(problems.iter().any(|p| p.starts_with("listener no-port:")));
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.contains("cannot read TLS key /missing/key.pem"))
+        );
+        assert!(!problems.iter().any(|p| p.contains("TLS certificate")));
+        assert_eq!(check_dir("root", &dir), None);
+    }
+}
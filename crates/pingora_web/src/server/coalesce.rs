@@ -0,0 +1,102 @@
+use bytes::{Bytes, BytesMut};
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+
+/// How streamed response chunks are merged before they are written
+///
+/// Off by default: every chunk is a write. With a `min_write_size`, chunks
+/// the stream already has ready are merged until the buffer reaches that
+/// size or holds `max_chunks` chunks. A stream that isn't ready is never
+/// waited on, so slow producers (server-sent events, progress output) are
+/// flushed right away, and nothing is pulled from the stream while a write
+/// is pending.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkCoalescing {
+    /// Merge chunks until a write has at least this many bytes (default: 0, off)
+    pub min_write_size: usize,
+    /// Write after merging this many chunks even when still under
+    /// `min_write_size`; `0` for no cap (default: 0)
+    pub max_chunks: usize,
+}
+
+impl ChunkCoalescing {
+    pub fn new(min_write_size: usize) -> Self {
+        Self {
+            min_write_size,
+            max_chunks: 0,
+        }
+    }
+
+    /// Set the most chunks merged into one write
+    pub fn max_chunks(mut self, count: usize) -> Self {
+        self.max_chunks = count;
+        self
+    }
+
+    /// Merge ready chunks after `first`; also returns whether the stream ended
+    pub(crate) fn coalesce(
+        &self,
+        first: Bytes,
+        stream: &mut BoxStream<'static, Bytes>,
+    ) -> (Bytes, bool) {
+        if first.len() >= self.min_write_size {
+            return (first, false);
+        }
+        let mut buf = BytesMut::from(first);
+        let mut chunks = 1;
+        let mut ended = false;
+        while buf.len() < self.min_write_size && (self.max_chunks == 0 || chunks < self.max_chunks)
+        {
+            match stream.next().now_or_never() {
+                Some(Some(chunk)) => {
+                    buf.extend_from_slice(&chunk);
+                    chunks += 1;
+                }
+                Some(None) => {
+                    ended = true;
+                    break;
+                }
+                None => break,
+            }
+        }
+        (buf.freeze(), ended)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_ready_chunks_up_to_the_limits() {
+        let chunks =
+            || futures::stream::iter((0..10).map(|n| Bytes::from(format!("chunk-{n}\n")))).boxed();
+
+        let mut stream = chunks();
+        let coalescing = ChunkCoalescing::new(20);
+        let (first, ended) = coalescing.coalesce(Bytes::from_static(b"start\n"), &mut stream);
+        assert_eq!(first, "start\nchunk-0\nchunk-1\n");
+        assert!(!ended);
+
+        let mut stream = chunks();
+        let coalescing = ChunkCoalescing::new(1024).max_chunks(3);
+        let (first, _) = coalescing.coalesce(Bytes::from_static(b"start\n"), &mut stream);
+        assert_eq!(first, "start\nchunk-0\nchunk-1\n");
+        let (rest, ended) = coalescing.coalesce(Bytes::new(), &mut stream);
+        assert_eq!(rest, "chunk-2\nchunk-3\n");
+        assert!(!ended);
+
+        let mut stream = chunks();
+        let (all, ended) = ChunkCoalescing::new(1024).coalesce(Bytes::new(), &mut stream);
+        assert_eq!(all.len(), 80);
+        assert!(ended);
+
+        // Producers that aren't ready are not waited for
+        let mut pending = futures::stream::pending().boxed();
+        let (first, ended) =
+            ChunkCoalescing::new(1024).coalesce(Bytes::from_static(b"a"), &mut pending);
+        assert_eq!(first, "a");
+        assert!(!ended);
+    }
+}
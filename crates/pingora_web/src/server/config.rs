@@ -0,0 +1,383 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+
+use super::{KeepAlive, ParserLimits};
+use crate::middleware::LimitsConfig;
+
+/// Prefix of the environment variables read by `AppConfig::with_env`
+pub const ENV_PREFIX: &str = "PINGORA_WEB_";
+
+/// Deployment settings loaded from a file and/or the environment
+///
+/// Every field is optional; missing ones keep the App's defaults. Apply it
+/// with `App::from_config` or `App::apply_config`, then start the configured
+/// listeners with `App::run`.
+///
+/// # Example
+/// ```
+/// use pingora_web::AppConfig;
+///
+/// let config = AppConfig::from_yaml(
+///     r#"
+/// listen: ["0.0.0.0:8080"]
+/// log_level: info
+/// compression_level: 6
+/// keep_alive:
+///   idle_timeout_secs: 30
+///   max_requests: 1000
+/// limits:
+///   max_body_size: 2097152
+/// "#,
+/// )
+/// .unwrap();
+/// assert_eq!(config.listen, ["0.0.0.0:8080"]);
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    /// Plain HTTP listen addresses, e.g. `0.0.0.0:8080`
+    pub listen: Vec<String>,
+    /// HTTPS listeners (need the `openssl` or `boringssl` feature)
+    pub tls: Vec<TlsListener>,
+    /// Log level for your tracing subscriber: `off`, `error` ... `trace`
+    pub log_level: Option<String>,
+    /// Enable response compression at this level (1-9)
+    pub compression_level: Option<u32>,
+    /// See `App::set_response_chunk_size`
+    pub response_chunk_size: Option<usize>,
+    /// See `App::set_graceful_shutdown_timeout`
+    pub graceful_shutdown_timeout_secs: Option<u64>,
+    pub keep_alive: KeepAliveSettings,
+    pub limits: LimitSettings,
+}
+
+/// One HTTPS listener
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsListener {
+    pub addr: String,
+    pub cert_path: String,
+    pub key_path: String,
+    /// Offer HTTP/2 through ALPN
+    #[serde(default)]
+    pub h2: bool,
+}
+
+/// Overrides for `KeepAlive`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeepAliveSettings {
+    pub enabled: Option<bool>,
+    pub idle_timeout_secs: Option<u64>,
+    pub max_requests: Option<u32>,
+}
+
+/// Overrides for `ParserLimits` and `LimitsConfig`
+///
+/// `LimitsMiddleware` is installed only when one of its limits is set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitSettings {
+    pub max_header_bytes: Option<usize>,
+    pub max_header_count: Option<usize>,
+    pub request_timeout_secs: Option<u64>,
+    pub max_body_size: Option<usize>,
+    pub max_path_length: Option<usize>,
+}
+
+/// Why a configuration could not be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Io(String, std::io::Error),
+    /// The document is malformed or has unknown keys
+    Parse(String),
+    /// A value is out of range or can't be parsed
+    Invalid { key: String, value: String },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "cannot read config {path}: {e}"),
+            Self::Parse(e) => write!(f, "invalid config: {e}"),
+            Self::Invalid { key, value } => write!(f, "invalid value for {key}: {value:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl AppConfig {
+    pub fn from_yaml(text: &str) -> Result<Self, ConfigError> {
+        let config: Self =
+            serde_yaml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, ConfigError> {
+        let config: Self =
+            serde_json::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()
+    }
+
+    /// Load a `.yaml`/`.yml` or `.json` file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(name.clone(), e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::from_json(&text),
+            Some("yaml" | "yml") => Self::from_yaml(&text),
+            _ => Err(ConfigError::Parse(format!(
+                "{name}: expected a .yaml, .yml or .json file"
+            ))),
+        }
+    }
+
+    /// Defaults overridden by `PINGORA_WEB_*` environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::default().with_env()
+    }
+
+    /// Override settings with `PINGORA_WEB_*` environment variables
+    ///
+    /// `LISTEN` takes comma-separated addresses; `TLS_LISTEN`, `TLS_CERT`,
+    /// `TLS_KEY` and `TLS_H2` add one HTTPS listener. The others mirror the
+    /// fields: `LOG_LEVEL`, `COMPRESSION_LEVEL`, `RESPONSE_CHUNK_SIZE`,
+    /// `GRACEFUL_SHUTDOWN_TIMEOUT_SECS`, `KEEP_ALIVE`, `KEEP_ALIVE_IDLE_TIMEOUT_SECS`, `KEEP_ALIVE_MAX_REQUESTS`,
+    /// `MAX_HEADER_BYTES`, `MAX_HEADER_COUNT`, `REQUEST_TIMEOUT_SECS`,
+    /// `MAX_BODY_SIZE` and `MAX_PATH_LENGTH`.
+    pub fn with_env(self) -> Result<Self, ConfigError> {
+        self.with_vars(std::env::vars())
+    }
+
+    fn with_vars(
+        mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let vars: std::collections::HashMap<String, String> = vars
+            .into_iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix(ENV_PREFIX)?.to_string(), v)))
+            .collect();
+        let get = |key: &'static str| vars.get(key).map(|v| (key, v.trim()));
+
+        if let Some((_, listen)) = get("LISTEN") {
+            self.listen = listen
+                .split(',')
+                .map(str::trim)
+                .filter(|a| !a.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some((_, addr)) = get("TLS_LISTEN") {
+            let required = |key: &'static str| {
+                get(key)
+                    .map(|(_, v)| v.to_string())
+                    .ok_or_else(|| ConfigError::Invalid {
+                        key: format!("{ENV_PREFIX}{key}"),
+                        value: String::new(),
+                    })
+            };
+            self.tls = vec![TlsListener {
+                addr: addr.to_string(),
+                cert_path: required("TLS_CERT")?,
+                key_path: required("TLS_KEY")?,
+                h2: get("TLS_H2").map(parse).transpose()?.unwrap_or(false),
+            }];
+        }
+        if let Some((_, level)) = get("LOG_LEVEL") {
+            self.log_level = Some(level.to_string());
+        }
+        set(&mut self.compression_level, get("COMPRESSION_LEVEL"))?;
+        set(&mut self.response_chunk_size, get("RESPONSE_CHUNK_SIZE"))?;
+        set(
+            &mut self.graceful_shutdown_timeout_secs,
+            get("GRACEFUL_SHUTDOWN_TIMEOUT_SECS"),
+        )?;
+        set(&mut self.keep_alive.enabled, get("KEEP_ALIVE"))?;
+        set(
+            &mut self.keep_alive.idle_timeout_secs,
+            get("KEEP_ALIVE_IDLE_TIMEOUT_SECS"),
+        )?;
+        set(
+            &mut self.keep_alive.max_requests,
+            get("KEEP_ALIVE_MAX_REQUESTS"),
+        )?;
+        set(&mut self.limits.max_header_bytes, get("MAX_HEADER_BYTES"))?;
+        set(&mut self.limits.max_header_count, get("MAX_HEADER_COUNT"))?;
+        set(
+            &mut self.limits.request_timeout_secs,
+            get("REQUEST_TIMEOUT_SECS"),
+        )?;
+        set(&mut self.limits.max_body_size, get("MAX_BODY_SIZE"))?;
+        set(&mut self.limits.max_path_length, get("MAX_PATH_LENGTH"))?;
+        self.validate()
+    }
+
+    fn validate(self) -> Result<Self, ConfigError> {
+        if let Some(level) = &self.log_level
+            && LevelFilter::from_str(level).is_err()
+        {
+            return Err(invalid("log_level", level));
+        }
+        if let Some(level) = self.compression_level
+            && !(1..=9).contains(&level)
+        {
+            return Err(invalid("compression_level", level));
+        }
+        // Pingora's own caps apply first, so larger limits would never take effect
+        if let Some(bytes) = self.limits.max_header_bytes
+            && bytes > ParserLimits::MAX_HEADER_BYTES
+        {
+            return Err(invalid("max_header_bytes", bytes));
+        }
+        if let Some(count) = self.limits.max_header_count
+            && count > ParserLimits::MAX_HEADER_COUNT
+        {
+            return Err(invalid("max_header_count", count));
+        }
+        Ok(self)
+    }
+
+    /// `log_level` for a tracing subscriber
+    pub fn level_filter(&self) -> Option<LevelFilter> {
+        self.log_level.as_deref().and_then(|l| l.parse().ok())
+    }
+
+    pub(crate) fn listeners(&self) -> Vec<super::Listener> {
+        let plain = self.listen.iter().map(super::Listener::new);
+        let tls = self.tls.iter().map(|listener| {
+            let mut tls = super::TlsConfig::new(&listener.cert_path, &listener.key_path);
+            if listener.h2 {
+                tls = tls.enable_h2();
+            }
+            super::Listener::new(&listener.addr).tls(tls)
+        });
+        plain.chain(tls).collect()
+    }
+
+    pub(crate) fn parser_limits(&self) -> ParserLimits {
+        let mut limits = ParserLimits::default();
+        if let Some(bytes) = self.limits.max_header_bytes {
+            limits.max_header_bytes = bytes;
+        }
+        if let Some(count) = self.limits.max_header_count {
+            limits.max_header_count = count;
+        }
+        limits
+    }
+
+    pub(crate) fn keep_alive(&self) -> KeepAlive {
+        let settings = &self.keep_alive;
+        let mut keep_alive = KeepAlive::default();
+        if let Some(enabled) = settings.enabled {
+            keep_alive.enabled = enabled;
+        }
+        if let Some(secs) = settings.idle_timeout_secs {
+            keep_alive.idle_timeout = Some(Duration::from_secs(secs));
+        }
+        keep_alive.max_requests = settings.max_requests;
+        keep_alive
+    }
+
+    /// `None` unless a middleware limit is configured
+    pub(crate) fn limits_middleware(&self) -> Option<LimitsConfig> {
+        let limits = &self.limits;
+        if limits.request_timeout_secs.is_none()
+            && limits.max_body_size.is_none()
+            && limits.max_path_length.is_none()
+        {
+            return None;
+        }
+        let mut config = LimitsConfig::default();
+        if let Some(secs) = limits.request_timeout_secs {
+            config.request_timeout = Duration::from_secs(secs);
+        }
+        if let Some(size) = limits.max_body_size {
+            config.max_body_size = size;
+        }
+        if let Some(length) = limits.max_path_length {
+            config.max_path_length = length;
+        }
+        Some(config)
+    }
+}
+
+fn invalid(key: &str, value: impl std::fmt::Display) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn parse<T: FromStr>((key, value): (&str, &str)) -> Result<T, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| invalid(&format!("{ENV_PREFIX}{key}"), value))
+}
+
+fn set<T: FromStr>(field: &mut Option<T>, var: Option<(&str, &str)>) -> Result<(), ConfigError> {
+    if let Some(var) = var {
+        *field = Some(parse(var)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_overrides_file_values() {
+        let config = AppConfig::from_json(
+            r#"{"listen": ["127.0.0.1:8080"], "keep_alive": {"max_requests": 10}}"#,
+        )
+        .unwrap();
+        let vars = [
+            ("PINGORA_WEB_LISTEN", "0.0.0.0:80, 0.0.0.0:81"),
+            ("PINGORA_WEB_KEEP_ALIVE_IDLE_TIMEOUT_SECS", "5"),
+            ("PINGORA_WEB_MAX_BODY_SIZE", "1024"),
+            ("PINGORA_WEB_TLS_LISTEN", "0.0.0.0:443"),
+            ("PINGORA_WEB_TLS_CERT", "cert.pem"),
+            ("PINGORA_WEB_TLS_KEY", "key.pem"),
+            ("HOME", "/root"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let config = config.with_vars(vars).unwrap();
+
+        assert_eq!(config.listen, ["0.0.0.0:80", "0.0.0.0:81"]);
+        assert_eq!(config.tls[0].cert_path, "cert.pem");
+        let keep_alive = config.keep_alive();
+        assert_eq!(keep_alive.max_requests, Some(10));
+        assert_eq!(keep_alive.idle_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.limits_middleware().unwrap().max_body_size, 1024);
+        assert_eq!(config.parser_limits().max_header_count, 256);
+    }
+
+    #[test]
+    fn rejects_bad_values_and_unknown_keys() {
+        assert!(matches!(
+            AppConfig::from_yaml("log_level: loud"),
+            Err(ConfigError::Invalid { .. })
+        ));
+        assert!(matches!(
+            AppConfig::from_yaml("limits:\n  max_header_count: 1000"),
+            Err(ConfigError::Invalid { .. })
+        ));
+        assert!(matches!(
+            AppConfig::from_yaml("compresion_level: 6"),
+            Err(ConfigError::Parse(_))
+        ));
+        let vars =
+            [("PINGORA_WEB_MAX_HEADER_COUNT", "many")].map(|(k, v)| (k.to_string(), v.to_string()));
+        let err = AppConfig::default().with_vars(vars).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value for PINGORA_WEB_MAX_HEADER_COUNT: \"many\""
+        );
+    }
+}
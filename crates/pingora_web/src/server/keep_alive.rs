@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use pingora::protocols::SocketDigest;
+use pingora::protocols::http::ServerSession;
+
+/// HTTP/1.1 connection reuse settings
+///
+/// Applied to every HTTP/1 request before the handler runs; HTTP/2 manages its
+/// own connections and ignores these. When a connection won't be reused, the
+/// response carries `Connection: close`.
+#[derive(Clone, Debug)]
+pub struct KeepAlive {
+    /// Reuse connections at all (default: true)
+    pub enabled: bool,
+    /// How long an idle connection waits for its next request; `None` waits
+    /// forever (default: 60s)
+    pub idle_timeout: Option<Duration>,
+    /// Close the connection after this many requests (default: unlimited)
+    pub max_requests: Option<u32>,
+    /// Answer with `Connection: close` once graceful shutdown started, so
+    /// clients move to another instance (default: true)
+    pub close_on_drain: bool,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_timeout: Some(Duration::from_secs(60)),
+            max_requests: None,
+            close_on_drain: true,
+        }
+    }
+}
+
+impl KeepAlive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Close every connection after one request
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+
+    /// Set the idle timeout (rounded up to whole seconds), `None` for no timeout
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Set the maximum number of requests served per connection
+    pub fn max_requests(mut self, count: u32) -> Self {
+        self.max_requests = Some(count);
+        self
+    }
+
+    /// Keep reusing connections while draining instead of closing them
+    pub fn close_on_drain(mut self, close: bool) -> Self {
+        self.close_on_drain = close;
+        self
+    }
+
+    /// The value for `ServerSession::set_keepalive`: `None` closes, `Some(0)` never times out
+    fn session_timeout(&self) -> Option<u64> {
+        if !self.enabled {
+            return None;
+        }
+        Some(
+            self.idle_timeout
+                .map_or(0, |t| t.as_secs() + u64::from(t.subsec_nanos() > 0)),
+        )
+    }
+}
+
+/// Counts requests per HTTP/1 connection for `KeepAlive::max_requests`
+///
+/// Connections are told apart by their socket digest, which pingora keeps for
+/// the lifetime of the connection; entries of closed connections are pruned
+/// as the table grows.
+#[derive(Default)]
+pub(crate) struct ConnectionRequests {
+    table: Mutex<CountTable>,
+}
+
+#[derive(Default)]
+struct CountTable {
+    counts: HashMap<usize, (Weak<SocketDigest>, u32)>,
+    prune_at: usize,
+}
+
+impl ConnectionRequests {
+    /// Record a request on this connection and return how many it has served
+    fn next(&self, digest: &Arc<SocketDigest>) -> u32 {
+        let mut table = self.table.lock().unwrap();
+        let key = Arc::as_ptr(digest) as usize;
+        let entry = table
+            .counts
+            .entry(key)
+            .or_insert_with(|| (Arc::downgrade(digest), 0));
+        // The address may belong to a closed connection's digest
+        if !entry.0.upgrade().is_some_and(|d| Arc::ptr_eq(&d, digest)) {
+            *entry = (Arc::downgrade(digest), 0);
+        }
+        entry.1 += 1;
+        let served = entry.1;
+
+        if table.counts.len() > table.prune_at {
+            table.counts.retain(|_, (conn, _)| conn.strong_count() > 0);
+            table.prune_at = (table.counts.len() * 2).max(1024);
+        }
+        served
+    }
+
+    /// Apply `keep_alive` to the session of a new request
+    pub(crate) fn apply(&self, keep_alive: &KeepAlive, http: &mut ServerSession, draining: bool) {
+        let mut timeout = keep_alive.session_timeout();
+        if draining && keep_alive.close_on_drain {
+            timeout = None;
+        }
+        if let Some(max) = keep_alive.max_requests
+            && timeout.is_some()
+            && let Some(digest) = http.digest().and_then(|d| d.socket_digest.clone())
+            && self.next(&digest) >= max
+        {
+            timeout = None;
+        }
+        http.set_keepalive(timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_per_connection() {
+        let requests = ConnectionRequests::default();
+        let a = Arc::new(SocketDigest::from_raw_fd(0));
+        let b = Arc::new(SocketDigest::from_raw_fd(1));
+        assert_eq!(requests.next(&a), 1);
+        assert_eq!(requests.next(&a), 2);
+        assert_eq!(requests.next(&b), 1);
+
+        assert_eq!(KeepAlive::disabled().session_timeout(), None);
+        let keep_alive = KeepAlive::new().idle_timeout(Some(Duration::from_millis(1500)));
+        assert_eq!(keep_alive.session_timeout(), Some(2));
+        assert_eq!(
+            KeepAlive::new().idle_timeout(None).session_timeout(),
+            Some(0)
+        );
+    }
+}
@@ -0,0 +1,481 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use pingora::apps::ServerApp;
+use pingora::protocols::Stream;
+use pingora::protocols::l4::ext::TcpKeepalive;
+use pingora::server::ShutdownWatch;
+use pingora_core::listeners::TcpSocketOptions;
+
+use super::TlsConfig;
+
+/// A listening socket for `App::add_listener` and `App::run`
+///
+/// Options are applied where the platform supports them and skipped
+/// elsewhere: `reuse_port` needs a Unix system, `fast_open` Linux, and
+/// `ipv6_only` only affects IPv6 addresses. `nodelay`, `send_buffer` and
+/// `recv_buffer` are set on each accepted connection (Unix only). The
+/// backlog (65535) and `TCP_DEFER_ACCEPT` cannot be changed, since pingora
+/// creates the listening socket.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, Listener};
+///
+/// let mut app = App::default();
+/// // IPv4 and IPv6 clients on one socket
+/// app.add_listener(Listener::new("[::]:8080").dual_stack());
+/// // app.run().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Listener {
+    pub(crate) addr: String,
+    ipv6_only: Option<bool>,
+    reuse_port: Option<bool>,
+    fast_open: Option<usize>,
+    keepalive: Option<TcpKeepalive>,
+    connection: ConnectionOptions,
+    pub(crate) tls: Option<TlsConfig>,
+}
+
+/// Options set on accepted connections before the App reads from them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ConnectionOptions {
+    nodelay: Option<bool>,
+    send_buffer: Option<usize>,
+    recv_buffer: Option<usize>,
+}
+
+impl Listener {
+    /// Plain TCP listener on `addr`, e.g. `0.0.0.0:8080` or `[::1]:8080`
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            ipv6_only: None,
+            reuse_port: None,
+            fast_open: None,
+            keepalive: None,
+            connection: ConnectionOptions::default(),
+            tls: None,
+        }
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Serve HTTPS (requires the `openssl` or `boringssl` feature to run)
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Accept only IPv6 clients on an IPv6 address (default: the OS setting,
+    /// dual-stack on most Linux systems but not on Windows or OpenBSD)
+    pub fn ipv6_only(mut self, enabled: bool) -> Self {
+        self.ipv6_only = Some(enabled);
+        self
+    }
+
+    /// Accept IPv4 clients too on an IPv6 address, whatever the OS default
+    pub fn dual_stack(self) -> Self {
+        self.ipv6_only(false)
+    }
+
+    /// Set `SO_REUSEPORT` so several sockets can bind this address (Unix only)
+    pub fn reuse_port(mut self, enabled: bool) -> Self {
+        self.reuse_port = Some(enabled);
+        self
+    }
+
+    /// Enable TCP Fast Open with a queue of `backlog` pending requests (Linux only)
+    pub fn fast_open(mut self, backlog: usize) -> Self {
+        self.fast_open = Some(backlog);
+        self
+    }
+
+    /// Set `TCP_NODELAY` on accepted connections (default: on); turn it off to
+    /// let the kernel coalesce small writes
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.connection.nodelay = Some(enabled);
+        self
+    }
+
+    /// Probe idle connections: the first probe after `idle`, then every
+    /// `interval`, dropping the connection after `count` unanswered probes
+    pub fn keepalive(mut self, idle: Duration, interval: Duration, count: usize) -> Self {
+        self.keepalive = Some(TcpKeepalive {
+            idle,
+            interval,
+            count,
+            #[cfg(target_os = "linux")]
+            user_timeout: Duration::ZERO,
+        });
+        self
+    }
+
+    /// `SO_SNDBUF` of accepted connections, in bytes
+    pub fn send_buffer(mut self, bytes: usize) -> Self {
+        self.connection.send_buffer = Some(bytes);
+        self
+    }
+
+    /// `SO_RCVBUF` of accepted connections, in bytes
+    ///
+    /// Set after the handshake, so it does not raise the window scale the
+    /// connection started with.
+    pub fn recv_buffer(mut self, bytes: usize) -> Self {
+        self.connection.recv_buffer = Some(bytes);
+        self
+    }
+
+    /// The resolved address and per-connection options, if any are set
+    pub(crate) fn connection_tuning(&self) -> Option<(SocketAddr, ConnectionOptions)> {
+        if self.connection == ConnectionOptions::default() {
+            return None;
+        }
+        if cfg!(not(unix)) {
+            tracing::warn!(
+                addr = self.addr.as_str(),
+                "Per-connection socket options are not supported on this platform, ignoring"
+            );
+            return None;
+        }
+        let addr = self.addr.to_socket_addrs().ok()?.next()?;
+        Some((addr, self.connection))
+    }
+
+    /// The address as shown in the startup summary
+    pub(crate) fn label(&self) -> String {
+        match self.tls {
+            Some(_) => format!("{} (tls)", self.addr),
+            None => self.addr.clone(),
+        }
+    }
+
+    fn is_ipv6(&self) -> bool {
+        self.addr.starts_with('[')
+    }
+
+    /// Socket options for pingora, without the ones this platform or address ignores
+    pub(crate) fn socket_options(&self) -> Option<TcpSocketOptions> {
+        let mut options = TcpSocketOptions::default();
+        // IPV6_V6ONLY fails on IPv4 sockets
+        options.ipv6_only = self.ipv6_only.filter(|_| self.is_ipv6());
+        if cfg!(unix) {
+            options.so_reuseport = self.reuse_port;
+        } else if self.reuse_port == Some(true) {
+            tracing::warn!(
+                addr = self.addr.as_str(),
+                "SO_REUSEPORT is not supported on this platform, ignoring"
+            );
+        }
+        options.tcp_fastopen = self.fast_open;
+        options.tcp_keepalive = self.keepalive.clone();
+        let unchanged = options.ipv6_only.is_none()
+            && options.so_reuseport.is_none()
+            && options.tcp_fastopen.is_none()
+            && options.tcp_keepalive.is_none();
+        (!unchanged).then_some(options)
+    }
+
+    /// Add this listener to a pingora service
+    pub(crate) fn add_to<A>(
+        &self,
+        service: &mut pingora::services::listening::Service<A>,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        A: pingora::apps::ServerApp + Send + Sync + 'static,
+    {
+        match &self.tls {
+            None => match self.socket_options() {
+                Some(options) => service.add_tcp_with_settings(&self.addr, options),
+                None => service.add_tcp(&self.addr),
+            },
+            #[cfg(any(feature = "openssl", feature = "boringssl"))]
+            Some(tls) => {
+                let settings = tls.clone().into_settings()?;
+                service.add_tls_with_settings(&self.addr, self.socket_options(), settings);
+            }
+            #[cfg(not(any(feature = "openssl", feature = "boringssl")))]
+            Some(_) => {
+                return Err("TLS listeners need the `openssl` or `boringssl` feature".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A pingora service serving `app` on `listeners`
+pub(crate) fn listening_service<A>(
+    app: A,
+    listeners: &[Listener],
+) -> Result<Box<dyn pingora::services::Service>, Box<dyn std::error::Error>>
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    let mut service = pingora::services::listening::Service::new("pingora_web".to_string(), app);
+    for listener in listeners {
+        listener.add_to(&mut service)?;
+    }
+    Ok(Box::new(service))
+}
+
+/// Wraps an App to set listener options on each connection it is handed
+pub(crate) struct Tuned<A> {
+    app: Arc<A>,
+    /// Listener addresses with the options for connections accepted on them
+    listeners: Vec<(SocketAddr, ConnectionOptions)>,
+}
+
+impl<A> Tuned<A> {
+    pub(crate) fn new(app: Arc<A>, listeners: Vec<(SocketAddr, ConnectionOptions)>) -> Self {
+        Self { app, listeners }
+    }
+
+    /// Options of the listener that accepted a connection to `local`
+    fn options_for(&self, local: SocketAddr) -> Option<ConnectionOptions> {
+        self.listeners
+            .iter()
+            .find(|(addr, _)| {
+                addr.port() == local.port()
+                    && (addr.ip().is_unspecified() || addr.ip() == local.ip().to_canonical())
+            })
+            .map(|(_, options)| *options)
+    }
+
+    #[cfg(unix)]
+    fn apply(&self, stream: &Stream) {
+        use std::os::fd::{AsRawFd, BorrowedFd};
+
+        if self.listeners.is_empty() {
+            return;
+        }
+        let Some(fd) = tcp_fd(stream) else {
+            return;
+        };
+        // SAFETY: `stream` owns the descriptor and outlives this borrow
+        let fd = unsafe { BorrowedFd::borrow_raw(fd.as_raw_fd()) };
+        let socket = socket2::SockRef::from(&fd);
+        let Some(local) = socket.local_addr().ok().and_then(|a| a.as_socket()) else {
+            return;
+        };
+        let Some(options) = self.options_for(local) else {
+            return;
+        };
+        let result = (|| {
+            if let Some(nodelay) = options.nodelay {
+                socket.set_tcp_nodelay(nodelay)?;
+            }
+            if let Some(size) = options.send_buffer {
+                socket.set_send_buffer_size(size)?;
+            }
+            if let Some(size) = options.recv_buffer {
+                socket.set_recv_buffer_size(size)?;
+            }
+            Ok::<_, std::io::Error>(())
+        })();
+        if let Err(e) = result {
+            tracing::warn!(error = %e, %local, "Failed to set connection socket options");
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn apply(&self, _stream: &Stream) {}
+}
+
+/// Descriptor of a plain or TLS TCP stream
+#[cfg(unix)]
+fn tcp_fd(stream: &Stream) -> Option<std::os::fd::RawFd> {
+    use pingora::protocols::l4::stream::Stream as TcpStream;
+    use std::os::fd::AsRawFd;
+
+    let any = stream.as_any();
+    if let Some(tcp) = any.downcast_ref::<TcpStream>() {
+        return Some(tcp.as_raw_fd());
+    }
+    #[cfg(any(feature = "openssl", feature = "boringssl"))]
+    if let Some(tls) = any.downcast_ref::<pingora::protocols::tls::SslStream<TcpStream>>() {
+        return Some(tls.get_ref().as_raw_fd());
+    }
+    None
+}
+
+#[async_trait]
+impl<A: ServerApp + Send + Sync + 'static> ServerApp for Tuned<A> {
+    async fn process_new(
+        self: &Arc<Self>,
+        session: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        // Kept-alive connections come back here; the options are idempotent
+        self.apply(&session);
+        self.app.process_new(session, shutdown).await
+    }
+
+    async fn cleanup(&self) {
+        self.app.cleanup().await
+    }
+}
+
+/// Runs a future, e.g. the App's warmup, on the service's own runtime before
+/// the wrapped service builds its listeners and starts accepting
+pub(crate) struct StartAfter {
+    inner: Box<dyn pingora::services::Service>,
+    /// In a mutex only to make the service `Sync`
+    before: std::sync::Mutex<Option<futures::future::BoxFuture<'static, ()>>>,
+}
+
+impl StartAfter {
+    pub(crate) fn new(
+        inner: Box<dyn pingora::services::Service>,
+        before: impl std::future::Future<Output = ()> + Send + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            before: std::sync::Mutex::new(Some(Box::pin(before))),
+        }
+    }
+}
+
+#[async_trait]
+impl pingora::services::Service for StartAfter {
+    async fn start_service(
+        &mut self,
+        #[cfg(unix)] fds: Option<pingora::server::ListenFds>,
+        shutdown: ShutdownWatch,
+        listeners_per_fd: usize,
+    ) {
+        let before = self.before.get_mut().map_or(None, Option::take);
+        if let Some(before) = before {
+            before.await;
+        }
+        self.inner
+            .start_service(
+                #[cfg(unix)]
+                fds,
+                shutdown,
+                listeners_per_fd,
+            )
+            .await
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn threads(&self) -> Option<usize> {
+        self.inner.threads()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_fall_back_per_address_and_platform() {
+        assert!(Listener::new("0.0.0.0:8080").socket_options().is_none());
+        // IPV6_V6ONLY only applies to IPv6 addresses
+        assert!(
+            Listener::new("127.0.0.1:8080")
+                .dual_stack()
+                .socket_options()
+                .is_none()
+        );
+        let options = Listener::new("[::]:8080")
+            .dual_stack()
+            .reuse_port(true)
+            .fast_open(16)
+            .socket_options()
+            .unwrap();
+        assert_eq!(options.ipv6_only, Some(false));
+        assert_eq!(options.so_reuseport, cfg!(unix).then_some(true));
+        assert_eq!(options.tcp_fastopen, Some(16));
+        assert_eq!(
+            Listener::new("[::1]:8443")
+                .tls(TlsConfig::new("cert.pem", "key.pem"))
+                .label(),
+            "[::1]:8443 (tls)"
+        );
+    }
+    #[cfg(unix)]
+    #[test]
+    fn connection_options_follow_the_accepting_listener() {
+        assert!(Listener::new("127.0.0.1:80").connection_tuning().is_none());
+        let keepalive = Listener::new("127.0.0.1:80")
+            .keepalive(Duration::from_secs(30), Duration::from_secs(5), 3)
+            .socket_options()
+            .unwrap();
+        assert_eq!(keepalive.tcp_keepalive.unwrap().count, 3);
+
+        let (addr, options) = Listener::new("0.0.0.0:8080")
+            .nodelay(false)
+            .recv_buffer(1 << 20)
+            .connection_tuning()
+            .unwrap();
+        assert_eq!(options.nodelay, Some(false));
+        let tuned = Tuned::new(Arc::new(()), vec![(addr, options)]);
+        assert_eq!(
+            tuned.options_for("10.1.2.3:8080".parse().unwrap()),
+            Some(options)
+        );
+        // IPv4 clients of a dual-stack socket show up as mapped addresses
+        assert!(
+            tuned
+                .options_for("[::ffff:10.1.2.3]:8080".parse().unwrap())
+                .is_some()
+        );
+        assert!(
+            tuned
+                .options_for("10.1.2.3:8443".parse().unwrap())
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn start_after_runs_before_the_service() {
+        use pingora::services::Service;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        struct Inner(Arc<AtomicBool>, Arc<AtomicBool>);
+
+        #[async_trait]
+        impl Service for Inner {
+            async fn start_service(
+                &mut self,
+                #[cfg(unix)] _fds: Option<pingora::server::ListenFds>,
+                _shutdown: ShutdownWatch,
+                _listeners_per_fd: usize,
+            ) {
+                self.1
+                    .store(self.0.load(Ordering::SeqCst), Ordering::SeqCst);
+            }
+
+            fn name(&self) -> &str {
+                "inner"
+            }
+        }
+
+        let warmed = Arc::new(AtomicBool::new(false));
+        let started_warm = Arc::new(AtomicBool::new(false));
+        let inner = Inner(warmed.clone(), started_warm.clone());
+        let mut service = StartAfter::new(Box::new(inner), async move {
+            tokio::task::yield_now().await;
+            warmed.store(true, Ordering::SeqCst);
+        });
+        let (_tx, shutdown) = tokio::sync::watch::channel(false);
+        service
+            .start_service(
+                #[cfg(unix)]
+                None,
+                shutdown,
+                1,
+            )
+            .await;
+        assert!(started_warm.load(Ordering::SeqCst));
+        assert_eq!(service.name(), "inner");
+    }
+}
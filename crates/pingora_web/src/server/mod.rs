@@ -0,0 +1,21 @@
+pub mod check;
+pub mod coalesce;
+pub mod config;
+pub mod keep_alive;
+pub mod listener;
+pub mod parser_limits;
+#[cfg(all(unix, feature = "systemd"))]
+pub mod systemd;
+pub mod tls;
+pub mod workers;
+pub mod write_failure;
+
+pub use check::CheckError;
+pub use coalesce::ChunkCoalescing;
+pub use config::{AppConfig, ConfigError, KeepAliveSettings, LimitSettings, TlsListener};
+pub use keep_alive::KeepAlive;
+pub use listener::Listener;
+pub use parser_limits::ParserLimits;
+pub use tls::{ALPN, ClientAuth, TlsConfig, TlsVersion};
+pub use workers::Workers;
+pub use write_failure::{WriteFailure, WriteStage};
@@ -0,0 +1,124 @@
+use pingora_http::RequestHeader;
+
+/// Header limits checked once Pingora has parsed the request head
+///
+/// Pingora reads and parses the whole head first, under its own fixed caps
+/// (`MAX_HEADER_BYTES` and `MAX_HEADER_COUNT`), so these limits can only be
+/// tighter than those and do not save the memory spent reading the head.
+/// They run before the request is copied into a `PingoraHttpRequest`, routed,
+/// or passed through HTTP modules and middleware. Requests exceeding a limit
+/// get a bare `431 Request Header Fields Too Large` and the connection is
+/// closed.
+///
+/// Unlike `LimitsMiddleware`, which validates requests after they've been built,
+/// these limits skip all per-request work for oversized heads.
+#[derive(Clone, Debug)]
+pub struct ParserLimits {
+    /// Maximum size of the request head in bytes: method, path, and all header
+    /// names and values (default and upper bound: `MAX_HEADER_BYTES`)
+    pub max_header_bytes: usize,
+    /// Maximum number of request headers (default and upper bound: `MAX_HEADER_COUNT`)
+    pub max_header_count: usize,
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: Self::MAX_HEADER_BYTES,
+            max_header_count: Self::MAX_HEADER_COUNT,
+        }
+    }
+}
+
+impl ParserLimits {
+    /// Pingora's own cap on the raw request head; larger heads never get here
+    pub const MAX_HEADER_BYTES: usize = 1024 * 1024 - 1;
+    /// Pingora's own cap on the number of request headers
+    pub const MAX_HEADER_COUNT: usize = 256;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set maximum request head size in bytes, at most `MAX_HEADER_BYTES`
+    pub fn max_header_bytes(mut self, size: usize) -> Self {
+        self.max_header_bytes = size.min(Self::MAX_HEADER_BYTES);
+        self
+    }
+
+    /// Set maximum number of request headers, at most `MAX_HEADER_COUNT`
+    pub fn max_header_count(mut self, count: usize) -> Self {
+        self.max_header_count = count.min(Self::MAX_HEADER_COUNT);
+        self
+    }
+
+    /// Return `true` if the parsed request head is within all limits
+    pub(crate) fn allows(&self, header: &RequestHeader) -> bool {
+        let count = header.headers.len();
+        if count > self.max_header_count {
+            tracing::warn!(
+                "Too many request headers: {} > {}",
+                count,
+                self.max_header_count
+            );
+            return false;
+        }
+
+        let size = header.method.as_str().len()
+            + header.raw_path().len()
+            + header
+                .headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>();
+        if size > self.max_header_bytes {
+            tracing::warn!(
+                "Request head too large: {} > {}",
+                size,
+                self.max_header_bytes
+            );
+            return false;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(path: &str, headers: &[(&str, &str)]) -> RequestHeader {
+        let mut h = RequestHeader::build("GET", path.as_bytes(), None).unwrap();
+        for (k, v) in headers {
+            h.append_header(k.to_string(), *v).unwrap();
+        }
+        h
+    }
+
+    #[test]
+    fn default_limits_allow_normal_requests() {
+        let limits = ParserLimits::default();
+        assert!(limits.allows(&head("/", &[("host", "example.com")])));
+    }
+
+    #[test]
+    fn header_count_limit() {
+        let limits = ParserLimits::new().max_header_count(2);
+        assert!(limits.allows(&head("/", &[("a", "1"), ("b", "2")])));
+        assert!(!limits.allows(&head("/", &[("a", "1"), ("b", "2"), ("c", "3")])));
+
+        // Pingora rejects more than its own cap before these checks run
+        let limits = ParserLimits::new().max_header_count(10_000);
+        assert_eq!(limits.max_header_count, ParserLimits::MAX_HEADER_COUNT);
+    }
+
+    #[test]
+    fn header_bytes_limit() {
+        // "GET" + "/" + "x-a" + "12345" = 12 bytes
+        let limits = ParserLimits::new().max_header_bytes(12);
+        assert!(limits.allows(&head("/", &[("x-a", "12345")])));
+        assert!(!limits.allows(&head("/", &[("x-a", "123456")])));
+        assert!(!limits.allows(&head("/long", &[("x-a", "12345")])));
+    }
+}
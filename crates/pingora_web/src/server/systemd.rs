@@ -0,0 +1,147 @@
+//! systemd service notifications (`sd_notify`)
+//!
+//! With `Type=notify` units, `App::listen`, `listen_tls*` and `run` send
+//! `READY=1` once the listeners accept connections (warmup has run by then),
+//! `WATCHDOG=1` heartbeats when `WatchdogSec=` is set, and `STOPPING=1` when
+//! graceful shutdown starts. Nothing is sent when `NOTIFY_SOCKET` is unset.
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+
+/// How long to wait for the listeners before reporting ready anyway
+const BIND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Send `state` (e.g. `"READY=1"`) to the service manager
+///
+/// Returns `Ok(false)` when not started by systemd (`NOTIFY_SOCKET` unset).
+pub fn notify(state: &str) -> io::Result<bool> {
+    match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => notify_to(Path::new(&socket), state).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+fn notify_to(socket: &Path, state: &str) -> io::Result<()> {
+    let sock = UnixDatagram::unbound()?;
+    // `@name` is a socket in the abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket.as_os_str().as_encoded_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        return sock.send_to_addr(state.as_bytes(), &addr).map(drop);
+    }
+    sock.send_to(state.as_bytes(), socket).map(drop)
+}
+
+/// Heartbeat interval: half of `WATCHDOG_USEC`, if the watchdog targets this process
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID")
+        && pid.trim().parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.trim().parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Background service sending the notifications for one App's listeners
+pub(crate) struct Notifier {
+    /// `host:port` of each listener, probed to tell when they are bound
+    pub(crate) addrs: Vec<String>,
+}
+
+impl Notifier {
+    /// A pingora service, or `None` outside systemd
+    pub(crate) fn service(addrs: Vec<String>) -> Option<Box<dyn pingora::services::Service>> {
+        std::env::var_os("NOTIFY_SOCKET")?;
+        let task = std::sync::Arc::new(Self { addrs });
+        Some(Box::new(
+            pingora::services::background::GenBackgroundService::new(
+                "systemd notify".to_string(),
+                task,
+            ),
+        ))
+    }
+
+    /// Wait until every listener accepts connections
+    async fn wait_for_listeners(&self) {
+        let deadline = tokio::time::Instant::now() + BIND_TIMEOUT;
+        for addr in &self.addrs {
+            let target = loopback(addr);
+            while tokio::net::TcpStream::connect(&target).await.is_err() {
+                if tokio::time::Instant::now() >= deadline {
+                    tracing::warn!(
+                        addr = addr.as_str(),
+                        "Listener not reachable, reporting ready"
+                    );
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+    }
+}
+
+/// Wildcard listen addresses are probed on loopback
+fn loopback(addr: &str) -> String {
+    if let Some(port) = addr.strip_prefix("0.0.0.0:") {
+        format!("127.0.0.1:{port}")
+    } else if let Some(port) = addr.strip_prefix("[::]:") {
+        format!("[::1]:{port}")
+    } else {
+        addr.to_string()
+    }
+}
+
+fn send(state: &str) {
+    if let Err(e) = notify(state) {
+        tracing::warn!(error = %e, state, "systemd notification failed");
+    }
+}
+
+#[async_trait]
+impl BackgroundService for Notifier {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        tokio::select! {
+            _ = self.wait_for_listeners() => send("READY=1"),
+            _ = shutdown.changed() => {}
+        }
+        let interval = watchdog_interval().unwrap_or(Duration::MAX);
+        while !*shutdown.borrow() {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => send("WATCHDOG=1"),
+                changed = shutdown.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        send("STOPPING=1");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_states_as_datagrams() {
+        let path = std::env::temp_dir().join(format!("pw-notify-{}", crate::utils::generate()));
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        notify_to(&path, "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loopback("0.0.0.0:8080"), "127.0.0.1:8080");
+        assert_eq!(loopback("10.0.0.1:80"), "10.0.0.1:80");
+    }
+}
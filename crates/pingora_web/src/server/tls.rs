@@ -0,0 +1,135 @@
+pub use pingora_core::listeners::ALPN;
+
+/// Minimum TLS protocol version accepted by a listener
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+/// Client certificate authentication policy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Do not ask clients for a certificate (default)
+    None,
+    /// Ask for a certificate signed by the given CA bundle, but accept clients without one
+    Optional(String),
+    /// Reject clients that don't present a certificate signed by the given CA bundle
+    Required(String),
+}
+
+/// TLS settings for an HTTPS listener
+///
+/// Starts from Mozilla's "intermediate" server profile and lets you adjust the
+/// commonly needed knobs without touching Pingora's TLS APIs directly.
+///
+/// # Example
+/// ```no_run
+/// use pingora_web::{ALPN, TlsConfig, TlsVersion};
+///
+/// let tls = TlsConfig::new("cert.pem", "key.pem")
+///     .alpn(ALPN::H2H1)
+///     .min_version(TlsVersion::Tls13);
+/// # let _ = tls;
+/// ```
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    /// Path to the PEM certificate chain
+    pub cert_path: String,
+    /// Path to the PEM private key
+    pub key_path: String,
+    /// ALPN preference (default: HTTP/1.1 only)
+    pub alpn: ALPN,
+    /// Client certificate policy (default: none)
+    pub client_auth: ClientAuth,
+    /// Minimum accepted protocol version (default: the intermediate profile's TLS 1.2)
+    pub min_version: Option<TlsVersion>,
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: impl Into<String>, key_path: impl Into<String>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            alpn: ALPN::H1,
+            client_auth: ClientAuth::None,
+            min_version: None,
+        }
+    }
+
+    /// Set ALPN preference
+    pub fn alpn(mut self, alpn: ALPN) -> Self {
+        self.alpn = alpn;
+        self
+    }
+
+    /// Prefer HTTP/2 and fall back to HTTP/1.1
+    pub fn enable_h2(self) -> Self {
+        self.alpn(ALPN::H2H1)
+    }
+
+    /// Set client certificate policy
+    pub fn client_auth(mut self, client_auth: ClientAuth) -> Self {
+        self.client_auth = client_auth;
+        self
+    }
+
+    /// Set minimum accepted protocol version
+    pub fn min_version(mut self, version: TlsVersion) -> Self {
+        self.min_version = Some(version);
+        self
+    }
+
+    /// Build Pingora TLS settings from this configuration
+    #[cfg(any(feature = "openssl", feature = "boringssl"))]
+    pub fn into_settings(self) -> pingora_core::Result<pingora_core::listeners::tls::TlsSettings> {
+        use pingora_core::OrErr;
+        use pingora_core::listeners::tls::{TLS_CONF_ERR, TlsSettings};
+        use pingora_core::tls::ssl::{SslVerifyMode, SslVersion};
+
+        let mut settings = TlsSettings::intermediate(&self.cert_path, &self.key_path)?;
+        settings.set_alpn(self.alpn);
+
+        if let Some(version) = self.min_version {
+            let version = match version {
+                TlsVersion::Tls12 => SslVersion::TLS1_2,
+                TlsVersion::Tls13 => SslVersion::TLS1_3,
+            };
+            settings
+                .set_min_proto_version(Some(version))
+                .or_err(TLS_CONF_ERR, "fail to set minimum TLS version")?;
+        }
+
+        let (ca_path, mode) = match &self.client_auth {
+            ClientAuth::None => return Ok(settings),
+            ClientAuth::Optional(ca) => (ca, SslVerifyMode::PEER),
+            ClientAuth::Required(ca) => (
+                ca,
+                SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+            ),
+        };
+        settings
+            .set_ca_file(ca_path)
+            .or_err_with(TLS_CONF_ERR, || format!("fail to read CA file {ca_path}"))?;
+        settings.set_verify(mode);
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_sets_fields() {
+        let tls = TlsConfig::new("c.pem", "k.pem")
+            .enable_h2()
+            .client_auth(ClientAuth::Required("ca.pem".into()))
+            .min_version(TlsVersion::Tls13);
+        assert_eq!(tls.cert_path, "c.pem");
+        assert_eq!(tls.key_path, "k.pem");
+        assert!(matches!(tls.alpn, ALPN::H2H1));
+        assert_eq!(tls.client_auth, ClientAuth::Required("ca.pem".into()));
+        assert_eq!(tls.min_version, Some(TlsVersion::Tls13));
+    }
+}
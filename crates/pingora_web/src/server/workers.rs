@@ -0,0 +1,179 @@
+//! Worker processes sharing listen ports through `SO_REUSEPORT`
+//!
+//! Each worker re-runs the current executable and binds its own sockets on
+//! the same addresses; the kernel spreads new connections across them.
+//! Several services in one process cannot share a port this way: pingora
+//! keeps one socket per address for graceful upgrades and would hand the
+//! same socket to each of them. Use pingora's `threads` setting for more
+//! runtime threads in one process instead.
+
+use std::io;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+/// Environment variable carrying the worker index into worker processes
+pub const WORKER_ENV: &str = "PINGORA_WEB_WORKER";
+
+/// A number of worker processes sharing listen ports (Unix only)
+///
+/// # Example
+/// ```no_run
+/// use pingora_web::{App, MetricsMiddleware, Workers};
+///
+/// // The parent waits here; each worker gets its index back
+/// let Some(worker) = Workers::new(4).spawn_processes().unwrap() else {
+///     return;
+/// };
+/// let mut app = App::default();
+/// app.use_middleware(MetricsMiddleware::for_worker(worker));
+/// app.get_fn("/", |_| "hello");
+/// app.listen("0.0.0.0:8080").unwrap();
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Workers {
+    count: usize,
+}
+
+impl Workers {
+    /// `count` workers (at least one)
+    pub fn new(count: usize) -> Self {
+        Self {
+            count: count.max(1),
+        }
+    }
+
+    /// One worker per available CPU
+    pub fn per_cpu() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Index of this process when started by `spawn_processes`
+    pub fn current() -> Option<usize> {
+        std::env::var(WORKER_ENV).ok()?.parse().ok()
+    }
+
+    /// Re-run the current executable once per worker, with the same arguments
+    ///
+    /// In a worker process this returns its index right away; `App::run` and
+    /// `listen*` then bind every listener with `SO_REUSEPORT`. In the parent
+    /// it waits for every worker to exit and returns `None`, or an error if
+    /// one of them failed. While waiting, the parent forwards `SIGTERM`,
+    /// `SIGINT` and `SIGQUIT` to the workers, so stopping the parent (e.g. as
+    /// PID 1 in a container) stops them too. `SIGKILL` cannot be forwarded.
+    pub fn spawn_processes(&self) -> io::Result<Option<usize>> {
+        if let Some(worker) = Self::current() {
+            return Ok(Some(worker));
+        }
+        if cfg!(not(unix)) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "SO_REUSEPORT workers need a Unix system",
+            ));
+        }
+        let exe = std::env::current_exe()?;
+        let args: Vec<_> = std::env::args_os().skip(1).collect();
+        #[cfg(unix)]
+        signals::install();
+        let mut children: Vec<Child> = Vec::with_capacity(self.count);
+        for worker in 0..self.count {
+            let child = Command::new(&exe)
+                .args(&args)
+                .env(WORKER_ENV, worker.to_string())
+                .spawn();
+            match child {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    #[cfg(unix)]
+                    signals::restore();
+                    for mut child in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let result = wait_all(children);
+        #[cfg(unix)]
+        signals::restore();
+        result
+    }
+}
+
+/// Wait for every worker, forwarding shutdown signals received meanwhile
+fn wait_all(children: Vec<Child>) -> io::Result<Option<usize>> {
+    let mut running: Vec<(usize, Child)> = children.into_iter().enumerate().collect();
+    let mut failed = None;
+    while !running.is_empty() {
+        #[cfg(unix)]
+        if let Some(signal) = signals::take() {
+            for (_, child) in &running {
+                signals::send(child, signal);
+            }
+        }
+        let mut i = 0;
+        while i < running.len() {
+            let Some(status) = running[i].1.try_wait()? else {
+                i += 1;
+                continue;
+            };
+            let (worker, _) = running.swap_remove(i);
+            if !status.success() {
+                tracing::error!(worker, %status, "Worker process failed");
+                failed.get_or_insert(io::Error::other(format!(
+                    "worker {worker} exited with {status}"
+                )));
+            }
+        }
+        if !running.is_empty() {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+    failed.map_or(Ok(None), Err)
+}
+
+#[cfg(unix)]
+mod signals {
+    use std::process::Child;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    const FORWARDED: [libc::c_int; 3] = [libc::SIGTERM, libc::SIGINT, libc::SIGQUIT];
+
+    /// Last signal received by the parent and not yet forwarded
+    static PENDING: AtomicI32 = AtomicI32::new(0);
+
+    extern "C" fn record(signal: libc::c_int) {
+        PENDING.store(signal, Ordering::SeqCst);
+    }
+
+    pub(super) fn install() {
+        let handler = record as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        for signal in FORWARDED {
+            // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+            unsafe { libc::signal(signal, handler) };
+        }
+    }
+
+    pub(super) fn restore() {
+        for signal in FORWARDED {
+            // SAFETY: resets the disposition installed by `install`
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+
+    pub(super) fn take() -> Option<libc::c_int> {
+        match PENDING.swap(0, Ordering::SeqCst) {
+            0 => None,
+            signal => Some(signal),
+        }
+    }
+
+    pub(super) fn send(child: &Child, signal: libc::c_int) {
+        // SAFETY: plain syscall; the child has not been reaped, so its pid is still ours
+        unsafe { libc::kill(child.id() as libc::pid_t, signal) };
+    }
+}
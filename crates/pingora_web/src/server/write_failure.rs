@@ -0,0 +1,53 @@
+use http::{Method, StatusCode};
+
+/// Step of writing a response that failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteStage {
+    /// An HTTP module rejected the response header
+    HeaderFilter,
+    /// The response header could not be sent
+    Header,
+    /// An HTTP module failed on a body chunk
+    BodyFilter,
+    /// A body chunk could not be sent
+    Body,
+    /// Trailers could not be sent
+    Trailers,
+    /// The body's producer aborted it (see `PingoraWebHttpResponse::abort_handle`)
+    Aborted,
+}
+
+impl WriteStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::HeaderFilter => "header_filter",
+            Self::Header => "header",
+            Self::BodyFilter => "body_filter",
+            Self::Body => "body",
+            Self::Trailers => "trailers",
+            Self::Aborted => "aborted",
+        }
+    }
+}
+
+impl std::fmt::Display for WriteStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A response that could not be written completely
+///
+/// The connection is closed right after, so clients see a short body (or a
+/// reset HTTP/2 stream) instead of a response that looks complete.
+#[derive(Clone, Debug)]
+pub struct WriteFailure {
+    pub stage: WriteStage,
+    /// Error reported by the module or the connection
+    pub error: String,
+    pub method: Method,
+    pub path: String,
+    pub status: StatusCode,
+    /// Body bytes that reached the connection before the failure
+    pub body_bytes_sent: usize,
+}
@@ -0,0 +1,153 @@
+//! Real-socket test servers
+//!
+//! `App::test_client()` skips the network entirely. Use `spawn_server` when a
+//! test needs an actual connection: keepalive, streaming, HTTP modules, or h2c.
+
+use std::net::SocketAddr;
+use std::thread::JoinHandle;
+
+use pingora::services::Service as _;
+use pingora::services::listening::Service;
+use tokio::sync::watch;
+
+use crate::App;
+
+/// A running test server; dropping it shuts the server down
+///
+/// # Example
+/// ```
+/// use pingora_web::App;
+/// use std::io::{Read, Write};
+///
+/// let mut app = App::default();
+/// app.get_fn("/", |_req| "hi");
+/// let server = pingora_web::test::spawn_server(app);
+///
+/// let mut conn = std::net::TcpStream::connect(server.addr()).unwrap();
+/// conn.write_all(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n").unwrap();
+/// let mut res = String::new();
+/// conn.read_to_string(&mut res).unwrap();
+/// assert!(res.ends_with("\r\n\r\nhi"));
+/// ```
+pub struct TestServer {
+    addr: SocketAddr,
+    url: String,
+    shutdown: watch::Sender<bool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Address the server listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base URL, e.g. `http://127.0.0.1:41234`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Stop accepting connections and wait for the server thread to exit
+    pub fn shutdown(self) {}
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.shutdown.send(true);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Serve `app` on an ephemeral 127.0.0.1 port from a background thread
+///
+/// The server has its own runtime, so it works from sync tests and from any
+/// tokio test flavor. Warmup paths run before the call returns.
+///
+/// # Panics
+/// If no local port can be bound.
+pub fn spawn_server<S: Send + Sync + 'static>(app: App<S>) -> TestServer {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind ephemeral port");
+    let addr = listener.local_addr().expect("local address");
+    let (shutdown, watch) = watch::channel(false);
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+    let thread = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .expect("test server runtime");
+        runtime.block_on(async move {
+            app.run_warmup().await;
+            let mut service = Service::new("pingora_web_test".to_string(), app);
+            service.add_tcp(&addr.to_string());
+
+            // Hand the already bound socket to pingora so no other process can take the port
+            #[cfg(unix)]
+            let fds = {
+                use std::os::fd::IntoRawFd;
+                listener
+                    .set_nonblocking(true)
+                    .expect("non-blocking listener");
+                let mut fds = pingora::server::Fds::new();
+                fds.add(addr.to_string(), listener.into_raw_fd());
+                Some(std::sync::Arc::new(tokio::sync::Mutex::new(fds)))
+            };
+            #[cfg(not(unix))]
+            drop(listener);
+
+            let _ = ready_tx.send(());
+            service
+                .start_service(
+                    #[cfg(unix)]
+                    fds,
+                    watch,
+                    1,
+                )
+                .await;
+        });
+    });
+    ready_rx.recv().expect("test server started");
+
+    TestServer {
+        addr,
+        url: format!("http://{addr}"),
+        shutdown,
+        thread: Some(thread),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn serves_keepalive_requests_until_dropped() {
+        let mut app = App::default();
+        app.get_fn("/ping", |_req| "pong");
+        let server = spawn_server(app);
+        assert!(server.url().starts_with("http://127.0.0.1:"));
+
+        let mut conn = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+        for _ in 0..2 {
+            conn.write_all(b"GET /ping HTTP/1.1\r\nHost: x\r\n\r\n")
+                .await
+                .unwrap();
+            let mut buf = Vec::new();
+            while !buf.ends_with(b"pong") {
+                let mut chunk = [0u8; 512];
+                let n = conn.read(&mut chunk).await.unwrap();
+                assert!(n > 0, "connection closed");
+                buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+        drop(conn);
+
+        let addr = server.addr();
+        server.shutdown();
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+}
@@ -0,0 +1,545 @@
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::{Compress, Compression, FlushCompress, Status};
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use http::HeaderValue;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+use crate::core::PingoraWebHttpResponse;
+
+/// Flush to the client once this much archive data is buffered
+const CHUNK_SIZE: usize = 64 * 1024;
+
+enum Source {
+    File(PathBuf),
+    Bytes(Bytes),
+    Stream(BoxStream<'static, Bytes>),
+}
+
+struct Entry {
+    name: String,
+    modified: SystemTime,
+    source: Source,
+}
+
+/// A ZIP archive streamed to the client as it is built
+///
+/// Entries are read one at a time while the response is written, so neither
+/// files nor the archive are buffered whole. Entries are deflated unless
+/// `store()` is used. ZIP64 is not supported: archives and entries must stay
+/// under 4 GiB, otherwise the stream ends early.
+///
+/// ```
+/// use pingora_web::{App, utils::ArchiveStream};
+///
+/// let mut app = App::default();
+/// app.get_fn("/download-all", |_req| {
+///     ArchiveStream::new()
+///         .dir("assets", "./public")
+///         .unwrap_or_else(|_| ArchiveStream::new())
+///         .bytes("README.txt", "Generated export")
+///         .into_response("export.zip")
+/// });
+/// ```
+pub struct ArchiveStream {
+    entries: Vec<Entry>,
+    deflate: bool,
+}
+
+impl ArchiveStream {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            deflate: true,
+        }
+    }
+
+    /// Store entries uncompressed (for already compressed content like images)
+    pub fn store(mut self) -> Self {
+        self.deflate = false;
+        self
+    }
+
+    /// Add a file from disk, read when the archive reaches it
+    pub fn file<N: AsRef<str>, P: Into<PathBuf>>(mut self, name: N, path: P) -> Self {
+        let path = path.into();
+        let modified = std::fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .unwrap_or_else(|_| SystemTime::now());
+        self.push(name.as_ref(), modified, Source::File(path));
+        self
+    }
+
+    /// Add in-memory content
+    pub fn bytes<N: AsRef<str>, B: Into<Bytes>>(mut self, name: N, data: B) -> Self {
+        self.push(name.as_ref(), SystemTime::now(), Source::Bytes(data.into()));
+        self
+    }
+
+    /// Add generated content, pulled from `stream` when the archive reaches it
+    pub fn stream<N: AsRef<str>>(mut self, name: N, stream: BoxStream<'static, Bytes>) -> Self {
+        self.push(name.as_ref(), SystemTime::now(), Source::Stream(stream));
+        self
+    }
+
+    /// Add every regular file under `root`, named `prefix/relative/path`
+    ///
+    /// Symlinks are skipped so the archive cannot reach outside `root`.
+    pub fn dir<N: AsRef<str>, P: AsRef<Path>>(
+        mut self,
+        prefix: N,
+        root: P,
+    ) -> std::io::Result<Self> {
+        let mut files = Vec::new();
+        walk(root.as_ref(), Path::new(""), &mut files)?;
+        files.sort();
+        for rel in files {
+            let name = Path::new(prefix.as_ref()).join(&rel);
+            self = self.file(name.to_string_lossy(), root.as_ref().join(rel));
+        }
+        Ok(self)
+    }
+
+    fn push(&mut self, name: &str, modified: SystemTime, source: Source) {
+        let name = sanitize(name);
+        if !name.is_empty() {
+            self.entries.push(Entry {
+                name,
+                modified,
+                source,
+            });
+        }
+    }
+
+    /// A 200 `application/zip` attachment streaming the archive
+    pub fn into_response(self, filename: &str) -> PingoraWebHttpResponse {
+        let (tx, res) = PingoraWebHttpResponse::from_channel(2);
+        tokio::spawn(async move {
+            let mut zip = ZipWriter::new(tx, self.deflate);
+            for entry in self.entries {
+                if let Err(e) = zip.entry(entry).await {
+                    tracing::warn!("Archive stream aborted: {}", e);
+                    return;
+                }
+            }
+            if let Err(e) = zip.finish().await {
+                tracing::warn!("Archive stream aborted: {}", e);
+            }
+        });
+
+        let filename: String = filename
+            .chars()
+            .filter(|c| !c.is_control() && *c != '"' && *c != '\\')
+            .collect();
+        res.header(http::header::CONTENT_TYPE, "application/zip")
+            .header(
+                http::header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&format!("attachment; filename=\"{filename}\""))
+                    .unwrap_or(HeaderValue::from_static("attachment")),
+            )
+    }
+}
+
+impl Default for ArchiveStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn walk(root: &Path, rel: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(root.join(rel))? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        let path = rel.join(entry.file_name());
+        if kind.is_dir() {
+            walk(root, &path, out)?;
+        } else if kind.is_file() {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Forward-slash entry name without `..`, root or drive components (no zip slip)
+fn sanitize(name: &str) -> String {
+    let normalized = name.replace('\\', "/");
+    Path::new(&normalized)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// MS-DOS (time, date) of a timestamp, in UTC
+fn dos_datetime(at: SystemTime) -> (u16, u16) {
+    let secs = at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    // DOS dates start in 1980
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((rem / 3600) << 11) | (((rem % 3600) / 60) << 5) | ((rem % 60) / 2);
+    let date = (((year - 1980).min(127) as u64) << 9) | ((month as u64) << 5) | day as u64;
+    (time as u16, date as u16)
+}
+
+struct CentralRecord {
+    name: String,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed: u32,
+    size: u32,
+    offset: u32,
+}
+
+type ArchiveResult = Result<(), String>;
+
+/// Writes ZIP records into the response channel, tracking offsets for the central directory
+struct ZipWriter {
+    tx: mpsc::Sender<Bytes>,
+    buf: BytesMut,
+    offset: u64,
+    deflate: bool,
+    records: Vec<CentralRecord>,
+}
+
+impl ZipWriter {
+    const FLAGS: u16 = 0x0808; // sizes in data descriptor, UTF-8 names
+    const VERSION: u16 = 20;
+
+    fn new(tx: mpsc::Sender<Bytes>, deflate: bool) -> Self {
+        Self {
+            tx,
+            buf: BytesMut::new(),
+            offset: 0,
+            deflate,
+            records: Vec::new(),
+        }
+    }
+
+    fn method(&self) -> u16 {
+        if self.deflate { 8 } else { 0 }
+    }
+
+    fn offset32(&self) -> Result<u32, String> {
+        u32::try_from(self.offset).map_err(|_| "archive exceeds 4 GiB".to_string())
+    }
+
+    async fn write(&mut self, data: &[u8]) -> ArchiveResult {
+        self.buf.extend_from_slice(data);
+        self.offset += data.len() as u64;
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> ArchiveResult {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.tx
+            .send(self.buf.split().freeze())
+            .await
+            .map_err(|_| "client disconnected".to_string())
+    }
+
+    async fn entry(&mut self, entry: Entry) -> ArchiveResult {
+        let (time, date) = dos_datetime(entry.modified);
+        let offset = self.offset32()?;
+
+        let mut header = BytesMut::with_capacity(30 + entry.name.len());
+        header.put_u32_le(0x0403_4b50);
+        header.put_u16_le(Self::VERSION);
+        header.put_u16_le(Self::FLAGS);
+        header.put_u16_le(self.method());
+        header.put_u16_le(time);
+        header.put_u16_le(date);
+        header.put_bytes(0, 12); // crc and sizes follow in the data descriptor
+        header.put_u16_le(entry.name.len() as u16);
+        header.put_u16_le(0);
+        header.put_slice(entry.name.as_bytes());
+        self.write(&header).await?;
+
+        let mut body = EntryBody::new(self.deflate);
+        match entry.source {
+            Source::Bytes(data) => self.write_data(&mut body, &data).await?,
+            Source::Stream(mut stream) => {
+                while let Some(chunk) = stream.next().await {
+                    self.write_data(&mut body, &chunk).await?;
+                }
+            }
+            Source::File(path) => {
+                let mut file = tokio::fs::File::open(&path)
+                    .await
+                    .map_err(|e| format!("{}: {e}", path.display()))?;
+                let mut chunk = vec![0u8; CHUNK_SIZE];
+                loop {
+                    let n = file
+                        .read(&mut chunk)
+                        .await
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    if n == 0 {
+                        break;
+                    }
+                    self.write_data(&mut body, &chunk[..n]).await?;
+                }
+            }
+        }
+        let tail = body.finish()?;
+        self.write(&tail).await?;
+
+        let too_large = || format!("{} exceeds 4 GiB", entry.name);
+        let crc = body.crc.clone().finalize();
+        let compressed = u32::try_from(body.compressed).map_err(|_| too_large())?;
+        let size = u32::try_from(body.size).map_err(|_| too_large())?;
+
+        let mut descriptor = BytesMut::with_capacity(16);
+        descriptor.put_u32_le(0x0807_4b50);
+        descriptor.put_u32_le(crc);
+        descriptor.put_u32_le(compressed);
+        descriptor.put_u32_le(size);
+        self.write(&descriptor).await?;
+
+        self.records.push(CentralRecord {
+            name: entry.name,
+            time,
+            date,
+            crc,
+            compressed,
+            size,
+            offset,
+        });
+        Ok(())
+    }
+
+    async fn write_data(&mut self, body: &mut EntryBody, data: &[u8]) -> ArchiveResult {
+        let out = body.update(data)?;
+        self.write(&out).await
+    }
+
+    /// Central directory and end record
+    async fn finish(mut self) -> ArchiveResult {
+        let start = self.offset32()?;
+        let mut directory = BytesMut::new();
+        for r in &self.records {
+            directory.put_u32_le(0x0201_4b50);
+            directory.put_u16_le(0x0300 | Self::VERSION); // made by: Unix
+            directory.put_u16_le(Self::VERSION);
+            directory.put_u16_le(Self::FLAGS);
+            directory.put_u16_le(self.method());
+            directory.put_u16_le(r.time);
+            directory.put_u16_le(r.date);
+            directory.put_u32_le(r.crc);
+            directory.put_u32_le(r.compressed);
+            directory.put_u32_le(r.size);
+            directory.put_u16_le(r.name.len() as u16);
+            directory.put_bytes(0, 8); // extra, comment, disk, internal attributes
+            directory.put_u32_le(0o100644 << 16); // regular file, rw-r--r--
+            directory.put_u32_le(r.offset);
+            directory.put_slice(r.name.as_bytes());
+        }
+        let size = u32::try_from(directory.len()).map_err(|_| "directory exceeds 4 GiB")?;
+        let count = u16::try_from(self.records.len()).map_err(|_| "more than 65535 entries")?;
+        directory.put_u32_le(0x0605_4b50);
+        directory.put_bytes(0, 4); // disk numbers
+        directory.put_u16_le(count);
+        directory.put_u16_le(count);
+        directory.put_u32_le(size);
+        directory.put_u32_le(start);
+        directory.put_u16_le(0);
+        self.write(&directory).await?;
+        self.flush().await
+    }
+}
+
+/// CRC, sizes and (optionally) deflate state of the entry being written
+struct EntryBody {
+    crc: crc32fast::Hasher,
+    size: u64,
+    compressed: u64,
+    deflater: Option<Compress>,
+}
+
+impl EntryBody {
+    fn new(deflate: bool) -> Self {
+        Self {
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+            compressed: 0,
+            deflater: deflate.then(|| Compress::new(Compression::default(), false)),
+        }
+    }
+
+    /// Bytes to write for `data`
+    fn update(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        self.crc.update(data);
+        self.size += data.len() as u64;
+        let out = match &mut self.deflater {
+            None => data.to_vec(),
+            Some(deflater) => deflate(deflater, data, FlushCompress::None)?,
+        };
+        self.compressed += out.len() as u64;
+        Ok(out)
+    }
+
+    /// Remaining compressed bytes once all data was written
+    fn finish(&mut self) -> Result<Vec<u8>, String> {
+        let out = match &mut self.deflater {
+            None => Vec::new(),
+            Some(deflater) => deflate(deflater, &[], FlushCompress::Finish)?,
+        };
+        self.compressed += out.len() as u64;
+        Ok(out)
+    }
+}
+
+fn deflate(
+    deflater: &mut Compress,
+    mut input: &[u8],
+    flush: FlushCompress,
+) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 64);
+    loop {
+        if out.capacity() - out.len() < 64 {
+            out.reserve(out.capacity().max(1024));
+        }
+        let before = deflater.total_in();
+        let status = deflater
+            .compress_vec(input, &mut out, flush)
+            .map_err(|e| e.to_string())?;
+        input = &input[(deflater.total_in() - before) as usize..];
+        let done = match flush {
+            FlushCompress::Finish => status == Status::StreamEnd,
+            _ => input.is_empty() && out.len() < out.capacity(),
+        };
+        if done {
+            return Ok(out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    fn u16_at(b: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([b[at], b[at + 1]])
+    }
+
+    fn u32_at(b: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    /// (name, method, content) of every entry, located through the central directory
+    fn read_zip(zip: &[u8]) -> Vec<(String, u16, Vec<u8>)> {
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(zip, end), 0x0605_4b50);
+        let count = u16_at(zip, end + 10) as usize;
+        let mut at = u32_at(zip, end + 16) as usize;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(zip, at), 0x0201_4b50);
+            let method = u16_at(zip, at + 10);
+            let crc = u32_at(zip, at + 16);
+            let compressed = u32_at(zip, at + 20) as usize;
+            let name_len = u16_at(zip, at + 28) as usize;
+            let offset = u32_at(zip, at + 42) as usize;
+            let name = String::from_utf8(zip[at + 46..at + 46 + name_len].to_vec()).unwrap();
+
+            assert_eq!(u32_at(zip, offset), 0x0403_4b50);
+            let data_at = offset + 30 + u16_at(zip, offset + 26) as usize;
+            let raw = &zip[data_at..data_at + compressed];
+            let content = if method == 8 {
+                let mut out = Vec::new();
+                std::io::Read::read_to_end(&mut flate2::read::DeflateDecoder::new(raw), &mut out)
+                    .unwrap();
+                out
+            } else {
+                raw.to_vec()
+            };
+            assert_eq!(crc32fast::hash(&content), crc);
+            entries.push((name, method, content));
+            at += 46 + name_len;
+        }
+        entries
+    }
+
+    #[tokio::test]
+    async fn streams_directory_and_generated_entries() {
+        let root = std::env::temp_dir().join(format!("pingora_web_archive_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("css")).unwrap();
+        std::fs::write(root.join("index.html"), "<h1>hi</h1>".repeat(1000)).unwrap();
+        std::fs::write(root.join("css/site.css"), "body {}").unwrap();
+        let dir = root.clone();
+
+        let archive = move |store: bool| {
+            let generated = futures::stream::iter(["a,b\n", "1,2\n"].map(Bytes::from)).boxed();
+            let archive = ArchiveStream::new().dir("site", &root).unwrap();
+            let archive = if store { archive.store() } else { archive };
+            archive
+                .stream("report.csv", generated)
+                .bytes("../../etc/evil", "x")
+                .into_response("all.zip")
+        };
+        let stored = archive.clone();
+        let mut app = App::default();
+        app.get_fn("/all.zip", move |_req| archive(false));
+        app.get_fn("/stored.zip", move |_req| stored(true));
+        let client = app.test_client();
+
+        for (path, method) in [("/all.zip", 8), ("/stored.zip", 0)] {
+            let res = client.get(path).send().await;
+            assert_eq!(res.header("content-type"), Some("application/zip"));
+            assert_eq!(
+                res.header("content-disposition"),
+                Some("attachment; filename=\"all.zip\"")
+            );
+            let entries = read_zip(res.bytes());
+            let names: Vec<_> = entries.iter().map(|e| e.0.as_str()).collect();
+            assert_eq!(
+                names,
+                [
+                    "site/css/site.css",
+                    "site/index.html",
+                    "report.csv",
+                    "etc/evil"
+                ]
+            );
+            assert!(entries.iter().all(|e| e.1 == method));
+            assert_eq!(entries[1].2, "<h1>hi</h1>".repeat(1000).as_bytes());
+            assert_eq!(entries[2].2, b"a,b\n1,2\n");
+        }
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn dos_timestamps() {
+        // 2024-02-29 13:45:30 UTC
+        let at = UNIX_EPOCH + std::time::Duration::from_secs(1_709_214_330);
+        let (time, date) = dos_datetime(at);
+        assert_eq!(time, (13 << 11) | (45 << 5) | 15);
+        assert_eq!(date, (44 << 9) | (2 << 5) | 29);
+    }
+}
@@ -0,0 +1,429 @@
+use std::io;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use http::StatusCode;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use super::{ByteRange, Unsatisfiable};
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Bytes sniffed for the content type of stored blobs
+const SNIFF_LEN: usize = 8 * 1024;
+
+/// A stored blob, named by the SHA-256 of its content
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlobRef {
+    /// Lowercase hex SHA-256 of the content
+    pub key: String,
+    pub len: u64,
+    /// Type detected from the first bytes, if recognized
+    pub content_type: Option<&'static str>,
+    /// Where `BlobHandler` serves the blob; never changes for the same content
+    pub url: String,
+}
+
+/// Content-addressable storage for uploads
+///
+/// Blobs are stored under the hash of their content, so storing the same
+/// bytes twice keeps one copy and returns the same `BlobRef`.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, data: Bytes) -> io::Result<BlobRef>;
+
+    /// Store the content of a file, e.g. a body from `req.spooled_body()`
+    async fn put_file(&self, path: &Path) -> io::Result<BlobRef> {
+        self.put(tokio::fs::read(path).await?.into()).await
+    }
+
+    /// The blob for `key`, `None` when nothing is stored under it
+    async fn stat(&self, key: &str) -> io::Result<Option<BlobRef>>;
+
+    /// Stream the blob, or the given range of it
+    ///
+    /// The stream ends early if reading fails.
+    async fn read(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> io::Result<BoxStream<'static, Bytes>>;
+
+    /// Remove the blob, returning whether it existed
+    async fn delete(&self, key: &str) -> io::Result<bool>;
+}
+
+/// Whether `key` looks like a SHA-256 key (and so is safe to use in a path)
+fn valid_key(key: &str) -> bool {
+    key.len() == 64 && key.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn sniff(head: &[u8]) -> Option<&'static str> {
+    infer::get(head).map(|kind| kind.mime_type())
+}
+
+/// Whether a browser can render `content_type` without running anything in it
+///
+/// HTML, XML, SVG and the like can carry script, which would run on the app's
+/// own origin.
+fn is_passive(content_type: &str) -> bool {
+    let passive = ["image/", "audio/", "video/", "font/"];
+    passive.iter().any(|p| content_type.starts_with(p)) && content_type != "image/svg+xml"
+}
+
+/// `BlobStore` keeping blobs in a directory, as `<dir>/<ab>/<abcdef...>`
+///
+/// New blobs are written to a temporary file and renamed into place, so
+/// readers never see partial content.
+///
+/// ```
+/// use pingora_web::App;
+/// use pingora_web::utils::{BlobHandler, FsBlobStore};
+/// use std::sync::Arc;
+///
+/// let store = Arc::new(FsBlobStore::new("./blobs").base_url("/blobs/"));
+/// let mut app = App::default();
+/// app.get("/blobs/{key}", Arc::new(BlobHandler::new(store.clone())));
+/// ```
+pub struct FsBlobStore {
+    dir: PathBuf,
+    base_url: String,
+}
+
+impl FsBlobStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            base_url: "/blobs/".to_string(),
+        }
+    }
+
+    /// URL prefix of the route serving the store (default: `/blobs/`)
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into();
+        if !self.base_url.ends_with('/') {
+            self.base_url.push('/');
+        }
+        self
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(key)
+    }
+
+    fn blob_ref(&self, key: String, len: u64, head: &[u8]) -> BlobRef {
+        BlobRef {
+            url: format!("{}{key}", self.base_url),
+            key,
+            len,
+            content_type: sniff(head),
+        }
+    }
+
+    /// Move the temporary file `tmp` into place as `key`, unless it is already stored
+    async fn commit(&self, tmp: &Path, key: &str) -> io::Result<()> {
+        let path = self.path(key);
+        if tokio::fs::try_exists(&path).await? {
+            tokio::fs::remove_file(tmp).await?;
+            return Ok(());
+        }
+        tokio::fs::create_dir_all(path.parent().expect("blob paths have a parent")).await?;
+        if let Err(e) = tokio::fs::rename(tmp, &path).await {
+            tokio::fs::remove_file(tmp).await.ok();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        self.dir
+            .join(format!(".tmp-{}", crate::utils::request_id::generate()))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    async fn put(&self, data: Bytes) -> io::Result<BlobRef> {
+        let key = format!("{:x}", Sha256::digest(&data));
+        if !tokio::fs::try_exists(self.path(&key)).await? {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            let tmp = self.tmp_path();
+            tokio::fs::write(&tmp, &data).await?;
+            self.commit(&tmp, &key).await?;
+        }
+        let head = &data[..data.len().min(SNIFF_LEN)];
+        Ok(self.blob_ref(key, data.len() as u64, head))
+    }
+
+    /// Copies the file while hashing it, without loading it into memory
+    async fn put_file(&self, path: &Path) -> io::Result<BlobRef> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let tmp = self.tmp_path();
+        let mut src = tokio::fs::File::open(path).await?;
+        let mut dst = tokio::fs::File::create(&tmp).await?;
+        let mut hasher = Sha256::new();
+        let mut head = Vec::new();
+        let mut len = 0u64;
+        let mut buf = vec![0u8; 64 * 1024];
+        let copied = async {
+            loop {
+                let n = src.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                if head.len() < SNIFF_LEN {
+                    let take = n.min(SNIFF_LEN - head.len());
+                    head.extend_from_slice(&buf[..take]);
+                }
+                dst.write_all(&buf[..n]).await?;
+                len += n as u64;
+            }
+            dst.flush().await
+        }
+        .await;
+        drop(dst);
+        if let Err(e) = copied {
+            tokio::fs::remove_file(&tmp).await.ok();
+            return Err(e);
+        }
+        let key = format!("{:x}", hasher.finalize());
+        self.commit(&tmp, &key).await?;
+        Ok(self.blob_ref(key, len, &head))
+    }
+
+    async fn stat(&self, key: &str) -> io::Result<Option<BlobRef>> {
+        if !valid_key(key) {
+            return Ok(None);
+        }
+        let mut file = match tokio::fs::File::open(self.path(key)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let len = file.metadata().await?.len();
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        (&mut file)
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .await?;
+        Ok(Some(self.blob_ref(key.to_string(), len, &head)))
+    }
+
+    async fn read(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> io::Result<BoxStream<'static, Bytes>> {
+        if !valid_key(key) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let mut file = tokio::fs::File::open(self.path(key)).await?;
+        let reader = match range {
+            Some(range) => {
+                file.seek(SeekFrom::Start(range.start)).await?;
+                file.take(range.len())
+            }
+            None => file.take(u64::MAX),
+        };
+        Ok(tokio_util::io::ReaderStream::new(reader)
+            .take_while(|chunk| futures::future::ready(chunk.is_ok()))
+            .filter_map(|chunk| futures::future::ready(chunk.ok()))
+            .boxed())
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<bool> {
+        if !valid_key(key) {
+            return Ok(false);
+        }
+        match tokio::fs::remove_file(self.path(key)).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Serve blobs of a `BlobStore` by key
+///
+/// Blobs never change, so responses are cached as immutable with the key as
+/// a strong `ETag` (answering `If-None-Match` with 304), and single byte
+/// ranges get 206 like `MediaHandler`. Uploads are untrusted, so only images,
+/// audio, video and fonts are served inline under their detected type; other
+/// blobs (HTML, SVG, XML, ...) are `application/octet-stream` downloads. All
+/// responses carry `nosniff` and a sandboxing `Content-Security-Policy`.
+pub struct BlobHandler {
+    store: Arc<dyn BlobStore>,
+    param: String,
+}
+
+impl BlobHandler {
+    pub fn new(store: Arc<dyn BlobStore>) -> Self {
+        Self {
+            store,
+            param: "key".to_string(),
+        }
+    }
+
+    /// Route parameter holding the key (default: `key`)
+    pub fn with_param_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.param = name.into();
+        self
+    }
+}
+
+#[async_trait]
+impl Handler for BlobHandler {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let key = req.param(&self.param).unwrap_or_default();
+        let Some(blob) = self
+            .store
+            .stat(key)
+            .await
+            .map_err(crate::error::internal_error)?
+        else {
+            return Ok(PingoraWebHttpResponse::text(
+                StatusCode::NOT_FOUND,
+                "Not Found",
+            ));
+        };
+        let etag = format!("\"{}\"", blob.key);
+
+        let res = if req.if_none_match(&etag) {
+            let mut res = PingoraWebHttpResponse::text(StatusCode::OK, "");
+            res.make_not_modified();
+            res
+        } else {
+            let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+            let range = match header(http::header::RANGE) {
+                Some(range) if header(http::header::IF_RANGE).is_none_or(|tag| tag == etag) => {
+                    ByteRange::parse(range, blob.len)
+                }
+                _ => Ok(None),
+            };
+            match range {
+                Err(Unsatisfiable) => PingoraWebHttpResponse::text(
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    "Range Not Satisfiable",
+                )
+                .header(http::header::CONTENT_RANGE, format!("bytes */{}", blob.len)),
+                Ok(range) => {
+                    let body = self
+                        .store
+                        .read(&blob.key, range)
+                        .await
+                        .map_err(crate::error::internal_error)?;
+                    let res = match blob.content_type.filter(|t| is_passive(t)) {
+                        Some(content_type) => PingoraWebHttpResponse::stream(StatusCode::OK, body)
+                            .header(http::header::CONTENT_TYPE, content_type),
+                        None => PingoraWebHttpResponse::stream(StatusCode::OK, body)
+                            .header(http::header::CONTENT_TYPE, "application/octet-stream")
+                            .header(http::header::CONTENT_DISPOSITION, "attachment"),
+                    };
+                    match range {
+                        Some(range) => {
+                            let mut res = res
+                                .header(
+                                    http::header::CONTENT_RANGE,
+                                    format!("bytes {}-{}/{}", range.start, range.end, blob.len),
+                                )
+                                .header(http::header::CONTENT_LENGTH, range.len().to_string());
+                            res.status = StatusCode::PARTIAL_CONTENT;
+                            res
+                        }
+                        None => res.header(http::header::CONTENT_LENGTH, blob.len.to_string()),
+                    }
+                }
+            }
+        };
+        Ok(res
+            .header(http::header::ETAG, etag)
+            .header(http::header::ACCEPT_RANGES, "bytes")
+            .header(
+                http::header::CACHE_CONTROL,
+                "public, max-age=31536000, immutable",
+            )
+            .header(http::header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .header(http::header::CONTENT_SECURITY_POLICY, "sandbox"))
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    #[tokio::test]
+    async fn stores_by_content_and_serves_ranges() {
+        let dir = std::env::temp_dir().join(format!("pingora_web_blobs_{}", std::process::id()));
+        let store = Arc::new(FsBlobStore::new(&dir).base_url("/files"));
+
+        let png = Bytes::from_static(b"\x89PNG\r\n\x1a\n0123456789");
+        let first = store.put(png.clone()).await.unwrap();
+        let upload = dir.join("upload");
+        std::fs::write(&upload, &png).unwrap();
+        let second = store.put_file(&upload).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.url, format!("/files/{}", first.key));
+        assert_eq!(first.content_type, Some("image/png"));
+        assert_eq!(
+            std::fs::read_dir(dir.join(&first.key[..2]))
+                .unwrap()
+                .count(),
+            1
+        );
+
+        let mut app = App::default();
+        app.get("/files/{key}", Arc::new(BlobHandler::new(store.clone())));
+        let client = app.test_client();
+
+        let res = client.get(&first.url).send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.header("content-type"), Some("image/png"));
+        assert_eq!(res.bytes(), &png);
+        let etag = res.header("etag").unwrap().to_string();
+
+        let res = client
+            .get(&first.url)
+            .header("if-none-match", &etag)
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let res = client
+            .get(&first.url)
+            .header("range", "bytes=8-11")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.header("content-range"), Some("bytes 8-11/18"));
+        assert_eq!(res.text(), "0123");
+
+        // Active content is only offered as a download
+        let html = Bytes::from_static(b"<!DOCTYPE html><html><script>alert(1)</script></html>");
+        let page = store.put(html).await.unwrap();
+        let res = client.get(&page.url).send().await;
+        assert_eq!(res.header("content-type"), Some("application/octet-stream"));
+        assert_eq!(res.header("content-disposition"), Some("attachment"));
+        assert_eq!(res.header("content-security-policy"), Some("sandbox"));
+
+        let res = client.get("/files/..%2F..%2Fetc%2Fpasswd").send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        assert!(store.delete(&first.key).await.unwrap());
+        let res = client.get(&first.url).send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use http::StatusCode;
+
+use crate::core::Handler;
+use crate::core::{PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Handlers tried in order until one handles the request
+///
+/// A handler passes by answering 404 (as `ServeDir` does for missing files,
+/// or explicitly with `FallbackChain::not_handled()`); the next one then gets
+/// a fresh copy of the request. The last handler's answer is returned as is,
+/// and errors stop the chain. The body is buffered up front so every
+/// candidate can read it.
+///
+/// ```
+/// use pingora_web::App;
+/// use pingora_web::utils::{FallbackChain, ProxyHandler, ServeDir};
+/// use std::sync::Arc;
+///
+/// let mut app = App::default();
+/// let site = Arc::new(
+///     FallbackChain::new()
+///         .then(ServeDir::new("public"))
+///         .then(ProxyHandler::new(([127, 0, 0, 1], 8081))),
+/// );
+/// app.get("/{*path}", site.clone());
+/// app.post("/{*path}", site);
+/// ```
+#[derive(Clone, Default)]
+pub struct FallbackChain {
+    handlers: Vec<Arc<dyn Handler>>,
+}
+
+impl FallbackChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Try `handler` after the ones added before it
+    pub fn then(self, handler: impl Handler) -> Self {
+        self.then_arc(Arc::new(handler))
+    }
+
+    /// Like `then`, for a handler that is shared with other routes
+    pub fn then_arc(mut self, handler: Arc<dyn Handler>) -> Self {
+        self.handlers.push(handler);
+        self
+    }
+
+    /// Response telling the chain to try the next handler
+    pub fn not_handled() -> PingoraWebHttpResponse {
+        PingoraWebHttpResponse::text(StatusCode::NOT_FOUND, "Not Found")
+    }
+}
+
+#[async_trait]
+impl Handler for FallbackChain {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let Some((last, candidates)) = self.handlers.split_last() else {
+            return Ok(Self::not_handled());
+        };
+        for handler in candidates {
+            // The body is buffered, so a copy is always available
+            let Some(attempt) = req.try_clone() else {
+                break;
+            };
+            let res = handler.handle(attempt).await?;
+            if res.status != StatusCode::NOT_FOUND {
+                return Ok(res);
+            }
+        }
+        last.handle(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    #[tokio::test]
+    async fn next_handler_runs_when_one_passes() {
+        let mut app = App::default();
+        let chain = FallbackChain::new()
+            .then(crate::core::router::ResultClosure::new(
+                |req: PingoraHttpRequest| match req.param("path") {
+                    Some("local") => PingoraWebHttpResponse::text(StatusCode::OK, "local"),
+                    _ => FallbackChain::not_handled(),
+                },
+            ))
+            .then(crate::core::router::ResultClosure::new(
+                |req: PingoraHttpRequest| match req.param("path") {
+                    Some("legacy") => {
+                        let body = String::from_utf8_lossy(req.body()).to_string();
+                        PingoraWebHttpResponse::text(StatusCode::OK, format!("legacy {body}"))
+                    }
+                    _ => FallbackChain::not_handled(),
+                },
+            ));
+        let chain = Arc::new(chain);
+        app.get("/{*path}", chain.clone());
+        app.post("/{*path}", chain);
+        let client = app.test_client();
+
+        assert_eq!(client.get("/local").send().await.text(), "local");
+        let res = client.post("/legacy").body("form").send().await;
+        assert_eq!(res.text(), "legacy form");
+        let res = client.get("/missing").send().await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,452 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use http::{HeaderMap, HeaderValue, StatusCode};
+
+use crate::core::{BodyError, Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+
+/// gRPC status codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum GrpcCode {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+    Unauthenticated = 16,
+}
+
+impl GrpcCode {
+    /// The code a failed plain HTTP status corresponds to (per the gRPC HTTP mapping)
+    pub fn from_http(status: StatusCode) -> Self {
+        match status.as_u16() {
+            400 => GrpcCode::Internal,
+            401 => GrpcCode::Unauthenticated,
+            403 => GrpcCode::PermissionDenied,
+            404 => GrpcCode::Unimplemented,
+            413 => GrpcCode::ResourceExhausted,
+            429 | 502..=504 => GrpcCode::Unavailable,
+            _ => GrpcCode::Unknown,
+        }
+    }
+}
+
+/// Outcome of a gRPC call, sent as `grpc-status` / `grpc-message`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrpcStatus {
+    pub code: GrpcCode,
+    pub message: String,
+}
+
+impl GrpcStatus {
+    pub fn new<S: Into<String>>(code: GrpcCode, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(GrpcCode::Ok, "")
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("grpc-status", HeaderValue::from(self.code as u16));
+        if !self.message.is_empty()
+            && let Ok(message) = HeaderValue::from_str(&percent_encode(&self.message))
+        {
+            headers.insert("grpc-message", message);
+        }
+        headers
+    }
+}
+
+impl From<BodyError> for GrpcStatus {
+    fn from(e: BodyError) -> Self {
+        let code = match e {
+            BodyError::TooLarge(_) => GrpcCode::ResourceExhausted,
+            BodyError::Read(_) => GrpcCode::Cancelled,
+            BodyError::Spool(_) => GrpcCode::Internal,
+        };
+        GrpcStatus::new(code, e.to_string())
+    }
+}
+
+/// `grpc-message` encoding: printable ASCII except `%` is kept, the rest percent-encoded
+fn percent_encode(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    for b in message.bytes() {
+        if (0x20..=0x7e).contains(&b) && b != b'%' {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+/// A decoded gRPC call
+pub struct GrpcRequest {
+    /// Method name from the path, e.g. `SayHello` for `/helloworld.Greeter/SayHello`
+    pub method: String,
+    /// Length-prefixed messages of the request body, still serialized
+    pub messages: Vec<Bytes>,
+    /// The underlying HTTP request (headers carry custom metadata), body already consumed
+    pub http: PingoraHttpRequest,
+}
+
+impl GrpcRequest {
+    /// The single message of a unary call
+    pub fn message(&self) -> Result<&Bytes, GrpcStatus> {
+        match self.messages.as_slice() {
+            [message] => Ok(message),
+            _ => Err(GrpcStatus::new(
+                GrpcCode::InvalidArgument,
+                "Expected exactly one request message",
+            )),
+        }
+    }
+}
+
+/// Serialized messages answering a gRPC call
+pub enum GrpcResponse {
+    /// Messages known up front (unary replies)
+    Messages(Vec<Bytes>),
+    /// Server streaming: an `Err` ends the call with that status
+    Stream(BoxStream<'static, Result<Bytes, GrpcStatus>>),
+}
+
+impl GrpcResponse {
+    pub fn unary<B: Into<Bytes>>(message: B) -> Self {
+        GrpcResponse::Messages(vec![message.into()])
+    }
+
+    pub fn stream(stream: BoxStream<'static, Result<Bytes, GrpcStatus>>) -> Self {
+        GrpcResponse::Stream(stream)
+    }
+}
+
+/// Raw gRPC handler working on serialized messages
+///
+/// Bring your own (de)serialization, e.g. `prost::Message::decode(req.message()?)`.
+#[async_trait]
+pub trait GrpcHandler: Send + Sync + 'static {
+    async fn call(&self, req: GrpcRequest) -> Result<GrpcResponse, GrpcStatus>;
+}
+
+/// Mount a `GrpcHandler` as the route `/{service}/{method}`
+///
+/// Takes care of the gRPC wire format: length-prefixed framing, `grpc-timeout`,
+/// and status in trailers (or in headers for immediate errors). gRPC needs
+/// HTTP/2, so serve it over TLS with ALPN or enable `App::enable_h2c()`.
+/// Compressed messages are rejected with `UNIMPLEMENTED`.
+///
+/// ```
+/// use async_trait::async_trait;
+/// use pingora_web::App;
+/// use pingora_web::utils::{GrpcHandler, GrpcRequest, GrpcResponse, GrpcService, GrpcStatus};
+/// use std::sync::Arc;
+///
+/// struct Echo;
+///
+/// #[async_trait]
+/// impl GrpcHandler for Echo {
+///     async fn call(&self, req: GrpcRequest) -> Result<GrpcResponse, GrpcStatus> {
+///         Ok(GrpcResponse::unary(req.message()?.clone()))
+///     }
+/// }
+///
+/// let mut app = App::default();
+/// app.enable_h2c();
+/// app.post("/echo.Echo/{method}", Arc::new(GrpcService::new(Echo)));
+/// ```
+pub struct GrpcService {
+    handler: Arc<dyn GrpcHandler>,
+}
+
+impl GrpcService {
+    pub fn new<H: GrpcHandler>(handler: H) -> Self {
+        Self {
+            handler: Arc::new(handler),
+        }
+    }
+
+    async fn call(&self, mut req: PingoraHttpRequest) -> Result<GrpcResponse, GrpcStatus> {
+        let body = req.body_bytes().await?.clone();
+        let messages = decode_messages(body)?;
+        let method = req
+            .path()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let timeout = req
+            .headers()
+            .get("grpc-timeout")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_timeout);
+        let call = self.handler.call(GrpcRequest {
+            method,
+            messages,
+            http: req,
+        });
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, call)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(GrpcStatus::new(
+                        GrpcCode::DeadlineExceeded,
+                        "Deadline exceeded",
+                    ))
+                }),
+            None => call.await,
+        }
+    }
+}
+
+/// Split a request body into its length-prefixed messages
+fn decode_messages(mut body: Bytes) -> Result<Vec<Bytes>, GrpcStatus> {
+    let mut messages = Vec::new();
+    while body.has_remaining() {
+        if body.remaining() < 5 {
+            return Err(GrpcStatus::new(
+                GrpcCode::Internal,
+                "Truncated message frame",
+            ));
+        }
+        let compressed = body.get_u8();
+        let len = body.get_u32() as usize;
+        if compressed != 0 {
+            return Err(GrpcStatus::new(
+                GrpcCode::Unimplemented,
+                "Message compression is not supported",
+            ));
+        }
+        if body.remaining() < len {
+            return Err(GrpcStatus::new(GrpcCode::Internal, "Truncated message"));
+        }
+        messages.push(body.split_to(len));
+    }
+    Ok(messages)
+}
+
+fn encode_message(message: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(5 + message.len());
+    frame.put_u8(0);
+    frame.put_u32(message.len() as u32);
+    frame.put_slice(message);
+    frame.freeze()
+}
+
+/// `grpc-timeout` value: up to 8 digits and a unit (`H`, `M`, `S`, `m`, `u`, `n`)
+fn parse_timeout(value: &str) -> Option<Duration> {
+    let unit = value.chars().last()?;
+    let amount = &value[..value.len() - unit.len_utf8()];
+    if amount.is_empty() || amount.len() > 8 || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+    Some(match unit {
+        'H' => Duration::from_secs(amount.checked_mul(3600)?),
+        'M' => Duration::from_secs(amount.checked_mul(60)?),
+        'S' => Duration::from_secs(amount),
+        'm' => Duration::from_millis(amount),
+        'u' => Duration::from_micros(amount),
+        'n' => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+fn grpc_response() -> PingoraWebHttpResponse {
+    PingoraWebHttpResponse::new(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, GRPC_CONTENT_TYPE)
+}
+
+#[async_trait]
+impl Handler for GrpcService {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let is_grpc = req
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with(GRPC_CONTENT_TYPE));
+        if !is_grpc {
+            return Ok(PingoraWebHttpResponse::text(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Unsupported Media Type",
+            ));
+        }
+
+        let response = match self.call(req).await {
+            Ok(response) => response,
+            Err(status) => {
+                // Trailers-only response: the status goes in the headers
+                let mut res = grpc_response();
+                res.headers.extend(status.headers());
+                return Ok(res);
+            }
+        };
+
+        match response {
+            GrpcResponse::Messages(messages) => {
+                let mut body = BytesMut::new();
+                for message in &messages {
+                    body.extend_from_slice(&encode_message(message));
+                }
+                let mut res = PingoraWebHttpResponse::bytes(StatusCode::OK, body.freeze())
+                    .header(http::header::CONTENT_TYPE, GRPC_CONTENT_TYPE);
+                let trailers = res.trailers(["grpc-status", "grpc-message"]);
+                trailers.set("grpc-status", "0");
+                Ok(res)
+            }
+            GrpcResponse::Stream(mut messages) => {
+                let (tx, res) = PingoraWebHttpResponse::from_channel(4);
+                let mut res = res.header(http::header::CONTENT_TYPE, GRPC_CONTENT_TYPE);
+                let trailers = res.trailers(["grpc-status", "grpc-message"]);
+                tokio::spawn(async move {
+                    let mut status = GrpcStatus::ok();
+                    while let Some(message) = messages.next().await {
+                        match message {
+                            Ok(message) => {
+                                if tx.send(encode_message(&message)).await.is_err() {
+                                    return;
+                                }
+                            }
+                            Err(e) => {
+                                status = e;
+                                break;
+                            }
+                        }
+                    }
+                    for (name, value) in status.headers().iter() {
+                        trailers.set(name, value.to_str().unwrap_or_default());
+                    }
+                });
+                Ok(res)
+            }
+        }
+    }
+
+    fn buffer_body(&self) -> bool {
+        // Read in `call` so body errors become gRPC statuses
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    struct Greeter;
+
+    #[async_trait]
+    impl GrpcHandler for Greeter {
+        async fn call(&self, req: GrpcRequest) -> Result<GrpcResponse, GrpcStatus> {
+            match req.method.as_str() {
+                "SayHello" => {
+                    let name = String::from_utf8_lossy(req.message()?).to_string();
+                    Ok(GrpcResponse::unary(format!("Hello {name}")))
+                }
+                "Count" => {
+                    let items = req.messages.len();
+                    let stream = futures::stream::iter(
+                        (0..items)
+                            .map(|i| Ok(Bytes::from(i.to_string())))
+                            .chain([Err(GrpcStatus::new(GrpcCode::Aborted, "stop 100%"))]),
+                    );
+                    Ok(GrpcResponse::stream(stream.boxed()))
+                }
+                "Slow" => {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    Ok(GrpcResponse::Messages(Vec::new()))
+                }
+                _ => Err(GrpcStatus::new(GrpcCode::Unimplemented, "Unknown method")),
+            }
+        }
+    }
+
+    fn frames(messages: &[&str]) -> Vec<u8> {
+        messages
+            .iter()
+            .flat_map(|m| encode_message(m.as_bytes()).to_vec())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn frames_messages_and_reports_status() {
+        let mut app = App::default();
+        app.post(
+            "/greet.Greeter/{method}",
+            Arc::new(GrpcService::new(Greeter)),
+        );
+        let client = app.test_client();
+        let call = |method: &str, messages: &[&str]| {
+            client
+                .post(format!("/greet.Greeter/{method}"))
+                .header("content-type", "application/grpc")
+                .body(frames(messages))
+        };
+
+        let res = call("SayHello", &["bob"]).send().await;
+        assert_eq!(res.header("content-type"), Some("application/grpc"));
+        assert_eq!(res.bytes().as_ref(), frames(&["Hello bob"]));
+        assert_eq!(res.trailers().get("grpc-status").unwrap(), "0");
+
+        let res = call("Count", &["a", "b"]).send().await;
+        assert_eq!(res.bytes().as_ref(), frames(&["0", "1"]));
+        assert_eq!(res.trailers().get("grpc-status").unwrap(), "10");
+        assert_eq!(res.trailers().get("grpc-message").unwrap(), "stop 100%25");
+
+        // Errors before any message are trailers-only responses
+        let res = call("SayHello", &[]).send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.header("grpc-status"), Some("3"));
+        assert!(res.bytes().is_empty());
+
+        let res = call("Slow", &[]).header("grpc-timeout", "20m").send().await;
+        assert_eq!(res.header("grpc-status"), Some("4"));
+
+        let res = client
+            .post("/greet.Greeter/SayHello")
+            .body("plain")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn parses_timeouts() {
+        assert_eq!(parse_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_timeout("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_timeout("S"), None);
+        assert_eq!(parse_timeout(""), None);
+        assert_eq!(
+            parse_timeout("99999999H"),
+            Some(Duration::from_secs(99_999_999 * 3600))
+        );
+        assert_eq!(parse_timeout("999999999999999999H"), None);
+        assert_eq!(parse_timeout("+5S"), None);
+        assert_eq!(parse_timeout("5é"), None);
+    }
+}
@@ -0,0 +1,529 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::StatusCode;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{OnceCell, Semaphore};
+
+use super::ServeDir;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+#[derive(Deserialize, Default)]
+struct Params {
+    w: Option<u32>,
+    h: Option<u32>,
+    format: Option<String>,
+}
+
+/// Identifies a transformed variant; changes when the source file does
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct VariantKey {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+    width: Option<u32>,
+    height: Option<u32>,
+    format: &'static str,
+}
+
+impl VariantKey {
+    fn etag(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.path.to_string_lossy().as_bytes());
+        if let Some(modified) = self.modified
+            && let Ok(since) = modified.duration_since(SystemTime::UNIX_EPOCH)
+        {
+            hasher.update(since.as_nanos().to_le_bytes());
+        }
+        hasher.update(self.len.to_le_bytes());
+        hasher.update(format!(
+            "{:?}x{:?}.{}",
+            self.width, self.height, self.format
+        ));
+        let digest = hasher.finalize();
+        let hex: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+        format!("\"{hex}\"")
+    }
+}
+
+/// Transformed images kept in memory up to a byte budget, oldest evicted first
+struct VariantCache {
+    capacity: usize,
+    size: usize,
+    entries: HashMap<VariantKey, Bytes>,
+    order: VecDeque<VariantKey>,
+}
+
+impl VariantCache {
+    fn get(&self, key: &VariantKey) -> Option<Bytes> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: VariantKey, data: Bytes) {
+        if data.len() > self.capacity || self.entries.contains_key(&key) {
+            return;
+        }
+        while self.size + data.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.size -= evicted.len();
+            }
+        }
+        self.size += data.len();
+        self.order.push_back(key.clone());
+        self.entries.insert(key, data);
+    }
+}
+
+fn output_format(name: &str) -> Option<ImageFormat> {
+    match name {
+        "webp" => Some(ImageFormat::WebP),
+        "png" => Some(ImageFormat::Png),
+        "jpeg" | "jpg" => Some(ImageFormat::Jpeg),
+        "gif" => Some(ImageFormat::Gif),
+        _ => None,
+    }
+}
+
+fn format_name(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::WebP => "webp",
+        ImageFormat::Png => "png",
+        ImageFormat::Jpeg => "jpeg",
+        ImageFormat::Gif => "gif",
+        _ => "bin",
+    }
+}
+
+/// Decode, resize to fit within `width`x`height` (never upscaling) and re-encode
+fn transform(
+    source: &[u8],
+    width: Option<u32>,
+    height: Option<u32>,
+    format: ImageFormat,
+) -> Result<Vec<u8>, image::ImageError> {
+    let mut img = image::load_from_memory(source)?;
+    if width.is_some() || height.is_some() {
+        let w = width.unwrap_or(u32::MAX).min(img.width());
+        let h = height.unwrap_or(u32::MAX).min(img.height());
+        if (w, h) != (img.width(), img.height()) {
+            img = img.resize(w, h, FilterType::Lanczos3);
+        }
+    }
+    if format == ImageFormat::Jpeg {
+        // JPEG has no alpha channel
+        img = DynamicImage::ImageRgb8(img.to_rgb8());
+    }
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, format)?;
+    Ok(out.into_inner())
+}
+
+/// Serve images from a directory, resized and converted on the fly
+///
+/// Query parameters: `w` and `h` (fit within, never upscaled) and `format`
+/// (`webp`, `png`, `jpeg`, `gif`, or `auto` for WebP when the client accepts
+/// it). Requests without parameters get the original file. Variants are cached
+/// in memory and served with `Cache-Control`, `ETag` and, for `format=auto`,
+/// `Vary: Accept`.
+///
+/// Transforms run on the blocking pool, a few at a time (see
+/// `max_concurrent_transforms`); concurrent requests for the same variant share
+/// one transform. `allowed_sizes` restricts `w` and `h` to a fixed set so
+/// clients cannot fill the cache with arbitrary variants.
+///
+/// ```
+/// use pingora_web::{App, utils::ImageHandler};
+/// use std::sync::Arc;
+///
+/// let mut app = App::default();
+/// // GET /img/photos/cat.jpg?w=320&format=webp
+/// app.get("/img/{*path}", Arc::new(ImageHandler::new("./images")));
+/// ```
+pub struct ImageHandler {
+    dir: ServeDir,
+    max_dimension: u32,
+    allowed_sizes: Option<Vec<u32>>,
+    max_age: Duration,
+    cache: Mutex<VariantCache>,
+    transforms: Semaphore,
+    in_flight: Mutex<HashMap<VariantKey, Arc<Transform>>>,
+}
+
+/// Outcome of one transform, shared by every request waiting on it
+type Transform = OnceCell<Result<Bytes, StatusCode>>;
+
+impl ImageHandler {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            dir: ServeDir::new(root),
+            max_dimension: 4096,
+            allowed_sizes: None,
+            max_age: Duration::from_secs(86_400),
+            cache: Mutex::new(VariantCache {
+                capacity: 64 * 1024 * 1024,
+                size: 0,
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            transforms: Semaphore::new(4),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Route parameter holding the image path (see `ServeDir::with_param_name`)
+    pub fn with_param_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.dir = self.dir.with_param_name(name);
+        self
+    }
+
+    /// Largest `w` / `h` accepted (default 4096)
+    pub fn max_dimension(mut self, pixels: u32) -> Self {
+        self.max_dimension = pixels;
+        self
+    }
+
+    /// Only accept these values for `w` and `h` (default: anything up to `max_dimension`)
+    pub fn allowed_sizes<I: IntoIterator<Item = u32>>(mut self, sizes: I) -> Self {
+        self.allowed_sizes = Some(sizes.into_iter().collect());
+        self
+    }
+
+    /// Transforms allowed to run at once; further misses wait (default 4)
+    pub fn max_concurrent_transforms(mut self, limit: usize) -> Self {
+        self.transforms = Semaphore::new(limit);
+        self
+    }
+
+    /// `Cache-Control: max-age` of responses (default one day)
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Memory budget for transformed variants in bytes (default 64 MiB)
+    pub fn cache_size(self, bytes: usize) -> Self {
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .capacity = bytes;
+        self
+    }
+
+    fn cached(&self, key: &VariantKey) -> Option<Bytes> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key)
+    }
+
+    fn store(&self, key: VariantKey, data: Bytes) {
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, data);
+    }
+
+    /// Cached variant, or the result of transforming `path` (shared with concurrent misses)
+    async fn variant(
+        &self,
+        key: VariantKey,
+        path: &std::path::Path,
+        format: ImageFormat,
+    ) -> Result<Bytes, StatusCode> {
+        if let Some(data) = self.cached(&key) {
+            return Ok(data);
+        }
+        let cell = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let result = cell
+            .get_or_init(|| async {
+                if let Some(data) = self.cached(&key) {
+                    return Ok(data);
+                }
+                let _permit = self
+                    .transforms
+                    .acquire()
+                    .await
+                    .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+                let source = tokio::fs::read(path)
+                    .await
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                let (w, h) = (key.width, key.height);
+                let encoded = tokio::task::spawn_blocking(move || transform(&source, w, h, format))
+                    .await
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                    .map_err(|_| StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+                let data = Bytes::from(encoded);
+                self.store(key.clone(), data.clone());
+                Ok(data)
+            })
+            .await
+            .clone();
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        if in_flight.get(&key).is_some_and(|c| Arc::ptr_eq(c, &cell)) {
+            in_flight.remove(&key);
+        }
+        result
+    }
+
+    fn cache_control(&self) -> String {
+        format!("public, max-age={}", self.max_age.as_secs())
+    }
+}
+
+fn bad_request(message: &str) -> PingoraWebHttpResponse {
+    PingoraWebHttpResponse::text(StatusCode::BAD_REQUEST, message)
+}
+
+#[async_trait]
+impl Handler for ImageHandler {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let Some(path) = self.dir.resolve(&req).await else {
+            return Ok(PingoraWebHttpResponse::text(
+                StatusCode::NOT_FOUND,
+                "Not Found",
+            ));
+        };
+        let Ok(params) =
+            serde_urlencoded::from_str::<Params>(req.inner.uri().query().unwrap_or_default())
+        else {
+            return Ok(bad_request("Invalid image parameters"));
+        };
+        if [params.w, params.h].iter().flatten().any(|&d| {
+            d == 0
+                || d > self.max_dimension
+                || self
+                    .allowed_sizes
+                    .as_ref()
+                    .is_some_and(|sizes| !sizes.contains(&d))
+        }) {
+            return Ok(bad_request("Image dimensions out of range"));
+        }
+
+        let negotiated = params.format.as_deref() == Some("auto");
+        let format = match params.format.as_deref() {
+            None if params.w.is_none() && params.h.is_none() => {
+                return Ok(PingoraWebHttpResponse::stream_file(StatusCode::OK, &path)
+                    .cache_control(self.cache_control()));
+            }
+            None => ImageFormat::from_path(&path).ok(),
+            Some("auto") => {
+                // Browsers send `*/*` too, so only an explicit mention counts
+                let accepts_webp = req
+                    .headers()
+                    .get(http::header::ACCEPT)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|v| v.contains("image/webp"))
+                    && req.accepts("image/webp");
+                if accepts_webp {
+                    Some(ImageFormat::WebP)
+                } else {
+                    ImageFormat::from_path(&path).ok()
+                }
+            }
+            Some(name) => match output_format(name) {
+                Some(format) => Some(format),
+                None => return Ok(bad_request("Unsupported image format")),
+            },
+        };
+        let Some(format) = format.filter(|f| output_format(format_name(*f)).is_some()) else {
+            return Ok(PingoraWebHttpResponse::text(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Unsupported image format",
+            ));
+        };
+
+        let meta = tokio::fs::metadata(&path)
+            .await
+            .map_err(crate::error::internal_error)?;
+        let key = VariantKey {
+            path: path.clone(),
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            width: params.w,
+            height: params.h,
+            format: format_name(format),
+        };
+        let etag = key.etag();
+
+        let res = if req.if_none_match(&etag) {
+            PingoraWebHttpResponse::not_modified()
+        } else {
+            let data = match self.variant(key, &path, format).await {
+                Ok(data) => data,
+                Err(StatusCode::UNSUPPORTED_MEDIA_TYPE) => {
+                    return Ok(PingoraWebHttpResponse::text(
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        "Unsupported image format",
+                    ));
+                }
+                Err(status) => {
+                    return Err(crate::error::internal_error(format!(
+                        "image transform failed: {status}"
+                    )));
+                }
+            };
+            PingoraWebHttpResponse::bytes(StatusCode::OK, data).content_type(format.to_mime_type())
+        };
+
+        let mut res = res.cache_control(self.cache_control()).etag(&etag);
+        if negotiated {
+            res.add_vary("Accept");
+        }
+        Ok(res)
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use std::sync::Arc;
+
+    fn app(root: &std::path::Path) -> App {
+        let img = image::RgbaImage::from_pixel(64, 32, image::Rgba([200, 10, 10, 255]));
+        img.save(root.join("red.png")).unwrap();
+        let mut app = App::default();
+        app.get("/img/{*path}", Arc::new(ImageHandler::new(root)));
+        app
+    }
+
+    #[tokio::test]
+    async fn resizes_and_converts() {
+        let root = std::env::temp_dir().join(format!("pingora_web_img_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let app = app(&root);
+        let client = app.test_client();
+
+        let res = client.get("/img/red.png?w=16&format=webp").send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.header("content-type"), Some("image/webp"));
+        assert_eq!(res.header("cache-control"), Some("public, max-age=86400"));
+        assert_eq!(res.header("vary"), None);
+        let out = image::load_from_memory(res.bytes()).unwrap();
+        assert_eq!((out.width(), out.height()), (16, 8));
+
+        // Cached variants revalidate by ETag
+        let etag = res.header("etag").unwrap().to_string();
+        let res = client
+            .get("/img/red.png?w=16&format=webp")
+            .header("if-none-match", &etag)
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        // `auto` negotiates on Accept
+        let res = client
+            .get("/img/red.png?h=16&format=auto")
+            .header("accept", "image/avif,image/webp,*/*")
+            .send()
+            .await;
+        assert_eq!(res.header("content-type"), Some("image/webp"));
+        assert_eq!(res.header("vary"), Some("Accept"));
+        let res = client.get("/img/red.png?h=16&format=auto").send().await;
+        assert_eq!(res.header("content-type"), Some("image/png"));
+
+        // Never upscales; originals pass through untouched
+        let res = client.get("/img/red.png?w=1000&format=jpeg").send().await;
+        let out = image::load_from_memory(res.bytes()).unwrap();
+        assert_eq!((out.width(), out.height()), (64, 32));
+        let res = client.get("/img/red.png").send().await;
+        assert_eq!(
+            res.bytes().as_ref(),
+            std::fs::read(root.join("red.png")).unwrap()
+        );
+
+        assert_eq!(
+            client.get("/img/red.png?w=0").send().await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            client.get("/img/red.png?format=tiff").send().await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            client.get("/img/missing.png?w=5").send().await.status(),
+            StatusCode::NOT_FOUND
+        );
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn cache_evicts_oldest_variants() {
+        let key = |w| VariantKey {
+            path: PathBuf::from("a.png"),
+            modified: None,
+            len: 1,
+            width: Some(w),
+            height: None,
+            format: "png",
+        };
+        let mut cache = VariantCache {
+            capacity: 10,
+            size: 0,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        };
+        cache.insert(key(1), Bytes::from_static(b"12345"));
+        cache.insert(key(2), Bytes::from_static(b"12345"));
+        cache.insert(key(3), Bytes::from_static(b"123"));
+        assert!(cache.get(&key(1)).is_none());
+        assert!(cache.get(&key(2)).is_some());
+        assert_eq!(cache.size, 8);
+        assert_ne!(key(1).etag(), key(2).etag());
+    }
+
+    #[tokio::test]
+    async fn limits_variants() {
+        let root = std::env::temp_dir().join(format!("pingora_web_img_lim_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let img = image::RgbaImage::from_pixel(64, 32, image::Rgba([10, 200, 10, 255]));
+        img.save(root.join("green.png")).unwrap();
+        let handler = Arc::new(
+            ImageHandler::new(&root)
+                .allowed_sizes([16, 32])
+                .max_concurrent_transforms(1)
+                .cache_size(0),
+        );
+        let mut app = App::default();
+        app.get("/img/{*path}", handler.clone());
+        let client = app.test_client();
+
+        assert_eq!(
+            client.get("/img/green.png?w=17").send().await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        // Concurrent misses for one variant share a transform
+        let responses =
+            futures::future::join_all((0..8).map(|_| client.get("/img/green.png?w=16").send()))
+                .await;
+        for res in &responses {
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.bytes(), responses[0].bytes());
+        }
+        assert!(handler.in_flight.lock().unwrap().is_empty());
+        std::fs::remove_dir_all(root).ok();
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::StreamExt;
+use http::{HeaderValue, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::ServeDir;
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse, Range};
+use crate::error::WebError;
+
+/// Read size when streaming file ranges
+const READ_SIZE: usize = 64 * 1024;
+
+/// A `Range` header that no part of the resource satisfies (416)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsatisfiable;
+
+/// An inclusive byte range of a resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Parse a `Range` header for a resource of `len` bytes
+    ///
+    /// `Ok(None)` means the header should be ignored and the full resource
+    /// served: it is not a single `bytes` range (multipart ranges are not
+    /// supported).
+    pub fn parse(header: &str, len: u64) -> Result<Option<Self>, Unsatisfiable> {
+        match Range::parse(header).and_then(|r| r.single()) {
+            Some(spec) => spec.resolve(len).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Number of bytes in the range (never zero)
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// In-flight range responses per client address
+#[derive(Default)]
+struct RangeLimiter {
+    active: Mutex<HashMap<Option<IpAddr>, usize>>,
+}
+
+/// Held by a range response body until it is finished or dropped
+struct RangePermit {
+    limiter: Arc<RangeLimiter>,
+    client: Option<IpAddr>,
+}
+
+impl RangeLimiter {
+    fn acquire(self: &Arc<Self>, client: Option<IpAddr>, max: usize) -> Option<RangePermit> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let count = active.entry(client).or_default();
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(RangePermit {
+            limiter: self.clone(),
+            client,
+        })
+    }
+}
+
+impl Drop for RangePermit {
+    fn drop(&mut self) {
+        let mut active = self
+            .limiter
+            .active
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = active.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// Stream `range` of the file at `path`, keeping `permit` until the body ends
+fn range_stream(
+    path: PathBuf,
+    range: ByteRange,
+    permit: Option<RangePermit>,
+) -> PingoraWebHttpResponse {
+    let stream = futures::stream::unfold(
+        (None::<tokio::fs::File>, path, range.len(), permit),
+        move |(file, path, remaining, permit)| async move {
+            if remaining == 0 {
+                return None;
+            }
+            let mut file = match file {
+                Some(f) => f,
+                None => {
+                    let mut f = tokio::fs::File::open(&path).await.ok()?;
+                    f.seek(SeekFrom::Start(range.start)).await.ok()?;
+                    f
+                }
+            };
+            let mut buf = vec![0u8; READ_SIZE.min(remaining as usize)];
+            match file.read(&mut buf).await {
+                Ok(0) | Err(_) => None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    let remaining = remaining - n as u64;
+                    Some((Bytes::from(buf), (Some(file), path, remaining, permit)))
+                }
+            }
+        },
+    );
+    PingoraWebHttpResponse::stream(StatusCode::OK, stream.boxed())
+}
+
+/// Serve audio/video files for progressive playback with byte ranges
+///
+/// Advertises `Accept-Ranges: bytes` and answers single ranges with 206 (416
+/// when unsatisfiable, honoring `If-Range`). Open-ended ranges such as the
+/// `bytes=0-` players start with are capped at `chunk_size`, so a player
+/// looking for an MP4 `moov` atom at the end of the file can seek there
+/// without first downloading the whole file. Each client may have at most
+/// `max_concurrent_ranges` range responses in flight; more get 429.
+///
+/// ```
+/// use pingora_web::{App, utils::MediaHandler};
+/// use std::sync::Arc;
+///
+/// let mut app = App::default();
+/// app.get("/media/{*path}", Arc::new(MediaHandler::new("./videos").chunk_size(1 << 20)));
+/// ```
+pub struct MediaHandler {
+    dir: ServeDir,
+    chunk_size: u64,
+    max_concurrent: usize,
+    limiter: Arc<RangeLimiter>,
+}
+
+impl MediaHandler {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            dir: ServeDir::new(root),
+            chunk_size: 2 * 1024 * 1024,
+            max_concurrent: 4,
+            limiter: Arc::default(),
+        }
+    }
+
+    /// Route parameter holding the file path (see `ServeDir::with_param_name`)
+    pub fn with_param_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.dir = self.dir.with_param_name(name);
+        self
+    }
+
+    /// Most bytes sent for an open-ended range (default 2 MiB)
+    pub fn chunk_size(mut self, bytes: u64) -> Self {
+        self.chunk_size = bytes.max(1);
+        self
+    }
+
+    /// Range responses one client address may stream at once (default 4)
+    pub fn max_concurrent_ranges(mut self, max: usize) -> Self {
+        self.max_concurrent = max;
+        self
+    }
+}
+
+/// Validator from size and modification time (strong, so `If-Range` can use it)
+fn etag(meta: &std::fs::Metadata) -> String {
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    format!("\"{:x}-{:x}\"", meta.len(), modified)
+}
+
+fn content_type(path: &Path) -> HeaderValue {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    HeaderValue::from_str(mime.as_ref())
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"))
+}
+
+#[async_trait]
+impl Handler for MediaHandler {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let Some(path) = self.dir.resolve(&req).await else {
+            return Ok(PingoraWebHttpResponse::text(
+                StatusCode::NOT_FOUND,
+                "Not Found",
+            ));
+        };
+        let meta = tokio::fs::metadata(&path)
+            .await
+            .map_err(crate::error::internal_error)?;
+        let len = meta.len();
+        let etag = etag(&meta);
+
+        let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+        // A range only applies to the representation the client already has
+        let range = match header(http::header::RANGE) {
+            Some(range) if header(http::header::IF_RANGE).is_none_or(|tag| tag == etag) => {
+                ByteRange::parse(range, len)
+            }
+            _ => Ok(None),
+        };
+
+        let mut res = match range {
+            Err(Unsatisfiable) => PingoraWebHttpResponse::text(
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "Range Not Satisfiable",
+            )
+            .header(http::header::CONTENT_RANGE, format!("bytes */{len}")),
+            Ok(None) => PingoraWebHttpResponse::stream_file(StatusCode::OK, &path),
+            Ok(Some(mut range)) => {
+                let open_ended =
+                    header(http::header::RANGE).is_some_and(|r| r.trim_end().ends_with('-'));
+                if open_ended && range.len() > self.chunk_size {
+                    range.end = range.start + self.chunk_size - 1;
+                }
+                let Some(permit) = self
+                    .limiter
+                    .acquire(req.peer_addr().map(|a| a.ip()), self.max_concurrent)
+                else {
+                    return Ok(PingoraWebHttpResponse::text(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        "Too Many Range Requests",
+                    )
+                    .header(http::header::RETRY_AFTER, "1"));
+                };
+                let mut res = range_stream(path.clone(), range, Some(permit));
+                res.status = StatusCode::PARTIAL_CONTENT;
+                res.header(
+                    http::header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{len}", range.start, range.end),
+                )
+                .header(http::header::CONTENT_LENGTH, range.len().to_string())
+                .header(http::header::CONTENT_TYPE, content_type(&path))
+            }
+        };
+        res.set_header(http::header::ACCEPT_RANGES, "bytes");
+        res.set_header(http::header::ETAG, etag);
+        Ok(res)
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    #[test]
+    fn parses_ranges() {
+        let r = |start, end| Ok(Some(ByteRange { start, end }));
+        assert_eq!(ByteRange::parse("bytes=0-99", 1000), r(0, 99));
+        assert_eq!(ByteRange::parse("bytes=900-", 1000), r(900, 999));
+        assert_eq!(ByteRange::parse("bytes=-100", 1000), r(900, 999));
+        assert_eq!(ByteRange::parse("bytes=-5000", 1000), r(0, 999));
+        assert_eq!(ByteRange::parse("bytes=500-5000", 1000), r(500, 999));
+        assert_eq!(ByteRange::parse("bytes=1000-", 1000), Err(Unsatisfiable));
+        assert_eq!(ByteRange::parse("bytes=-0", 1000), Err(Unsatisfiable));
+        assert_eq!(ByteRange::parse("bytes=0-1,5-9", 1000), Ok(None));
+        assert_eq!(ByteRange::parse("bytes=9-1", 1000), Ok(None));
+        assert_eq!(ByteRange::parse("items=0-1", 1000), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn serves_ranges_with_limits() {
+        let root = std::env::temp_dir().join(format!("pingora_web_media_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 256) as u8).collect();
+        std::fs::write(root.join("clip.mp4"), &data).unwrap();
+
+        let media = Arc::new(
+            MediaHandler::new(&root)
+                .chunk_size(4096)
+                .max_concurrent_ranges(1),
+        );
+        let mut app = App::default();
+        app.get("/media/{*path}", media.clone());
+        let client = app.test_client();
+
+        let res = client.get("/media/clip.mp4").send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.header("accept-ranges"), Some("bytes"));
+        assert_eq!(res.bytes().len(), 10_000);
+
+        let res = client
+            .get("/media/clip.mp4")
+            .header("range", "bytes=100-199")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.header("content-range"), Some("bytes 100-199/10000"));
+        assert_eq!(res.header("content-type"), Some("video/mp4"));
+        assert_eq!(res.bytes().as_ref(), &data[100..200]);
+
+        // Open-ended ranges are capped; suffix ranges reach the end of the file
+        let res = client
+            .get("/media/clip.mp4")
+            .header("range", "bytes=0-")
+            .send()
+            .await;
+        assert_eq!(res.header("content-range"), Some("bytes 0-4095/10000"));
+        assert_eq!(res.bytes().len(), 4096);
+        let res = client
+            .get("/media/clip.mp4")
+            .header("range", "bytes=-16")
+            .send()
+            .await;
+        assert_eq!(res.bytes().as_ref(), &data[9984..]);
+
+        let res = client
+            .get("/media/clip.mp4")
+            .header("range", "bytes=20000-")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.header("content-range"), Some("bytes */10000"));
+
+        // A stale If-Range gets the full file
+        let res = client
+            .get("/media/clip.mp4")
+            .header("range", "bytes=0-9")
+            .header("if-range", "\"stale\"")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.header("etag").unwrap().to_string();
+        let res = client
+            .get("/media/clip.mp4")
+            .header("range", "bytes=0-9")
+            .header("if-range", &etag)
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+
+        // While one range body is unfinished, the client is at its limit
+        let ranged = |r: &str| {
+            let mut req = PingoraHttpRequest::new(http::Method::GET, "/media/clip.mp4");
+            req.params.insert("path", "clip.mp4");
+            req.inner
+                .headers_mut()
+                .insert("range", HeaderValue::from_str(r).unwrap());
+            req
+        };
+        let first = media.handle(ranged("bytes=0-10")).await.unwrap();
+        let second = media.handle(ranged("bytes=0-10")).await.unwrap();
+        assert_eq!(second.status, StatusCode::TOO_MANY_REQUESTS);
+        drop(first);
+        let third = media.handle(ranged("bytes=0-10")).await.unwrap();
+        assert_eq!(third.status, StatusCode::PARTIAL_CONTENT);
+
+        std::fs::remove_dir_all(root).ok();
+    }
+}
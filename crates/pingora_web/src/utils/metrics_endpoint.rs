@@ -0,0 +1,324 @@
+use std::fmt::Write;
+
+use async_trait::async_trait;
+use http::StatusCode;
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use prometheus::{Encoder, Registry, TextEncoder};
+
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+const OPENMETRICS_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Latest exemplar per histogram bucket, keyed by metric name and label set
+pub(crate) mod exemplars {
+    use std::collections::HashMap;
+    use std::sync::{Mutex, OnceLock};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[derive(Clone)]
+    pub(crate) struct Exemplar {
+        pub(crate) trace_id: String,
+        pub(crate) value: f64,
+        pub(crate) timestamp: f64,
+    }
+
+    /// (metric, labels sorted by name, bucket upper bound bits)
+    type Key = (String, Vec<(String, String)>, u64);
+
+    fn store() -> &'static Mutex<HashMap<Key, Exemplar>> {
+        static STORE: OnceLock<Mutex<HashMap<Key, Exemplar>>> = OnceLock::new();
+        STORE.get_or_init(Default::default)
+    }
+
+    fn key(metric: &str, labels: impl Iterator<Item = (String, String)>, bound: f64) -> Key {
+        let mut labels: Vec<_> = labels.collect();
+        labels.sort();
+        (metric.to_string(), labels, bound.to_bits())
+    }
+
+    /// Remember `trace_id` as the exemplar of the bucket `value` falls into
+    pub(crate) fn record(
+        metric: &str,
+        labels: &[(&str, &str)],
+        buckets: &[f64],
+        value: f64,
+        trace_id: &str,
+    ) {
+        let bound = buckets
+            .iter()
+            .copied()
+            .find(|b| value <= *b)
+            .unwrap_or(f64::INFINITY);
+        let labels = labels.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+        let exemplar = Exemplar {
+            trace_id: trace_id.to_string(),
+            value,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |d| d.as_secs_f64()),
+        };
+        if let Ok(mut store) = store().lock() {
+            store.insert(key(metric, labels, bound), exemplar);
+        }
+    }
+
+    pub(crate) fn get(
+        metric: &str,
+        labels: impl Iterator<Item = (String, String)>,
+        bound: f64,
+    ) -> Option<Exemplar> {
+        store()
+            .lock()
+            .ok()?
+            .get(&key(metric, labels, bound))
+            .cloned()
+    }
+}
+
+/// Handler exposing a Prometheus registry, with exemplars in OpenMetrics format
+///
+/// Scrapers asking for `application/openmetrics-text` get OpenMetrics output
+/// where latency buckets carry the trace id of a recent request
+/// (`# {trace_id="..."}`); others get the classic text format.
+///
+/// ```
+/// use pingora_web::{App, MetricsMiddleware, TracingMiddleware};
+/// use pingora_web::utils::MetricsEndpoint;
+/// use std::sync::Arc;
+///
+/// let mut app = App::default();
+/// app.use_middleware(TracingMiddleware::new());
+/// app.use_middleware(MetricsMiddleware::new());
+/// app.get("/metrics", Arc::new(MetricsEndpoint::new()));
+/// ```
+pub struct MetricsEndpoint {
+    registry: Registry,
+}
+
+impl MetricsEndpoint {
+    /// Serve the default registry
+    pub fn new() -> Self {
+        Self {
+            registry: prometheus::default_registry().clone(),
+        }
+    }
+
+    /// Serve a custom registry
+    pub fn with_registry(registry: Registry) -> Self {
+        Self { registry }
+    }
+}
+
+impl Default for MetricsEndpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Handler for MetricsEndpoint {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let families = self.registry.gather();
+        const OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0";
+        let openmetrics =
+            req.preferred_type(&["text/plain; version=0.0.4", OPENMETRICS]) == Some(OPENMETRICS);
+
+        if openmetrics {
+            return Ok(PingoraWebHttpResponse::bytes(
+                StatusCode::OK,
+                encode_openmetrics(&families),
+            )
+            .header(http::header::CONTENT_TYPE, OPENMETRICS_TYPE));
+        }
+
+        let encoder = TextEncoder::new();
+        let mut buf = Vec::new();
+        encoder
+            .encode(&families, &mut buf)
+            .map_err(crate::error::internal_error)?;
+        Ok(PingoraWebHttpResponse::bytes(StatusCode::OK, buf)
+            .header(http::header::CONTENT_TYPE, encoder.format_type()))
+    }
+
+    fn buffer_body(&self) -> bool {
+        false
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn number(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// `{a="1",b="2"}` from label pairs plus an optional extra pair
+fn labels(pairs: &[LabelPair], extra: Option<(&str, &str)>) -> String {
+    let rendered: Vec<String> = pairs
+        .iter()
+        .map(|l| (l.get_name(), l.get_value()))
+        .chain(extra)
+        .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+        .collect();
+    if rendered.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", rendered.join(","))
+    }
+}
+
+/// Encode metric families in the OpenMetrics 1.0 text format
+fn encode_openmetrics(families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in families {
+        let name = family.get_name();
+        let (base, kind) = match family.get_field_type() {
+            MetricType::COUNTER => (name.strip_suffix("_total").unwrap_or(name), "counter"),
+            MetricType::GAUGE => (name, "gauge"),
+            MetricType::HISTOGRAM => (name, "histogram"),
+            MetricType::SUMMARY => (name, "summary"),
+            MetricType::UNTYPED => (name, "unknown"),
+        };
+        let _ = writeln!(out, "# TYPE {base} {kind}");
+        let _ = writeln!(out, "# HELP {base} {}", escape(family.get_help()));
+
+        for metric in family.get_metric() {
+            let pairs = metric.get_label();
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    let value = metric.get_counter().get_value();
+                    let _ = writeln!(out, "{base}_total{} {}", labels(pairs, None), number(value));
+                }
+                MetricType::GAUGE => {
+                    let value = metric.get_gauge().get_value();
+                    let _ = writeln!(out, "{base}{} {}", labels(pairs, None), number(value));
+                }
+                MetricType::UNTYPED => {
+                    let value = metric.get_untyped().get_value();
+                    let _ = writeln!(out, "{base}{} {}", labels(pairs, None), number(value));
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for q in summary.get_quantile() {
+                        let quantile = number(q.get_quantile());
+                        let _ = writeln!(
+                            out,
+                            "{base}{} {}",
+                            labels(pairs, Some(("quantile", &quantile))),
+                            number(q.get_value())
+                        );
+                    }
+                    let l = labels(pairs, None);
+                    let _ = writeln!(out, "{base}_sum{l} {}", number(summary.get_sample_sum()));
+                    let _ = writeln!(out, "{base}_count{l} {}", summary.get_sample_count());
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let mut bounds: Vec<(f64, u64)> = histogram
+                        .get_bucket()
+                        .iter()
+                        .map(|b| (b.get_upper_bound(), b.get_cumulative_count()))
+                        .collect();
+                    if bounds.last().is_none_or(|(b, _)| *b != f64::INFINITY) {
+                        bounds.push((f64::INFINITY, histogram.get_sample_count()));
+                    }
+                    let label_set = || {
+                        pairs
+                            .iter()
+                            .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+                    };
+                    for (bound, count) in bounds {
+                        let le = number(bound);
+                        let _ = write!(
+                            out,
+                            "{base}_bucket{} {count}",
+                            labels(pairs, Some(("le", &le)))
+                        );
+                        if let Some(ex) = exemplars::get(name, label_set(), bound) {
+                            let _ = write!(
+                                out,
+                                " # {{trace_id=\"{}\"}} {} {:.3}",
+                                escape(&ex.trace_id),
+                                number(ex.value),
+                                ex.timestamp
+                            );
+                        }
+                        out.push('\n');
+                    }
+                    let l = labels(pairs, None);
+                    let _ = writeln!(out, "{base}_sum{l} {}", number(histogram.get_sample_sum()));
+                    let _ = writeln!(out, "{base}_count{l} {}", histogram.get_sample_count());
+                }
+            }
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{App, MetricsMiddleware, TracingMiddleware};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn exposes_trace_ids_as_exemplars() {
+        let registry = Registry::new();
+        let mut app = App::default();
+        app.use_middleware(TracingMiddleware::new());
+        app.use_middleware(MetricsMiddleware::with_registry(&registry).unwrap());
+        app.get_fn("/slow/{id}", |req| req.trace_id().unwrap_or("").to_string());
+        app.get(
+            "/metrics",
+            Arc::new(MetricsEndpoint::with_registry(registry)),
+        );
+        let client = app.test_client();
+
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let res = client
+            .get("/slow/1")
+            .header("traceparent", format!("00-{trace_id}-00f067aa0ba902b7-01"))
+            .send()
+            .await;
+        assert_eq!(res.text(), trace_id);
+
+        // Without a traceparent, TracingMiddleware generates one
+        let generated = client.get("/slow/2").send().await.text();
+        assert_eq!(generated.len(), 32);
+        assert_ne!(generated, trace_id);
+
+        let res = client
+            .get("/metrics")
+            .header("accept", "application/openmetrics-text; version=1.0.0")
+            .send()
+            .await;
+        assert_eq!(res.header("content-type"), Some(OPENMETRICS_TYPE));
+        let body = res.text();
+        assert!(body.ends_with("# EOF\n"));
+        assert!(body.contains("# TYPE pingora_web_requests counter"));
+        assert!(body.contains("pingora_web_requests_total{method=\"GET\",route=\"/slow/{id}\""));
+        let exemplar = body
+            .lines()
+            .find(|l| {
+                l.starts_with("pingora_web_request_duration_seconds_bucket") && l.contains(" # {")
+            })
+            .expect("latency bucket with exemplar");
+        assert!(exemplar.contains("route=\"/slow/{id}\""));
+
+        let plain = client.get("/metrics").send().await.text();
+        assert!(plain.contains("pingora_web_requests_total"));
+        assert!(!plain.contains("trace_id"));
+    }
+}
@@ -1,5 +1,43 @@
+pub mod archive_stream;
+pub mod blob_store;
+pub mod fallback;
+pub mod grpc;
+#[cfg(feature = "images")]
+pub mod image_handler;
+pub mod media;
+pub mod metrics_endpoint;
+pub mod proxy;
 pub mod request_id;
 pub mod serve_dir;
+pub mod signing;
+#[cfg(feature = "span-timings")]
+pub mod span_timings;
+pub mod static_export;
+#[cfg(feature = "templates")]
+pub mod templates;
+pub mod test_client;
+pub mod timeout;
+#[cfg(feature = "wasm")]
+pub mod wasm_handler;
 
-pub use request_id::generate;
+pub use archive_stream::ArchiveStream;
+pub use blob_store::{BlobHandler, BlobRef, BlobStore, FsBlobStore};
+pub use fallback::FallbackChain;
+pub use grpc::{GrpcCode, GrpcHandler, GrpcRequest, GrpcResponse, GrpcService, GrpcStatus};
+#[cfg(feature = "images")]
+pub use image_handler::ImageHandler;
+pub use media::{ByteRange, MediaHandler, Unsatisfiable};
+pub use metrics_endpoint::MetricsEndpoint;
+pub use proxy::ProxyHandler;
+pub use request_id::{RequestIdGenerator, RequestIdPolicy, generate};
 pub use serve_dir::ServeDir;
+pub use signing::Signer;
+#[cfg(feature = "span-timings")]
+pub use span_timings::SpanTimingLayer;
+pub use static_export::ExportReport;
+#[cfg(feature = "templates")]
+pub use templates::Templates;
+pub use test_client::{TestClient, TestRequest, TestResponse};
+pub use timeout::Timeout;
+#[cfg(feature = "wasm")]
+pub use wasm_handler::WasmHandler;
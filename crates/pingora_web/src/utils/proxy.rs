@@ -0,0 +1,398 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use http::{HeaderMap, HeaderName, StatusCode};
+use pingora_core::connectors::http::Connector;
+use pingora_core::upstreams::peer::HttpPeer;
+use pingora_http::RequestHeader;
+
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// Hop-by-hop headers (RFC 9110 §7.6.1), never forwarded in either direction
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// `headers` without hop-by-hop headers, including those named in `Connection`
+fn end_to_end(headers: &HeaderMap) -> impl Iterator<Item = (&HeaderName, &http::HeaderValue)> {
+    let listed: Vec<String> = headers
+        .get_all(http::header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    headers.iter().filter(move |(name, _)| {
+        !HOP_BY_HOP.contains(&name.as_str()) && !listed.iter().any(|l| l == name.as_str())
+    })
+}
+
+/// Forward requests to an upstream HTTP server and stream its responses back
+///
+/// Uses pingora's connector, so upstream connections are pooled. Hop-by-hop
+/// headers are dropped, `X-Forwarded-For`/`-Proto`/`-Host` are added, and the
+/// path can be rewritten by prefix. The request body is read (within the
+/// request's body limit) before forwarding; the response body is streamed.
+/// Connection failures answer 502, timeouts 504. If the upstream fails
+/// mid-body, the client's response is aborted rather than ended cleanly.
+///
+/// Upstreams are given as resolved addresses, so constructing a proxy never
+/// blocks on DNS or panics; resolve host names at startup, e.g. with
+/// `tokio::net::lookup_host`.
+///
+/// ```
+/// use pingora_web::{App, utils::ProxyHandler};
+/// use std::sync::Arc;
+///
+/// let mut app = App::default();
+/// app.get_fn("/", |_req| "local");
+/// // /api/users -> http://127.0.0.1:8081/v1/users
+/// let upstream = ProxyHandler::new(([127, 0, 0, 1], 8081));
+/// let api = Arc::new(upstream.rewrite_prefix("/api", "/v1"));
+/// app.get("/api/{*rest}", api.clone());
+/// app.post("/api/{*rest}", api);
+/// ```
+pub struct ProxyHandler {
+    connector: Arc<Connector>,
+    peer: HttpPeer,
+    rewrite: Option<(String, String)>,
+    host: Option<String>,
+    timeout: Duration,
+}
+
+impl ProxyHandler {
+    /// Plain-HTTP upstream at `addr` (e.g. `([127, 0, 0, 1], 8081)`)
+    pub fn new(addr: impl Into<SocketAddr>) -> Self {
+        Self {
+            connector: Arc::new(Connector::new(None)),
+            peer: HttpPeer::new(addr.into(), false, String::new()),
+            rewrite: None,
+            host: None,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// HTTPS upstream at `addr`, verified against `sni` (needs a TLS feature)
+    pub fn tls(addr: impl Into<SocketAddr>, sni: impl Into<String>) -> Self {
+        let addr = addr.into();
+        let mut proxy = Self::new(addr);
+        proxy.peer = HttpPeer::new(addr, true, sni.into());
+        proxy
+    }
+
+    /// Replace the leading `from` of request paths with `to` (e.g. `/api` -> `/v1`)
+    pub fn rewrite_prefix(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.rewrite = Some((from.into(), to.into()));
+        self
+    }
+
+    /// Send this `Host` upstream instead of the client's
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Time limit for connecting and for each upstream read/write (default 30s)
+    ///
+    /// Shortened to the request's deadline when one is set, e.g. by
+    /// `LimitsMiddleware` or a `TimeBudget` step.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn upstream_path(&self, req: &PingoraHttpRequest) -> String {
+        let path_and_query = req
+            .inner
+            .uri()
+            .path_and_query()
+            .map_or("/", |pq| pq.as_str());
+        match &self.rewrite {
+            Some((from, to)) => match path_and_query.strip_prefix(from.as_str()) {
+                Some(rest) if rest.is_empty() || rest.starts_with(['/', '?']) => {
+                    let path = format!("{to}{rest}");
+                    if path.starts_with('/') {
+                        path
+                    } else {
+                        format!("/{path}")
+                    }
+                }
+                _ => path_and_query.to_string(),
+            },
+            None => path_and_query.to_string(),
+        }
+    }
+
+    fn request_header(
+        &self,
+        req: &PingoraHttpRequest,
+        body_len: usize,
+    ) -> pingora_core::Result<RequestHeader> {
+        let path = self.upstream_path(req);
+        let mut header = RequestHeader::build(req.method().clone(), path.as_bytes(), None)?;
+        for (name, value) in end_to_end(req.headers()) {
+            if name != http::header::CONTENT_LENGTH {
+                header.append_header(name.clone(), value.clone())?;
+            }
+        }
+        if let Some(host) = &self.host {
+            header.insert_header(http::header::HOST, host.as_str())?;
+        }
+        if let Some(host) = req.headers().get(http::header::HOST) {
+            header.insert_header("x-forwarded-host", host.clone())?;
+        }
+        if let Some(peer) = req.peer_addr() {
+            let forwarded_for = match req
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+            {
+                Some(prior) => format!("{prior}, {}", peer.ip()),
+                None => peer.ip().to_string(),
+            };
+            header.insert_header("x-forwarded-for", forwarded_for)?;
+        }
+        let tls = req.connection().is_some_and(|c| c.tls.is_some());
+        header.insert_header("x-forwarded-proto", if tls { "https" } else { "http" })?;
+        header.insert_header(http::header::CONTENT_LENGTH, body_len.to_string())?;
+        Ok(header)
+    }
+
+    async fn forward(
+        &self,
+        mut req: PingoraHttpRequest,
+        timeout: Duration,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let body = req.body_bytes().await?.clone();
+        let header = self
+            .request_header(&req, body.len())
+            .map_err(crate::error::internal_error)?;
+
+        let bad_gateway = |e: Box<pingora_core::Error>| {
+            tracing::warn!("Proxy upstream error: {}", e);
+            PingoraWebHttpResponse::text(StatusCode::BAD_GATEWAY, "Bad Gateway")
+        };
+        let (mut session, _reused) = match self.connector.get_http_session(&self.peer).await {
+            Ok(session) => session,
+            Err(e) => return Ok(bad_gateway(e)),
+        };
+        session.set_read_timeout(Some(timeout));
+        session.set_write_timeout(Some(timeout));
+        let sent = async {
+            session.write_request_header(Box::new(header)).await?;
+            if !body.is_empty() {
+                session.write_request_body(body, true).await?;
+            }
+            session.finish_request_body().await?;
+            session.read_response_header().await
+        }
+        .await;
+        if let Err(e) = sent {
+            return Ok(bad_gateway(e));
+        }
+
+        let upstream = session
+            .response_header()
+            .expect("response header was read")
+            .clone();
+        let (tx, mut res) = PingoraWebHttpResponse::from_channel(4);
+        let abort = res.abort_handle();
+        res.status = upstream.status;
+        for (name, value) in end_to_end(&upstream.headers) {
+            res.headers.append(name.clone(), value.clone());
+        }
+
+        let connector = self.connector.clone();
+        let peer = self.peer.clone();
+        tokio::spawn(async move {
+            loop {
+                match session.read_response_body().await {
+                    Ok(Some(chunk)) => {
+                        // Client gone: drop the upstream connection mid-body
+                        if tx.send(chunk).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        // Don't present the partial body as complete
+                        tracing::warn!("Proxy upstream body error: {}", e);
+                        abort.abort();
+                        return;
+                    }
+                }
+            }
+            connector.release_http_session(session, &peer, None).await;
+        });
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Handler for ProxyHandler {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        // Never wait on the upstream past the request's own deadline
+        let timeout = req
+            .time_remaining()
+            .map_or(self.timeout, |left| left.min(self.timeout));
+        match tokio::time::timeout(timeout, self.forward(req, timeout)).await {
+            Ok(result) => result,
+            Err(_) => Ok(PingoraWebHttpResponse::text(
+                StatusCode::GATEWAY_TIMEOUT,
+                "Gateway Timeout",
+            )),
+        }
+    }
+
+    fn buffer_body(&self) -> bool {
+        // Read in `forward`, so only proxied routes pay for it
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Upstream answering with the request head it saw, as a chunked body
+    async fn upstream() -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut sock, _)) = listener.accept().await else {
+                    return;
+                };
+                tokio::spawn(async move {
+                    // Read the head, then as much body as it declares
+                    let mut seen = String::new();
+                    let mut buf = vec![0u8; 8192];
+                    loop {
+                        let n = sock.read(&mut buf).await.unwrap_or(0);
+                        seen.push_str(&String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase());
+                        let complete = seen.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                            let len = head
+                                .lines()
+                                .find_map(|l| l.strip_prefix("content-length: "))
+                                .map_or(0, |v| v.trim().parse().unwrap_or(0));
+                            body.len() >= len
+                        });
+                        if n == 0 || complete {
+                            break;
+                        }
+                    }
+                    let head = "HTTP/1.1 201 Created\r\nconnection: x-secret\r\nx-secret: 1\r\n\
+                                x-upstream: yes\r\ntransfer-encoding: chunked\r\n\r\n";
+                    let _ = sock.write_all(head.as_bytes()).await;
+                    for part in seen.as_bytes().chunks(64) {
+                        let chunk = format!("{:x}\r\n", part.len());
+                        let _ = sock.write_all(chunk.as_bytes()).await;
+                        let _ = sock.write_all(part).await;
+                        let _ = sock.write_all(b"\r\n").await;
+                    }
+                    let _ = sock.write_all(b"0\r\n\r\n").await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn forwards_and_streams_back() {
+        let addr = upstream().await;
+        let proxy = Arc::new(ProxyHandler::new(addr).rewrite_prefix("/api", "/v1"));
+        let mut app = App::default();
+        app.get_fn("/local", |_req| "local");
+        app.get("/api/{*rest}", proxy.clone());
+        app.post("/api/{*rest}", proxy);
+        let client = app.test_client();
+
+        assert_eq!(client.get("/local").send().await.text(), "local");
+
+        let res = client
+            .post("/api/users?page=2")
+            .header("host", "example.com")
+            .header("connection", "x-hop")
+            .header("x-hop", "1")
+            .header("te", "trailers")
+            .header("x-custom", "kept")
+            .body("name=bob")
+            .send()
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.header("x-upstream"), Some("yes"));
+        assert_eq!(res.header("x-secret"), None);
+        assert_eq!(res.header("connection"), None);
+
+        let seen = res.text();
+        assert!(
+            seen.starts_with("post /v1/users?page=2 http/1.1\r\n"),
+            "{seen}"
+        );
+        assert!(seen.contains("x-custom: kept\r\n"));
+        assert!(seen.contains("x-forwarded-host: example.com\r\n"));
+        assert!(seen.contains("x-forwarded-proto: http\r\n"));
+        assert!(seen.contains("content-length: 8\r\n"));
+        assert!(seen.ends_with("\r\n\r\nname=bob"));
+        assert!(!seen.contains("x-hop"));
+        assert!(!seen.contains("te: trailers"));
+    }
+
+    #[tokio::test]
+    async fn upstream_failing_mid_body_aborts_the_response() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            let _ = sock.read(&mut [0u8; 4096]).await;
+            let head = "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nhello\r\n";
+            let _ = sock.write_all(head.as_bytes()).await;
+            // Gone before the last chunk
+        });
+        let mut app = App::default();
+        app.get("/{*rest}", Arc::new(ProxyHandler::new(addr)));
+        let server = crate::test::spawn_server(app);
+
+        let mut client = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+        client
+            .write_all(b"GET /x HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        let read = client.read_to_end(&mut received);
+        tokio::time::timeout(Duration::from_secs(5), read)
+            .await
+            .expect("connection closed")
+            .unwrap();
+        let received = String::from_utf8_lossy(&received);
+        assert!(received.contains("hello"), "{received}");
+        assert!(!received.ends_with("0\r\n\r\n"), "{received}");
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream_is_bad_gateway() {
+        // Bind then drop to get a port nobody listens on
+        let addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut app = App::default();
+        app.get("/{*rest}", Arc::new(ProxyHandler::new(addr)));
+        let res = app.test_client().get("/x").send().await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+}
@@ -1,6 +1,11 @@
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use http::HeaderName;
+
+use crate::middleware::tracing_middleware::random_u64;
+
 static COUNTER: AtomicU64 = AtomicU64::new(0);
 
 pub fn generate() -> String {
@@ -12,3 +17,172 @@ pub fn generate() -> String {
     // Simple, collision-resistant enough for single-process: base36 timestamp + counter
     format!("{:x}-{:x}", ts, c)
 }
+
+/// How new request ids are made
+#[derive(Clone, Default)]
+pub enum RequestIdGenerator {
+    /// Hex timestamp and process counter, e.g. `61a2b3c4d5e6f-2a` (`generate()`)
+    #[default]
+    Counter,
+    /// Random UUID
+    UuidV4,
+    /// Time-ordered UUID, sortable by creation time
+    UuidV7,
+    /// Your own function
+    Custom(Arc<dyn Fn() -> String + Send + Sync>),
+}
+
+impl RequestIdGenerator {
+    pub fn custom(f: impl Fn() -> String + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(f))
+    }
+
+    pub fn generate(&self) -> String {
+        match self {
+            Self::Counter => generate(),
+            Self::UuidV4 => format_uuid(random_u128(), 4),
+            Self::UuidV7 => {
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                let bits = (millis & 0xffff_ffff_ffff) << 80 | random_u128() & ((1 << 80) - 1);
+                format_uuid(bits, 7)
+            }
+            Self::Custom(f) => f(),
+        }
+    }
+}
+
+impl std::fmt::Debug for RequestIdGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Counter => f.write_str("Counter"),
+            Self::UuidV4 => f.write_str("UuidV4"),
+            Self::UuidV7 => f.write_str("UuidV7"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+fn random_u128() -> u128 {
+    u128::from(random_u64()) << 64 | u128::from(random_u64())
+}
+
+/// Hyphenated UUID with the version nibble and RFC 9562 variant bits set
+fn format_uuid(bits: u128, version: u128) -> String {
+    let bits = bits & !(0xf << 76) | version << 76;
+    let bits = bits & !(0b11 << 62) | 0b10 << 62;
+    let hex = format!("{bits:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Request extension naming the header the App stored the request id in
+pub(crate) struct RequestIdHeader(pub(crate) HeaderName);
+
+/// Longest client-supplied request id kept by `RequestIdPolicy::Validate`
+pub const MAX_CLIENT_ID_LEN: usize = 128;
+
+/// How `x-request-id` values sent by clients are treated
+///
+/// Request ids end up in logs, spans and response headers, so by default only
+/// short ids made of safe characters are kept; anything else is replaced by a
+/// generated id.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RequestIdPolicy {
+    /// Keep ids of up to `MAX_CLIENT_ID_LEN` characters from `A-Z a-z 0-9 - _ . : + / = @`
+    #[default]
+    Validate,
+    /// Keep any non-empty id that is a valid header value
+    Trust,
+    /// Ignore client ids and always generate one
+    Generate,
+}
+
+impl RequestIdPolicy {
+    /// The client's id if this policy accepts it
+    pub fn accept<'a>(&self, id: &'a str) -> Option<&'a str> {
+        let ok = match self {
+            Self::Validate => {
+                !id.is_empty()
+                    && id.len() <= MAX_CLIENT_ID_LEN
+                    && id
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b"-_.:+/=@".contains(&b))
+            }
+            Self::Trust => !id.is_empty() && http::HeaderValue::from_str(id).is_ok(),
+            Self::Generate => false,
+        };
+        ok.then_some(id)
+    }
+
+    /// The request id to use for a request carrying `header` (if any)
+    pub fn resolve(
+        &self,
+        header: Option<&http::HeaderValue>,
+        generator: &RequestIdGenerator,
+    ) -> String {
+        let Some(value) = header else {
+            return generator.generate();
+        };
+        match value.to_str().ok().and_then(|id| self.accept(id)) {
+            Some(id) => id.to_string(),
+            None => {
+                if *self != Self::Generate {
+                    tracing::debug!(policy = ?self, "Replacing rejected client request id");
+                }
+                generator.generate()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_filter_client_ids() {
+        let policy = RequestIdPolicy::Validate;
+        assert_eq!(policy.accept("req-42.a:b"), Some("req-42.a:b"));
+        assert_eq!(policy.accept("a b"), None);
+        assert_eq!(policy.accept("<script>"), None);
+        assert_eq!(policy.accept(&"x".repeat(MAX_CLIENT_ID_LEN + 1)), None);
+        assert_eq!(RequestIdPolicy::Trust.accept("a b"), Some("a b"));
+        assert_eq!(RequestIdPolicy::Generate.accept("ok"), None);
+
+        let counter = RequestIdGenerator::default();
+        let header = http::HeaderValue::from_bytes(b"caf\xc3\xa9").unwrap();
+        assert_ne!(
+            RequestIdPolicy::Trust.resolve(Some(&header), &counter),
+            "café"
+        );
+        let header = http::HeaderValue::from_static("abc");
+        assert_eq!(policy.resolve(Some(&header), &counter), "abc");
+    }
+
+    #[test]
+    fn generators_make_uuids() {
+        let v4 = RequestIdGenerator::UuidV4.generate();
+        assert_eq!(v4.len(), 36);
+        assert_eq!(&v4[14..15], "4");
+        assert!(matches!(&v4[19..20], "8" | "9" | "a" | "b"));
+        assert_ne!(v4, RequestIdGenerator::UuidV4.generate());
+
+        let first = RequestIdGenerator::UuidV7.generate();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = RequestIdGenerator::UuidV7.generate();
+        assert_eq!(&first[14..15], "7");
+        assert!(first < second);
+
+        let custom = RequestIdGenerator::custom(|| "fixed".to_string());
+        assert_eq!(RequestIdPolicy::Generate.resolve(None, &custom), "fixed");
+    }
+}
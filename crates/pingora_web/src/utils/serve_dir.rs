@@ -21,6 +21,9 @@ pub struct ServeDir {
     // Optional fallback file used when the path is empty or resolves to a directory.
     // When None, missing/dir paths return 404.
     fallback: Option<PathBuf>,
+    read_size: usize,
+    #[cfg(feature = "mmap")]
+    mmap: bool,
 }
 
 impl ServeDir {
@@ -29,9 +32,27 @@ impl ServeDir {
             root: root.into(),
             param: None,
             fallback: None,
+            read_size: crate::core::response::FILE_READ_SIZE,
+            #[cfg(feature = "mmap")]
+            mmap: false,
         }
     }
 
+    /// Read files this many bytes at a time (default: 64KB)
+    pub fn with_read_size(mut self, bytes: usize) -> Self {
+        self.read_size = bytes;
+        self
+    }
+
+    /// Serve files from memory maps (see `PingoraWebHttpResponse::mmap_file`)
+    ///
+    /// Only for directories whose files are never modified in place.
+    #[cfg(feature = "mmap")]
+    pub fn with_mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
     /// Specify which route parameter to read the relative file path from.
     /// Example: router.get("/assets/*p", Arc::new(ServeDir::new("assets").with_param_name("p")))
     pub fn with_param_name<S: Into<String>>(mut self, name: S) -> Self {
@@ -91,81 +112,79 @@ impl ServeDir {
         if req.params.len() == 1 {
             let (_, v) = req.params.iter().next().unwrap();
             if !v.is_empty() {
-                return Some(v.as_str());
+                return Some(v);
             }
         }
         None
     }
-}
 
-#[async_trait]
-impl Handler for ServeDir {
-    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+    /// The file a request maps to, canonicalized and confined to the root
+    pub(crate) async fn resolve(&self, req: &PingoraHttpRequest) -> Option<PathBuf> {
         // Expect a param from pattern like "/assets/*path" or a configured param name.
         // If missing or empty (e.g., request "/assets"), use fallback when provided; else 404.
-        let mut full = if let Some(rel) = self.extract_rel_path(&req) {
+        let mut full = if let Some(rel) = self.extract_rel_path(req) {
             let safe = Self::sanitize(rel);
             self.root.join(safe)
         } else if let Some(fb) = &self.fallback {
             self.root.join(fb)
         } else {
-            return Ok(PingoraWebHttpResponse::text(
-                StatusCode::NOT_FOUND,
-                "Not Found",
-            ));
+            return None;
         };
 
         // If the path is a directory, try appending index.html
         if let Ok(meta) = tokio::fs::metadata(&full).await
             && meta.is_dir()
         {
-            if let Some(fb) = &self.fallback {
-                full = full.join(fb);
-            } else {
-                return Ok(PingoraWebHttpResponse::text(
-                    StatusCode::NOT_FOUND,
-                    "Not Found",
-                ));
-            }
+            full = full.join(self.fallback.as_ref()?);
         }
 
         // Canonicalize both root and the target to prevent escaping via symlinks
-        let root_canon = match tokio::fs::canonicalize(&self.root).await {
-            Ok(p) => p,
-            Err(_) => {
-                return Ok(PingoraWebHttpResponse::text(
-                    StatusCode::NOT_FOUND,
-                    "Not Found",
-                ));
-            }
-        };
-        let full_canon = match tokio::fs::canonicalize(&full).await {
-            Ok(p) => p,
-            Err(_) => {
-                return Ok(PingoraWebHttpResponse::text(
-                    StatusCode::NOT_FOUND,
-                    "Not Found",
-                ));
-            }
-        };
+        let root_canon = tokio::fs::canonicalize(&self.root).await.ok()?;
+        let full_canon = tokio::fs::canonicalize(&full).await.ok()?;
 
         // Enforce that the file must be within the root directory
         if !full_canon.starts_with(&root_canon) {
-            return Ok(PingoraWebHttpResponse::text(
-                StatusCode::NOT_FOUND,
-                "Not Found",
-            ));
+            return None;
         }
 
         match tokio::fs::metadata(&full_canon).await {
-            Ok(meta) if meta.is_file() => Ok(PingoraWebHttpResponse::stream_file(
+            Ok(meta) if meta.is_file() => Some(full_canon),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for ServeDir {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        match self.resolve(&req).await {
+            #[cfg(feature = "mmap")]
+            Some(path) if self.mmap => PingoraWebHttpResponse::mmap_file(StatusCode::OK, &path)
+                .map_err(crate::error::internal_error),
+            Some(path) => Ok(PingoraWebHttpResponse::stream_file_with_read_size(
                 StatusCode::OK,
-                &full_canon,
+                &path,
+                self.read_size,
             )),
-            _ => Ok(PingoraWebHttpResponse::text(
+            None => Ok(PingoraWebHttpResponse::text(
                 StatusCode::NOT_FOUND,
                 "Not Found",
             )),
         }
     }
+
+    // Static files never need the request body
+    fn buffer_body(&self) -> bool {
+        false
+    }
+
+    fn check(&self) -> Vec<String> {
+        let mut problems: Vec<String> = crate::server::check::check_dir("root", &self.root)
+            .into_iter()
+            .collect();
+        if self.read_size == 0 {
+            problems.push("read size is 0".to_string());
+        }
+        problems
+    }
 }
@@ -0,0 +1,236 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::span::{Attributes, Id};
+use tracing::{Span, Subscriber};
+use tracing_subscriber::Registry;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Target and name of the span opened by `TracingMiddleware`
+const REQUEST_TARGET: &str = "pingora_web::middleware::tracing_middleware";
+const REQUEST_SPAN: &str = "request";
+
+/// Layer recording how long the spans inside each request took
+///
+/// `TracingMiddleware::slow_requests` adds them to its slow request warning
+/// as an indented list in start order, e.g.
+/// `db.query +1.2ms 40.1ms (busy 0.3ms)`. Busy time counts only polls of
+/// the span's future; the rest is spent waiting. Spans that close after the
+/// response was built, such as those of spawned tasks, are not listed.
+///
+/// The subscriber must be built on `tracing_subscriber::Registry`.
+///
+/// # Example
+/// ```no_run
+/// use pingora_web::utils::SpanTimingLayer;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let subscriber = tracing_subscriber::registry().with(SpanTimingLayer::new());
+/// tracing::subscriber::set_global_default(subscriber).unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpanTimingLayer;
+
+impl SpanTimingLayer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+/// Timing of a span still open
+struct Timing {
+    start: Instant,
+    busy: Duration,
+    entered: Option<Instant>,
+}
+
+/// Finished spans collected on the request span
+struct RequestTimings {
+    start: Instant,
+    spans: Mutex<Vec<SpanTiming>>,
+}
+
+struct SpanTiming {
+    name: &'static str,
+    depth: usize,
+    offset: Duration,
+    total: Duration,
+    busy: Duration,
+}
+
+impl<S> Layer<S> for SpanTimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let start = Instant::now();
+        let mut extensions = span.extensions_mut();
+        let meta = attrs.metadata();
+        if meta.name() == REQUEST_SPAN && meta.target() == REQUEST_TARGET {
+            extensions.insert(RequestTimings {
+                start,
+                spans: Mutex::new(Vec::new()),
+            });
+        } else {
+            extensions.insert(Timing {
+                start,
+                busy: Duration::ZERO,
+                entered: None,
+            });
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(timing) = span.extensions_mut().get_mut::<Timing>()
+        {
+            timing.entered = Some(Instant::now());
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(timing) = span.extensions_mut().get_mut::<Timing>()
+            && let Some(entered) = timing.entered.take()
+        {
+            timing.busy += entered.elapsed();
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(timing) = span.extensions_mut().remove::<Timing>() else {
+            return;
+        };
+        for (depth, ancestor) in span.scope().skip(1).enumerate() {
+            let extensions = ancestor.extensions();
+            if let Some(request) = extensions.get::<RequestTimings>() {
+                request.spans.lock().unwrap().push(SpanTiming {
+                    name: span.name(),
+                    depth,
+                    offset: timing.start.saturating_duration_since(request.start),
+                    total: timing.start.elapsed(),
+                    busy: timing.busy,
+                });
+                return;
+            }
+        }
+    }
+}
+
+/// The finished spans inside `request`, one per line, when `SpanTimingLayer` runs
+pub(crate) fn request_timings(request: &Span) -> Option<String> {
+    request
+        .with_subscriber(|(id, dispatch)| {
+            let registry = dispatch.downcast_ref::<Registry>()?;
+            let span = registry.span(id)?;
+            let extensions = span.extensions();
+            let request = extensions.get::<RequestTimings>()?;
+            let mut spans = request.spans.lock().unwrap();
+            spans.sort_by_key(|s| s.offset);
+            let mut out = String::new();
+            for s in spans.iter() {
+                let _ = writeln!(
+                    out,
+                    "{:indent$}{} +{:.1?} {:.1?} (busy {:.1?})",
+                    "",
+                    s.name,
+                    s.offset,
+                    s.total,
+                    s.busy,
+                    indent = s.depth * 2
+                );
+            }
+            Some(out)
+        })
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{App, TracingMiddleware};
+    use std::sync::Arc;
+    use tracing::field::{Field, Visit};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    type Fields = Vec<(String, String)>;
+
+    /// Fields of every warning event
+    #[derive(Clone, Default)]
+    struct Warnings(Arc<Mutex<Vec<Fields>>>);
+
+    struct Recorder(Fields);
+
+    impl Visit for Recorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .push((field.name().to_string(), format!("{value:?}")));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push((field.name().to_string(), value.to_string()));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Warnings {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            if *event.metadata().level() == tracing::Level::WARN {
+                let mut fields = Recorder(Vec::new());
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_requests_list_their_spans() {
+        let warnings = Warnings::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(SpanTimingLayer::new())
+            .with(warnings.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut app = App::default();
+        app.use_middleware(TracingMiddleware::new().slow_requests(Duration::from_millis(20)));
+        app.get_fn("/fast", |_| "fast");
+        app.get_fn("/slow", |_| {
+            let _load = tracing::info_span!("load").entered();
+            tracing::info_span!("query").in_scope(|| {
+                std::thread::sleep(Duration::from_millis(30));
+            });
+            "slow"
+        });
+        let client = app.test_client();
+
+        client.get("/fast").send().await;
+        assert!(warnings.0.lock().unwrap().is_empty());
+
+        client
+            .get("/slow?page=2")
+            .header("user-agent", "probe/1.0")
+            .send()
+            .await;
+        let logged = warnings.0.lock().unwrap().pop().unwrap();
+        let field = |name: &str| {
+            logged
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(field("message"), Some("Slow request"));
+        assert_eq!(field("uri"), Some("/slow?page=2"));
+        assert_eq!(field("user_agent"), Some("probe/1.0"));
+        let timings = field("span_timings").unwrap();
+        let lines: Vec<&str> = timings.lines().collect();
+        assert!(lines[0].starts_with("load +"), "{timings}");
+        assert!(lines[1].starts_with("  query +"), "{timings}");
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use http::{Method, StatusCode};
+
+use crate::App;
+use crate::core::PingoraHttpRequest;
+
+/// Safety cap on crawled pages, in case dynamic pages keep producing new links
+const MAX_EXPORT_PAGES: usize = 10_000;
+
+/// Result of `App::export_static`
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    /// Files written, as `(url path, file path)`
+    pub written: Vec<(String, PathBuf)>,
+    /// Crawled paths that answered with an error status
+    pub errors: Vec<(String, StatusCode)>,
+    /// GET route patterns of the App's own router (not vhosts) no crawled path matched
+    pub unreachable: Vec<String>,
+}
+
+impl ExportReport {
+    /// `true` when every crawled page succeeded and every GET route was reached
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty() && self.unreachable.is_empty()
+    }
+}
+
+pub(crate) async fn export<S: Send + Sync + 'static>(
+    app: &App<S>,
+    output_dir: &Path,
+    seed_paths: Vec<String>,
+) -> std::io::Result<ExportReport> {
+    let mut report = ExportReport::default();
+    let mut visited: HashSet<String> = HashSet::new();
+    let mut queue: VecDeque<String> = seed_paths
+        .iter()
+        .filter_map(|p| resolve_link("/", p))
+        .collect();
+
+    while let Some(path) = queue.pop_front() {
+        if !visited.insert(path.clone()) {
+            continue;
+        }
+        if visited.len() > MAX_EXPORT_PAGES {
+            tracing::warn!("Static export stopped after {} pages", MAX_EXPORT_PAGES);
+            break;
+        }
+
+        let res = app
+            .handle(PingoraHttpRequest::new(Method::GET, &path))
+            .await;
+        let status = res.status;
+
+        if status.is_redirection() {
+            if let Some(next) = res
+                .headers
+                .get(http::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|loc| resolve_link(&path, loc))
+            {
+                queue.push_back(next);
+            }
+            continue;
+        }
+        if !status.is_success() {
+            report.errors.push((path, status));
+            continue;
+        }
+
+        let is_html = res
+            .headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.starts_with("text/html"));
+        let body = res.body.collect().await;
+
+        if is_html {
+            let html = String::from_utf8_lossy(&body);
+            for link in extract_links(&html) {
+                if let Some(next) = resolve_link(&path, &link)
+                    && !visited.contains(&next)
+                {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        let file = output_dir.join(file_for(&path, is_html));
+        if let Some(parent) = file.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&file, &body).await?;
+        report.written.push((path, file));
+    }
+
+    // Crawl requests have no Host header, so vhost routes are never reached
+    let patterns = app.router.patterns(&Method::GET);
+    let mut matcher = matchit::Router::new();
+    for (i, pattern) in patterns.iter().enumerate() {
+        // Already accepted by the App's router, so this can't conflict
+        let _ = matcher.insert(pattern.as_str(), i);
+    }
+    let mut reached = vec![false; patterns.len()];
+    for path in &visited {
+        if let Ok(matched) = matcher.at(path) {
+            reached[*matched.value] = true;
+        }
+    }
+    report.unreachable = patterns
+        .iter()
+        .zip(reached)
+        .filter(|(_, reached)| !reached)
+        .map(|(pattern, _)| pattern.clone())
+        .collect();
+
+    Ok(report)
+}
+
+/// Relative file path for a URL path (`/` -> `index.html`, `/docs` -> `docs/index.html`)
+fn file_for(path: &str, is_html: bool) -> PathBuf {
+    let trimmed = path.trim_start_matches('/');
+    let last = trimmed.rsplit('/').next().unwrap_or("");
+    let is_dir = trimmed.is_empty() || path.ends_with('/');
+    if is_dir || (is_html && !last.contains('.')) {
+        PathBuf::from(trimmed).join("index.html")
+    } else {
+        PathBuf::from(trimmed)
+    }
+}
+
+/// Collect `href` and `src` attribute values from HTML
+fn extract_links(html: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    for attr in ["href=", "src="] {
+        let mut rest = html;
+        while let Some(idx) = rest.find(attr) {
+            rest = &rest[idx + attr.len()..];
+            let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+                continue;
+            };
+            rest = &rest[1..];
+            if let Some(end) = rest.find(quote) {
+                links.push(rest[..end].to_string());
+                rest = &rest[end..];
+            }
+        }
+    }
+    links
+}
+
+/// Resolve a link against the current page path; `None` for external links
+fn resolve_link(base: &str, link: &str) -> Option<String> {
+    let link = link.split(['#', '?']).next().unwrap_or("").trim();
+    if link.is_empty() || link.starts_with("//") || link.contains(':') {
+        return None;
+    }
+
+    let joined = if link.starts_with('/') {
+        link.to_string()
+    } else {
+        let dir = &base[..base.rfind('/').map_or(0, |i| i + 1)];
+        format!("{dir}{link}")
+    };
+
+    // Normalize `.` and `..` so pages can't escape the output directory
+    let mut segments: Vec<&str> = Vec::new();
+    for seg in joined.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+    let mut out = format!("/{}", segments.join("/"));
+    if (joined.ends_with('/') || joined.ends_with("/.") || joined.ends_with("/..")) && out != "/" {
+        out.push('/');
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PingoraWebHttpResponse;
+
+    #[test]
+    fn resolves_links() {
+        assert_eq!(resolve_link("/docs/a", "b").as_deref(), Some("/docs/b"));
+        assert_eq!(resolve_link("/docs/", "../x#top").as_deref(), Some("/x"));
+        assert_eq!(resolve_link("/", "../../etc").as_deref(), Some("/etc"));
+        assert_eq!(resolve_link("/", "https://example.com"), None);
+        assert_eq!(resolve_link("/", "mailto:a@b.c"), None);
+        assert_eq!(file_for("/", true), PathBuf::from("index.html"));
+        assert_eq!(file_for("/docs", true), PathBuf::from("docs/index.html"));
+        assert_eq!(file_for("/app.css", false), PathBuf::from("app.css"));
+    }
+
+    #[tokio::test]
+    async fn exports_reachable_pages() {
+        let html = |s: &'static str| PingoraWebHttpResponse::html(StatusCode::OK, s);
+        let mut app = App::default();
+        app.get_fn("/", move |_req| {
+            html(
+                r#"<a href="/about">About</a> <a href='docs/intro'>Docs</a>
+                <link href="/style.css"> <a href="https://example.com">x</a>
+                <a href="/missing">broken</a>"#,
+            )
+        });
+        app.get_fn("/about", move |_req| html("<p>about</p>"));
+        app.get_fn("/docs/{page}", move |_req| {
+            html(r#"<a href="../">home</a>"#)
+        });
+        app.get_fn("/style.css", |_req| "body {}");
+        app.get_fn("/orphan", |_req| "never linked");
+        // Vhost routes aren't crawled nor reported
+        let mut blog = crate::core::router::Router::new();
+        blog.get_fn("/posts", |_req| "posts");
+        app.vhost("blog.test", blog);
+
+        let dir = std::env::temp_dir().join(format!("pw-export-{}", crate::utils::generate()));
+        let report = app.export_static(&dir, ["/"]).await.unwrap();
+
+        assert!(dir.join("index.html").exists());
+        assert!(dir.join("about/index.html").exists());
+        assert!(dir.join("docs/intro/index.html").exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("style.css")).unwrap(),
+            "body {}"
+        );
+        assert_eq!(report.written.len(), 4);
+        assert_eq!(
+            report.errors,
+            vec![("/missing".to_string(), StatusCode::NOT_FOUND)]
+        );
+        assert_eq!(report.unreachable, vec!["/orphan".to_string()]);
+        assert!(!report.is_complete());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
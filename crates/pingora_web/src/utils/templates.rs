@@ -0,0 +1,154 @@
+use std::path::Path;
+
+use http::{HeaderValue, StatusCode};
+use minijinja::Environment;
+use serde::Serialize;
+
+use crate::core::PingoraWebHttpResponse;
+use crate::error::WebError;
+
+/// Named minijinja templates, shared by the App (see `App::set_templates`)
+///
+/// Templates ending in `.html`, `.htm` or `.xml` are auto-escaped. Handlers
+/// render with `PingoraWebHttpResponse::render(&req, "page.html", context)`.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, PingoraWebHttpResponse};
+/// use pingora_web::utils::Templates;
+///
+/// let mut templates = Templates::new();
+/// templates.add("hello.html", "<p>Hello {{ name }}</p>").unwrap();
+///
+/// let mut app = App::default();
+/// app.set_templates(templates);
+/// app.get_fn("/hello", |req| {
+///     PingoraWebHttpResponse::render(&req, "hello.html", serde_json::json!({ "name": "Ada" }))
+/// });
+/// ```
+pub struct Templates {
+    env: Environment<'static>,
+}
+
+impl std::fmt::Debug for Templates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Templates").finish()
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Templates {
+    pub fn new() -> Self {
+        Self {
+            env: Environment::new(),
+        }
+    }
+
+    /// Load templates on first use from files under `dir`, named by relative path
+    pub fn from_dir(dir: impl AsRef<Path>) -> Self {
+        let mut env = Environment::new();
+        env.set_loader(minijinja::path_loader(dir.as_ref()));
+        Self { env }
+    }
+
+    /// Compile and register a template
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        source: impl Into<String>,
+    ) -> Result<&mut Self, minijinja::Error> {
+        self.env.add_template_owned(name.into(), source.into())?;
+        Ok(self)
+    }
+
+    /// The underlying environment, to add filters, functions or globals
+    pub fn environment_mut(&mut self) -> &mut Environment<'static> {
+        &mut self.env
+    }
+
+    /// Render `name` into a 200 response typed by its extension (HTML when unknown)
+    ///
+    /// A missing template or failed render is a server error: clients get a
+    /// plain 500 while the cause is logged with the template name.
+    pub fn render(
+        &self,
+        name: &str,
+        context: impl Serialize,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        let body = self
+            .env
+            .get_template(name)
+            .and_then(|template| template.render(context))
+            .map_err(|e| {
+                WebError::wrap(StatusCode::INTERNAL_SERVER_ERROR, e).with_context("template", name)
+            })?;
+        Ok(PingoraWebHttpResponse::html(StatusCode::OK, body)
+            .header(http::header::CONTENT_TYPE, content_type(name)))
+    }
+}
+
+fn content_type(name: &str) -> HeaderValue {
+    let Some(mime) = mime_guess::from_path(name).first() else {
+        return HeaderValue::from_static("text/html; charset=utf-8");
+    };
+    let value = if mime.type_() == mime_guess::mime::TEXT && mime.get_param("charset").is_none() {
+        format!("{mime}; charset=utf-8")
+    } else {
+        mime.to_string()
+    };
+    HeaderValue::from_str(&value).unwrap_or(HeaderValue::from_static("text/html; charset=utf-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+
+    #[tokio::test]
+    async fn renders_escaped_html_and_hides_template_errors() {
+        let mut templates = Templates::new();
+        templates
+            .add("hello.html", "<p>Hello {{ name }}</p>")
+            .unwrap()
+            .add("hello.txt", "Hello {{ name }}")
+            .unwrap()
+            .add("broken.html", "{{ missing.field }}")
+            .unwrap();
+        templates
+            .environment_mut()
+            .set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+        let mut app = App::default();
+        app.set_templates(templates);
+        app.get_fn("/html", |req| {
+            PingoraWebHttpResponse::render(&req, "hello.html", serde_json::json!({ "name": "<b>" }))
+        });
+        app.get_fn("/text", |req| {
+            PingoraWebHttpResponse::render(&req, "hello.txt", serde_json::json!({ "name": "<b>" }))
+        });
+        app.get_fn("/broken/page", |req| {
+            PingoraWebHttpResponse::render(&req, "broken.html", ())
+        });
+        let client = app.test_client();
+
+        let res = client.get("/html").send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.header("content-type"), Some("text/html; charset=utf-8"));
+        assert_eq!(res.text(), "<p>Hello &lt;b&gt;</p>");
+
+        let res = client.get("/text").send().await;
+        assert_eq!(
+            res.header("content-type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(res.text(), "Hello <b>");
+
+        let res = client.get("/broken/page").send().await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!res.text().contains("missing"));
+    }
+}
@@ -0,0 +1,244 @@
+use bytes::Bytes;
+use http::{HeaderMap, Method, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::App;
+use crate::core::{PingoraHttpRequest, PingoraWebHttpResponse};
+
+/// In-process client that runs requests through `App::handle` without a network listener.
+///
+/// # Example
+/// ```
+/// use pingora_web::{App, PingoraWebHttpResponse, StatusCode};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut app = App::default();
+/// app.get_fn("/", |_req| Ok(PingoraWebHttpResponse::text(StatusCode::OK, "hi")));
+///
+/// let res = app.test_client().get("/").send().await;
+/// assert_eq!(res.status(), StatusCode::OK);
+/// assert_eq!(res.text(), "hi");
+/// # }
+/// ```
+pub struct TestClient<'a, S = ()> {
+    app: &'a App<S>,
+}
+
+impl<'a, S: Send + Sync + 'static> TestClient<'a, S> {
+    pub fn new(app: &'a App<S>) -> Self {
+        Self { app }
+    }
+
+    /// Start building a request with an arbitrary method
+    pub fn request<P: AsRef<str>>(&self, method: Method, path: P) -> TestRequest<'a, S> {
+        TestRequest {
+            app: self.app,
+            req: PingoraHttpRequest::new(method, path),
+        }
+    }
+
+    pub fn get<P: AsRef<str>>(&self, path: P) -> TestRequest<'a, S> {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post<P: AsRef<str>>(&self, path: P) -> TestRequest<'a, S> {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put<P: AsRef<str>>(&self, path: P) -> TestRequest<'a, S> {
+        self.request(Method::PUT, path)
+    }
+
+    pub fn patch<P: AsRef<str>>(&self, path: P) -> TestRequest<'a, S> {
+        self.request(Method::PATCH, path)
+    }
+
+    pub fn delete<P: AsRef<str>>(&self, path: P) -> TestRequest<'a, S> {
+        self.request(Method::DELETE, path)
+    }
+
+    pub fn head<P: AsRef<str>>(&self, path: P) -> TestRequest<'a, S> {
+        self.request(Method::HEAD, path)
+    }
+}
+
+/// A request being built by `TestClient`
+pub struct TestRequest<'a, S = ()> {
+    app: &'a App<S>,
+    req: PingoraHttpRequest,
+}
+
+impl<S: Send + Sync + 'static> TestRequest<'_, S> {
+    /// Set a request header
+    pub fn header(mut self, k: impl AsRef<str>, v: impl AsRef<str>) -> Self {
+        self.req = self.req.header(k, v);
+        self
+    }
+
+    /// Make the request come from `addr`, as seen by `req.peer_addr()`
+    pub fn peer_addr(mut self, addr: std::net::SocketAddr) -> Self {
+        self.req = self.req.with_connection(crate::core::ConnectionInfo {
+            peer_addr: Some(addr),
+            ..Default::default()
+        });
+        self
+    }
+
+    /// Set the raw request body
+    pub fn body<B: Into<Bytes>>(mut self, body: B) -> Self {
+        self.req = self.req.with_body(body);
+        self
+    }
+
+    /// Set a JSON body and `content-type: application/json`
+    pub fn json(self, value: &impl serde::Serialize) -> Self {
+        let body = serde_json::to_vec(value).expect("serializable test body");
+        self.header("content-type", "application/json").body(body)
+    }
+
+    /// Set a form body and `content-type: application/x-www-form-urlencoded`
+    pub fn form(self, value: &impl serde::Serialize) -> Self {
+        let body = serde_urlencoded::to_string(value).expect("serializable test form");
+        self.header("content-type", "application/x-www-form-urlencoded")
+            .body(body)
+    }
+
+    /// Run the request through the App and collect the full response body
+    pub async fn send(self) -> TestResponse {
+        let is_head = self.req.method() == Method::HEAD;
+        let res = self.app.handle(self.req).await;
+        TestResponse::collect(res, is_head).await
+    }
+}
+
+/// A fully buffered response returned by `TestRequest::send`
+#[derive(Debug)]
+pub struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    trailers: HeaderMap,
+}
+
+impl TestResponse {
+    async fn collect(res: PingoraWebHttpResponse, is_head: bool) -> Self {
+        // Like the real transport, HEAD responses carry no body
+        let body = if is_head {
+            Bytes::new()
+        } else {
+            res.body.collect().await
+        };
+        Self {
+            status: res.status,
+            headers: res.headers,
+            body,
+            trailers: res.trailers.map(|t| t.values()).unwrap_or_default(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Get a header value as a string, if present and valid UTF-8
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// Trailer values set by the time the body ended
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// Body as text (lossy UTF-8)
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Deserialize the body as JSON
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use serde::Deserialize;
+
+    #[tokio::test]
+    async fn collects_streaming_body() {
+        let mut app = App::default();
+        app.get_fn("/stream", |_req| {
+            let chunks = futures::stream::iter(vec![
+                Bytes::from_static(b"hello "),
+                Bytes::from_static(b"world"),
+            ]);
+            Ok(PingoraWebHttpResponse::stream(
+                StatusCode::OK,
+                chunks.boxed(),
+            ))
+        });
+
+        let res = app.test_client().get("/stream").send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.header("transfer-encoding"), Some("chunked"));
+        assert_eq!(res.text(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn sends_headers_and_json() {
+        #[derive(Deserialize)]
+        struct Echo {
+            agent: String,
+            len: usize,
+        }
+
+        let mut app = App::default();
+        app.post_fn("/echo", |req| {
+            let agent = req
+                .headers()
+                .get("user-agent")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("")
+                .to_string();
+            Ok(PingoraWebHttpResponse::json(
+                StatusCode::OK,
+                serde_json::json!({"agent": agent, "len": req.body().len()}),
+            ))
+        });
+
+        let res = app
+            .test_client()
+            .post("/echo")
+            .header("user-agent", "test")
+            .json(&serde_json::json!({"a": 1}))
+            .send()
+            .await;
+        let echo: Echo = res.json().unwrap();
+        assert_eq!(echo.agent, "test");
+        assert_eq!(echo.len, 7);
+        assert!(res.header("x-request-id").is_some());
+    }
+
+    #[tokio::test]
+    async fn head_has_no_body() {
+        let mut app = App::default();
+        app.get_fn("/", |_req| Ok(PingoraWebHttpResponse::ok("body")));
+
+        let res = app.test_client().head("/").send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.header("content-length"), Some("4"));
+        assert!(res.bytes().is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use http::StatusCode;
+
+use crate::core::{Deadline, Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::WebError;
+
+/// A handler with its own time limit, for routes slower or faster than the rest
+///
+/// Sets the request's deadline (keeping an earlier one, e.g. from
+/// `LimitsMiddleware`) and answers 408 like `LimitsMiddleware` when the
+/// handler runs past it.
+///
+/// ```
+/// use pingora_web::App;
+/// use pingora_web::utils::{ProxyHandler, Timeout};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let mut app = App::default();
+/// let search = Timeout::new(Duration::from_secs(2), ProxyHandler::new(([127, 0, 0, 1], 8081)));
+/// app.get("/search", Arc::new(search));
+/// ```
+pub struct Timeout {
+    timeout: Duration,
+    handler: Arc<dyn Handler>,
+}
+
+impl Timeout {
+    pub fn new(timeout: Duration, handler: impl Handler) -> Self {
+        Self::new_arc(timeout, Arc::new(handler))
+    }
+
+    /// Like `new`, for a handler that is shared with other routes
+    pub fn new_arc(timeout: Duration, handler: Arc<dyn Handler>) -> Self {
+        Self { timeout, handler }
+    }
+}
+
+#[async_trait]
+impl Handler for Timeout {
+    async fn handle(
+        &self,
+        mut req: PingoraHttpRequest,
+    ) -> Result<PingoraWebHttpResponse, WebError> {
+        req.set_deadline(Deadline::after(self.timeout));
+        let remaining = req.time_remaining().unwrap_or(self.timeout);
+        match tokio::time::timeout(remaining, self.handler.handle(req)).await {
+            Ok(response) => response,
+            Err(_) => {
+                tracing::warn!("Route timeout after {}ms", remaining.as_millis());
+                Ok(PingoraWebHttpResponse::text(
+                    StatusCode::REQUEST_TIMEOUT,
+                    "Request Timeout",
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{App, LimitsConfig, LimitsMiddleware};
+
+    struct Slow;
+
+    #[async_trait]
+    impl Handler for Slow {
+        async fn handle(
+            &self,
+            _req: PingoraHttpRequest,
+        ) -> Result<PingoraWebHttpResponse, WebError> {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(PingoraWebHttpResponse::text(StatusCode::OK, "done"))
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_get_their_own_deadline() {
+        let mut app = App::default();
+        app.use_middleware(LimitsMiddleware::with_config(
+            LimitsConfig::new().request_timeout(Duration::from_secs(30)),
+        ));
+        app.get(
+            "/slow",
+            Arc::new(Timeout::new(Duration::from_millis(20), Slow)),
+        );
+        app.get_fn("/left", |req| {
+            format!("{}", req.time_remaining().unwrap().as_secs())
+        });
+        let client = app.test_client();
+
+        let res = client.get("/slow").send().await;
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+        let res = client.get("/left").send().await;
+        assert_eq!(res.text(), "29");
+    }
+}
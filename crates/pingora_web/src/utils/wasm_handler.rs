@@ -0,0 +1,401 @@
+//! Experimental WASM handler sandbox (feature `wasm`)
+//!
+//! A guest module exports `memory` and `handle() -> i32` and talks to the host
+//! through imports in the `pingora_web` module (all pointers are offsets into
+//! the guest's exported memory):
+//!
+//! | import | signature | purpose |
+//! |---|---|---|
+//! | `req_len(part)` | `(i32) -> i32` | byte length of a request part: 0 method, 1 path, 2 body |
+//! | `req_read(part, ptr, len)` | `(i32, i32, i32) -> i32` | copy a request part into guest memory, returns bytes copied |
+//! | `req_header(name_ptr, name_len, out_ptr, out_len)` | `(i32, i32, i32, i32) -> i32` | copy a header value, returns its full length or -1 when missing |
+//! | `resp_status(code)` | `(i32)` | set the response status (default 200) |
+//! | `resp_header(name_ptr, name_len, val_ptr, val_len)` | `(i32, i32, i32, i32)` | append a response header |
+//! | `resp_body(ptr, len)` | `(i32, i32)` | append bytes to the response body |
+//!
+//! `handle` returning non-zero, trapping, running out of fuel, or exceeding the
+//! memory, response body or response header limits yields
+//! `500 Internal Server Error`. Pointers and lengths outside the guest's memory
+//! trap before the host copies anything.
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use http::{HeaderName, HeaderValue, StatusCode};
+use std::path::Path;
+use wasmtime::{
+    Caller, Config, Engine, Extern, InstancePre, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+use crate::core::{Handler, PingoraHttpRequest, PingoraWebHttpResponse};
+use crate::error::{self, WebError};
+
+/// Host import module name
+const HOST_MODULE: &str = "pingora_web";
+
+/// Per-request state owned by the guest's `Store`
+struct HostState {
+    method: Bytes,
+    path: Bytes,
+    body: Bytes,
+    headers: http::HeaderMap,
+    status: u16,
+    response_headers: Vec<(String, Vec<u8>)>,
+    response_body: BytesMut,
+    limits: StoreLimits,
+    max_response_bytes: usize,
+    max_response_headers: usize,
+}
+
+impl HostState {
+    fn part(&self, part: i32) -> &[u8] {
+        match part {
+            0 => &self.method,
+            1 => &self.path,
+            2 => &self.body,
+            _ => &[],
+        }
+    }
+}
+
+/// Handler running a WASM module per request in a fresh, resource-limited instance
+///
+/// # Example
+/// ```no_run
+/// use pingora_web::App;
+/// use pingora_web::utils::WasmHandler;
+/// use std::sync::Arc;
+///
+/// let mut app = App::default();
+/// let plugin = WasmHandler::from_file("plugins/hello.wasm").unwrap().fuel(5_000_000);
+/// app.get("/plugin/{*rest}", Arc::new(plugin));
+/// ```
+pub struct WasmHandler {
+    engine: Engine,
+    instance_pre: InstancePre<HostState>,
+    fuel: u64,
+    max_memory: usize,
+    max_response_bytes: usize,
+    max_response_headers: usize,
+}
+
+impl WasmHandler {
+    /// Load a module from a `.wasm` (or `.wat`) file
+    pub fn from_file(path: impl AsRef<Path>) -> wasmtime::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Compile a module from binary or text format
+    pub fn from_bytes(bytes: &[u8]) -> wasmtime::Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::new(&engine, bytes)?;
+        let linker = host_linker(&engine)?;
+        let instance_pre = linker.instantiate_pre(&module)?;
+        Ok(Self {
+            engine,
+            instance_pre,
+            fuel: 10_000_000,
+            max_memory: 16 * 1024 * 1024,
+            max_response_bytes: 16 * 1024 * 1024,
+            max_response_headers: 100,
+        })
+    }
+
+    /// Fuel (roughly, WASM instructions) each request may consume (default 10M)
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// Maximum linear memory per instance in bytes (default 16 MiB)
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = bytes;
+        self
+    }
+
+    /// Maximum response body the guest may write in bytes (default 16 MiB)
+    pub fn max_response_bytes(mut self, bytes: usize) -> Self {
+        self.max_response_bytes = bytes;
+        self
+    }
+
+    /// Maximum number of response headers the guest may add (default 100)
+    pub fn max_response_headers(mut self, count: usize) -> Self {
+        self.max_response_headers = count;
+        self
+    }
+}
+
+#[async_trait]
+impl Handler for WasmHandler {
+    async fn handle(&self, req: PingoraHttpRequest) -> Result<PingoraWebHttpResponse, WebError> {
+        let state = HostState {
+            method: Bytes::copy_from_slice(req.method().as_str().as_bytes()),
+            path: Bytes::copy_from_slice(req.path().as_bytes()),
+            body: req.body().clone(),
+            headers: req.headers().clone(),
+            status: 200,
+            response_headers: Vec::new(),
+            response_body: BytesMut::new(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.max_memory)
+                .instances(1)
+                .build(),
+            max_response_bytes: self.max_response_bytes,
+            max_response_headers: self.max_response_headers,
+        };
+
+        // Guest code is CPU-bound; keep it off the async workers
+        let (engine, instance_pre, fuel) =
+            (self.engine.clone(), self.instance_pre.clone(), self.fuel);
+        let result = tokio::task::spawn_blocking(move || run(&engine, &instance_pre, fuel, state))
+            .await
+            .map_err(|e| error::internal_error(format!("wasm task failed: {e}")))?;
+
+        let state = result.map_err(|e| {
+            tracing::warn!(error = %e, "WASM handler failed");
+            error::internal_error("WASM handler failed")
+        })?;
+
+        let status =
+            StatusCode::from_u16(state.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut res = PingoraWebHttpResponse::bytes(status, state.response_body.freeze());
+        for (name, value) in state.response_headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_bytes(&value),
+            ) {
+                res.headers.append(name, value);
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Run `handle()` in a fresh instance with its own store, fuel, and memory limit
+fn run(
+    engine: &Engine,
+    instance_pre: &InstancePre<HostState>,
+    fuel: u64,
+    state: HostState,
+) -> wasmtime::Result<HostState> {
+    let mut store = Store::new(engine, state);
+    store.limiter(|s| &mut s.limits);
+    store.set_fuel(fuel)?;
+    let instance = instance_pre.instantiate(&mut store)?;
+    let handle = instance.get_typed_func::<(), i32>(&mut store, "handle")?;
+    let code = handle.call(&mut store, ())?;
+    if code != 0 {
+        wasmtime::bail!("guest handle() returned {code}");
+    }
+    Ok(store.into_data())
+}
+
+fn memory(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<Memory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(m)) => Ok(m),
+        _ => wasmtime::bail!("guest must export `memory`"),
+    }
+}
+
+/// Copy `len` bytes at `ptr` out of guest memory, checking the range first so
+/// a bogus length can't make the host allocate
+fn read_guest(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let mem = memory(caller)?;
+    let start = usize::try_from(ptr)?;
+    let end = start.saturating_add(usize::try_from(len)?);
+    match mem.data(&*caller).get(start..end) {
+        Some(bytes) => Ok(bytes.to_vec()),
+        None => wasmtime::bail!("guest range {start}..{end} is out of bounds"),
+    }
+}
+
+fn host_linker(engine: &Engine) -> wasmtime::Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "req_len",
+        |caller: Caller<'_, HostState>, part: i32| caller.data().part(part).len() as i32,
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "req_read",
+        |mut caller: Caller<'_, HostState>,
+         part: i32,
+         ptr: i32,
+         len: i32|
+         -> wasmtime::Result<i32> {
+            let data = caller.data().part(part);
+            let n = data.len().min(usize::try_from(len)?);
+            let chunk = data[..n].to_vec();
+            let mem = memory(&mut caller)?;
+            mem.write(&mut caller, usize::try_from(ptr)?, &chunk)?;
+            Ok(n as i32)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "req_header",
+        |mut caller: Caller<'_, HostState>,
+         name_ptr: i32,
+         name_len: i32,
+         out_ptr: i32,
+         out_len: i32|
+         -> wasmtime::Result<i32> {
+            let name = read_guest(&mut caller, name_ptr, name_len)?;
+            let Some(value) = std::str::from_utf8(&name)
+                .ok()
+                .and_then(|n| caller.data().headers.get(n))
+                .map(|v| v.as_bytes().to_vec())
+            else {
+                return Ok(-1);
+            };
+            let n = value.len().min(usize::try_from(out_len)?);
+            let mem = memory(&mut caller)?;
+            mem.write(&mut caller, usize::try_from(out_ptr)?, &value[..n])?;
+            Ok(value.len() as i32)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "resp_status",
+        |mut caller: Caller<'_, HostState>, code: i32| {
+            caller.data_mut().status = u16::try_from(code).unwrap_or(500);
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "resp_header",
+        |mut caller: Caller<'_, HostState>,
+         name_ptr: i32,
+         name_len: i32,
+         val_ptr: i32,
+         val_len: i32|
+         -> wasmtime::Result<()> {
+            let state = caller.data();
+            if state.response_headers.len() >= state.max_response_headers {
+                wasmtime::bail!(
+                    "guest exceeded {} response headers",
+                    state.max_response_headers
+                );
+            }
+            let name = read_guest(&mut caller, name_ptr, name_len)?;
+            let value = read_guest(&mut caller, val_ptr, val_len)?;
+            let name = String::from_utf8(name)?;
+            caller.data_mut().response_headers.push((name, value));
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "resp_body",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let state = caller.data();
+            let total = state
+                .response_body
+                .len()
+                .saturating_add(usize::try_from(len)?);
+            if total > state.max_response_bytes {
+                wasmtime::bail!("guest exceeded {} response bytes", state.max_response_bytes);
+            }
+            let chunk = read_guest(&mut caller, ptr, len)?;
+            caller.data_mut().response_body.extend_from_slice(&chunk);
+            Ok(())
+        },
+    )?;
+
+    Ok(linker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::App;
+    use std::sync::Arc;
+
+    // Echoes the request path with an `x-guest` header
+    const ECHO: &str = r#"
+        (module
+          (import "pingora_web" "req_len" (func $req_len (param i32) (result i32)))
+          (import "pingora_web" "req_read" (func $req_read (param i32 i32 i32) (result i32)))
+          (import "pingora_web" "resp_status" (func $resp_status (param i32)))
+          (import "pingora_web" "resp_header" (func $resp_header (param i32 i32 i32 i32)))
+          (import "pingora_web" "resp_body" (func $resp_body (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "x-guest" "wasm")
+          (func (export "handle") (result i32)
+            (local $len i32)
+            (local.set $len (call $req_len (i32.const 1)))
+            (drop (call $req_read (i32.const 1) (i32.const 64) (local.get $len)))
+            (call $resp_status (i32.const 201))
+            (call $resp_header (i32.const 0) (i32.const 7) (i32.const 7) (i32.const 4))
+            (call $resp_body (i32.const 64) (local.get $len))
+            (i32.const 0)))
+    "#;
+
+    const SPIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "handle") (result i32)
+            (loop $forever (br $forever))
+            (i32.const 0)))
+    "#;
+
+    #[tokio::test]
+    async fn guest_reads_request_and_writes_response() {
+        let mut app = App::default();
+        let handler = WasmHandler::from_bytes(ECHO.as_bytes()).unwrap();
+        app.get("/echo/{*rest}", Arc::new(handler));
+
+        let res = app.test_client().get("/echo/hello").send().await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.header("x-guest"), Some("wasm"));
+        assert_eq!(res.text(), "/echo/hello");
+    }
+
+    #[tokio::test]
+    async fn guest_output_is_limited() {
+        // Asks the host to copy far more than its memory holds
+        const HUGE: &str = r#"
+            (module
+              (import "pingora_web" "resp_body" (func $resp_body (param i32 i32)))
+              (memory (export "memory") 1)
+              (func (export "handle") (result i32)
+                (call $resp_body (i32.const 0) (i32.const 0x7fffffff))
+                (i32.const 0)))
+        "#;
+        let mut app = App::default();
+        let echo = || WasmHandler::from_bytes(ECHO.as_bytes()).unwrap();
+        app.get("/body/{*rest}", Arc::new(echo().max_response_bytes(4)));
+        app.get("/headers/{*rest}", Arc::new(echo().max_response_headers(0)));
+        let huge = WasmHandler::from_bytes(HUGE.as_bytes()).unwrap();
+        app.get("/huge", Arc::new(huge.max_response_bytes(usize::MAX)));
+
+        let client = app.test_client();
+        for path in ["/body/hello", "/headers/hello", "/huge"] {
+            let res = client.get(path).send().await;
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn runaway_guest_is_stopped_by_fuel() {
+        let mut app = App::default();
+        let handler = WasmHandler::from_bytes(SPIN.as_bytes())
+            .unwrap()
+            .fuel(10_000);
+        app.get("/spin", Arc::new(handler));
+
+        let res = app.test_client().get("/spin").send().await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -242,6 +242,18 @@ fn main() {
     // 添加请求级共享数据（插入开始时间）
     // router 已在构造时设置
 
+    // `--check`：只校验配置、不绑定端口，用于 CI/CD 预检
+    if std::env::args().any(|arg| arg == "--check") {
+        match app.check() {
+            Ok(()) => println!("configuration ok"),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     tracing::info!("🚀 启动 pingora_web 示例服务器");
     tracing::info!("📍 服务器地址: http://localhost:8080");
     tracing::info!("🔗 可用路由:");